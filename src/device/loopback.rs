@@ -1,43 +1,80 @@
-use std::ptr;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::u16;
 
 use crate::net::{
-    NetDevice, NetDeviceAddress, NetDeviceFlag, NetDeviceOps, NetDeviceType, NetProtocolType,
-    TransmitFnPtr, HARDWARE_ADDRESS_LENGTH,
+    NetDevice, NetDeviceAddress, NetDeviceFlag, NetDeviceOps, NetDeviceType, NetProtocol,
+    NetProtocolType, PollFnPtr, TransmitFnPtr, HARDWARE_ADDRESS_LENGTH,
 };
 
 const LOOPBACK_MTU: u16 = u16::MAX;
+const LOOPBACK_QUEUE_LIMIT: usize = 16;
+
+struct LoopbackQueueEntry {
+    protocol_type: u16,
+    data: Vec<u8>,
+}
+
+lazy_static! {
+    /// デバイス名ごとのキュー。別の loopback デバイスに送ったものは取り出さない
+    static ref LOOPBACK_QUEUES: Mutex<HashMap<String, VecDeque<LoopbackQueueEntry>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// デバイス名を重ならないようにする番号
+static LOOPBACK_INDEX: AtomicUsize = AtomicUsize::new(0);
 
 pub struct Loopback();
 
 impl Loopback {
-    pub fn transmit(
-        dev: &NetDevice,
-        protocol_type: u16,
-        data: *const u8,
-        size: usize,
-        dst: *mut u8,
-    ) -> isize {
+    pub fn transmit(dev: &NetDevice, protocol_type: u16, data: &[u8], _dst: *mut u8) -> isize {
+        let mut queues = LOOPBACK_QUEUES.lock().unwrap();
+        let queue = queues.entry(dev.name.clone()).or_default();
+        if queue.len() >= LOOPBACK_QUEUE_LIMIT {
+            eprintln!("queue is full DEV={}", dev.name);
+            return -1;
+        }
+        let size = data.len();
+        let data = data.to_vec();
+        queue.push_back(LoopbackQueueEntry {
+            protocol_type,
+            data,
+        });
         println!(
-            "DEV={} PROTOCOL_TYPE={:04x} SIZE={}",
-            dev.name, protocol_type, size
+            "queue pushed DEV={} PROTOCOL_TYPE={:04x} SIZE={} NUM={}",
+            dev.name,
+            protocol_type,
+            size,
+            queue.len()
         );
-        unsafe { ptr::copy_nonoverlapping(data, dst, size) };
-        for i in 0..size {
-            // コピーの検査
-            unsafe {
-                if *((data as usize + i) as *const u8) != *((dst as usize + i) as *const u8) {
+        size as isize
+    }
+
+    pub fn poll(dev: &'static NetDevice) -> isize {
+        let entry = LOOPBACK_QUEUES
+            .lock()
+            .unwrap()
+            .get_mut(&dev.name)
+            .and_then(|queue| queue.pop_front());
+        match entry {
+            Some(entry) => {
+                let size = entry.data.len();
+                // input_handler が Vec::from_raw_parts で所有権を引き取るので capacity を size に揃えて渡す
+                let data = Box::into_raw(entry.data.into_boxed_slice()) as *const u8;
+                if NetProtocol::input_handler(entry.protocol_type, data, size, dev).is_err() {
                     return -1;
                 }
+                size as isize
             }
+            None => -1,
         }
-        size as isize
     }
 
     pub fn new() -> Box<NetDevice> {
         let mut loopback = NetDevice::alloc();
         *loopback = NetDevice {
-            name: String::from("loopback"),
+            name: format!("loopback{}", LOOPBACK_INDEX.fetch_add(1, Ordering::Relaxed)),
             device_type: NetDeviceType::Loopback as u16 | NetProtocolType::Ip as u16,
             mtu: LOOPBACK_MTU,
             flags: NetDeviceFlag::Loopback as u16,
@@ -49,9 +86,9 @@ impl Loopback {
                 transmit: Option::from(Loopback::transmit as TransmitFnPtr),
                 open: None,
                 close: None,
-                poll: None,
+                poll: Option::from(Loopback::poll as PollFnPtr),
             },
-            interfaces: Vec::new(),
+            interfaces: Mutex::new(Vec::new()),
         };
         loopback
     }
@@ -1,3 +1,4 @@
+use std::sync::Mutex;
 use std::u16;
 
 use crate::net::{
@@ -10,18 +11,12 @@ const NULL_MTU: u16 = u16::MAX;
 pub struct Null();
 
 impl Null {
-    pub fn transmit(
-        dev: &NetDevice,
-        net_device_type: u16,
-        _data: *const u8,
-        size: usize,
-        _dst: *mut u8,
-    ) -> isize {
+    pub fn transmit(dev: &NetDevice, net_device_type: u16, data: &[u8], _dst: *mut u8) -> isize {
         eprintln!(
             "DEV={} TYPE={} SIZE={}",
             dev.name,
             NetDeviceType::from_u16(net_device_type),
-            size
+            data.len()
         );
         0
     }
@@ -43,7 +38,7 @@ impl Null {
                 close: None,
                 poll: None,
             },
-            interfaces: Vec::new(),
+            interfaces: Mutex::new(Vec::new()),
         };
         null
     }
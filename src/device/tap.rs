@@ -2,6 +2,7 @@ use std::fs::File;
 use std::io;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::Path;
+use std::str::FromStr;
 
 use ifstructs::ifreq;
 
@@ -0,0 +1,588 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{
+    DhcpError, DhcpErrorKind, DhcpMessage, DhcpMessageType, DhcpOption, DHCP_CLIENT_PORT,
+    DHCP_FLAG_BROADCAST, DHCP_HTYPE_ETHERNET, DHCP_OPTION_DNS_SERVER, DHCP_OPTION_LEASE_TIME,
+    DHCP_OPTION_REBINDING_TIME, DHCP_OPTION_RENEWAL_TIME, DHCP_OPTION_ROUTER,
+    DHCP_OPTION_SUBNET_MASK, DHCP_OP_BOOTREPLY, DHCP_SERVER_PORT,
+};
+use crate::dns;
+use crate::ipv4::{
+    route, IpInterface, Ipv4Address, Ipv4Endpoint, Ipv4Network, IP_ADDRESS_ANY,
+    IP_ADDRESS_BROADCAST,
+};
+use crate::net::NetDevice;
+use crate::udp;
+
+/// 再送間隔 (RFC 2131 4.1): 4 秒から倍々にして 64 秒で頭打ち
+const DHCP_RETRANSMIT_INITIAL: Duration = Duration::from_secs(4);
+const DHCP_RETRANSMIT_MAX: Duration = Duration::from_secs(64);
+/// REQUEST を送り直す回数。使い切ったら INIT からやり直す
+const DHCP_REQUEST_RETRIES: u32 = 4;
+/// RENEWING/REBINDING の再送間隔の下限 (RFC 2131 4.4.5)
+const DHCP_RENEW_RETRANSMIT_MIN: Duration = Duration::from_secs(60);
+/// 停止要求を確認する間隔
+const DHCP_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// サーバから得た設定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpLease {
+    pub address: Ipv4Address,
+    pub netmask: Ipv4Address,
+    pub gateway: Option<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address>,
+    pub server: Ipv4Address,
+    pub lease_time: Duration,
+    /// T1: この時間が経ったらリースを取得したサーバに延長を頼む
+    pub renewal_time: Duration,
+    /// T2: この時間が経ったら任意のサーバに延長を頼む
+    pub rebinding_time: Duration,
+    pub acquired: Instant,
+}
+
+impl DhcpLease {
+    /// ACK から作る。T1/T2 が無ければリース時間の 1/2 と 7/8 にする
+    pub fn from_ack(ack: &DhcpMessage, server: Ipv4Address, acquired: Instant) -> Option<Self> {
+        let lease_time = Duration::from_secs(ack.lease_time()? as u64);
+        let renewal_time = ack
+            .renewal_time()
+            .map_or(lease_time / 2, |t| Duration::from_secs(t as u64));
+        let rebinding_time = ack
+            .rebinding_time()
+            .map_or(lease_time * 7 / 8, |t| Duration::from_secs(t as u64));
+        let netmask = match ack.subnet_mask() {
+            Some(netmask) => netmask,
+            None => default_netmask(ack.yiaddr),
+        };
+        Some(DhcpLease {
+            address: ack.yiaddr,
+            netmask,
+            gateway: ack.routers().first().copied(),
+            dns_servers: ack.dns_servers(),
+            server: ack.server_identifier().unwrap_or(server),
+            lease_time,
+            renewal_time: renewal_time.min(lease_time),
+            rebinding_time: rebinding_time.clamp(renewal_time.min(lease_time), lease_time),
+            acquired,
+        })
+    }
+
+    pub fn network(&self) -> Option<Ipv4Network> {
+        Ipv4Network::with_netmask(self.address, self.netmask).ok()
+    }
+
+    pub fn renewal_deadline(&self) -> Instant {
+        self.acquired + self.renewal_time
+    }
+
+    pub fn rebinding_deadline(&self) -> Instant {
+        self.acquired + self.rebinding_time
+    }
+
+    pub fn expiry(&self) -> Instant {
+        self.acquired + self.lease_time
+    }
+}
+
+/// サブネットマスクが通知されなかったときはクラスから決める
+fn default_netmask(address: Ipv4Address) -> Ipv4Address {
+    match address.octets()[0] {
+        0..=127 => Ipv4Address::new(255, 0, 0, 0),
+        128..=191 => Ipv4Address::new(255, 255, 0, 0),
+        _ => Ipv4Address::new(255, 255, 255, 0),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpState {
+    Init,
+    Selecting,
+    Requesting,
+    Bound,
+    Renewing,
+    Rebinding,
+    Stopped,
+}
+
+struct DhcpShared {
+    state: Mutex<DhcpState>,
+    lease: Mutex<Option<DhcpLease>>,
+    /// 自分で入れたデフォルト経路のゲートウェイ
+    gateway: Mutex<Option<Ipv4Address>>,
+    stop: AtomicBool,
+    release: AtomicBool,
+}
+
+/// デバイス 1 つ分の DHCP クライアント
+pub struct DhcpClient {
+    dev: &'static NetDevice,
+    hostname: Option<String>,
+}
+
+impl DhcpClient {
+    pub fn new(dev: &'static NetDevice) -> Self {
+        DhcpClient {
+            dev,
+            hostname: None,
+        }
+    }
+
+    /// Host Name オプション (12) で名乗る名前
+    pub fn hostname(mut self, hostname: &str) -> Self {
+        self.hostname = Some(hostname.to_string());
+        self
+    }
+
+    /// 受信用の UDP を 0.0.0.0:68 に開き、状態遷移を回すスレッドを起動する
+    pub fn start(self) -> Result<DhcpClientHandle, DhcpError> {
+        let id = udp::open().map_err(|_| DhcpError::new(DhcpErrorKind::SocketError))?;
+        let bound = udp::bind_device(id, Some(self.dev))
+            .and_then(|_| udp::bind(id, Ipv4Endpoint::new(IP_ADDRESS_ANY, DHCP_CLIENT_PORT)));
+        if bound.is_err() {
+            let _ = udp::close(id);
+            return Err(DhcpError::new(DhcpErrorKind::SocketError));
+        }
+
+        let shared = Arc::new(DhcpShared {
+            state: Mutex::new(DhcpState::Init),
+            lease: Mutex::new(None),
+            gateway: Mutex::new(None),
+            stop: AtomicBool::new(false),
+            release: AtomicBool::new(false),
+        });
+        let mut runner = DhcpRunner {
+            dev: self.dev,
+            hostname: self.hostname,
+            pcb: id,
+            xid: 0,
+            started: Instant::now(),
+            shared: shared.clone(),
+        };
+        let thread = thread::spawn(move || runner.run());
+        Ok(DhcpClientHandle {
+            shared,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// 起動したクライアントを操作するハンドル
+/// drop すると release はせずにスレッドを止める
+pub struct DhcpClientHandle {
+    shared: Arc<DhcpShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DhcpClientHandle {
+    pub fn lease(&self) -> Option<DhcpLease> {
+        self.shared.lease.lock().unwrap().clone()
+    }
+
+    pub fn state(&self) -> DhcpState {
+        *self.shared.state.lock().unwrap()
+    }
+
+    /// DHCPRELEASE を送ってアドレスを返し、インターフェースを取り除いて止める
+    pub fn release(mut self) {
+        self.shared.release.store(true, Ordering::SeqCst);
+        self.join();
+    }
+
+    /// リースを返さずに止める。設定したインターフェースはそのまま残る
+    pub fn stop(mut self) {
+        self.join();
+    }
+
+    fn join(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for DhcpClientHandle {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+struct DhcpRunner {
+    dev: &'static NetDevice,
+    hostname: Option<String>,
+    pcb: usize,
+    xid: u32,
+    /// secs フィールドに入れる経過時間の起点
+    started: Instant,
+    shared: Arc<DhcpShared>,
+}
+
+impl DhcpRunner {
+    fn run(&mut self) {
+        let mut state = DhcpState::Init;
+        let mut offer: Option<DhcpMessage> = None;
+        while !self.stopping() {
+            self.set_state(state);
+            state = match state {
+                DhcpState::Init => {
+                    self.xid = generate_xid(&self.dev.hwaddr);
+                    self.started = Instant::now();
+                    DhcpState::Selecting
+                }
+                DhcpState::Selecting => match self.select() {
+                    Some(message) => {
+                        offer = Some(message);
+                        DhcpState::Requesting
+                    }
+                    None => DhcpState::Init,
+                },
+                DhcpState::Requesting => match offer.take().and_then(|o| self.request(&o)) {
+                    Some(lease) => {
+                        self.configure(lease);
+                        DhcpState::Bound
+                    }
+                    None => DhcpState::Init,
+                },
+                DhcpState::Bound => match self.lease() {
+                    Some(lease) if self.sleep_until(lease.renewal_deadline()) => {
+                        DhcpState::Renewing
+                    }
+                    Some(_) => DhcpState::Bound,
+                    None => DhcpState::Init,
+                },
+                DhcpState::Renewing => self.extend(false),
+                DhcpState::Rebinding => self.extend(true),
+                DhcpState::Stopped => break,
+            };
+        }
+
+        if self.shared.release.load(Ordering::SeqCst) {
+            if let Some(lease) = self.lease() {
+                self.send_release(&lease);
+                self.unconfigure();
+            }
+        }
+        let _ = udp::close(self.pcb);
+        self.set_state(DhcpState::Stopped);
+    }
+
+    fn stopping(&self) -> bool {
+        self.shared.stop.load(Ordering::SeqCst)
+    }
+
+    fn set_state(&self, state: DhcpState) {
+        let mut current = self.shared.state.lock().unwrap();
+        if *current != state {
+            eprintln!("DHCP DEV={} {:?} => {:?}", self.dev.name, *current, state);
+            *current = state;
+        }
+    }
+
+    fn lease(&self) -> Option<DhcpLease> {
+        self.shared.lease.lock().unwrap().clone()
+    }
+
+    /// DISCOVER を再送しながら最初に届いた OFFER を選ぶ
+    fn select(&mut self) -> Option<DhcpMessage> {
+        let mut interval = DHCP_RETRANSMIT_INITIAL;
+        while !self.stopping() {
+            let discover = self.message(DhcpMessageType::Discover, IP_ADDRESS_ANY);
+            self.send(&discover, IP_ADDRESS_ANY, IP_ADDRESS_BROADCAST);
+            let deadline = Instant::now() + interval;
+            while let Some(reply) = self.receive(deadline) {
+                if reply.message_type() == Some(DhcpMessageType::Offer)
+                    && !reply.yiaddr.is_unspecified()
+                {
+                    eprintln!(
+                        "DHCP offer DEV={} ADDR={} SERVER={}",
+                        self.dev.name,
+                        reply.yiaddr,
+                        reply.server_identifier().unwrap_or(IP_ADDRESS_ANY)
+                    );
+                    return Some(reply);
+                }
+            }
+            interval = (interval * 2).min(DHCP_RETRANSMIT_MAX);
+        }
+        None
+    }
+
+    /// OFFER に対して REQUEST を送り ACK を待つ。NAK か再送切れなら None
+    fn request(&mut self, offer: &DhcpMessage) -> Option<DhcpLease> {
+        let server = offer.server_identifier()?;
+        let mut interval = DHCP_RETRANSMIT_INITIAL;
+        for _ in 0..DHCP_REQUEST_RETRIES {
+            if self.stopping() {
+                return None;
+            }
+            let mut request = self.message(DhcpMessageType::Request, IP_ADDRESS_ANY);
+            request
+                .options
+                .push(DhcpOption::RequestedAddress(offer.yiaddr));
+            request.options.push(DhcpOption::ServerIdentifier(server));
+            let sent = Instant::now();
+            self.send(&request, IP_ADDRESS_ANY, IP_ADDRESS_BROADCAST);
+            let deadline = Instant::now() + interval;
+            while let Some(reply) = self.receive(deadline) {
+                if reply.server_identifier().is_some_and(|s| s != server) {
+                    continue;
+                }
+                match reply.message_type() {
+                    Some(DhcpMessageType::Ack) if reply.yiaddr != offer.yiaddr => {
+                        eprintln!(
+                            "DHCP ack for another address DEV={} ADDR={} REQUESTED={}",
+                            self.dev.name, reply.yiaddr, offer.yiaddr
+                        );
+                    }
+                    Some(DhcpMessageType::Ack) => return DhcpLease::from_ack(&reply, server, sent),
+                    Some(DhcpMessageType::Nak) => {
+                        eprintln!("DHCP nak DEV={} SERVER={}", self.dev.name, server);
+                        return None;
+                    }
+                    _ => {}
+                }
+            }
+            interval = (interval * 2).min(DHCP_RETRANSMIT_MAX);
+        }
+        None
+    }
+
+    /// RENEWING ならリースを得たサーバへユニキャスト、REBINDING ならブロードキャストで延長を頼む
+    fn extend(&mut self, rebinding: bool) -> DhcpState {
+        let lease = match self.lease() {
+            Some(lease) => lease,
+            None => return DhcpState::Init,
+        };
+        let (deadline, dst) = if rebinding {
+            (lease.expiry(), IP_ADDRESS_BROADCAST)
+        } else {
+            (lease.rebinding_deadline(), lease.server)
+        };
+        self.xid = generate_xid(&self.dev.hwaddr);
+        self.started = Instant::now();
+
+        while !self.stopping() {
+            let now = Instant::now();
+            if now >= deadline {
+                if rebinding {
+                    eprintln!(
+                        "DHCP lease expired DEV={} ADDR={}",
+                        self.dev.name, lease.address
+                    );
+                    self.unconfigure();
+                    return DhcpState::Init;
+                }
+                return DhcpState::Rebinding;
+            }
+            // 残り時間の半分待つ。ただし 60 秒より短くはしない
+            let wait = ((deadline - now) / 2).max(DHCP_RENEW_RETRANSMIT_MIN);
+            let retransmit = (now + wait).min(deadline);
+
+            // ciaddr を埋め、requested address と server identifier は付けない (RFC 2131 4.3.2)
+            let request = self.message(DhcpMessageType::Request, lease.address);
+            let sent = Instant::now();
+            self.send(&request, lease.address, dst);
+            while let Some(reply) = self.receive(retransmit) {
+                match reply.message_type() {
+                    Some(DhcpMessageType::Ack) if reply.yiaddr == lease.address => {
+                        if let Some(renewed) = DhcpLease::from_ack(&reply, lease.server, sent) {
+                            self.configure(renewed);
+                            return DhcpState::Bound;
+                        }
+                    }
+                    Some(DhcpMessageType::Nak) => {
+                        eprintln!("DHCP nak DEV={} ADDR={}", self.dev.name, lease.address);
+                        self.unconfigure();
+                        return DhcpState::Init;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        DhcpState::Stopped
+    }
+
+    fn message(&self, message_type: DhcpMessageType, ciaddr: Ipv4Address) -> DhcpMessage {
+        let hwaddr = &self.dev.hwaddr[..self.dev.address_length as usize];
+        let mut message = DhcpMessage::request(message_type, self.xid, hwaddr);
+        message.secs = self.started.elapsed().as_secs().min(u16::MAX as u64) as u16;
+        message.ciaddr = ciaddr;
+        // アドレスを持っていない間は応答をブロードキャストで返してもらう
+        if ciaddr.is_unspecified() {
+            message.flags |= DHCP_FLAG_BROADCAST;
+        }
+        let mut client_id = vec![DHCP_HTYPE_ETHERNET];
+        client_id.extend_from_slice(hwaddr);
+        message
+            .options
+            .push(DhcpOption::ClientIdentifier(client_id));
+        if message_type != DhcpMessageType::Release {
+            message.options.push(DhcpOption::ParameterRequestList(vec![
+                DHCP_OPTION_SUBNET_MASK,
+                DHCP_OPTION_ROUTER,
+                DHCP_OPTION_DNS_SERVER,
+                DHCP_OPTION_LEASE_TIME,
+                DHCP_OPTION_RENEWAL_TIME,
+                DHCP_OPTION_REBINDING_TIME,
+            ]));
+            if let Some(hostname) = &self.hostname {
+                message.options.push(DhcpOption::HostName(hostname.clone()));
+            }
+        }
+        message
+    }
+
+    fn send(&self, message: &DhcpMessage, src: Ipv4Address, dst: Ipv4Address) {
+        eprintln!(
+            "DHCP send DEV={} {} XID={:08x} DST={}",
+            self.dev.name,
+            message
+                .message_type()
+                .map_or(String::from("?"), |t| t.to_string()),
+            message.xid,
+            dst
+        );
+        let result = udp::output(
+            Ipv4Endpoint::new(src, DHCP_CLIENT_PORT),
+            Ipv4Endpoint::new(dst, DHCP_SERVER_PORT),
+            &message.encode(),
+            Some(self.dev),
+        );
+        if let Err(e) = result {
+            eprintln!("DHCP send error: {}", e.kind);
+        }
+    }
+
+    /// deadline までに届いた自分宛ての応答を 1 つ返す
+    fn receive(&self, deadline: Instant) -> Option<DhcpMessage> {
+        let hwaddr = &self.dev.hwaddr[..self.dev.address_length as usize];
+        loop {
+            let now = Instant::now();
+            if self.stopping() || now >= deadline {
+                return None;
+            }
+            let timeout = (deadline - now).min(DHCP_POLL_INTERVAL);
+            let datagram = match udp::recvfrom(self.pcb, Some(timeout)) {
+                Ok(datagram) => datagram,
+                Err(_) => continue,
+            };
+            let message = match DhcpMessage::parse(&datagram.data) {
+                Ok(message) => message,
+                Err(e) => {
+                    eprintln!("DHCP parse error: {}", e.kind);
+                    continue;
+                }
+            };
+            if message.op == DHCP_OP_BOOTREPLY
+                && message.xid == self.xid
+                && message.hwaddr() == hwaddr
+            {
+                return Some(message);
+            }
+        }
+    }
+
+    /// 停止要求があれば false を返す
+    fn sleep_until(&self, deadline: Instant) -> bool {
+        loop {
+            let now = Instant::now();
+            if self.stopping() {
+                return false;
+            }
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep((deadline - now).min(DHCP_POLL_INTERVAL));
+        }
+    }
+
+    /// リースの内容でインターフェースとデフォルトゲートウェイ、DNS サーバを設定する
+    fn configure(&self, lease: DhcpLease) {
+        let previous = self.lease();
+        if previous.as_ref().is_some_and(|p| {
+            p.address != lease.address
+                || p.netmask != lease.netmask
+                || p.gateway != lease.gateway
+                || p.dns_servers != lease.dns_servers
+        }) {
+            self.unconfigure();
+        }
+        let network = match lease.network() {
+            Some(network) => network,
+            None => {
+                eprintln!("DHCP invalid netmask {}", lease.netmask);
+                return;
+            }
+        };
+        if IpInterface::register(IpInterface::from_network(network), self.dev).is_err() {
+            eprintln!("DHCP interface register error ADDR={}", lease.address);
+            return;
+        }
+        if let Some(gateway) = lease.gateway {
+            if route::set_default_gateway(gateway, lease.address).is_ok() {
+                *self.shared.gateway.lock().unwrap() = Some(gateway);
+            } else {
+                eprintln!("DHCP default gateway error GATEWAY={}", gateway);
+            }
+        }
+        if !lease.dns_servers.is_empty() {
+            dns::set_servers(&lease.dns_servers);
+        }
+        eprintln!(
+            "DHCP bound DEV={} ADDR={} GATEWAY={} LEASE={}s",
+            self.dev.name,
+            network,
+            lease
+                .gateway
+                .map_or_else(|| String::from("(none)"), |g| g.to_string()),
+            lease.lease_time.as_secs()
+        );
+        *self.shared.lease.lock().unwrap() = Some(lease);
+    }
+
+    fn unconfigure(&self) {
+        let lease = match self.shared.lease.lock().unwrap().take() {
+            Some(lease) => lease,
+            None => return,
+        };
+        // 他で置き換えられたデフォルト経路や DNS サーバは残す
+        if let Some(gateway) = self.shared.gateway.lock().unwrap().take() {
+            if let Ok(default) = Ipv4Network::new(IP_ADDRESS_ANY, 0) {
+                route::delete_exact(default, Some(gateway), lease.address);
+            }
+        }
+        if !lease.dns_servers.is_empty() && dns::resolver::servers() == lease.dns_servers {
+            dns::set_servers(&[]);
+        }
+        let _ = IpInterface::unregister(lease.address, self.dev);
+        eprintln!(
+            "DHCP unconfigured DEV={} ADDR={}",
+            self.dev.name, lease.address
+        );
+    }
+
+    fn send_release(&mut self, lease: &DhcpLease) {
+        self.xid = generate_xid(&self.dev.hwaddr);
+        let mut release = self.message(DhcpMessageType::Release, lease.address);
+        release.secs = 0;
+        release
+            .options
+            .push(DhcpOption::ServerIdentifier(lease.server));
+        self.send(&release, lease.address, lease.server);
+    }
+}
+
+/// 現在時刻とハードウェアアドレスから transaction id を作る
+pub fn generate_xid(hwaddr: &[u8]) -> u32 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    let mut xid = (nanos ^ (nanos >> 32)) as u32;
+    for (i, b) in hwaddr.iter().enumerate() {
+        xid ^= (*b as u32) << ((i % 4) * 8);
+    }
+    xid
+}
@@ -0,0 +1,447 @@
+use std::fmt;
+
+use crate::ipv4::{Ipv4Address, IPV4_ADDRESS_SIZE, IP_ADDRESS_ANY};
+use crate::net::HARDWARE_ADDRESS_LENGTH;
+
+pub mod client;
+pub mod server;
+
+pub use client::{DhcpClient, DhcpClientHandle, DhcpLease};
+pub use server::{DhcpServer, DhcpServerHandle, DhcpServerLease};
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+pub const DHCP_OP_BOOTREQUEST: u8 = 1;
+pub const DHCP_OP_BOOTREPLY: u8 = 2;
+
+/// ARP の hardware type (Ethernet)
+pub const DHCP_HTYPE_ETHERNET: u8 = 1;
+
+/// flags の最上位ビット。応答をブロードキャストで返してもらう
+pub const DHCP_FLAG_BROADCAST: u16 = 0x8000;
+
+pub const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// op から file までの固定長部分
+pub const DHCP_FIXED_SIZE: usize = 236;
+/// RFC 2131 でクライアントが受け取れることになっている最小のメッセージ長
+pub const DHCP_MESSAGE_SIZE_MIN: usize = 300;
+
+const SNAME_SIZE: usize = 64;
+const FILE_SIZE: usize = 128;
+
+pub const DHCP_OPTION_PAD: u8 = 0;
+pub const DHCP_OPTION_SUBNET_MASK: u8 = 1;
+pub const DHCP_OPTION_ROUTER: u8 = 3;
+pub const DHCP_OPTION_DNS_SERVER: u8 = 6;
+pub const DHCP_OPTION_HOST_NAME: u8 = 12;
+pub const DHCP_OPTION_REQUESTED_ADDRESS: u8 = 50;
+pub const DHCP_OPTION_LEASE_TIME: u8 = 51;
+pub const DHCP_OPTION_MESSAGE_TYPE: u8 = 53;
+pub const DHCP_OPTION_SERVER_IDENTIFIER: u8 = 54;
+pub const DHCP_OPTION_PARAMETER_REQUEST_LIST: u8 = 55;
+pub const DHCP_OPTION_MESSAGE: u8 = 56;
+pub const DHCP_OPTION_RENEWAL_TIME: u8 = 58;
+pub const DHCP_OPTION_REBINDING_TIME: u8 = 59;
+pub const DHCP_OPTION_CLIENT_IDENTIFIER: u8 = 61;
+pub const DHCP_OPTION_END: u8 = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DhcpMessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+
+impl DhcpMessageType {
+    pub fn from_u8(u: u8) -> Option<DhcpMessageType> {
+        match u {
+            1 => Some(DhcpMessageType::Discover),
+            2 => Some(DhcpMessageType::Offer),
+            3 => Some(DhcpMessageType::Request),
+            4 => Some(DhcpMessageType::Decline),
+            5 => Some(DhcpMessageType::Ack),
+            6 => Some(DhcpMessageType::Nak),
+            7 => Some(DhcpMessageType::Release),
+            8 => Some(DhcpMessageType::Inform),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DhcpMessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DhcpMessageType::Discover => "DHCPDISCOVER",
+            DhcpMessageType::Offer => "DHCPOFFER",
+            DhcpMessageType::Request => "DHCPREQUEST",
+            DhcpMessageType::Decline => "DHCPDECLINE",
+            DhcpMessageType::Ack => "DHCPACK",
+            DhcpMessageType::Nak => "DHCPNAK",
+            DhcpMessageType::Release => "DHCPRELEASE",
+            DhcpMessageType::Inform => "DHCPINFORM",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// DHCP オプション (RFC 2132)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DhcpOption {
+    SubnetMask(Ipv4Address),
+    Router(Vec<Ipv4Address>),
+    DnsServer(Vec<Ipv4Address>),
+    HostName(String),
+    RequestedAddress(Ipv4Address),
+    LeaseTime(u32),
+    MessageType(DhcpMessageType),
+    ServerIdentifier(Ipv4Address),
+    ParameterRequestList(Vec<u8>),
+    Message(String),
+    RenewalTime(u32),
+    RebindingTime(u32),
+    ClientIdentifier(Vec<u8>),
+    Unknown { code: u8, data: Vec<u8> },
+}
+
+impl DhcpOption {
+    pub fn code(&self) -> u8 {
+        match self {
+            DhcpOption::SubnetMask(_) => DHCP_OPTION_SUBNET_MASK,
+            DhcpOption::Router(_) => DHCP_OPTION_ROUTER,
+            DhcpOption::DnsServer(_) => DHCP_OPTION_DNS_SERVER,
+            DhcpOption::HostName(_) => DHCP_OPTION_HOST_NAME,
+            DhcpOption::RequestedAddress(_) => DHCP_OPTION_REQUESTED_ADDRESS,
+            DhcpOption::LeaseTime(_) => DHCP_OPTION_LEASE_TIME,
+            DhcpOption::MessageType(_) => DHCP_OPTION_MESSAGE_TYPE,
+            DhcpOption::ServerIdentifier(_) => DHCP_OPTION_SERVER_IDENTIFIER,
+            DhcpOption::ParameterRequestList(_) => DHCP_OPTION_PARAMETER_REQUEST_LIST,
+            DhcpOption::Message(_) => DHCP_OPTION_MESSAGE,
+            DhcpOption::RenewalTime(_) => DHCP_OPTION_RENEWAL_TIME,
+            DhcpOption::RebindingTime(_) => DHCP_OPTION_REBINDING_TIME,
+            DhcpOption::ClientIdentifier(_) => DHCP_OPTION_CLIENT_IDENTIFIER,
+            DhcpOption::Unknown { code, .. } => *code,
+        }
+    }
+
+    fn data(&self) -> Vec<u8> {
+        fn addresses(list: &[Ipv4Address]) -> Vec<u8> {
+            list.iter().flat_map(|a| a.octets()).collect()
+        }
+        match self {
+            DhcpOption::SubnetMask(a)
+            | DhcpOption::RequestedAddress(a)
+            | DhcpOption::ServerIdentifier(a) => a.octets().to_vec(),
+            DhcpOption::Router(list) | DhcpOption::DnsServer(list) => addresses(list),
+            DhcpOption::HostName(s) | DhcpOption::Message(s) => s.as_bytes().to_vec(),
+            DhcpOption::LeaseTime(t)
+            | DhcpOption::RenewalTime(t)
+            | DhcpOption::RebindingTime(t) => t.to_be_bytes().to_vec(),
+            DhcpOption::MessageType(t) => vec![*t as u8],
+            DhcpOption::ParameterRequestList(d)
+            | DhcpOption::ClientIdentifier(d)
+            | DhcpOption::Unknown { data: d, .. } => d.clone(),
+        }
+    }
+
+    fn decode(code: u8, data: &[u8]) -> Result<DhcpOption, DhcpErrorKind> {
+        fn address(data: &[u8]) -> Result<Ipv4Address, DhcpErrorKind> {
+            if data.len() != IPV4_ADDRESS_SIZE {
+                return Err(DhcpErrorKind::InvalidOption);
+            }
+            Ok(Ipv4Address::new(data[0], data[1], data[2], data[3]))
+        }
+        fn addresses(data: &[u8]) -> Result<Vec<Ipv4Address>, DhcpErrorKind> {
+            if data.is_empty() || !data.len().is_multiple_of(IPV4_ADDRESS_SIZE) {
+                return Err(DhcpErrorKind::InvalidOption);
+            }
+            data.chunks(IPV4_ADDRESS_SIZE).map(address).collect()
+        }
+        fn seconds(data: &[u8]) -> Result<u32, DhcpErrorKind> {
+            if data.len() != 4 {
+                return Err(DhcpErrorKind::InvalidOption);
+            }
+            Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+        }
+        let option = match code {
+            DHCP_OPTION_SUBNET_MASK => DhcpOption::SubnetMask(address(data)?),
+            DHCP_OPTION_ROUTER => DhcpOption::Router(addresses(data)?),
+            DHCP_OPTION_DNS_SERVER => DhcpOption::DnsServer(addresses(data)?),
+            DHCP_OPTION_HOST_NAME => {
+                DhcpOption::HostName(String::from_utf8_lossy(data).into_owned())
+            }
+            DHCP_OPTION_REQUESTED_ADDRESS => DhcpOption::RequestedAddress(address(data)?),
+            DHCP_OPTION_LEASE_TIME => DhcpOption::LeaseTime(seconds(data)?),
+            DHCP_OPTION_MESSAGE_TYPE => {
+                if data.len() != 1 {
+                    return Err(DhcpErrorKind::InvalidOption);
+                }
+                match DhcpMessageType::from_u8(data[0]) {
+                    Some(t) => DhcpOption::MessageType(t),
+                    None => return Err(DhcpErrorKind::InvalidOption),
+                }
+            }
+            DHCP_OPTION_SERVER_IDENTIFIER => DhcpOption::ServerIdentifier(address(data)?),
+            DHCP_OPTION_PARAMETER_REQUEST_LIST => DhcpOption::ParameterRequestList(data.to_vec()),
+            DHCP_OPTION_MESSAGE => DhcpOption::Message(String::from_utf8_lossy(data).into_owned()),
+            DHCP_OPTION_RENEWAL_TIME => DhcpOption::RenewalTime(seconds(data)?),
+            DHCP_OPTION_REBINDING_TIME => DhcpOption::RebindingTime(seconds(data)?),
+            DHCP_OPTION_CLIENT_IDENTIFIER => DhcpOption::ClientIdentifier(data.to_vec()),
+            _ => DhcpOption::Unknown {
+                code,
+                data: data.to_vec(),
+            },
+        };
+        Ok(option)
+    }
+}
+
+#[derive(Debug)]
+pub struct DhcpError {
+    pub kind: DhcpErrorKind,
+}
+
+impl DhcpError {
+    pub fn new(kind: DhcpErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DhcpErrorKind {
+    Truncated,
+    InvalidCookie,
+    InvalidOption,
+    MissingMessageType,
+    SocketError,
+    InvalidConfig,
+}
+
+impl fmt::Display for DhcpErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DhcpErrorKind::Truncated => "truncated",
+            DhcpErrorKind::InvalidCookie => "invalid magic cookie",
+            DhcpErrorKind::InvalidOption => "invalid option",
+            DhcpErrorKind::MissingMessageType => "missing message type",
+            DhcpErrorKind::SocketError => "socket error",
+            DhcpErrorKind::InvalidConfig => "invalid config",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// DHCP メッセージ (RFC 2131)
+/// sname と file はオプションの格納に使われていない前提で読み捨てる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpMessage {
+    pub op: u8,
+    pub htype: u8,
+    pub hlen: u8,
+    pub hops: u8,
+    pub xid: u32,
+    pub secs: u16,
+    pub flags: u16,
+    pub ciaddr: Ipv4Address,
+    pub yiaddr: Ipv4Address,
+    pub siaddr: Ipv4Address,
+    pub giaddr: Ipv4Address,
+    pub chaddr: [u8; HARDWARE_ADDRESS_LENGTH],
+    pub options: Vec<DhcpOption>,
+}
+
+impl DhcpMessage {
+    /// クライアントが送る BOOTREQUEST の雛形
+    pub fn request(message_type: DhcpMessageType, xid: u32, hwaddr: &[u8]) -> Self {
+        let mut chaddr = [0; HARDWARE_ADDRESS_LENGTH];
+        let hlen = hwaddr.len().min(HARDWARE_ADDRESS_LENGTH);
+        chaddr[..hlen].copy_from_slice(&hwaddr[..hlen]);
+        DhcpMessage {
+            op: DHCP_OP_BOOTREQUEST,
+            htype: DHCP_HTYPE_ETHERNET,
+            hlen: hlen as u8,
+            hops: 0,
+            xid,
+            secs: 0,
+            flags: 0,
+            ciaddr: IP_ADDRESS_ANY,
+            yiaddr: IP_ADDRESS_ANY,
+            siaddr: IP_ADDRESS_ANY,
+            giaddr: IP_ADDRESS_ANY,
+            chaddr,
+            options: vec![DhcpOption::MessageType(message_type)],
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, DhcpError> {
+        if data.len() < DHCP_FIXED_SIZE + DHCP_MAGIC_COOKIE.len() {
+            return Err(DhcpError::new(DhcpErrorKind::Truncated));
+        }
+        if data[DHCP_FIXED_SIZE..DHCP_FIXED_SIZE + 4] != DHCP_MAGIC_COOKIE {
+            return Err(DhcpError::new(DhcpErrorKind::InvalidCookie));
+        }
+        let address = |i: usize| Ipv4Address::new(data[i], data[i + 1], data[i + 2], data[i + 3]);
+        let mut chaddr = [0; HARDWARE_ADDRESS_LENGTH];
+        chaddr.copy_from_slice(&data[28..28 + HARDWARE_ADDRESS_LENGTH]);
+
+        let mut options = Vec::new();
+        let mut i = DHCP_FIXED_SIZE + DHCP_MAGIC_COOKIE.len();
+        while i < data.len() {
+            match data[i] {
+                DHCP_OPTION_PAD => i += 1,
+                DHCP_OPTION_END => break,
+                code => {
+                    if i + 1 >= data.len() {
+                        return Err(DhcpError::new(DhcpErrorKind::Truncated));
+                    }
+                    let len = data[i + 1] as usize;
+                    let body = data
+                        .get(i + 2..i + 2 + len)
+                        .ok_or_else(|| DhcpError::new(DhcpErrorKind::Truncated))?;
+                    options.push(DhcpOption::decode(code, body).map_err(DhcpError::new)?);
+                    i += 2 + len;
+                }
+            }
+        }
+
+        if !options
+            .iter()
+            .any(|option| matches!(option, DhcpOption::MessageType(_)))
+        {
+            return Err(DhcpError::new(DhcpErrorKind::MissingMessageType));
+        }
+
+        Ok(DhcpMessage {
+            op: data[0],
+            htype: data[1],
+            hlen: data[2],
+            hops: data[3],
+            xid: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            secs: u16::from_be_bytes([data[8], data[9]]),
+            flags: u16::from_be_bytes([data[10], data[11]]),
+            ciaddr: address(12),
+            yiaddr: address(16),
+            siaddr: address(20),
+            giaddr: address(24),
+            chaddr,
+            options,
+        })
+    }
+
+    /// 最小長に満たない分は PAD で埋める
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(DHCP_MESSAGE_SIZE_MIN);
+        data.push(self.op);
+        data.push(self.htype);
+        data.push(self.hlen);
+        data.push(self.hops);
+        data.extend_from_slice(&self.xid.to_be_bytes());
+        data.extend_from_slice(&self.secs.to_be_bytes());
+        data.extend_from_slice(&self.flags.to_be_bytes());
+        data.extend_from_slice(&self.ciaddr.octets());
+        data.extend_from_slice(&self.yiaddr.octets());
+        data.extend_from_slice(&self.siaddr.octets());
+        data.extend_from_slice(&self.giaddr.octets());
+        data.extend_from_slice(&self.chaddr);
+        data.resize(data.len() + SNAME_SIZE + FILE_SIZE, 0);
+        data.extend_from_slice(&DHCP_MAGIC_COOKIE);
+        for option in self.options.iter() {
+            // 255 バイトを超える値は切り詰める (RFC 3396 の分割は行わない)
+            let mut body = option.data();
+            body.truncate(u8::MAX as usize);
+            data.push(option.code());
+            data.push(body.len() as u8);
+            data.extend_from_slice(&body);
+        }
+        data.push(DHCP_OPTION_END);
+        if data.len() < DHCP_MESSAGE_SIZE_MIN {
+            data.resize(DHCP_MESSAGE_SIZE_MIN, DHCP_OPTION_PAD);
+        }
+        data
+    }
+
+    pub fn hwaddr(&self) -> &[u8] {
+        &self.chaddr[..(self.hlen as usize).min(HARDWARE_ADDRESS_LENGTH)]
+    }
+
+    pub fn option(&self, code: u8) -> Option<&DhcpOption> {
+        self.options.iter().find(|option| option.code() == code)
+    }
+
+    pub fn message_type(&self) -> Option<DhcpMessageType> {
+        match self.option(DHCP_OPTION_MESSAGE_TYPE) {
+            Some(DhcpOption::MessageType(t)) => Some(*t),
+            _ => None,
+        }
+    }
+
+    pub fn subnet_mask(&self) -> Option<Ipv4Address> {
+        match self.option(DHCP_OPTION_SUBNET_MASK) {
+            Some(DhcpOption::SubnetMask(a)) => Some(*a),
+            _ => None,
+        }
+    }
+
+    pub fn routers(&self) -> Vec<Ipv4Address> {
+        match self.option(DHCP_OPTION_ROUTER) {
+            Some(DhcpOption::Router(list)) => list.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn dns_servers(&self) -> Vec<Ipv4Address> {
+        match self.option(DHCP_OPTION_DNS_SERVER) {
+            Some(DhcpOption::DnsServer(list)) => list.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn server_identifier(&self) -> Option<Ipv4Address> {
+        match self.option(DHCP_OPTION_SERVER_IDENTIFIER) {
+            Some(DhcpOption::ServerIdentifier(a)) => Some(*a),
+            _ => None,
+        }
+    }
+
+    pub fn requested_address(&self) -> Option<Ipv4Address> {
+        match self.option(DHCP_OPTION_REQUESTED_ADDRESS) {
+            Some(DhcpOption::RequestedAddress(a)) => Some(*a),
+            _ => None,
+        }
+    }
+
+    pub fn lease_time(&self) -> Option<u32> {
+        match self.option(DHCP_OPTION_LEASE_TIME) {
+            Some(DhcpOption::LeaseTime(t)) => Some(*t),
+            _ => None,
+        }
+    }
+
+    pub fn renewal_time(&self) -> Option<u32> {
+        match self.option(DHCP_OPTION_RENEWAL_TIME) {
+            Some(DhcpOption::RenewalTime(t)) => Some(*t),
+            _ => None,
+        }
+    }
+
+    pub fn rebinding_time(&self) -> Option<u32> {
+        match self.option(DHCP_OPTION_REBINDING_TIME) {
+            Some(DhcpOption::RebindingTime(t)) => Some(*t),
+            _ => None,
+        }
+    }
+
+    pub fn client_identifier(&self) -> Option<&[u8]> {
+        match self.option(DHCP_OPTION_CLIENT_IDENTIFIER) {
+            Some(DhcpOption::ClientIdentifier(id)) => Some(id),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,554 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{
+    DhcpError, DhcpErrorKind, DhcpMessage, DhcpMessageType, DhcpOption, DHCP_CLIENT_PORT,
+    DHCP_FLAG_BROADCAST, DHCP_OP_BOOTREPLY, DHCP_OP_BOOTREQUEST, DHCP_SERVER_PORT,
+};
+use crate::ipv4::{Ipv4Address, Ipv4Endpoint, Ipv4Network, IP_ADDRESS_ANY, IP_ADDRESS_BROADCAST};
+use crate::net::NetDevice;
+use crate::udp;
+
+const DHCP_LEASE_TIME_DEFAULT: Duration = Duration::from_secs(3600);
+/// OFFER したアドレスを REQUEST が来るまで確保しておく時間
+const DHCP_OFFER_HOLD: Duration = Duration::from_secs(60);
+/// DECLINE されたアドレスを使わずにおく時間
+const DHCP_DECLINE_HOLD: Duration = Duration::from_secs(600);
+/// 停止要求を確認する間隔
+const DHCP_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpLeaseState {
+    Offered,
+    Bound,
+    /// DECLINE で他の誰かが使っていると分かったアドレス
+    Declined,
+}
+
+/// サーバが払い出したアドレスの記録
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpServerLease {
+    pub hwaddr: Vec<u8>,
+    pub address: Ipv4Address,
+    pub expires: SystemTime,
+    pub state: DhcpLeaseState,
+}
+
+/// DHCP サーバ。設定を組み立ててから start でデバイスに結び付けて動かす
+pub struct DhcpServer {
+    network: Ipv4Network,
+    pool_start: Ipv4Address,
+    pool_end: Ipv4Address,
+    lease_time: Duration,
+    router: Option<Ipv4Address>,
+    dns_servers: Vec<Ipv4Address>,
+    reservations: HashMap<Vec<u8>, Ipv4Address>,
+    lease_file: Option<PathBuf>,
+    leases: Vec<DhcpServerLease>,
+}
+
+impl DhcpServer {
+    /// network のアドレスをサーバ自身のアドレス (server identifier) にする
+    /// プールの既定値はネットワーク内のサーバ以外の全ホスト
+    pub fn new(network: Ipv4Network) -> Self {
+        let mut hosts = network.hosts();
+        let first = hosts.next().unwrap_or(network.address());
+        let last = hosts.last().unwrap_or(first);
+        DhcpServer {
+            network,
+            pool_start: first,
+            pool_end: last,
+            lease_time: DHCP_LEASE_TIME_DEFAULT,
+            router: None,
+            dns_servers: Vec::new(),
+            reservations: HashMap::new(),
+            lease_file: None,
+            leases: Vec::new(),
+        }
+    }
+
+    /// 払い出すアドレスの範囲 (両端を含む)
+    pub fn pool(mut self, start: Ipv4Address, end: Ipv4Address) -> Self {
+        self.pool_start = start;
+        self.pool_end = end;
+        self
+    }
+
+    pub fn lease_time(mut self, lease_time: Duration) -> Self {
+        self.lease_time = lease_time;
+        self
+    }
+
+    pub fn router(mut self, router: Ipv4Address) -> Self {
+        self.router = Some(router);
+        self
+    }
+
+    pub fn dns_server(mut self, server: Ipv4Address) -> Self {
+        self.dns_servers.push(server);
+        self
+    }
+
+    /// MAC アドレスに決まったアドレスを割り当てる。プールの外でもよい
+    pub fn reserve(mut self, hwaddr: &[u8], address: Ipv4Address) -> Self {
+        self.reservations.insert(hwaddr.to_vec(), address);
+        self
+    }
+
+    /// リースを書き出すファイル。start 時に読み込み、変更のたびに書き直す
+    pub fn lease_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.lease_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn server_address(&self) -> Ipv4Address {
+        self.network.address()
+    }
+
+    pub fn leases(&self) -> &[DhcpServerLease] {
+        &self.leases
+    }
+
+    /// 0.0.0.0:67 を dev に固定して開き、要求を処理するスレッドを起動する
+    pub fn start(mut self, dev: &'static NetDevice) -> Result<DhcpServerHandle, DhcpError> {
+        if !self.network.contains(self.pool_start)
+            || !self.network.contains(self.pool_end)
+            || self.pool_start > self.pool_end
+        {
+            eprintln!(
+                "DHCP server pool {}-{} is not in {}",
+                self.pool_start, self.pool_end, self.network
+            );
+            return Err(DhcpError::new(DhcpErrorKind::InvalidConfig));
+        }
+        if let Some(path) = self.lease_file.clone() {
+            match load_leases(&path) {
+                Ok(leases) => self.leases = leases,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => eprintln!("DHCP lease file read error {}: {}", path.display(), e),
+            }
+            self.expire(SystemTime::now());
+        }
+
+        let id = udp::open().map_err(|_| DhcpError::new(DhcpErrorKind::SocketError))?;
+        let bound = udp::bind_device(id, Some(dev))
+            .and_then(|_| udp::bind(id, Ipv4Endpoint::new(IP_ADDRESS_ANY, DHCP_SERVER_PORT)));
+        if bound.is_err() {
+            let _ = udp::close(id);
+            return Err(DhcpError::new(DhcpErrorKind::SocketError));
+        }
+        eprintln!(
+            "DHCP server started DEV={} SERVER={} POOL={}-{}",
+            dev.name, self.network, self.pool_start, self.pool_end
+        );
+
+        let server = Arc::new(Mutex::new(self));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let server = server.clone();
+            let stop = stop.clone();
+            thread::spawn(move || serve(server, stop, id, dev))
+        };
+        Ok(DhcpServerHandle {
+            server,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// 要求を 1 つ処理して応答と送り先を返す。応答しないときは None
+    pub fn process(&mut self, request: &DhcpMessage) -> Option<(DhcpMessage, Ipv4Endpoint)> {
+        if request.op != DHCP_OP_BOOTREQUEST {
+            return None;
+        }
+        let now = SystemTime::now();
+        self.expire(now);
+        let hwaddr = request.hwaddr().to_vec();
+        let message_type = request.message_type()?;
+        eprintln!(
+            "DHCP server received {} XID={:08x} HWADDR={}",
+            message_type,
+            request.xid,
+            format_hwaddr(&hwaddr)
+        );
+
+        let reply = match message_type {
+            DhcpMessageType::Discover => {
+                let address = self.select_address(&hwaddr, request.requested_address())?;
+                self.record(
+                    &hwaddr,
+                    address,
+                    DhcpLeaseState::Offered,
+                    now + DHCP_OFFER_HOLD,
+                );
+                self.reply(request, DhcpMessageType::Offer, address)
+            }
+            DhcpMessageType::Request => {
+                match request.server_identifier() {
+                    // 他のサーバの OFFER が選ばれた
+                    Some(server) if server != self.server_address() => {
+                        self.leases.retain(|l| {
+                            !(l.hwaddr == hwaddr && l.state == DhcpLeaseState::Offered)
+                        });
+                        return None;
+                    }
+                    _ => {}
+                }
+                // SELECTING/INIT-REBOOT なら requested address、RENEWING/REBINDING なら ciaddr
+                let address = match request.requested_address() {
+                    Some(address) => address,
+                    None if !request.ciaddr.is_unspecified() => request.ciaddr,
+                    None => return None,
+                };
+                // INIT-REBOOT は記録のあるクライアントにだけ応える (RFC 2131 4.3.2)
+                if request.server_identifier().is_none()
+                    && request.ciaddr.is_unspecified()
+                    && !self.has_lease(&hwaddr, address)
+                {
+                    eprintln!("DHCP server no lease for init-reboot ADDR={}", address);
+                    return None;
+                }
+                if self.available_for(&hwaddr, address) {
+                    self.record(
+                        &hwaddr,
+                        address,
+                        DhcpLeaseState::Bound,
+                        now + self.lease_time,
+                    );
+                    self.save();
+                    self.reply(request, DhcpMessageType::Ack, address)
+                } else {
+                    self.reply(request, DhcpMessageType::Nak, IP_ADDRESS_ANY)
+                }
+            }
+            DhcpMessageType::Decline => {
+                let address = request.requested_address()?;
+                // 自分が貸して、そのクライアントが持っているアドレスだけ受け付ける
+                if request.server_identifier() != Some(self.server_address())
+                    || !self.has_lease(&hwaddr, address)
+                {
+                    eprintln!("DHCP server decline ignored ADDR={}", address);
+                    return None;
+                }
+                eprintln!("DHCP server address declined ADDR={}", address);
+                self.leases.retain(|l| l.address != address);
+                self.leases.push(DhcpServerLease {
+                    hwaddr: Vec::new(),
+                    address,
+                    expires: now + DHCP_DECLINE_HOLD,
+                    state: DhcpLeaseState::Declined,
+                });
+                self.save();
+                return None;
+            }
+            DhcpMessageType::Release => {
+                // 他のサーバ宛ての RELEASE で自分のリースを消さない
+                if request.server_identifier() != Some(self.server_address()) {
+                    eprintln!("DHCP server release ignored ADDR={}", request.ciaddr);
+                    return None;
+                }
+                self.leases
+                    .retain(|l| !(l.hwaddr == hwaddr && l.address == request.ciaddr));
+                self.save();
+                return None;
+            }
+            // アドレスは既に持っているので設定だけ返す
+            DhcpMessageType::Inform => self.reply(request, DhcpMessageType::Ack, IP_ADDRESS_ANY),
+            _ => return None,
+        };
+        let dst = destination(request, &reply);
+        Some((reply, dst))
+    }
+
+    fn in_pool(&self, address: Ipv4Address) -> bool {
+        address >= self.pool_start && address <= self.pool_end && address != self.server_address()
+    }
+
+    /// hwaddr に address を貸している
+    fn has_lease(&self, hwaddr: &[u8], address: Ipv4Address) -> bool {
+        self.leases
+            .iter()
+            .any(|l| l.hwaddr == hwaddr && l.address == address && l.state == DhcpLeaseState::Bound)
+    }
+
+    /// address が DECLINE されたか、hwaddr 以外のクライアントに貸している
+    fn in_use_by_other(&self, hwaddr: &[u8], address: Ipv4Address) -> bool {
+        self.leases
+            .iter()
+            .any(|l| l.address == address && l.hwaddr != hwaddr)
+    }
+
+    /// hwaddr が address を使ってよいか
+    fn available_for(&self, hwaddr: &[u8], address: Ipv4Address) -> bool {
+        if let Some(reserved) = self.reservations.get(hwaddr) {
+            return *reserved == address && !self.in_use_by_other(hwaddr, address);
+        }
+        if !self.in_pool(address) || self.reservations.values().any(|r| *r == address) {
+            return false;
+        }
+        !self.in_use_by_other(hwaddr, address)
+    }
+
+    /// 予約、既存のリース、クライアントの希望、プールの空きの順に選ぶ
+    /// 予約したアドレスが使えないときはプールからも選ばない
+    fn select_address(&self, hwaddr: &[u8], requested: Option<Ipv4Address>) -> Option<Ipv4Address> {
+        if let Some(reserved) = self.reservations.get(hwaddr) {
+            if self.in_use_by_other(hwaddr, *reserved) {
+                eprintln!("DHCP server reserved address is in use ADDR={}", reserved);
+                return None;
+            }
+            return Some(*reserved);
+        }
+        if let Some(lease) = self
+            .leases
+            .iter()
+            .find(|l| l.hwaddr == hwaddr && l.state != DhcpLeaseState::Declined)
+        {
+            return Some(lease.address);
+        }
+        if let Some(requested) = requested {
+            if self.available_for(hwaddr, requested) {
+                return Some(requested);
+            }
+        }
+        let found = (self.pool_start.to_u32()..=self.pool_end.to_u32())
+            .map(Ipv4Address::from_u32)
+            .find(|address| self.available_for(hwaddr, *address));
+        if found.is_none() {
+            eprintln!("DHCP server pool exhausted");
+        }
+        found
+    }
+
+    fn record(
+        &mut self,
+        hwaddr: &[u8],
+        address: Ipv4Address,
+        state: DhcpLeaseState,
+        expires: SystemTime,
+    ) {
+        self.leases
+            .retain(|l| l.hwaddr != hwaddr && l.address != address);
+        self.leases.push(DhcpServerLease {
+            hwaddr: hwaddr.to_vec(),
+            address,
+            expires,
+            state,
+        });
+    }
+
+    fn expire(&mut self, now: SystemTime) {
+        self.leases.retain(|l| l.expires > now);
+    }
+
+    fn reply(
+        &self,
+        request: &DhcpMessage,
+        message_type: DhcpMessageType,
+        yiaddr: Ipv4Address,
+    ) -> DhcpMessage {
+        let mut reply = request.clone();
+        reply.op = DHCP_OP_BOOTREPLY;
+        reply.hops = 0;
+        reply.secs = 0;
+        reply.yiaddr = yiaddr;
+        reply.siaddr = IP_ADDRESS_ANY;
+        // ciaddr を返すのは ACK のときだけ
+        if message_type != DhcpMessageType::Ack {
+            reply.ciaddr = IP_ADDRESS_ANY;
+        }
+        reply.options = vec![
+            DhcpOption::MessageType(message_type),
+            DhcpOption::ServerIdentifier(self.server_address()),
+        ];
+        if message_type == DhcpMessageType::Nak {
+            return reply;
+        }
+        if !yiaddr.is_unspecified() {
+            let lease_time = self.lease_time.as_secs().min(u32::MAX as u64) as u32;
+            reply.options.push(DhcpOption::LeaseTime(lease_time));
+            reply.options.push(DhcpOption::RenewalTime(lease_time / 2));
+            reply.options.push(DhcpOption::RebindingTime(
+                (lease_time as u64 * 7 / 8) as u32,
+            ));
+        }
+        reply
+            .options
+            .push(DhcpOption::SubnetMask(self.network.netmask()));
+        if let Some(router) = self.router {
+            reply.options.push(DhcpOption::Router(vec![router]));
+        }
+        if !self.dns_servers.is_empty() {
+            reply
+                .options
+                .push(DhcpOption::DnsServer(self.dns_servers.clone()));
+        }
+        reply
+    }
+
+    fn save(&self) {
+        if let Some(path) = &self.lease_file {
+            if let Err(e) = save_leases(path, &self.leases) {
+                eprintln!("DHCP lease file write error {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// 応答の送り先 (RFC 2131 4.1)
+/// ARP でアドレスを解決する仕組みが無いので、yiaddr へのユニキャストはブロードキャストで代用する
+fn destination(request: &DhcpMessage, reply: &DhcpMessage) -> Ipv4Endpoint {
+    if !request.giaddr.is_unspecified() {
+        return Ipv4Endpoint::new(request.giaddr, DHCP_SERVER_PORT);
+    }
+    let nak = reply.message_type() == Some(DhcpMessageType::Nak);
+    if !nak && !request.ciaddr.is_unspecified() && request.flags & DHCP_FLAG_BROADCAST == 0 {
+        return Ipv4Endpoint::new(request.ciaddr, DHCP_CLIENT_PORT);
+    }
+    Ipv4Endpoint::new(IP_ADDRESS_BROADCAST, DHCP_CLIENT_PORT)
+}
+
+/// 起動したサーバを操作するハンドル。drop するとスレッドを止める
+pub struct DhcpServerHandle {
+    server: Arc<Mutex<DhcpServer>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DhcpServerHandle {
+    pub fn leases(&self) -> Vec<DhcpServerLease> {
+        self.server.lock().unwrap().leases().to_vec()
+    }
+
+    pub fn stop(mut self) {
+        self.join();
+    }
+
+    fn join(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for DhcpServerHandle {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+fn serve(
+    server: Arc<Mutex<DhcpServer>>,
+    stop: Arc<AtomicBool>,
+    id: usize,
+    dev: &'static NetDevice,
+) {
+    while !stop.load(Ordering::SeqCst) {
+        let datagram = match udp::recvfrom(id, Some(DHCP_POLL_INTERVAL)) {
+            Ok(datagram) => datagram,
+            Err(_) => continue,
+        };
+        let request = match DhcpMessage::parse(&datagram.data) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("DHCP server parse error: {}", e.kind);
+                continue;
+            }
+        };
+        let (reply, dst, src) = {
+            let mut server = server.lock().unwrap();
+            match server.process(&request) {
+                Some((reply, dst)) => (reply, dst, server.server_address()),
+                None => continue,
+            }
+        };
+        eprintln!(
+            "DHCP server send {} XID={:08x} YIADDR={} DST={}",
+            reply
+                .message_type()
+                .map_or(String::from("?"), |t| t.to_string()),
+            reply.xid,
+            reply.yiaddr,
+            dst
+        );
+        let result = udp::output(
+            Ipv4Endpoint::new(src, DHCP_SERVER_PORT),
+            dst,
+            &reply.encode(),
+            Some(dev),
+        );
+        if let Err(e) = result {
+            eprintln!("DHCP server send error: {}", e.kind);
+        }
+    }
+    let _ = udp::close(id);
+}
+
+fn format_hwaddr(hwaddr: &[u8]) -> String {
+    hwaddr
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn parse_hwaddr(s: &str) -> Option<Vec<u8>> {
+    s.split(':')
+        .map(|b| u8::from_str_radix(b, 16).ok())
+        .collect()
+}
+
+/// 確定したリースだけを「MAC アドレス IP アドレス 期限の UNIX 時刻」の行で書き出す
+pub fn save_leases(path: &Path, leases: &[DhcpServerLease]) -> io::Result<()> {
+    // 書きかけのファイルが残らないよう、別名で書いてから置き換える
+    let tmp = path.with_extension("tmp");
+    {
+        let mut file = fs::File::create(&tmp)?;
+        for lease in leases.iter().filter(|l| l.state == DhcpLeaseState::Bound) {
+            let expires = lease
+                .expires
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            writeln!(
+                file,
+                "{} {} {}",
+                format_hwaddr(&lease.hwaddr),
+                lease.address,
+                expires
+            )?;
+        }
+        file.sync_all()?;
+    }
+    fs::rename(tmp, path)
+}
+
+/// 読めない行は読み飛ばす
+pub fn load_leases(path: &Path) -> io::Result<Vec<DhcpServerLease>> {
+    let mut leases = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let lease = match fields.as_slice() {
+            [hwaddr, address, expires] => parse_hwaddr(hwaddr).and_then(|hwaddr| {
+                Some(DhcpServerLease {
+                    hwaddr,
+                    address: address.parse().ok()?,
+                    expires: UNIX_EPOCH + Duration::from_secs(expires.parse().ok()?),
+                    state: DhcpLeaseState::Bound,
+                })
+            }),
+            _ => None,
+        };
+        match lease {
+            Some(lease) => leases.push(lease),
+            None => eprintln!("DHCP lease file invalid line: {}", line),
+        }
+    }
+    Ok(leases)
+}
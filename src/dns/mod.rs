@@ -0,0 +1,405 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::net::Ipv6Addr;
+
+use crate::ipv4::Ipv4Address;
+
+pub mod resolver;
+
+pub use resolver::{lookup, resolve, set_servers};
+
+pub const DNS_PORT: u16 = 53;
+pub const DNS_HEADER_SIZE: usize = 12;
+/// EDNS0 を使わないときの UDP メッセージの上限 (RFC 1035 2.3.4)
+pub const DNS_UDP_SIZE_MAX: usize = 512;
+
+const DNS_NAME_SIZE_MAX: usize = 255;
+const DNS_LABEL_SIZE_MAX: usize = 63;
+/// 圧縮ポインタを辿る回数の上限。ループしたメッセージ対策
+const DNS_POINTER_LIMIT: usize = 32;
+
+pub const DNS_CLASS_IN: u16 = 1;
+
+pub const DNS_FLAG_QR: u16 = 0x8000;
+pub const DNS_FLAG_AA: u16 = 0x0400;
+pub const DNS_FLAG_TC: u16 = 0x0200;
+pub const DNS_FLAG_RD: u16 = 0x0100;
+pub const DNS_FLAG_RA: u16 = 0x0080;
+const DNS_RCODE_MASK: u16 = 0x000f;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum DnsRecordType {
+    A = 1,
+    Ns = 2,
+    Cname = 5,
+    Soa = 6,
+    Ptr = 12,
+    Mx = 15,
+    Txt = 16,
+    Aaaa = 28,
+}
+
+impl DnsRecordType {
+    pub fn from_u16(u: u16) -> Option<DnsRecordType> {
+        match u {
+            1 => Some(DnsRecordType::A),
+            2 => Some(DnsRecordType::Ns),
+            5 => Some(DnsRecordType::Cname),
+            6 => Some(DnsRecordType::Soa),
+            12 => Some(DnsRecordType::Ptr),
+            15 => Some(DnsRecordType::Mx),
+            16 => Some(DnsRecordType::Txt),
+            28 => Some(DnsRecordType::Aaaa),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DnsRecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DnsRecordType::A => "A",
+            DnsRecordType::Ns => "NS",
+            DnsRecordType::Cname => "CNAME",
+            DnsRecordType::Soa => "SOA",
+            DnsRecordType::Ptr => "PTR",
+            DnsRecordType::Mx => "MX",
+            DnsRecordType::Txt => "TXT",
+            DnsRecordType::Aaaa => "AAAA",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsResponseCode {
+    NoError,
+    FormatError,
+    ServerFailure,
+    NameError,
+    NotImplemented,
+    Refused,
+    Other(u8),
+}
+
+impl DnsResponseCode {
+    pub fn from_u8(u: u8) -> DnsResponseCode {
+        match u {
+            0 => DnsResponseCode::NoError,
+            1 => DnsResponseCode::FormatError,
+            2 => DnsResponseCode::ServerFailure,
+            3 => DnsResponseCode::NameError,
+            4 => DnsResponseCode::NotImplemented,
+            5 => DnsResponseCode::Refused,
+            u => DnsResponseCode::Other(u),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DnsError {
+    pub kind: DnsErrorKind,
+}
+
+impl DnsError {
+    pub fn new(kind: DnsErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DnsErrorKind {
+    InvalidName,
+    Truncated,
+    InvalidMessage,
+    NoServer,
+    Timeout,
+    NameNotFound,
+    ServerFailure,
+    NoRecords,
+    SocketError,
+    TooManyRedirects,
+}
+
+impl fmt::Display for DnsErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DnsErrorKind::InvalidName => "invalid name",
+            DnsErrorKind::Truncated => "truncated",
+            DnsErrorKind::InvalidMessage => "invalid message",
+            DnsErrorKind::NoServer => "no server",
+            DnsErrorKind::Timeout => "timeout",
+            DnsErrorKind::NameNotFound => "name not found",
+            DnsErrorKind::ServerFailure => "server failure",
+            DnsErrorKind::NoRecords => "no records",
+            DnsErrorKind::SocketError => "socket error",
+            DnsErrorKind::TooManyRedirects => "too many redirects",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsRecordData {
+    A(Ipv4Address),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Other(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsRecord {
+    pub name: String,
+    pub rtype: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub data: DnsRecordData,
+}
+
+/// DNS メッセージ (RFC 1035 4.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsMessage {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+    pub authorities: Vec<DnsRecord>,
+    pub additionals: Vec<DnsRecord>,
+}
+
+impl DnsMessage {
+    /// 再帰を要求する問い合わせ
+    pub fn query(id: u16, name: &str, qtype: DnsRecordType) -> Self {
+        DnsMessage {
+            id,
+            flags: DNS_FLAG_RD,
+            questions: vec![DnsQuestion {
+                name: normalize_name(name),
+                qtype: qtype as u16,
+                qclass: DNS_CLASS_IN,
+            }],
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags & DNS_FLAG_QR != 0
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.flags & DNS_FLAG_TC != 0
+    }
+
+    pub fn response_code(&self) -> DnsResponseCode {
+        DnsResponseCode::from_u8((self.flags & DNS_RCODE_MASK) as u8)
+    }
+
+    /// 名前は圧縮せずに書き出す
+    pub fn encode(&self) -> Result<Vec<u8>, DnsError> {
+        let mut data = Vec::with_capacity(DNS_UDP_SIZE_MAX);
+        data.extend_from_slice(&self.id.to_be_bytes());
+        data.extend_from_slice(&self.flags.to_be_bytes());
+        for count in [
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additionals.len(),
+        ] {
+            data.extend_from_slice(&(count as u16).to_be_bytes());
+        }
+        for question in self.questions.iter() {
+            encode_name(&mut data, &question.name)?;
+            data.extend_from_slice(&question.qtype.to_be_bytes());
+            data.extend_from_slice(&question.qclass.to_be_bytes());
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(self.authorities.iter())
+            .chain(self.additionals.iter())
+        {
+            encode_name(&mut data, &record.name)?;
+            data.extend_from_slice(&record.rtype.to_be_bytes());
+            data.extend_from_slice(&record.class.to_be_bytes());
+            data.extend_from_slice(&record.ttl.to_be_bytes());
+            let rdata = match &record.data {
+                DnsRecordData::A(address) => address.octets().to_vec(),
+                DnsRecordData::Aaaa(address) => address.octets().to_vec(),
+                DnsRecordData::Cname(name) => {
+                    let mut rdata = Vec::new();
+                    encode_name(&mut rdata, name)?;
+                    rdata
+                }
+                DnsRecordData::Other(rdata) => rdata.clone(),
+            };
+            data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            data.extend_from_slice(&rdata);
+        }
+        Ok(data)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, DnsError> {
+        if data.len() < DNS_HEADER_SIZE {
+            return Err(DnsError::new(DnsErrorKind::Truncated));
+        }
+        let read_u16 = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        let counts = [read_u16(4), read_u16(6), read_u16(8), read_u16(10)];
+        let mut offset = DNS_HEADER_SIZE;
+
+        let mut questions = Vec::new();
+        for _ in 0..counts[0] {
+            let name = decode_name(data, &mut offset)?;
+            let fixed = data
+                .get(offset..offset + 4)
+                .ok_or_else(|| DnsError::new(DnsErrorKind::Truncated))?;
+            questions.push(DnsQuestion {
+                name,
+                qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+                qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+            });
+            offset += 4;
+        }
+        let mut sections = [Vec::new(), Vec::new(), Vec::new()];
+        for (section, count) in sections.iter_mut().zip(counts[1..].iter()) {
+            for _ in 0..*count {
+                section.push(decode_record(data, &mut offset)?);
+            }
+        }
+        let [answers, authorities, additionals] = sections;
+
+        Ok(DnsMessage {
+            id: read_u16(0),
+            flags: read_u16(2),
+            questions,
+            answers,
+            authorities,
+            additionals,
+        })
+    }
+}
+
+/// 比較できるように小文字にして末尾の '.' を落とす
+pub fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn encode_name(data: &mut Vec<u8>, name: &str) -> Result<(), DnsError> {
+    let name = name.trim_end_matches('.');
+    if name.len() + 2 > DNS_NAME_SIZE_MAX {
+        return Err(DnsError::new(DnsErrorKind::InvalidName));
+    }
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > DNS_LABEL_SIZE_MAX {
+                return Err(DnsError::new(DnsErrorKind::InvalidName));
+            }
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+        }
+    }
+    data.push(0);
+    Ok(())
+}
+
+/// offset から名前を読み、offset を名前の直後に進める。圧縮ポインタ (RFC 1035 4.1.4) を辿る
+fn decode_name(data: &[u8], offset: &mut usize) -> Result<String, DnsError> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut position = *offset;
+    let mut jumped = false;
+    let mut pointers = 0;
+    let mut length = 0;
+    loop {
+        let len = *data
+            .get(position)
+            .ok_or_else(|| DnsError::new(DnsErrorKind::Truncated))? as usize;
+        match len & 0xc0 {
+            0x00 => {
+                if len == 0 {
+                    if !jumped {
+                        *offset = position + 1;
+                    }
+                    break;
+                }
+                let label = data
+                    .get(position + 1..position + 1 + len)
+                    .ok_or_else(|| DnsError::new(DnsErrorKind::Truncated))?;
+                length += len + 1;
+                if length > DNS_NAME_SIZE_MAX {
+                    return Err(DnsError::new(DnsErrorKind::InvalidName));
+                }
+                labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                position += 1 + len;
+            }
+            0xc0 => {
+                let low = *data
+                    .get(position + 1)
+                    .ok_or_else(|| DnsError::new(DnsErrorKind::Truncated))?
+                    as usize;
+                pointers += 1;
+                if pointers > DNS_POINTER_LIMIT {
+                    return Err(DnsError::new(DnsErrorKind::InvalidMessage));
+                }
+                if !jumped {
+                    *offset = position + 2;
+                    jumped = true;
+                }
+                position = ((len & 0x3f) << 8) | low;
+            }
+            _ => return Err(DnsError::new(DnsErrorKind::InvalidMessage)),
+        }
+    }
+    Ok(labels.join("."))
+}
+
+fn decode_record(data: &[u8], offset: &mut usize) -> Result<DnsRecord, DnsError> {
+    let name = decode_name(data, offset)?;
+    let fixed = data
+        .get(*offset..*offset + 10)
+        .ok_or_else(|| DnsError::new(DnsErrorKind::Truncated))?;
+    let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+    let class = u16::from_be_bytes([fixed[2], fixed[3]]);
+    let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+    let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+    let start = *offset + 10;
+    let rdata = data
+        .get(start..start + rdlength)
+        .ok_or_else(|| DnsError::new(DnsErrorKind::Truncated))?;
+    let record_data = match DnsRecordType::from_u16(rtype) {
+        Some(DnsRecordType::A) => {
+            let octets: [u8; 4] = rdata
+                .try_into()
+                .map_err(|_| DnsError::new(DnsErrorKind::InvalidMessage))?;
+            DnsRecordData::A(Ipv4Address::from(octets))
+        }
+        Some(DnsRecordType::Aaaa) => {
+            let octets: [u8; 16] = rdata
+                .try_into()
+                .map_err(|_| DnsError::new(DnsErrorKind::InvalidMessage))?;
+            DnsRecordData::Aaaa(Ipv6Addr::from(octets))
+        }
+        Some(DnsRecordType::Cname) => {
+            // CNAME の中の名前も圧縮されていることがあるのでメッセージ全体から読む
+            let mut position = start;
+            DnsRecordData::Cname(decode_name(data, &mut position)?)
+        }
+        _ => DnsRecordData::Other(rdata.to_vec()),
+    };
+    *offset = start + rdlength;
+    Ok(DnsRecord {
+        name,
+        rtype,
+        class,
+        ttl,
+        data: record_data,
+    })
+}
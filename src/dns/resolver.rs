@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{
+    normalize_name, DnsError, DnsErrorKind, DnsMessage, DnsRecordData, DnsRecordType,
+    DnsResponseCode, DNS_PORT,
+};
+use crate::ipv4::{Ipv4Address, Ipv4Endpoint};
+use crate::tcp;
+use crate::udp;
+
+/// 1 回の問い合わせで応答を待つ時間
+const DNS_QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// サーバ 1 つあたりの送信回数
+const DNS_QUERY_ATTEMPTS: usize = 2;
+/// CNAME を辿り直す回数の上限
+const DNS_CNAME_LIMIT: usize = 8;
+/// キャッシュに置く時間の上限
+const DNS_CACHE_TTL_MAX: u32 = 86400;
+
+/// 切り詰められた応答を受けたときに TCP で問い合わせ直す関数
+/// (サーバ, 長さの前置きを含まない問い合わせ, タイムアウト) を受け取って応答を返す
+pub type DnsTcpTransport = fn(Ipv4Endpoint, &[u8], Duration) -> Result<Vec<u8>, DnsError>;
+
+struct DnsCacheEntry {
+    addresses: Vec<IpAddr>,
+    expires: Instant,
+}
+
+lazy_static! {
+    static ref SERVERS: Mutex<Vec<Ipv4Address>> = Mutex::new(Vec::new());
+    static ref CACHE: Mutex<HashMap<(String, DnsRecordType), DnsCacheEntry>> =
+        Mutex::new(HashMap::new());
+    static ref TCP_TRANSPORT: Mutex<Option<DnsTcpTransport>> = Mutex::new(Some(exchange_tcp));
+}
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+/// 問い合わせ先のサーバ。前から順に試す
+pub fn set_servers(servers: &[Ipv4Address]) {
+    *SERVERS.lock().unwrap() = servers.to_vec();
+}
+
+pub fn servers() -> Vec<Ipv4Address> {
+    SERVERS.lock().unwrap().clone()
+}
+
+/// 既定ではこのスタックの TCP (exchange_tcp) を使う。None にすると TCP で問い合わせ直さない
+pub fn set_tcp_transport(transport: Option<DnsTcpTransport>) {
+    *TCP_TRANSPORT.lock().unwrap() = transport;
+}
+
+pub fn flush_cache() {
+    CACHE.lock().unwrap().clear();
+}
+
+/// 名前を A と AAAA で引き、得られたアドレスをすべて返す
+/// アドレスの文字列表記ならそのまま返す
+pub fn resolve(name: &str) -> Result<Vec<IpAddr>, DnsError> {
+    if let Ok(address) = name.parse::<IpAddr>() {
+        return Ok(vec![address]);
+    }
+    let mut addresses = Vec::new();
+    let mut error = None;
+    for rtype in [DnsRecordType::A, DnsRecordType::Aaaa] {
+        match lookup(name, rtype) {
+            Ok(found) => addresses.extend(found),
+            // 名前が存在しなければ AAAA を引くまでもない
+            Err(e) if e.kind == DnsErrorKind::NameNotFound => return Err(e),
+            Err(e) => error = Some(e),
+        }
+    }
+    if addresses.is_empty() {
+        return Err(error.unwrap_or_else(|| DnsError::new(DnsErrorKind::NoRecords)));
+    }
+    Ok(addresses)
+}
+
+/// rtype (A か AAAA) のレコードを引く。CNAME は辿る
+pub fn lookup(name: &str, rtype: DnsRecordType) -> Result<Vec<IpAddr>, DnsError> {
+    let name = normalize_name(name);
+    if name.is_empty() {
+        return Err(DnsError::new(DnsErrorKind::InvalidName));
+    }
+    if let Some(addresses) = cache_lookup(&name, rtype) {
+        return Ok(addresses);
+    }
+
+    let mut target = name.clone();
+    for _ in 0..DNS_CNAME_LIMIT {
+        let response = query(&target, rtype)?;
+        let answer = extract(&response, &target, rtype);
+        if !answer.addresses.is_empty() {
+            cache_insert(&name, rtype, &answer.addresses, answer.ttl);
+            return Ok(answer.addresses);
+        }
+        if answer.canonical == target {
+            return Err(DnsError::new(DnsErrorKind::NoRecords));
+        }
+        // 別名の先のアドレスが応答に含まれていなかったので引き直す
+        target = answer.canonical;
+    }
+    Err(DnsError::new(DnsErrorKind::TooManyRedirects))
+}
+
+/// 応答から取り出した答え
+#[derive(Debug, PartialEq, Eq)]
+pub struct DnsAnswer {
+    pub addresses: Vec<IpAddr>,
+    /// CNAME を辿った先の名前。別名が無ければ問い合わせた名前
+    pub canonical: String,
+    /// 辿ったレコードの TTL の最小値
+    pub ttl: u32,
+}
+
+/// name から応答内の CNAME を辿り、行き着いた名前の rtype のアドレスを集める
+pub fn extract(message: &DnsMessage, name: &str, rtype: DnsRecordType) -> DnsAnswer {
+    let mut canonical = normalize_name(name);
+    let mut ttl = u32::MAX;
+    for _ in 0..DNS_CNAME_LIMIT {
+        let alias = message
+            .answers
+            .iter()
+            .find_map(|record| match &record.data {
+                DnsRecordData::Cname(target) if record.name == canonical => {
+                    Some((target.clone(), record.ttl))
+                }
+                _ => None,
+            });
+        match alias {
+            Some((target, record_ttl)) => {
+                canonical = target;
+                ttl = ttl.min(record_ttl);
+            }
+            None => break,
+        }
+    }
+    let mut addresses = Vec::new();
+    for record in message.answers.iter().filter(|r| r.name == canonical) {
+        let address = match (&record.data, rtype) {
+            (DnsRecordData::A(address), DnsRecordType::A) => IpAddr::V4(Ipv4Addr::from(*address)),
+            (DnsRecordData::Aaaa(address), DnsRecordType::Aaaa) => IpAddr::V6(*address),
+            _ => continue,
+        };
+        ttl = ttl.min(record.ttl);
+        addresses.push(address);
+    }
+    DnsAnswer {
+        addresses,
+        canonical,
+        ttl: if ttl == u32::MAX { 0 } else { ttl },
+    }
+}
+
+fn cache_lookup(name: &str, rtype: DnsRecordType) -> Option<Vec<IpAddr>> {
+    let mut cache = CACHE.lock().unwrap();
+    let key = (name.to_string(), rtype);
+    match cache.get(&key) {
+        Some(entry) if entry.expires > Instant::now() => Some(entry.addresses.clone()),
+        Some(_) => {
+            cache.remove(&key);
+            None
+        }
+        None => None,
+    }
+}
+
+fn cache_insert(name: &str, rtype: DnsRecordType, addresses: &[IpAddr], ttl: u32) {
+    if ttl == 0 {
+        return;
+    }
+    let ttl = ttl.min(DNS_CACHE_TTL_MAX);
+    CACHE.lock().unwrap().insert(
+        (name.to_string(), rtype),
+        DnsCacheEntry {
+            addresses: addresses.to_vec(),
+            expires: Instant::now() + Duration::from_secs(ttl as u64),
+        },
+    );
+}
+
+fn generate_id() -> u16 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    (nanos as u16) ^ ((nanos >> 16) as u16) ^ NEXT_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+/// 設定されたサーバに順に問い合わせる
+fn query(name: &str, rtype: DnsRecordType) -> Result<DnsMessage, DnsError> {
+    let servers = servers();
+    if servers.is_empty() {
+        eprintln!("DNS no server configured");
+        return Err(DnsError::new(DnsErrorKind::NoServer));
+    }
+    let mut error = DnsError::new(DnsErrorKind::Timeout);
+    for server in servers {
+        let server = Ipv4Endpoint::new(server, DNS_PORT);
+        match query_server(server, name, rtype) {
+            Ok(response) => match response.response_code() {
+                DnsResponseCode::NoError => return Ok(response),
+                DnsResponseCode::NameError => {
+                    return Err(DnsError::new(DnsErrorKind::NameNotFound))
+                }
+                code => {
+                    eprintln!("DNS server error SERVER={} RCODE={:?}", server, code);
+                    error = DnsError::new(DnsErrorKind::ServerFailure);
+                }
+            },
+            Err(e) => {
+                eprintln!("DNS query error SERVER={}: {}", server, e.kind);
+                error = e;
+            }
+        }
+    }
+    Err(error)
+}
+
+fn query_server(
+    server: Ipv4Endpoint,
+    name: &str,
+    rtype: DnsRecordType,
+) -> Result<DnsMessage, DnsError> {
+    let request = DnsMessage::query(generate_id(), name, rtype);
+    let data = request.encode()?;
+    let id = udp::open().map_err(|_| DnsError::new(DnsErrorKind::SocketError))?;
+    let result = exchange_udp(id, server, &request, &data);
+    let _ = udp::close(id);
+    let response = result?;
+    if !response.is_truncated() {
+        return Ok(response);
+    }
+
+    eprintln!(
+        "DNS response truncated, retrying over TCP SERVER={}",
+        server
+    );
+    let transport = *TCP_TRANSPORT.lock().unwrap();
+    match transport {
+        Some(transport) => {
+            let data = transport(server, &data, DNS_QUERY_TIMEOUT)?;
+            let response = DnsMessage::parse(&data)?;
+            if !matches_request(&request, &response) {
+                return Err(DnsError::new(DnsErrorKind::InvalidMessage));
+            }
+            Ok(response)
+        }
+        // TCP が使えなければ切り詰められた応答に含まれている分だけ使う
+        None if !response.answers.is_empty() => Ok(response),
+        None => Err(DnsError::new(DnsErrorKind::Truncated)),
+    }
+}
+
+fn exchange_udp(
+    id: usize,
+    server: Ipv4Endpoint,
+    request: &DnsMessage,
+    data: &[u8],
+) -> Result<DnsMessage, DnsError> {
+    for _ in 0..DNS_QUERY_ATTEMPTS {
+        eprintln!(
+            "DNS query SERVER={} NAME={} TYPE={} ID={:04x}",
+            server,
+            request.questions[0].name,
+            DnsRecordType::from_u16(request.questions[0].qtype)
+                .map_or_else(|| String::from("?"), |t| t.to_string()),
+            request.id
+        );
+        udp::sendto(id, data, server).map_err(|_| DnsError::new(DnsErrorKind::SocketError))?;
+        let deadline = Instant::now() + DNS_QUERY_TIMEOUT;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            let datagram = match udp::recvfrom(id, Some(deadline - now)) {
+                Ok(datagram) => datagram,
+                Err(_) => break,
+            };
+            if datagram.foreign != server {
+                continue;
+            }
+            match DnsMessage::parse(&datagram.data) {
+                Ok(response) if matches_request(request, &response) => return Ok(response),
+                Ok(_) => continue,
+                Err(e) => eprintln!("DNS parse error: {}", e.kind),
+            }
+        }
+    }
+    Err(DnsError::new(DnsErrorKind::Timeout))
+}
+
+/// 2 バイトの長さを前置きして TCP で問い合わせる (RFC 1035 4.2.2)
+pub fn exchange_tcp(
+    server: Ipv4Endpoint,
+    data: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>, DnsError> {
+    let deadline = Instant::now() + timeout;
+    let id = tcp::open().map_err(|_| DnsError::new(DnsErrorKind::SocketError))?;
+    let result = exchange_tcp_connected(id, server, data, deadline);
+    let _ = tcp::close(id);
+    result
+}
+
+fn exchange_tcp_connected(
+    id: usize,
+    server: Ipv4Endpoint,
+    data: &[u8],
+    deadline: Instant,
+) -> Result<Vec<u8>, DnsError> {
+    tcp::connect(id, server, Some(remaining(deadline)?)).map_err(tcp_error)?;
+    let mut message = Vec::with_capacity(data.len() + 2);
+    message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    message.extend_from_slice(data);
+    let sent = tcp::send(id, &message, Some(remaining(deadline)?)).map_err(tcp_error)?;
+    if sent < message.len() {
+        return Err(DnsError::new(DnsErrorKind::Timeout));
+    }
+
+    let mut length = [0; 2];
+    receive_exact(id, &mut length, deadline)?;
+    let mut response = vec![0; u16::from_be_bytes(length) as usize];
+    receive_exact(id, &mut response, deadline)?;
+    Ok(response)
+}
+
+fn receive_exact(id: usize, buf: &mut [u8], deadline: Instant) -> Result<(), DnsError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let timeout = Some(remaining(deadline)?);
+        match tcp::receive(id, &mut buf[filled..], timeout).map_err(tcp_error)? {
+            0 => return Err(DnsError::new(DnsErrorKind::InvalidMessage)),
+            len => filled += len,
+        }
+    }
+    Ok(())
+}
+
+fn remaining(deadline: Instant) -> Result<Duration, DnsError> {
+    let now = Instant::now();
+    if now >= deadline {
+        return Err(DnsError::new(DnsErrorKind::Timeout));
+    }
+    Ok(deadline - now)
+}
+
+fn tcp_error(e: tcp::TcpError) -> DnsError {
+    match e.kind {
+        tcp::TcpErrorKind::Timeout => DnsError::new(DnsErrorKind::Timeout),
+        _ => DnsError::new(DnsErrorKind::SocketError),
+    }
+}
+
+/// ID と質問が一致する応答だけを受け付ける
+fn matches_request(request: &DnsMessage, response: &DnsMessage) -> bool {
+    response.is_response()
+        && response.id == request.id
+        && response.questions.len() == 1
+        && response.questions[0] == request.questions[0]
+}
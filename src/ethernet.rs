@@ -1,9 +1,12 @@
 use std::convert::TryInto;
 use std::fmt;
 use std::num::ParseIntError;
+use std::sync::Mutex;
 
 // use crate::ipv4;
 // use crate::ipv4::Ipv4Header;
+use crate::ipv4::Ipv4Address;
+use crate::net::NetDevice;
 use crate::packet::Packet;
 
 pub const MAC_LENGTH: usize = 6;
@@ -33,6 +36,94 @@ impl MacAddress {
     }
 }
 
+impl From<[u8; MAC_LENGTH]> for MacAddress {
+    fn from(octets: [u8; MAC_LENGTH]) -> Self {
+        MacAddress(octets)
+    }
+}
+
+impl MacAddress {
+    pub fn octets(&self) -> [u8; MAC_LENGTH] {
+        self.0
+    }
+
+    /// 先頭オクテットの最下位ビットが I/G ビット
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == MAC_BROADCAST
+    }
+}
+
+/// IPv4 マルチキャストアドレスの下位 23 ビットを 01:00:5e に続けた MAC アドレス (RFC 1112 6.4)
+pub fn ipv4_multicast_mac(address: Ipv4Address) -> MacAddress {
+    let octets = address.octets();
+    MacAddress([0x01, 0x00, 0x5e, octets[1] & 0x7f, octets[2], octets[3]])
+}
+
+/// 224.0.0.1 に対応する MAC アドレス。参加しなくても常に受け取る
+const MAC_ALL_SYSTEMS: MacAddress = MacAddress([0x01, 0x00, 0x5e, 0x00, 0x00, 0x01]);
+
+struct MulticastFilterEntry {
+    dev: String,
+    mac: MacAddress,
+    users: usize,
+}
+
+lazy_static! {
+    static ref MULTICAST_FILTER: Mutex<Vec<MulticastFilterEntry>> = Mutex::new(Vec::new());
+}
+
+/// dev で受け取るマルチキャスト MAC アドレスを加える。同じアドレスは参照を数える
+/// 下位 23 ビットしか写らないので、異なるグループが同じアドレスを共有することがある
+pub fn multicast_filter_add(dev: &str, mac: MacAddress) {
+    let mut filter = MULTICAST_FILTER.lock().unwrap();
+    match filter
+        .iter_mut()
+        .find(|entry| entry.dev == dev && entry.mac == mac)
+    {
+        Some(entry) => entry.users += 1,
+        None => filter.push(MulticastFilterEntry {
+            dev: dev.to_string(),
+            mac,
+            users: 1,
+        }),
+    }
+}
+
+pub fn multicast_filter_remove(dev: &str, mac: MacAddress) {
+    let mut filter = MULTICAST_FILTER.lock().unwrap();
+    for entry in filter.iter_mut() {
+        if entry.dev == dev && entry.mac == mac {
+            entry.users -= 1;
+        }
+    }
+    filter.retain(|entry| entry.users > 0);
+}
+
+/// Ethernet のドライバは受信したフレームの宛先をこれで確かめ、true のときだけ
+/// ヘッダを外して NetProtocol::input_handler に渡す
+/// 自分宛て、ブロードキャスト、参加しているグループ宛てを受け取る
+/// IPv6 のマルチキャスト (33:33:xx:xx:xx:xx) は MLD を実装するまですべて受け取る
+pub fn accepts(dev: &NetDevice, dst: &MacAddress) -> bool {
+    if dst.is_broadcast() || *dst == MAC_ALL_SYSTEMS {
+        return true;
+    }
+    if !dst.is_multicast() {
+        return dev.hwaddr[..MAC_LENGTH] == dst.0;
+    }
+    if dst.0[0] == 0x33 && dst.0[1] == 0x33 {
+        return true;
+    }
+    MULTICAST_FILTER
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|entry| entry.dev == dev.name && entry.mac == *dst)
+}
+
 impl Default for MacAddress {
     fn default() -> MacAddress {
         MacAddress([0; MAC_LENGTH])
@@ -0,0 +1,315 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::ipv4::pmtu;
+use crate::ipv4::{
+    self, IpInterface, Ipv4Header, Protocol, IP_ADDRESS_ANY, IP_HEADER_SIZE_MIN, IP_VERSION_IPV4,
+};
+use crate::net::NetDevice;
+use crate::tcp;
+use crate::utils::{checksum, RateLimiter};
+
+pub const ICMP_HEADER_SIZE: usize = 8;
+
+/// エラーメッセージに含める元のデータグラムのペイロード長 (RFC 792)
+pub const ICMP_ERROR_ORIGINAL_PAYLOAD_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IcmpType {
+    EchoReply = 0,
+    DestinationUnreachable = 3,
+    SourceQuench = 4,
+    Redirect = 5,
+    Echo = 8,
+    TimeExceeded = 11,
+    ParameterProblem = 12,
+    Timestamp = 13,
+    TimestampReply = 14,
+    Unknown,
+}
+
+impl IcmpType {
+    pub fn from_u8(u: u8) -> IcmpType {
+        match u {
+            0 => IcmpType::EchoReply,
+            3 => IcmpType::DestinationUnreachable,
+            4 => IcmpType::SourceQuench,
+            5 => IcmpType::Redirect,
+            8 => IcmpType::Echo,
+            11 => IcmpType::TimeExceeded,
+            12 => IcmpType::ParameterProblem,
+            13 => IcmpType::Timestamp,
+            14 => IcmpType::TimestampReply,
+            _ => IcmpType::Unknown,
+        }
+    }
+
+    /// エラーメッセージか (エラーに対してエラーを返してはならない)
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            IcmpType::DestinationUnreachable
+                | IcmpType::SourceQuench
+                | IcmpType::Redirect
+                | IcmpType::TimeExceeded
+                | IcmpType::ParameterProblem
+        )
+    }
+}
+
+impl fmt::Display for IcmpType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            IcmpType::EchoReply => "EchoReply",
+            IcmpType::DestinationUnreachable => "DestinationUnreachable",
+            IcmpType::SourceQuench => "SourceQuench",
+            IcmpType::Redirect => "Redirect",
+            IcmpType::Echo => "Echo",
+            IcmpType::TimeExceeded => "TimeExceeded",
+            IcmpType::ParameterProblem => "ParameterProblem",
+            IcmpType::Timestamp => "Timestamp",
+            IcmpType::TimestampReply => "TimestampReply",
+            IcmpType::Unknown => "Unknown",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Destination Unreachable のコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum UnreachableCode {
+    Net = 0,
+    Host = 1,
+    Protocol = 2,
+    Port = 3,
+    FragmentationNeeded = 4,
+    SourceRouteFailed = 5,
+}
+
+/// Time Exceeded のコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TimeExceededCode {
+    TimeToLive = 0,
+    FragmentReassembly = 1,
+}
+
+/// バイト列の上に被せて ICMP メッセージを読み書きするビュー
+pub struct IcmpMessage<T: AsRef<[u8]>> {
+    buffer: T,
+}
+
+impl<T: AsRef<[u8]>> IcmpMessage<T> {
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        if buffer.as_ref().len() < ICMP_HEADER_SIZE {
+            return None;
+        }
+        Some(IcmpMessage { buffer })
+    }
+
+    pub fn message_type(&self) -> IcmpType {
+        IcmpType::from_u8(self.buffer.as_ref()[0])
+    }
+
+    pub fn code(&self) -> u8 {
+        self.buffer.as_ref()[1]
+    }
+
+    pub fn checksum(&self) -> u16 {
+        let data = self.buffer.as_ref();
+        u16::from_be_bytes([data[2], data[3]])
+    }
+
+    /// タイプごとに意味の異なるヘッダの後半 4 バイト
+    pub fn values(&self) -> u32 {
+        let data = self.buffer.as_ref();
+        u32::from_be_bytes([data[4], data[5], data[6], data[7]])
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.buffer.as_ref()[ICMP_HEADER_SIZE..]
+    }
+
+    pub fn verify_checksum(&self) -> bool {
+        checksum(self.buffer.as_ref(), 0) == 0
+    }
+}
+
+/// ヘッダとデータを連結し、チェックサムを埋めたメッセージを作る
+pub fn build(message_type: IcmpType, code: u8, values: u32, data: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(ICMP_HEADER_SIZE + data.len());
+    message.push(message_type as u8);
+    message.push(code);
+    message.extend_from_slice(&[0, 0]);
+    message.extend_from_slice(&values.to_be_bytes());
+    message.extend_from_slice(data);
+    let sum = checksum(&message, 0);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    message
+}
+
+const ICMP_ERROR_RATE_DEFAULT: u32 = 10;
+const ICMP_ERROR_BURST_DEFAULT: u32 = 10;
+
+lazy_static! {
+    /// エラーメッセージの送出を制限する
+    static ref RATE_LIMITER: Mutex<RateLimiter> = Mutex::new(RateLimiter::new(
+        ICMP_ERROR_RATE_DEFAULT,
+        ICMP_ERROR_BURST_DEFAULT
+    ));
+}
+
+/// エラーメッセージを毎秒 rate 通、最大 burst 通まで連続して送れるようにする
+pub fn set_error_rate_limit(rate: u32, burst: u32) {
+    let mut limiter = RATE_LIMITER.lock().unwrap();
+    *limiter = RateLimiter::new(rate, burst);
+}
+
+/// original の送信元へエラーメッセージを送る
+/// RFC 1122 3.2.2 に従い、エラーに対するエラー、ブロードキャストやマルチキャスト宛て、
+/// 先頭以外のフラグメント、送信元が特定できないパケットに対しては送らない
+pub fn error(message_type: IcmpType, code: u8, values: u32, original: &[u8]) {
+    // ヘッダが壊れていて Parameter Problem を返す場合もあるので、全長は信用しない
+    if original.len() < IP_HEADER_SIZE_MIN as usize {
+        return;
+    }
+    let ipv4_hdr = Ipv4Header::new_unchecked(original);
+    if ipv4_hdr.version() != IP_VERSION_IPV4 {
+        return;
+    }
+    let src = ipv4_hdr.src_address();
+    let dst = ipv4_hdr.dst_address();
+    if src.is_unspecified() || ipv4::is_broadcast(src) || src.is_multicast() {
+        return;
+    }
+    if ipv4::is_broadcast(dst) || dst.is_multicast() {
+        return;
+    }
+    if ipv4_hdr.offset() > 0 {
+        return;
+    }
+    let header_length = (ipv4_hdr.header_length() as usize).min(original.len());
+    if let Protocol::Icmp = ipv4_hdr.protocol() {
+        match original.get(header_length) {
+            Some(message_type) if !IcmpType::from_u8(*message_type).is_error() => (),
+            _ => return,
+        }
+    }
+    if !RATE_LIMITER.lock().unwrap().acquire() {
+        eprintln!("ICMP error rate limited TYPE={} DST={}", message_type, src);
+        return;
+    }
+
+    // 元のヘッダ (オプション含む) とペイロードの先頭 8 バイトを載せる
+    let length = (header_length + ICMP_ERROR_ORIGINAL_PAYLOAD_SIZE).min(original.len());
+    let message = build(message_type, code, values, &original[..length]);
+
+    // 自分宛てだったパケットなら、そのアドレスから返す
+    let local = if ipv4::IpInterface::select(dst).is_some() {
+        dst
+    } else {
+        IP_ADDRESS_ANY
+    };
+    eprintln!(
+        "ICMP error output TYPE={} CODE={} SRC={} DST={}",
+        message_type, code, local, src
+    );
+    let _ = ipv4::output(Protocol::Icmp as u8, &message, local, src);
+}
+
+pub fn destination_unreachable(code: UnreachableCode, original: &[u8]) {
+    error(IcmpType::DestinationUnreachable, code as u8, 0, original);
+}
+
+/// next_hop_mtu を載せた Fragmentation Needed (RFC 1191)
+pub fn fragmentation_needed(next_hop_mtu: u16, original: &[u8]) {
+    error(
+        IcmpType::DestinationUnreachable,
+        UnreachableCode::FragmentationNeeded as u8,
+        next_hop_mtu as u32,
+        original,
+    );
+}
+
+pub fn time_exceeded(code: TimeExceededCode, original: &[u8]) {
+    error(IcmpType::TimeExceeded, code as u8, 0, original);
+}
+
+/// pointer は元のデータグラムの先頭から問題のあるバイトまでのオフセット
+pub fn parameter_problem(pointer: u8, original: &[u8]) {
+    error(
+        IcmpType::ParameterProblem,
+        0,
+        (pointer as u32) << 24,
+        original,
+    );
+}
+
+pub fn input(packet: &[u8], dev: &'static NetDevice) {
+    let ipv4_hdr = Ipv4Header::new_unchecked(packet);
+    let message = match IcmpMessage::new_checked(ipv4_hdr.payload()) {
+        Some(message) => message,
+        None => {
+            eprintln!("ICMP message too short");
+            return;
+        }
+    };
+    if !message.verify_checksum() {
+        eprintln!("ICMP checksum error");
+        return;
+    }
+    eprintln!(
+        "ICMP input DEV={} TYPE={} CODE={} SRC={} DST={}",
+        dev.name,
+        message.message_type(),
+        message.code(),
+        ipv4_hdr.src_address(),
+        ipv4_hdr.dst_address()
+    );
+
+    if message.message_type() == IcmpType::DestinationUnreachable
+        && message.code() == UnreachableCode::FragmentationNeeded as u8
+    {
+        // 載っている元のヘッダから宛先を取り出して PMTU を更新する
+        // 自分が送ったものでなければ偽の通知なので無視する
+        let original = Ipv4Header::new_unchecked(message.data());
+        if message.data().len() < IP_HEADER_SIZE_MIN as usize
+            || original.version() != IP_VERSION_IPV4
+        {
+            return;
+        }
+        if IpInterface::select(original.src_address()).is_none() {
+            eprintln!(
+                "ICMP fragmentation needed for a packet we did not send SRC={}",
+                original.src_address()
+            );
+            return;
+        }
+        pmtu::update(
+            original.dst_address(),
+            (message.values() & 0xffff) as u16,
+            original.total_length(),
+        );
+        tcp::path_mtu_changed(original.dst_address());
+        return;
+    }
+
+    if let IcmpType::Echo = message.message_type() {
+        let reply = build(IcmpType::EchoReply, 0, message.values(), message.data());
+        let dst = ipv4_hdr.dst_address();
+        let src = if ipv4::is_broadcast(dst) || dst.is_multicast() {
+            IP_ADDRESS_ANY
+        } else {
+            dst
+        };
+        let _ = ipv4::output(Protocol::Icmp as u8, &reply, src, ipv4_hdr.src_address());
+    }
+}
+
+pub fn init() {
+    if ipv4::protocol_register(Protocol::Icmp as u8, input).is_err() {
+        eprintln!("ICMP is already registered");
+    }
+}
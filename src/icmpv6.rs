@@ -0,0 +1,317 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::ipv6::{
+    self, nd, slaac, Ipv6Address, Ipv6Header, Ipv6Interface, IPV6_ADDRESS_UNSPECIFIED,
+    IPV6_HEADER_SIZE, IPV6_MTU_MIN, IPV6_NEXT_HEADER_ICMPV6,
+};
+use crate::net::NetDevice;
+use crate::utils::{checksum, RateLimiter};
+
+pub const ICMPV6_HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Icmpv6Type {
+    DestinationUnreachable = 1,
+    PacketTooBig = 2,
+    TimeExceeded = 3,
+    ParameterProblem = 4,
+    EchoRequest = 128,
+    EchoReply = 129,
+    RouterSolicitation = 133,
+    RouterAdvertisement = 134,
+    NeighborSolicitation = 135,
+    NeighborAdvertisement = 136,
+    Redirect = 137,
+    Unknown,
+}
+
+impl Icmpv6Type {
+    pub fn from_u8(u: u8) -> Icmpv6Type {
+        match u {
+            1 => Icmpv6Type::DestinationUnreachable,
+            2 => Icmpv6Type::PacketTooBig,
+            3 => Icmpv6Type::TimeExceeded,
+            4 => Icmpv6Type::ParameterProblem,
+            128 => Icmpv6Type::EchoRequest,
+            129 => Icmpv6Type::EchoReply,
+            133 => Icmpv6Type::RouterSolicitation,
+            134 => Icmpv6Type::RouterAdvertisement,
+            135 => Icmpv6Type::NeighborSolicitation,
+            136 => Icmpv6Type::NeighborAdvertisement,
+            137 => Icmpv6Type::Redirect,
+            _ => Icmpv6Type::Unknown,
+        }
+    }
+
+    /// タイプの最上位ビットが 0 ならエラーメッセージ (RFC 4443 2.1)
+    pub fn is_error(type_value: u8) -> bool {
+        type_value < 128
+    }
+}
+
+impl fmt::Display for Icmpv6Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Icmpv6Type::DestinationUnreachable => "DestinationUnreachable",
+            Icmpv6Type::PacketTooBig => "PacketTooBig",
+            Icmpv6Type::TimeExceeded => "TimeExceeded",
+            Icmpv6Type::ParameterProblem => "ParameterProblem",
+            Icmpv6Type::EchoRequest => "EchoRequest",
+            Icmpv6Type::EchoReply => "EchoReply",
+            Icmpv6Type::RouterSolicitation => "RouterSolicitation",
+            Icmpv6Type::RouterAdvertisement => "RouterAdvertisement",
+            Icmpv6Type::NeighborSolicitation => "NeighborSolicitation",
+            Icmpv6Type::NeighborAdvertisement => "NeighborAdvertisement",
+            Icmpv6Type::Redirect => "Redirect",
+            Icmpv6Type::Unknown => "Unknown",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Destination Unreachable のコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Icmpv6UnreachableCode {
+    NoRoute = 0,
+    AdministrativelyProhibited = 1,
+    BeyondScope = 2,
+    Address = 3,
+    Port = 4,
+}
+
+/// Parameter Problem のコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Icmpv6ParameterProblemCode {
+    Header = 0,
+    NextHeader = 1,
+    Option = 2,
+}
+
+/// バイト列の上に被せて ICMPv6 メッセージを読み書きするビュー
+pub struct Icmpv6Message<T: AsRef<[u8]>> {
+    buffer: T,
+}
+
+impl<T: AsRef<[u8]>> Icmpv6Message<T> {
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        if buffer.as_ref().len() < ICMPV6_HEADER_SIZE {
+            return None;
+        }
+        Some(Icmpv6Message { buffer })
+    }
+
+    pub fn type_value(&self) -> u8 {
+        self.buffer.as_ref()[0]
+    }
+
+    pub fn message_type(&self) -> Icmpv6Type {
+        Icmpv6Type::from_u8(self.type_value())
+    }
+
+    pub fn code(&self) -> u8 {
+        self.buffer.as_ref()[1]
+    }
+
+    pub fn checksum(&self) -> u16 {
+        let data = self.buffer.as_ref();
+        u16::from_be_bytes([data[2], data[3]])
+    }
+
+    /// タイプごとに意味の異なるヘッダの後半 4 バイト
+    pub fn values(&self) -> u32 {
+        let data = self.buffer.as_ref();
+        u32::from_be_bytes([data[4], data[5], data[6], data[7]])
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.buffer.as_ref()[ICMPV6_HEADER_SIZE..]
+    }
+
+    /// ICMPv6 のチェックサムは疑似ヘッダを含む
+    pub fn verify_checksum(&self, src: Ipv6Address, dst: Ipv6Address) -> bool {
+        let data = self.buffer.as_ref();
+        let pseudo = ipv6::pseudo_header_sum(src, dst, IPV6_NEXT_HEADER_ICMPV6, data.len() as u32);
+        checksum(data, pseudo) == 0
+    }
+}
+
+/// ヘッダとデータを連結し、src と dst の疑似ヘッダを含めたチェックサムを埋める
+pub fn build(
+    message_type: Icmpv6Type,
+    code: u8,
+    values: u32,
+    data: &[u8],
+    src: Ipv6Address,
+    dst: Ipv6Address,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(ICMPV6_HEADER_SIZE + data.len());
+    message.push(message_type as u8);
+    message.push(code);
+    message.extend_from_slice(&[0, 0]);
+    message.extend_from_slice(&values.to_be_bytes());
+    message.extend_from_slice(data);
+    let pseudo = ipv6::pseudo_header_sum(src, dst, IPV6_NEXT_HEADER_ICMPV6, message.len() as u32);
+    let sum = checksum(&message, pseudo);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    message
+}
+
+const ICMPV6_ERROR_RATE_DEFAULT: u32 = 10;
+const ICMPV6_ERROR_BURST_DEFAULT: u32 = 10;
+
+lazy_static! {
+    /// エラーメッセージの送出を制限する (RFC 4443 2.4 (f))
+    static ref RATE_LIMITER: Mutex<RateLimiter> = Mutex::new(RateLimiter::new(
+        ICMPV6_ERROR_RATE_DEFAULT,
+        ICMPV6_ERROR_BURST_DEFAULT
+    ));
+}
+
+/// エラーメッセージを毎秒 rate 通、最大 burst 通まで連続して送れるようにする
+pub fn set_error_rate_limit(rate: u32, burst: u32) {
+    let mut limiter = RATE_LIMITER.lock().unwrap();
+    *limiter = RateLimiter::new(rate, burst);
+}
+
+/// original の送信元へエラーメッセージを送る
+/// RFC 4443 2.4 に従い、エラーに対するエラー、送信元が特定できないパケットには送らない
+/// マルチキャスト宛てのパケットには Packet Too Big と未知のオプションの Parameter Problem だけを返す
+pub fn error(message_type: Icmpv6Type, code: u8, values: u32, original: &[u8]) {
+    if original.len() < IPV6_HEADER_SIZE {
+        return;
+    }
+    let ipv6_hdr = Ipv6Header::new_unchecked(original);
+    let src = ipv6_hdr.src_address();
+    let dst = ipv6_hdr.dst_address();
+    if src.is_unspecified() || src.is_multicast() {
+        return;
+    }
+    let multicast_allowed = message_type == Icmpv6Type::PacketTooBig
+        || (message_type == Icmpv6Type::ParameterProblem
+            && code == Icmpv6ParameterProblemCode::Option as u8);
+    if dst.is_multicast() && !multicast_allowed {
+        return;
+    }
+    if let Ok((IPV6_NEXT_HEADER_ICMPV6, offset)) = ipv6::upper_layer(original) {
+        match original.get(offset) {
+            Some(type_value) if !Icmpv6Type::is_error(*type_value) => (),
+            _ => return,
+        }
+    }
+    if !RATE_LIMITER.lock().unwrap().acquire() {
+        eprintln!(
+            "ICMPv6 error rate limited TYPE={} DST={}",
+            message_type, src
+        );
+        return;
+    }
+
+    let local = if !dst.is_multicast() && Ipv6Interface::select(dst).is_some() {
+        dst
+    } else {
+        match Ipv6Interface::select_source(src) {
+            Some(interface) => interface.unicast,
+            None => return,
+        }
+    };
+    // 最小 MTU に収まるだけ元のパケットを載せる
+    let length = original
+        .len()
+        .min(IPV6_MTU_MIN as usize - IPV6_HEADER_SIZE - ICMPV6_HEADER_SIZE);
+    let message = build(message_type, code, values, &original[..length], local, src);
+    eprintln!(
+        "ICMPv6 error output TYPE={} CODE={} SRC={} DST={}",
+        message_type, code, local, src
+    );
+    let _ = ipv6::output(IPV6_NEXT_HEADER_ICMPV6, &message, local, src);
+}
+
+pub fn destination_unreachable(code: Icmpv6UnreachableCode, original: &[u8]) {
+    error(Icmpv6Type::DestinationUnreachable, code as u8, 0, original);
+}
+
+pub fn packet_too_big(mtu: u32, original: &[u8]) {
+    error(Icmpv6Type::PacketTooBig, 0, mtu, original);
+}
+
+/// code 0 はホップリミット超過、1 はフラグメント再構築の時間切れ
+pub fn time_exceeded(code: u8, original: &[u8]) {
+    error(Icmpv6Type::TimeExceeded, code, 0, original);
+}
+
+/// pointer は元のパケットの先頭から問題のあるバイトまでのオフセット
+pub fn parameter_problem(code: Icmpv6ParameterProblemCode, pointer: u32, original: &[u8]) {
+    error(Icmpv6Type::ParameterProblem, code as u8, pointer, original);
+}
+
+pub fn input(packet: &[u8], offset: usize, dev: &'static NetDevice) {
+    let ipv6_hdr = Ipv6Header::new_unchecked(packet);
+    let src = ipv6_hdr.src_address();
+    let dst = ipv6_hdr.dst_address();
+    let message = match Icmpv6Message::new_checked(&packet[offset..]) {
+        Some(message) => message,
+        None => {
+            eprintln!("ICMPv6 message too short");
+            return;
+        }
+    };
+    if !message.verify_checksum(src, dst) {
+        eprintln!("ICMPv6 checksum error");
+        return;
+    }
+    eprintln!(
+        "ICMPv6 input DEV={} TYPE={} CODE={} SRC={} DST={}",
+        dev.name,
+        message.message_type(),
+        message.code(),
+        src,
+        dst
+    );
+
+    match message.message_type() {
+        Icmpv6Type::EchoRequest => {
+            // マルチキャスト宛ての要求には自分のユニキャストアドレスから返す
+            let local = if dst.is_multicast() {
+                IPV6_ADDRESS_UNSPECIFIED
+            } else {
+                dst
+            };
+            let local = match local {
+                local if !local.is_unspecified() => local,
+                _ => match Ipv6Interface::select_source(src) {
+                    Some(interface) => interface.unicast,
+                    None => return,
+                },
+            };
+            let reply = build(
+                Icmpv6Type::EchoReply,
+                0,
+                message.values(),
+                message.data(),
+                local,
+                src,
+            );
+            let _ = ipv6::output(IPV6_NEXT_HEADER_ICMPV6, &reply, local, src);
+        }
+        Icmpv6Type::NeighborSolicitation | Icmpv6Type::NeighborAdvertisement => {
+            nd::input(&ipv6_hdr, &message, dev);
+        }
+        Icmpv6Type::RouterAdvertisement => {
+            slaac::input(&ipv6_hdr, &message, dev);
+        }
+        Icmpv6Type::PacketTooBig => {
+            eprintln!("ICMPv6 packet too big MTU={}", message.values());
+        }
+        _ => (),
+    }
+}
+
+pub fn init() {
+    if ipv6::protocol_register(IPV6_NEXT_HEADER_ICMPV6, input).is_err() {
+        eprintln!("ICMPv6 is already registered");
+    }
+}
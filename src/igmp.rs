@@ -0,0 +1,611 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::ethernet;
+use crate::ipv4::{
+    self, IpInterface, Ipv4Address, Ipv4Header, Ipv4Option, Protocol, IP_ADDRESS_ANY,
+};
+use crate::net::{device_by_name, NetDevice, NetInterfaceFamily, NetInterfaceType, NetTimer};
+use crate::utils::checksum;
+
+pub const IGMP_HEADER_SIZE: usize = 8;
+/// IGMPv3 の Membership Query の最小長 (RFC 3376 4.1)
+pub const IGMPV3_QUERY_SIZE_MIN: usize = 12;
+
+pub const IGMP_TYPE_MEMBERSHIP_QUERY: u8 = 0x11;
+pub const IGMP_TYPE_V1_MEMBERSHIP_REPORT: u8 = 0x12;
+pub const IGMP_TYPE_V2_MEMBERSHIP_REPORT: u8 = 0x16;
+pub const IGMP_TYPE_V2_LEAVE_GROUP: u8 = 0x17;
+pub const IGMP_TYPE_V3_MEMBERSHIP_REPORT: u8 = 0x22;
+
+/// 224.0.0.1。すべてのホストが常に参加している
+pub const IGMP_ALL_SYSTEMS: Ipv4Address = Ipv4Address::new(224, 0, 0, 1);
+/// 224.0.0.2。IGMPv2 の Leave Group の宛先
+pub const IGMP_ALL_ROUTERS: Ipv4Address = Ipv4Address::new(224, 0, 0, 2);
+/// 224.0.0.22。IGMPv3 の Report の宛先
+pub const IGMPV3_ROUTERS: Ipv4Address = Ipv4Address::new(224, 0, 0, 22);
+
+/// RFC 3376 8 の既定値
+const IGMP_ROBUSTNESS: u32 = 2;
+const IGMP_QUERY_INTERVAL: Duration = Duration::from_secs(125);
+const IGMP_QUERY_RESPONSE_INTERVAL: Duration = Duration::from_secs(10);
+const IGMPV3_UNSOLICITED_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// RFC 2236 8.10
+const IGMPV2_UNSOLICITED_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+/// RFC 2236 8.11 の Version 1 Router Present Timeout
+const IGMPV1_ROUTER_PRESENT_TIMEOUT: Duration = Duration::from_secs(400);
+/// IGMPv1 の Query には最大応答時間が無いので 10 秒とみなす
+const IGMPV1_MAX_RESPONSE_TIME: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IgmpVersion {
+    V1,
+    V2,
+    V3,
+}
+
+impl fmt::Display for IgmpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            IgmpVersion::V1 => "IGMPv1",
+            IgmpVersion::V2 => "IGMPv2",
+            IgmpVersion::V3 => "IGMPv3",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// IGMPv3 の Group Record の種類 (RFC 3376 4.2.12)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IgmpRecordType {
+    ModeIsInclude = 1,
+    ModeIsExclude = 2,
+    ChangeToInclude = 3,
+    ChangeToExclude = 4,
+    AllowNewSources = 5,
+    BlockOldSources = 6,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgmpGroupRecord {
+    pub record_type: IgmpRecordType,
+    pub group: Ipv4Address,
+    pub sources: Vec<Ipv4Address>,
+}
+
+#[derive(Debug)]
+pub struct IgmpError {
+    pub kind: IgmpErrorKind,
+}
+
+impl IgmpError {
+    pub fn new(kind: IgmpErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IgmpErrorKind {
+    InvalidGroup,
+    NoInterface,
+    NotMember,
+}
+
+impl fmt::Display for IgmpErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            IgmpErrorKind::InvalidGroup => "invalid multicast group",
+            IgmpErrorKind::NoInterface => "no interface",
+            IgmpErrorKind::NotMember => "not a member",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// バイト列の上に被せて IGMP メッセージを読むビュー
+pub struct IgmpMessage<T: AsRef<[u8]>> {
+    buffer: T,
+}
+
+impl<T: AsRef<[u8]>> IgmpMessage<T> {
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        if buffer.as_ref().len() < IGMP_HEADER_SIZE {
+            return None;
+        }
+        Some(IgmpMessage { buffer })
+    }
+
+    fn data(&self) -> &[u8] {
+        self.buffer.as_ref()
+    }
+
+    pub fn message_type(&self) -> u8 {
+        self.data()[0]
+    }
+
+    pub fn max_resp_code(&self) -> u8 {
+        self.data()[1]
+    }
+
+    pub fn group(&self) -> Ipv4Address {
+        let data = self.data();
+        Ipv4Address::new(data[4], data[5], data[6], data[7])
+    }
+
+    pub fn verify_checksum(&self) -> bool {
+        checksum(self.data(), 0) == 0
+    }
+
+    /// Query の版は長さと最大応答時間で見分ける (RFC 3376 7.1)
+    pub fn query_version(&self) -> IgmpVersion {
+        if self.data().len() >= IGMPV3_QUERY_SIZE_MIN {
+            IgmpVersion::V3
+        } else if self.max_resp_code() == 0 {
+            IgmpVersion::V1
+        } else {
+            IgmpVersion::V2
+        }
+    }
+
+    /// 最大応答時間
+    pub fn max_response_time(&self) -> Duration {
+        match self.query_version() {
+            IgmpVersion::V1 => IGMPV1_MAX_RESPONSE_TIME,
+            IgmpVersion::V2 => Duration::from_millis(self.max_resp_code() as u64 * 100),
+            IgmpVersion::V3 => {
+                Duration::from_millis(decode_max_resp_code(self.max_resp_code()) as u64 * 100)
+            }
+        }
+    }
+
+    /// IGMPv3 の Group-and-Source-Specific Query の送信元
+    pub fn sources(&self) -> Vec<Ipv4Address> {
+        let data = self.data();
+        if data.len() < IGMPV3_QUERY_SIZE_MIN {
+            return Vec::new();
+        }
+        let count = u16::from_be_bytes([data[10], data[11]]) as usize;
+        data[IGMPV3_QUERY_SIZE_MIN..]
+            .chunks_exact(4)
+            .take(count)
+            .map(|s| Ipv4Address::new(s[0], s[1], s[2], s[3]))
+            .collect()
+    }
+}
+
+/// 128 以上の Max Resp Code は浮動小数点形式 (RFC 3376 4.1.1)。単位は 0.1 秒
+pub fn decode_max_resp_code(code: u8) -> u32 {
+    if code < 128 {
+        return code as u32;
+    }
+    let mant = (code & 0x0f) as u32;
+    let exp = ((code >> 4) & 0x07) as u32;
+    (mant | 0x10) << (exp + 3)
+}
+
+fn fill_checksum(message: &mut [u8]) {
+    let sum = checksum(message, 0);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+}
+
+/// IGMPv1/v2 の形式のメッセージを組み立てる
+pub fn build(message_type: u8, max_resp_code: u8, group: Ipv4Address) -> Vec<u8> {
+    let mut message = vec![message_type, max_resp_code, 0, 0];
+    message.extend_from_slice(&group.octets());
+    fill_checksum(&mut message);
+    message
+}
+
+/// IGMPv3 の Membership Report を組み立てる (RFC 3376 4.2)
+pub fn build_v3_report(records: &[IgmpGroupRecord]) -> Vec<u8> {
+    let mut message = vec![IGMP_TYPE_V3_MEMBERSHIP_REPORT, 0, 0, 0, 0, 0];
+    message.extend_from_slice(&(records.len() as u16).to_be_bytes());
+    for record in records {
+        message.push(record.record_type as u8);
+        message.push(0);
+        message.extend_from_slice(&(record.sources.len() as u16).to_be_bytes());
+        message.extend_from_slice(&record.group.octets());
+        for source in record.sources.iter() {
+            message.extend_from_slice(&source.octets());
+        }
+    }
+    fill_checksum(&mut message);
+    message
+}
+
+/// グループへの参加。同じグループへの参加は数えて、最後の脱退で報告する
+struct IgmpMembership {
+    dev: String,
+    interface: Ipv4Address,
+    group: Ipv4Address,
+    users: usize,
+    /// Query への応答を送る時刻
+    report_at: Option<Instant>,
+    /// Group-and-Source-Specific Query で尋ねられた送信元
+    query_sources: Vec<Ipv4Address>,
+    /// 参加直後に繰り返し送る報告の残り
+    unsolicited: u32,
+    next_unsolicited: Instant,
+    /// IGMPv2 で最後に報告したのが自分なら脱退を知らせる (RFC 2236 3)
+    last_reporter: bool,
+}
+
+/// 古い版のルータが居る間はその版で振る舞う (RFC 3376 7.2.1)
+struct IgmpCompatibility {
+    dev: String,
+    version: IgmpVersion,
+    until: Instant,
+}
+
+lazy_static! {
+    static ref MEMBERSHIPS: Mutex<Vec<IgmpMembership>> = Mutex::new(Vec::new());
+    static ref COMPATIBILITY: Mutex<Vec<IgmpCompatibility>> = Mutex::new(Vec::new());
+}
+
+fn compatibility_mode(dev: &str) -> IgmpVersion {
+    let now = Instant::now();
+    COMPATIBILITY
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry.dev == dev && entry.until > now)
+        .map(|entry| entry.version)
+        .min_by_key(|version| *version as u8)
+        .unwrap_or(IgmpVersion::V3)
+}
+
+fn set_compatibility_mode(dev: &str, version: IgmpVersion) {
+    let timeout = match version {
+        IgmpVersion::V1 => IGMPV1_ROUTER_PRESENT_TIMEOUT,
+        IgmpVersion::V2 => IGMP_QUERY_INTERVAL * IGMP_ROBUSTNESS + IGMP_QUERY_RESPONSE_INTERVAL,
+        IgmpVersion::V3 => return,
+    };
+    let mut entries = COMPATIBILITY.lock().unwrap();
+    entries.retain(|entry| !(entry.dev == dev && entry.version == version));
+    entries.push(IgmpCompatibility {
+        dev: dev.to_string(),
+        version,
+        until: Instant::now() + timeout,
+    });
+}
+
+/// 0 から max までの待ち時間を選ぶ
+fn random_delay(max: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    let max = max.as_millis() as u64;
+    if max == 0 {
+        return Duration::from_millis(0);
+    }
+    Duration::from_millis(nanos as u64 % max)
+}
+
+/// interface のアドレスを持つデバイスで group に参加する
+/// interface が 0.0.0.0 なら group への送信に使うインターフェースを選ぶ
+pub fn join(group: Ipv4Address, interface: Ipv4Address) -> Result<(), IgmpError> {
+    if !group.is_multicast() {
+        return Err(IgmpError::new(IgmpErrorKind::InvalidGroup));
+    }
+    let ip_interface = if interface.is_unspecified() {
+        IpInterface::select_source(group)
+    } else {
+        IpInterface::select(interface)
+    };
+    let ip_interface = ip_interface.ok_or_else(|| IgmpError::new(IgmpErrorKind::NoInterface))?;
+    let dev = ip_interface
+        .device()
+        .ok_or_else(|| IgmpError::new(IgmpErrorKind::NoInterface))?;
+    {
+        let mut memberships = MEMBERSHIPS.lock().unwrap();
+        if let Some(entry) = memberships
+            .iter_mut()
+            .find(|entry| entry.dev == dev.name && entry.group == group)
+        {
+            entry.users += 1;
+            return Ok(());
+        }
+        let now = Instant::now();
+        memberships.push(IgmpMembership {
+            dev: dev.name.clone(),
+            interface: ip_interface.unicast,
+            group,
+            users: 1,
+            report_at: None,
+            query_sources: Vec::new(),
+            unsolicited: IGMP_ROBUSTNESS - 1,
+            next_unsolicited: now + unsolicited_interval(compatibility_mode(&dev.name)),
+            last_reporter: true,
+        });
+    }
+    ethernet::multicast_filter_add(&dev.name, ethernet::ipv4_multicast_mac(group));
+    println!("IGMP join DEV={} GROUP={}", dev.name, group);
+    if group != IGMP_ALL_SYSTEMS {
+        send_change(dev, ip_interface.unicast, group, true);
+    }
+    Ok(())
+}
+
+pub fn leave(group: Ipv4Address, interface: Ipv4Address) -> Result<(), IgmpError> {
+    let ip_interface = if interface.is_unspecified() {
+        IpInterface::select_source(group)
+    } else {
+        IpInterface::select(interface)
+    };
+    let ip_interface = ip_interface.ok_or_else(|| IgmpError::new(IgmpErrorKind::NoInterface))?;
+    let dev = ip_interface
+        .device()
+        .ok_or_else(|| IgmpError::new(IgmpErrorKind::NoInterface))?;
+    let last_reporter = {
+        let mut memberships = MEMBERSHIPS.lock().unwrap();
+        let position = memberships
+            .iter()
+            .position(|entry| entry.dev == dev.name && entry.group == group)
+            .ok_or_else(|| IgmpError::new(IgmpErrorKind::NotMember))?;
+        memberships[position].users -= 1;
+        if memberships[position].users > 0 {
+            return Ok(());
+        }
+        memberships.remove(position).last_reporter
+    };
+    ethernet::multicast_filter_remove(&dev.name, ethernet::ipv4_multicast_mac(group));
+    println!("IGMP leave DEV={} GROUP={}", dev.name, group);
+    if group == IGMP_ALL_SYSTEMS {
+        return Ok(());
+    }
+    match compatibility_mode(&dev.name) {
+        IgmpVersion::V1 => (),
+        IgmpVersion::V2 if !last_reporter => (),
+        IgmpVersion::V2 => {
+            let message = build(IGMP_TYPE_V2_LEAVE_GROUP, 0, group);
+            send(dev, ip_interface.unicast, IGMP_ALL_ROUTERS, &message);
+        }
+        IgmpVersion::V3 => send_change(dev, ip_interface.unicast, group, false),
+    }
+    Ok(())
+}
+
+/// dev で参加しているグループ
+pub fn groups(dev: &str) -> Vec<Ipv4Address> {
+    MEMBERSHIPS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry.dev == dev)
+        .map(|entry| entry.group)
+        .collect()
+}
+
+/// dev で group 宛てのパケットを受け取るか。224.0.0.1 は常に受け取る
+pub fn accepts(dev: &NetDevice, group: Ipv4Address) -> bool {
+    if !group.is_multicast() {
+        return false;
+    }
+    if group == IGMP_ALL_SYSTEMS {
+        return true;
+    }
+    MEMBERSHIPS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|entry| entry.dev == dev.name && entry.group == group)
+}
+
+fn unsolicited_interval(version: IgmpVersion) -> Duration {
+    match version {
+        IgmpVersion::V3 => IGMPV3_UNSOLICITED_REPORT_INTERVAL,
+        _ => IGMPV2_UNSOLICITED_REPORT_INTERVAL,
+    }
+}
+
+/// IGMP のメッセージは TTL 1 で Router Alert オプションを付けて送る (RFC 2236 2、RFC 3376 4)
+fn send(dev: &NetDevice, src: Ipv4Address, dst: Ipv4Address, message: &[u8]) {
+    let _ = ipv4::output_device(
+        dev,
+        Protocol::Igmp as u8,
+        message,
+        src,
+        dst,
+        &[Ipv4Option::RouterAlert(0)],
+        0,
+    );
+}
+
+/// 参加や脱退で状態が変わったことを知らせる
+fn send_change(dev: &NetDevice, src: Ipv4Address, group: Ipv4Address, join: bool) {
+    match compatibility_mode(&dev.name) {
+        IgmpVersion::V3 => {
+            // 送信元を絞らない参加は EXCLUDE {}、脱退は INCLUDE {}
+            let record_type = if join {
+                IgmpRecordType::ChangeToExclude
+            } else {
+                IgmpRecordType::ChangeToInclude
+            };
+            let message = build_v3_report(&[IgmpGroupRecord {
+                record_type,
+                group,
+                sources: Vec::new(),
+            }]);
+            send(dev, src, IGMPV3_ROUTERS, &message);
+        }
+        version => send_report(dev, src, group, version),
+    }
+}
+
+fn send_report(dev: &NetDevice, src: Ipv4Address, group: Ipv4Address, version: IgmpVersion) {
+    let message_type = match version {
+        IgmpVersion::V1 => IGMP_TYPE_V1_MEMBERSHIP_REPORT,
+        _ => IGMP_TYPE_V2_MEMBERSHIP_REPORT,
+    };
+    let message = build(message_type, 0, group);
+    send(dev, src, group, &message);
+}
+
+pub fn input(packet: &[u8], dev: &'static NetDevice) {
+    let ipv4_hdr = Ipv4Header::new_unchecked(packet);
+    let message = match IgmpMessage::new_checked(ipv4_hdr.payload()) {
+        Some(message) => message,
+        None => {
+            eprintln!("IGMP message too short");
+            return;
+        }
+    };
+    if !message.verify_checksum() {
+        eprintln!("IGMP checksum error");
+        return;
+    }
+    eprintln!(
+        "IGMP input DEV={} TYPE={:#04x} GROUP={} SRC={}",
+        dev.name,
+        message.message_type(),
+        message.group(),
+        ipv4_hdr.src_address()
+    );
+
+    match message.message_type() {
+        IGMP_TYPE_MEMBERSHIP_QUERY => query(&message, dev),
+        IGMP_TYPE_V1_MEMBERSHIP_REPORT | IGMP_TYPE_V2_MEMBERSHIP_REPORT => {
+            // 他のホストが先に報告したら自分の報告はやめる。IGMPv3 では抑制しない
+            if compatibility_mode(&dev.name) == IgmpVersion::V3 {
+                return;
+            }
+            let mut memberships = MEMBERSHIPS.lock().unwrap();
+            for entry in memberships.iter_mut() {
+                if entry.dev == dev.name && entry.group == message.group() {
+                    entry.report_at = None;
+                    entry.last_reporter = false;
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Query を受けたら、最大応答時間までのどこかで報告するよう予約する (RFC 3376 5.2)
+fn query<T: AsRef<[u8]>>(message: &IgmpMessage<T>, dev: &NetDevice) {
+    let version = message.query_version();
+    set_compatibility_mode(&dev.name, version);
+    let group = message.group();
+    if !group.is_unspecified() && !group.is_multicast() {
+        return;
+    }
+    let sources = message.sources();
+    let now = Instant::now();
+    let delay = random_delay(message.max_response_time());
+    let mut memberships = MEMBERSHIPS.lock().unwrap();
+    for entry in memberships.iter_mut() {
+        if entry.dev != dev.name || (!group.is_unspecified() && entry.group != group) {
+            continue;
+        }
+        let report_at = now + delay;
+        // すでに予約した応答の方が早ければそのままにする
+        if entry.report_at.is_some_and(|at| at <= report_at) {
+            if sources.is_empty() {
+                entry.query_sources.clear();
+            }
+            continue;
+        }
+        entry.report_at = Some(report_at);
+        entry.query_sources = sources.clone();
+    }
+}
+
+/// 予約した応答と参加直後の報告の繰り返しを送る
+fn timer() {
+    let now = Instant::now();
+    let mut due = Vec::new();
+    {
+        let mut memberships = MEMBERSHIPS.lock().unwrap();
+        for entry in memberships.iter_mut() {
+            if entry.group == IGMP_ALL_SYSTEMS {
+                continue;
+            }
+            if entry.report_at.is_some_and(|at| at <= now) {
+                entry.report_at = None;
+                entry.last_reporter = true;
+                due.push((
+                    entry.dev.clone(),
+                    entry.interface,
+                    entry.group,
+                    std::mem::take(&mut entry.query_sources),
+                    false,
+                ));
+            }
+            if entry.unsolicited > 0 && entry.next_unsolicited <= now {
+                entry.unsolicited -= 1;
+                entry.next_unsolicited = now + unsolicited_interval(compatibility_mode(&entry.dev));
+                due.push((
+                    entry.dev.clone(),
+                    entry.interface,
+                    entry.group,
+                    Vec::new(),
+                    true,
+                ));
+            }
+        }
+    }
+    COMPATIBILITY
+        .lock()
+        .unwrap()
+        .retain(|entry| entry.until > now);
+
+    for (dev, src, group, sources, unsolicited) in due {
+        let dev = match device_by_name(&dev) {
+            Some(dev) => dev,
+            None => continue,
+        };
+        let version = compatibility_mode(&dev.name);
+        if unsolicited {
+            send_change(dev, src, group, true);
+            continue;
+        }
+        match version {
+            IgmpVersion::V3 => {
+                // 送信元を絞らずに参加しているので、尋ねられた送信元はすべて受け取る
+                let record = if sources.is_empty() {
+                    IgmpGroupRecord {
+                        record_type: IgmpRecordType::ModeIsExclude,
+                        group,
+                        sources: Vec::new(),
+                    }
+                } else {
+                    IgmpGroupRecord {
+                        record_type: IgmpRecordType::ModeIsInclude,
+                        group,
+                        sources,
+                    }
+                };
+                send(dev, src, IGMPV3_ROUTERS, &build_v3_report(&[record]));
+            }
+            version => send_report(dev, src, group, version),
+        }
+    }
+}
+
+/// デバイスのアドレスが外されたら、そのアドレスで参加していたグループを別のアドレスに移す
+pub fn interface_removed(dev: &NetDevice, unicast: Ipv4Address) {
+    let replacement = dev
+        .get_interfaces(NetInterfaceFamily::Ip)
+        .into_iter()
+        .find_map(|interface| match interface {
+            NetInterfaceType::Ip(interface) => Some(interface.unicast),
+            _ => None,
+        })
+        .unwrap_or(IP_ADDRESS_ANY);
+    let mut memberships = MEMBERSHIPS.lock().unwrap();
+    for entry in memberships.iter_mut() {
+        if entry.dev == dev.name && entry.interface == unicast {
+            entry.interface = replacement;
+        }
+    }
+}
+
+pub fn init() {
+    if ipv4::protocol_register(Protocol::Igmp as u8, input).is_err() {
+        eprintln!("IGMP is already registered");
+        return;
+    }
+    NetTimer::register(Duration::from_millis(100), timer);
+}
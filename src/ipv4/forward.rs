@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::option::{self, Ipv4Option};
+use super::{route, transmit, Ipv4Address, Ipv4ErrorKind, Ipv4Header, IP_HEADER_SIZE_MIN};
+use crate::icmp::{self, TimeExceededCode, UnreachableCode};
+use crate::net::NetDevice;
+
+static FORWARDING: AtomicBool = AtomicBool::new(false);
+
+/// 自分宛てでないパケットを他のデバイスへ転送するかどうか (既定は無効)
+pub fn set_forwarding(enable: bool) {
+    FORWARDING.store(enable, Ordering::Release);
+}
+
+pub fn is_forwarding() -> bool {
+    FORWARDING.load(Ordering::Acquire)
+}
+
+/// 自分宛てでないパケットを経路表に従って転送する
+pub fn forward(data: &[u8], dev: &'static NetDevice) {
+    let ipv4_hdr = Ipv4Header::new_unchecked(data);
+    let src = ipv4_hdr.src_address();
+    let dst = ipv4_hdr.dst_address();
+    if dst.is_broadcast() || dst.is_multicast() || dst.is_unspecified() || src.is_broadcast() {
+        return;
+    }
+    if ipv4_hdr.time_to_live() <= 1 {
+        eprintln!(
+            "Time exceeded (TTL={}) SRC={} DST={}",
+            ipv4_hdr.time_to_live(),
+            src,
+            dst
+        );
+        icmp::time_exceeded(TimeExceededCode::TimeToLive, data);
+        return;
+    }
+
+    let route = match route::lookup(dst) {
+        Some(route) => route,
+        None => {
+            eprintln!("no route to host DST={}", dst);
+            icmp::destination_unreachable(UnreachableCode::Net, data);
+            return;
+        }
+    };
+    let out_dev = match route.interface.device() {
+        Some(out_dev) => out_dev,
+        None => {
+            eprintln!("device not found ADDR={}", route.interface.unicast);
+            icmp::destination_unreachable(UnreachableCode::Host, data);
+            return;
+        }
+    };
+
+    let mut packet = data.to_vec();
+    {
+        let mut header = Ipv4Header::new_unchecked(&mut packet[..]);
+        if header.header_length() > IP_HEADER_SIZE_MIN as u8 {
+            if let Err(e) = forward_options(&mut header, route.interface.unicast) {
+                eprintln!("IP option error: {} OFFSET={}", e.kind, e.offset);
+                icmp::parameter_problem((IP_HEADER_SIZE_MIN as usize + e.offset) as u8, data);
+                return;
+            }
+        }
+        header.set_time_to_live(header.time_to_live() - 1);
+        header.fill_checksum();
+    }
+
+    let next_hop = route.gateway_for(dst);
+    eprintln!(
+        "IP forward DEV={} -> DEV={} SRC={} DST={} NEXTHOP={} TOTAL={}",
+        dev.name,
+        out_dev.name,
+        src,
+        dst,
+        next_hop,
+        packet.len()
+    );
+    if let Err(e) = transmit(out_dev, &packet, next_hop, out_dev.mtu) {
+        if e.kind == Ipv4ErrorKind::TooLong {
+            icmp::fragmentation_needed(out_dev.mtu, data);
+        }
+    }
+}
+
+/// 自分宛てだがソースルートに経由地が残っているパケットを次の経由地へ転送する
+pub fn source_route(data: &[u8], dev: &'static NetDevice) {
+    let ipv4_hdr = Ipv4Header::new_unchecked(data);
+    let mut options = match option::parse(ipv4_hdr.options()) {
+        Ok(options) => options,
+        Err(_) => return,
+    };
+    let mut next = None;
+    let mut strict = false;
+    for option in options.iter_mut() {
+        let slot = match option.next_route_slot() {
+            Some(slot) => slot,
+            None => continue,
+        };
+        let hop = match option {
+            Ipv4Option::LooseSourceRoute { route, .. } => route[slot],
+            Ipv4Option::StrictSourceRoute { route, .. } => {
+                strict = true;
+                route[slot]
+            }
+            _ => continue,
+        };
+        // 辿った経由地の枠には送出するインターフェースのアドレスを記録する
+        let out = match route::lookup(hop) {
+            Some(out) => out,
+            None => {
+                eprintln!("no route to source route hop HOP={}", hop);
+                icmp::destination_unreachable(UnreachableCode::SourceRouteFailed, data);
+                return;
+            }
+        };
+        if strict && out.nexthop.is_some() {
+            eprintln!("strict source route hop is not on-link HOP={}", hop);
+            icmp::destination_unreachable(UnreachableCode::SourceRouteFailed, data);
+            return;
+        }
+        option.record(out.interface.unicast);
+        next = Some(hop);
+    }
+    let next: Ipv4Address = match next {
+        Some(next) => next,
+        None => return,
+    };
+
+    let mut packet = data.to_vec();
+    {
+        let encoded = option::emit(&options);
+        let mut header = Ipv4Header::new_unchecked(&mut packet[..]);
+        header.options_mut()[..encoded.len()].copy_from_slice(&encoded);
+        header.set_dst_address(next);
+        header.fill_checksum();
+    }
+    forward(&packet, dev);
+}
+
+/// 転送時のオプション処理。Record Route と Timestamp に送出インターフェースを記録する
+fn forward_options(
+    header: &mut Ipv4Header<&mut [u8]>,
+    address: Ipv4Address,
+) -> Result<(), option::Ipv4OptionError> {
+    let mut options = option::parse(header.options())?;
+    for option in options.iter_mut() {
+        match option {
+            Ipv4Option::RecordRoute { .. } => {
+                option.record(address);
+            }
+            Ipv4Option::Timestamp { .. } => {
+                option.stamp(address, option::timestamp_now());
+            }
+            _ => (),
+        }
+    }
+    let encoded = option::emit(&options);
+    header.options_mut()[..encoded.len()].copy_from_slice(&encoded);
+    Ok(())
+}
@@ -0,0 +1,394 @@
+use std::fmt;
+
+use super::{Ipv4Address, Protocol, IPV4_ADDRESS_SIZE, IP_HEADER_SIZE_MAX, IP_HEADER_SIZE_MIN};
+use super::{IP_TOTAL_SIZE_MAX, IP_VERSION_IPV4};
+use crate::utils::checksum;
+
+mod field {
+    use std::ops::Range;
+
+    pub const VHL: usize = 0;
+    pub const TOS: usize = 1;
+    pub const TOTAL_LENGTH: Range<usize> = 2..4;
+    pub const ID: Range<usize> = 4..6;
+    pub const FLAGS_OFFSET: Range<usize> = 6..8;
+    pub const TTL: usize = 8;
+    pub const PROTOCOL: usize = 9;
+    pub const CHECKSUM: Range<usize> = 10..12;
+    pub const SRC: Range<usize> = 12..16;
+    pub const DST: Range<usize> = 16..20;
+}
+
+/// Parameter Problem で全長フィールドを指すときのオフセット
+pub const TOTAL_LENGTH_OFFSET: usize = field::TOTAL_LENGTH.start;
+
+pub const IP_FLAG_MF: u16 = 0b001;
+pub const IP_FLAG_DF: u16 = 0b010;
+
+pub const IP_TTL_DEFAULT: u8 = 64;
+/// マルチキャストの既定の TTL
+pub const IP_MULTICAST_TTL_DEFAULT: u8 = 1;
+
+#[derive(Debug)]
+pub struct Ipv4HeaderError {
+    pub kind: Ipv4HeaderErrorKind,
+}
+
+impl Ipv4HeaderError {
+    pub fn new(kind: Ipv4HeaderErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ipv4HeaderErrorKind {
+    TooShort,
+    Version,
+    HeaderLength,
+    TotalLength,
+    Checksum,
+}
+
+impl fmt::Display for Ipv4HeaderErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Ipv4HeaderErrorKind::TooShort => "header too short",
+            Ipv4HeaderErrorKind::Version => "version error",
+            Ipv4HeaderErrorKind::HeaderLength => "header length error",
+            Ipv4HeaderErrorKind::TotalLength => "total length error",
+            Ipv4HeaderErrorKind::Checksum => "checksum error",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// バイト列の上に被せて IPv4 ヘッダを読み書きするビュー
+/// 多バイトのフィールドはすべてネットワークバイトオーダーとして扱う
+#[derive(Debug, Clone)]
+pub struct Ipv4Header<T: AsRef<[u8]>> {
+    buffer: T,
+}
+
+impl<T: AsRef<[u8]>> Ipv4Header<T> {
+    /// 長さを検査せずに被せる
+    /// 固定長部分 (20 バイト) に満たないバッファのフィールドを読むと panic する
+    pub fn new_unchecked(buffer: T) -> Self {
+        Ipv4Header { buffer }
+    }
+
+    /// 長さ、バージョン、ヘッダ長、全長を検査してから被せる
+    /// チェックサムは検査しないので必要なら verify_checksum を呼ぶ
+    pub fn new_checked(buffer: T) -> Result<Self, Ipv4HeaderError> {
+        let header = Ipv4Header::new_unchecked(buffer);
+        header.check()?;
+        Ok(header)
+    }
+
+    fn check(&self) -> Result<(), Ipv4HeaderError> {
+        let len = self.buffer.as_ref().len();
+        if len < IP_HEADER_SIZE_MIN as usize {
+            return Err(Ipv4HeaderError::new(Ipv4HeaderErrorKind::TooShort));
+        }
+        if self.version() != IP_VERSION_IPV4 {
+            return Err(Ipv4HeaderError::new(Ipv4HeaderErrorKind::Version));
+        }
+        let header_length = self.header_length() as usize;
+        if header_length < IP_HEADER_SIZE_MIN as usize || len < header_length {
+            return Err(Ipv4HeaderError::new(Ipv4HeaderErrorKind::HeaderLength));
+        }
+        let total_length = self.total_length() as usize;
+        if total_length < header_length || len < total_length {
+            return Err(Ipv4HeaderError::new(Ipv4HeaderErrorKind::TotalLength));
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.buffer.as_ref()
+    }
+
+    pub fn vhl(&self) -> u8 {
+        self.buffer.as_ref()[field::VHL]
+    }
+
+    pub fn version(&self) -> u8 {
+        self.vhl() >> 4
+    }
+
+    /// ヘッダ長 (バイト単位)
+    pub fn header_length(&self) -> u8 {
+        (self.vhl() & 0b1111) << 2
+    }
+
+    pub fn type_of_service(&self) -> u8 {
+        self.buffer.as_ref()[field::TOS]
+    }
+
+    pub fn dscp(&self) -> u8 {
+        self.type_of_service() >> 2
+    }
+
+    pub fn ecn(&self) -> u8 {
+        self.type_of_service() & 0b11
+    }
+
+    pub fn total_length(&self) -> u16 {
+        self.read_u16(field::TOTAL_LENGTH.start)
+    }
+
+    pub fn id(&self) -> u16 {
+        self.read_u16(field::ID.start)
+    }
+
+    pub fn flags(&self) -> u16 {
+        self.read_u16(field::FLAGS_OFFSET.start) >> 13
+    }
+
+    pub fn dont_fragment(&self) -> bool {
+        self.flags() & IP_FLAG_DF > 0
+    }
+
+    pub fn more_fragments(&self) -> bool {
+        self.flags() & IP_FLAG_MF > 0
+    }
+
+    /// フラグメントオフセット (8 バイト単位)
+    pub fn offset(&self) -> u16 {
+        self.read_u16(field::FLAGS_OFFSET.start) & 0b0001_1111_1111_1111
+    }
+
+    pub fn time_to_live(&self) -> u8 {
+        self.buffer.as_ref()[field::TTL]
+    }
+
+    pub fn protocol_number(&self) -> u8 {
+        self.buffer.as_ref()[field::PROTOCOL]
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        Protocol::from_u8(self.protocol_number())
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.read_u16(field::CHECKSUM.start)
+    }
+
+    pub fn src_address(&self) -> Ipv4Address {
+        self.read_address(field::SRC.start)
+    }
+
+    pub fn dst_address(&self) -> Ipv4Address {
+        self.read_address(field::DST.start)
+    }
+
+    pub fn verify_checksum(&self) -> bool {
+        checksum(self.header_bytes(), 0) == 0
+    }
+
+    /// 固定長部分を含むヘッダ全体
+    pub fn header_bytes(&self) -> &[u8] {
+        &self.buffer.as_ref()[..self.header_length() as usize]
+    }
+
+    /// 固定長部分より後ろのオプション領域
+    pub fn options(&self) -> &[u8] {
+        &self.buffer.as_ref()[IP_HEADER_SIZE_MIN as usize..self.header_length() as usize]
+    }
+
+    /// ヘッダの直後から全長までのペイロード
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[self.header_length() as usize..self.total_length() as usize]
+    }
+
+    fn read_u16(&self, index: usize) -> u16 {
+        let data = self.buffer.as_ref();
+        u16::from_be_bytes([data[index], data[index + 1]])
+    }
+
+    fn read_address(&self, index: usize) -> Ipv4Address {
+        let mut octets = [0; IPV4_ADDRESS_SIZE];
+        octets.copy_from_slice(&self.buffer.as_ref()[index..index + IPV4_ADDRESS_SIZE]);
+        Ipv4Address::from(octets)
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> Ipv4Header<T> {
+    pub fn set_version(&mut self, version: u8) {
+        let data = self.buffer.as_mut();
+        data[field::VHL] = (data[field::VHL] & 0b1111) | (version << 4);
+    }
+
+    /// ヘッダ長 (バイト単位) を設定する。4 の倍数でなければならない
+    pub fn set_header_length(&mut self, length: u8) {
+        let data = self.buffer.as_mut();
+        data[field::VHL] = (data[field::VHL] & 0b1111_0000) | ((length >> 2) & 0b1111);
+    }
+
+    pub fn set_type_of_service(&mut self, tos: u8) {
+        self.buffer.as_mut()[field::TOS] = tos;
+    }
+
+    pub fn set_total_length(&mut self, length: u16) {
+        self.write_u16(field::TOTAL_LENGTH.start, length);
+    }
+
+    pub fn set_id(&mut self, id: u16) {
+        self.write_u16(field::ID.start, id);
+    }
+
+    pub fn set_flags(&mut self, flags: u16) {
+        let value = (flags << 13) | self.offset();
+        self.write_u16(field::FLAGS_OFFSET.start, value);
+    }
+
+    pub fn set_offset(&mut self, offset: u16) {
+        let value = (self.flags() << 13) | (offset & 0b0001_1111_1111_1111);
+        self.write_u16(field::FLAGS_OFFSET.start, value);
+    }
+
+    pub fn set_time_to_live(&mut self, ttl: u8) {
+        self.buffer.as_mut()[field::TTL] = ttl;
+    }
+
+    pub fn set_protocol(&mut self, protocol: u8) {
+        self.buffer.as_mut()[field::PROTOCOL] = protocol;
+    }
+
+    pub fn set_checksum(&mut self, sum: u16) {
+        self.write_u16(field::CHECKSUM.start, sum);
+    }
+
+    pub fn set_src_address(&mut self, address: Ipv4Address) {
+        self.buffer.as_mut()[field::SRC].copy_from_slice(&address.octets());
+    }
+
+    pub fn set_dst_address(&mut self, address: Ipv4Address) {
+        self.buffer.as_mut()[field::DST].copy_from_slice(&address.octets());
+    }
+
+    /// チェックサムフィールドを 0 にしてから計算し直して書き込む
+    pub fn fill_checksum(&mut self) {
+        self.set_checksum(0);
+        let sum = checksum(self.header_bytes(), 0);
+        self.set_checksum(sum);
+    }
+
+    pub fn options_mut(&mut self) -> &mut [u8] {
+        let header_length = self.header_length() as usize;
+        &mut self.buffer.as_mut()[IP_HEADER_SIZE_MIN as usize..header_length]
+    }
+
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let header_length = self.header_length() as usize;
+        let total_length = self.total_length() as usize;
+        &mut self.buffer.as_mut()[header_length..total_length]
+    }
+
+    fn write_u16(&mut self, index: usize, value: u16) {
+        self.buffer.as_mut()[index..index + 2].copy_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// 送信するパケットを組み立てる
+#[derive(Debug, Clone)]
+pub struct Ipv4HeaderBuilder {
+    tos: u8,
+    id: u16,
+    flags: u16,
+    offset: u16,
+    ttl: u8,
+    protocol: u8,
+    src: Ipv4Address,
+    dst: Ipv4Address,
+    options: Vec<u8>,
+}
+
+impl Ipv4HeaderBuilder {
+    pub fn new(protocol: u8, src: Ipv4Address, dst: Ipv4Address) -> Self {
+        Ipv4HeaderBuilder {
+            tos: 0,
+            id: 0,
+            flags: 0,
+            offset: 0,
+            ttl: IP_TTL_DEFAULT,
+            protocol,
+            src,
+            dst,
+            options: Vec::new(),
+        }
+    }
+
+    pub fn type_of_service(mut self, tos: u8) -> Self {
+        self.tos = tos;
+        self
+    }
+
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn flags(mut self, flags: u16) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn offset(mut self, offset: u16) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn time_to_live(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// エンコード済みのオプション。4 バイト境界に揃うよう End of Option List で埋める
+    pub fn options(mut self, options: &[u8]) -> Self {
+        self.options = options.to_vec();
+        while !self.options.len().is_multiple_of(4) {
+            self.options.push(0);
+        }
+        self
+    }
+
+    pub fn header_length(&self) -> usize {
+        IP_HEADER_SIZE_MIN as usize + self.options.len()
+    }
+
+    /// ヘッダとペイロードを連結し、チェックサムを埋めたパケットを返す
+    pub fn build(&self, payload: &[u8]) -> Result<Vec<u8>, Ipv4HeaderError> {
+        let header_length = self.header_length();
+        if header_length > IP_HEADER_SIZE_MAX as usize {
+            return Err(Ipv4HeaderError::new(Ipv4HeaderErrorKind::HeaderLength));
+        }
+        let total_length = header_length + payload.len();
+        if total_length > IP_TOTAL_SIZE_MAX as usize {
+            return Err(Ipv4HeaderError::new(Ipv4HeaderErrorKind::TotalLength));
+        }
+
+        let mut packet = vec![0; total_length];
+        packet[IP_HEADER_SIZE_MIN as usize..header_length].copy_from_slice(&self.options);
+        packet[header_length..].copy_from_slice(payload);
+        {
+            let mut header = Ipv4Header::new_unchecked(&mut packet[..]);
+            header.set_version(IP_VERSION_IPV4);
+            header.set_header_length(header_length as u8);
+            header.set_type_of_service(self.tos);
+            header.set_total_length(total_length as u16);
+            header.set_id(self.id);
+            header.set_flags(self.flags);
+            header.set_offset(self.offset);
+            header.set_time_to_live(self.ttl);
+            header.set_protocol(self.protocol);
+            header.set_src_address(self.src);
+            header.set_dst_address(self.dst);
+            header.fill_checksum();
+        }
+        Ok(packet)
+    }
+}
@@ -0,0 +1,1210 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::net::{AddrParseError, Ipv4Addr, SocketAddrV4};
+use std::ptr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{io, io::Write};
+
+use crate::ethernet::{self, MacAddress, MAC_LENGTH};
+use crate::icmp::{self, TimeExceededCode, UnreachableCode};
+use crate::igmp;
+use crate::net::{
+    NetDevice, NetDeviceErrorKind, NetDeviceFlag, NetInterface, NetInterfaceFamily,
+    NetInterfaceType, NetProtocol, NetProtocolErrorKind, NetProtocolType, HARDWARE_ADDRESS_LENGTH,
+    NET_DEVICES,
+};
+
+pub mod forward;
+pub mod header;
+pub mod neighbor;
+pub mod option;
+pub mod pmtu;
+pub mod raw;
+pub mod route;
+
+pub use forward::{is_forwarding, set_forwarding};
+pub use header::{
+    Ipv4Header, Ipv4HeaderBuilder, Ipv4HeaderError, Ipv4HeaderErrorKind, IP_FLAG_DF, IP_FLAG_MF,
+    IP_MULTICAST_TTL_DEFAULT, IP_TTL_DEFAULT,
+};
+pub use option::{Ipv4Option, Ipv4OptionError, Ipv4OptionErrorKind};
+
+pub const IP_HEADER_SIZE_MIN: u16 = 20;
+pub const IP_HEADER_SIZE_MAX: u16 = 60;
+
+pub const IP_VERSION_IPV4: u8 = 4;
+
+pub const IP_TOTAL_SIZE_MAX: u16 = u16::MAX;
+pub const IP_PAYLOAD_SIZE_MAX: u16 = IP_TOTAL_SIZE_MAX - IP_HEADER_SIZE_MIN;
+
+pub const IPV4_ADDRESS_SIZE: usize = 4;
+pub const IPV4_PREFIX_LENGTH_MAX: u8 = 32;
+
+/// ネットワークバイトオーダーで保持する IPv4 アドレス
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ipv4Address([u8; IPV4_ADDRESS_SIZE]);
+
+pub const IP_ADDRESS_ANY: Ipv4Address = Ipv4Address([0; IPV4_ADDRESS_SIZE]);
+pub const IP_ADDRESS_BROADCAST: Ipv4Address = Ipv4Address([255; IPV4_ADDRESS_SIZE]);
+
+impl fmt::Display for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+impl FromStr for Ipv4Address {
+    type Err = AddrParseError;
+
+    fn from_str(address_str: &str) -> Result<Self, Self::Err> {
+        Ok(Ipv4Address::from(Ipv4Addr::from_str(address_str)?))
+    }
+}
+
+impl From<Ipv4Addr> for Ipv4Address {
+    fn from(addr: Ipv4Addr) -> Self {
+        Ipv4Address(addr.octets())
+    }
+}
+
+impl From<Ipv4Address> for Ipv4Addr {
+    fn from(addr: Ipv4Address) -> Self {
+        Ipv4Addr::from(addr.0)
+    }
+}
+
+impl From<[u8; IPV4_ADDRESS_SIZE]> for Ipv4Address {
+    fn from(octets: [u8; IPV4_ADDRESS_SIZE]) -> Self {
+        Ipv4Address(octets)
+    }
+}
+
+impl Ipv4Address {
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Ipv4Address([a, b, c, d])
+    }
+
+    /// ホストバイトオーダーの u32 (0x7f000001 が 127.0.0.1) から作る
+    pub fn from_u32(u: u32) -> Self {
+        Ipv4Address(u.to_be_bytes())
+    }
+
+    /// ホストバイトオーダーの u32 に変換する
+    pub fn to_u32(&self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub fn octets(&self) -> [u8; IPV4_ADDRESS_SIZE] {
+        self.0
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == IP_ADDRESS_ANY
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == IP_ADDRESS_BROADCAST
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.0[0] == 127
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xf0 == 0xe0
+    }
+}
+
+#[derive(Debug)]
+pub struct Ipv4NetworkError {
+    pub kind: Ipv4NetworkErrorKind,
+}
+
+impl Ipv4NetworkError {
+    pub fn new(kind: Ipv4NetworkErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug)]
+pub enum Ipv4NetworkErrorKind {
+    InvalidAddress,
+    InvalidPrefix,
+    InvalidNetmask,
+}
+
+/// アドレスとプレフィックス長の組 (例: 192.0.2.2/24)
+/// アドレスはホスト部を含んだまま保持する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ipv4Network {
+    address: Ipv4Address,
+    prefix: u8,
+}
+
+impl Ipv4Network {
+    pub fn new(address: Ipv4Address, prefix: u8) -> Result<Self, Ipv4NetworkError> {
+        if prefix > IPV4_PREFIX_LENGTH_MAX {
+            return Err(Ipv4NetworkError::new(Ipv4NetworkErrorKind::InvalidPrefix));
+        }
+        Ok(Ipv4Network { address, prefix })
+    }
+
+    /// 255.255.255.0 のようなネットマスクから作る
+    /// 1 のビットが連続していないネットマスクはエラーにする
+    pub fn with_netmask(
+        address: Ipv4Address,
+        netmask: Ipv4Address,
+    ) -> Result<Self, Ipv4NetworkError> {
+        let mask = netmask.to_u32();
+        let prefix = mask.leading_ones();
+        if mask.checked_shl(prefix).unwrap_or(0) != 0 {
+            return Err(Ipv4NetworkError::new(Ipv4NetworkErrorKind::InvalidNetmask));
+        }
+        Ipv4Network::new(address, prefix as u8)
+    }
+
+    pub fn address(&self) -> Ipv4Address {
+        self.address
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn netmask(&self) -> Ipv4Address {
+        Ipv4Address::from_u32(self.mask())
+    }
+
+    pub fn network(&self) -> Ipv4Address {
+        Ipv4Address::from_u32(self.address.to_u32() & self.mask())
+    }
+
+    pub fn broadcast(&self) -> Ipv4Address {
+        Ipv4Address::from_u32(self.address.to_u32() | !self.mask())
+    }
+
+    pub fn contains(&self, address: Ipv4Address) -> bool {
+        (address.to_u32() & self.mask()) == self.network().to_u32()
+    }
+
+    /// ネットワークアドレスからブロードキャストアドレスまでのすべてのアドレス
+    pub fn iter(&self) -> Ipv4NetworkIter {
+        Ipv4NetworkIter {
+            next: Some(self.network().to_u32()),
+            last: self.broadcast().to_u32(),
+        }
+    }
+
+    /// ホストに割り当て可能なアドレス
+    /// /31 と /32 はネットワークアドレスとブロードキャストアドレスを区別しない
+    pub fn hosts(&self) -> Ipv4NetworkIter {
+        let mut iter = self.iter();
+        if self.prefix < IPV4_PREFIX_LENGTH_MAX - 1 {
+            iter.next = iter.next.map(|n| n + 1);
+            iter.last -= 1;
+        }
+        iter
+    }
+
+    fn mask(&self) -> u32 {
+        u32::MAX
+            .checked_shl((IPV4_PREFIX_LENGTH_MAX - self.prefix) as u32)
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Ipv4Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+impl FromStr for Ipv4Network {
+    type Err = Ipv4NetworkError;
+
+    fn from_str(network_str: &str) -> Result<Self, Self::Err> {
+        let mut split = network_str.splitn(2, '/');
+        let address = split
+            .next()
+            .and_then(|s| Ipv4Address::from_str(s).ok())
+            .ok_or_else(|| Ipv4NetworkError::new(Ipv4NetworkErrorKind::InvalidAddress))?;
+        let prefix = match split.next() {
+            Some(s) => s
+                .parse::<u8>()
+                .map_err(|_| Ipv4NetworkError::new(Ipv4NetworkErrorKind::InvalidPrefix))?,
+            None => IPV4_PREFIX_LENGTH_MAX,
+        };
+        Ipv4Network::new(address, prefix)
+    }
+}
+
+pub struct Ipv4NetworkIter {
+    next: Option<u32>,
+    last: u32,
+}
+
+impl Iterator for Ipv4NetworkIter {
+    type Item = Ipv4Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+        if current > self.last {
+            self.next = None;
+            return None;
+        }
+        self.next = current.checked_add(1);
+        Some(Ipv4Address::from_u32(current))
+    }
+}
+
+#[repr(u8)]
+pub enum Protocol {
+    Icmp = 1,
+    Igmp = 2,
+    Ip = 4,
+    Tcp = 6,
+    Udp = 17,
+    Unimplement,
+}
+
+impl Protocol {
+    pub fn from_u8(u: u8) -> Protocol {
+        match u {
+            1 => Protocol::Icmp,
+            2 => Protocol::Igmp,
+            4 => Protocol::Ip,
+            6 => Protocol::Tcp,
+            17 => Protocol::Udp,
+            _ => Protocol::Unimplement,
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Icmp => {
+                write!(f, "ICMP")
+            }
+            Protocol::Igmp => {
+                write!(f, "IGMP")
+            }
+            Protocol::Ip => {
+                write!(f, "IP")
+            }
+            Protocol::Tcp => {
+                write!(f, "TCP")
+            }
+            Protocol::Udp => {
+                write!(f, "UDP")
+            }
+            Protocol::Unimplement => {
+                write!(f, "Unimplement")
+            }
+        }
+    }
+}
+
+/// IPv4 アドレスとポート番号の組
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ipv4Endpoint {
+    pub address: Ipv4Address,
+    pub port: u16,
+}
+
+impl Ipv4Endpoint {
+    pub const fn new(address: Ipv4Address, port: u16) -> Self {
+        Ipv4Endpoint { address, port }
+    }
+}
+
+impl fmt::Display for Ipv4Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.address, self.port)
+    }
+}
+
+impl From<SocketAddrV4> for Ipv4Endpoint {
+    fn from(addr: SocketAddrV4) -> Self {
+        Ipv4Endpoint::new(Ipv4Address::from(*addr.ip()), addr.port())
+    }
+}
+
+impl From<Ipv4Endpoint> for SocketAddrV4 {
+    fn from(endpoint: Ipv4Endpoint) -> Self {
+        SocketAddrV4::new(Ipv4Addr::from(endpoint.address), endpoint.port)
+    }
+}
+
+pub struct LockableIpInterfaces {
+    pub items: Arc<Mutex<Vec<Box<IpInterface>>>>,
+}
+
+impl LockableIpInterfaces {
+    pub fn new() -> Self {
+        LockableIpInterfaces {
+            items: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn lock(&self) -> LockedIpInterfaces<'_> {
+        LockedIpInterfaces {
+            items: self.items.lock().unwrap(),
+        }
+    }
+}
+
+pub struct LockedIpInterfaces<'a> {
+    pub items: MutexGuard<'a, Vec<Box<IpInterface>>>,
+}
+
+impl<'a> LockedIpInterfaces<'a> {
+    fn iter(&self) -> impl Iterator<Item = &Box<IpInterface>> {
+        self.items.iter()
+    }
+
+    // fn iter_mut(&mut self) -> impl Iterator<Item = &mut Option<&'static IpInterface>> {
+    //     self.items.iter_mut()
+    // }
+}
+
+lazy_static! {
+    pub static ref IP_INTERFACES: LockableIpInterfaces = LockableIpInterfaces::new();
+}
+
+#[derive(Clone)]
+pub struct IpInterface {
+    pub net_interface: NetInterface,
+    pub unicast: Ipv4Address,
+    pub netmask: Ipv4Address,
+    pub broadcast: Ipv4Address,
+    /// サブネットのブロードキャスト宛て (directed broadcast) を受け取るか
+    pub directed_broadcast: bool,
+}
+
+impl IpInterface {
+    pub fn alloc(unicast: &str, netmask: &str) -> Option<Box<Self>> {
+        let mut interface = IpInterface::default();
+        interface.net_interface.family = NetInterfaceFamily::Ip;
+        if let Ok(addr) = Ipv4Address::from_str(unicast) {
+            interface.unicast = addr;
+        } else {
+            eprintln!("Invalid unicast IP address");
+            return None;
+        }
+
+        if let Ok(addr) = Ipv4Address::from_str(netmask) {
+            interface.netmask = addr;
+        } else {
+            eprintln!("Invalid netmask");
+            return None;
+        }
+
+        if let Ok(network) = Ipv4Network::with_netmask(interface.unicast, interface.netmask) {
+            interface.broadcast = network.broadcast();
+        } else {
+            eprintln!("Invalid netmask");
+            return None;
+        }
+
+        Option::from(Box::new(interface))
+    }
+
+    /// 192.0.2.2/24 のような CIDR 表記から作る
+    pub fn alloc_cidr(network: &str) -> Option<Box<Self>> {
+        let network = match Ipv4Network::from_str(network) {
+            Ok(network) => network,
+            Err(_) => {
+                eprintln!("Invalid network");
+                return None;
+            }
+        };
+        Option::from(IpInterface::from_network(network))
+    }
+
+    /// DHCP などで得た Ipv4Network から作る
+    pub fn from_network(network: Ipv4Network) -> Box<Self> {
+        let mut interface = IpInterface::default();
+        interface.net_interface.family = NetInterfaceFamily::Ip;
+        interface.unicast = network.address();
+        interface.netmask = network.netmask();
+        interface.broadcast = network.broadcast();
+
+        Box::new(interface)
+    }
+
+    pub fn register(ip_interface: Box<Self>, dev: &NetDevice) -> Result<(), ()> {
+        let iface = ip_interface.clone();
+        if let Err(e) = dev.add_interface(NetInterfaceType::Ip(*ip_interface)) {
+            match e.kind {
+                NetDeviceErrorKind::AlreadyRegistered => {
+                    return Ok(());
+                }
+                _ => {
+                    eprintln!("add interface is failed");
+                    return Err(());
+                }
+            }
+        }
+        {
+            let mut interfaces = IP_INTERFACES.lock();
+            interfaces.items.push(iface);
+        }
+
+        Ok(())
+    }
+
+    /// デバイスから unicast のインターフェースを取り除く
+    pub fn unregister(unicast: Ipv4Address, dev: &NetDevice) -> Result<(), Ipv4Error> {
+        if dev.remove_interface(unicast).is_none() {
+            eprintln!("interface not found DEV={} ADDR={}", dev.name, unicast);
+            return Err(Ipv4Error::new(Ipv4ErrorKind::NoInterface));
+        }
+        {
+            let mut interfaces = IP_INTERFACES.lock();
+            interfaces.items.retain(|entry| entry.unicast != unicast);
+        }
+        igmp::interface_removed(dev, unicast);
+
+        Ok(())
+    }
+
+    /// 登録済みのインターフェースで directed broadcast を受け取るかを切り替える
+    pub fn set_directed_broadcast(unicast: Ipv4Address, accept: bool) -> Result<(), Ipv4Error> {
+        let dev = {
+            let mut interfaces = IP_INTERFACES.lock();
+            let entry = match interfaces
+                .items
+                .iter_mut()
+                .find(|entry| entry.unicast == unicast)
+            {
+                Some(entry) => entry,
+                None => {
+                    eprintln!("interface not found ADDR={}", unicast);
+                    return Err(Ipv4Error::new(Ipv4ErrorKind::NoInterface));
+                }
+            };
+            entry.directed_broadcast = accept;
+            entry.device()
+        };
+        // デバイスが持っている複製も揃える
+        if let Some(dev) = dev {
+            for interface in dev.interfaces.lock().unwrap().iter_mut() {
+                if let NetInterfaceType::Ip(ip_interface) = &mut **interface {
+                    if ip_interface.unicast == unicast {
+                        ip_interface.directed_broadcast = accept;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 他のホストのアドレスと重なる /31 と /32 にはブロードキャストアドレスが無い (RFC 3021)
+    fn has_broadcast(&self) -> bool {
+        self.network().prefix() < IPV4_PREFIX_LENGTH_MAX - 1
+    }
+
+    pub fn select(address: Ipv4Address) -> Option<Box<IpInterface>> {
+        let interfaces = IP_INTERFACES.lock();
+        for entry in interfaces.iter() {
+            if entry.unicast == address {
+                return Some((*entry).clone());
+            }
+        }
+        None
+    }
+
+    /// 宛先と同じサブネットにあるインターフェースのうちプレフィックスが最長のものを送信元に選ぶ
+    /// どのサブネットにも含まれない宛先には最初に登録されたインターフェースを使う
+    pub fn select_source(dst: Ipv4Address) -> Option<Box<IpInterface>> {
+        let interfaces = IP_INTERFACES.lock();
+        let mut selected: Option<&Box<IpInterface>> = None;
+        for entry in interfaces.iter() {
+            if !entry.contains(dst) {
+                continue;
+            }
+            if let Some(current) = selected {
+                if entry.network().prefix() <= current.network().prefix() {
+                    continue;
+                }
+            }
+            selected = Some(entry);
+        }
+        match selected {
+            Some(entry) => Some(entry.clone()),
+            None => interfaces.iter().next().cloned(),
+        }
+    }
+
+    /// このインターフェースが登録されているデバイスを探す
+    pub fn device(&self) -> Option<&'static NetDevice> {
+        let devices = NET_DEVICES.lock();
+        for dev in devices.items.iter() {
+            let dev: &'static NetDevice = dev;
+            for interface in dev.get_interfaces(NetInterfaceFamily::Ip) {
+                if let NetInterfaceType::Ip(interface) = interface {
+                    if interface.unicast == self.unicast {
+                        return Some(dev);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    pub fn network(&self) -> Ipv4Network {
+        Ipv4Network::with_netmask(self.unicast, self.netmask).unwrap_or(Ipv4Network {
+            address: self.unicast,
+            prefix: IPV4_PREFIX_LENGTH_MAX,
+        })
+    }
+
+    pub fn contains(&self, address: Ipv4Address) -> bool {
+        self.network().contains(address)
+    }
+}
+
+impl Default for IpInterface {
+    fn default() -> Self {
+        Self {
+            net_interface: NetInterface::default(),
+            unicast: IP_ADDRESS_ANY,
+            netmask: IP_ADDRESS_ANY,
+            broadcast: IP_ADDRESS_ANY,
+            directed_broadcast: true,
+        }
+    }
+}
+
+pub fn dump(data: &[u8]) -> io::Result<()> {
+    let stderr = io::stderr();
+    let mut handle = stderr.lock();
+
+    let ipv4_hdr = match Ipv4Header::new_checked(data) {
+        Ok(hdr) => hdr,
+        Err(e) => {
+            return writeln!(handle, "IPv4 Header: {}", e.kind);
+        }
+    };
+
+    writeln!(handle, "IPv4 Header ==========")?;
+    writeln!(
+        handle,
+        "            vhl: 0x{:02x} [version: {}, header length: {}]",
+        ipv4_hdr.vhl(),
+        ipv4_hdr.version(),
+        ipv4_hdr.header_length()
+    )?;
+    writeln!(
+        handle,
+        "type of service: 0x{:02x} [dscp: {}, ecn: {}]",
+        ipv4_hdr.type_of_service(),
+        ipv4_hdr.dscp(),
+        ipv4_hdr.ecn()
+    )?;
+
+    writeln!(
+        handle,
+        "   total length: {} (payload {})",
+        ipv4_hdr.total_length(),
+        ipv4_hdr.total_length() - ipv4_hdr.header_length() as u16
+    )?;
+    writeln!(handle, "             id: {}", ipv4_hdr.id())?;
+
+    writeln!(handle, "           flag: 0x{:x}", ipv4_hdr.flags())?;
+    writeln!(handle, "         offset: {}", ipv4_hdr.offset())?;
+    writeln!(handle, "   time to live: {}", ipv4_hdr.time_to_live())?;
+    writeln!(
+        handle,
+        "       protocol: {} ({})",
+        ipv4_hdr.protocol(),
+        ipv4_hdr.protocol_number()
+    )?;
+    writeln!(handle, "       checksum: 0x{:04x}", ipv4_hdr.checksum())?;
+    writeln!(handle, "    src address: {}", ipv4_hdr.src_address())?;
+    writeln!(handle, "    dst address: {}", ipv4_hdr.dst_address())?;
+
+    handle.flush()
+}
+
+pub fn handle<T: AsRef<[u8]>>(_packet: &Ipv4Header<T>) {}
+
+pub fn input(data: &Vec<u8>, dev: &'static NetDevice) {
+    let ipv4_hdr = match Ipv4Header::new_checked(&data[..]) {
+        Ok(hdr) => hdr,
+        Err(e) => {
+            eprintln!("IP input error: {}, length={}", e.kind, data.len());
+            if e.kind == Ipv4HeaderErrorKind::TotalLength
+                && Ipv4Header::new_unchecked(&data[..]).verify_checksum()
+            {
+                // ヘッダ自体は壊れていないので全長フィールドを指して知らせる
+                icmp::parameter_problem(header::TOTAL_LENGTH_OFFSET as u8, &data[..]);
+            }
+            return;
+        }
+    };
+
+    if !ipv4_hdr.verify_checksum() {
+        eprintln!("Checksum error");
+        return;
+    }
+
+    if ipv4_hdr.time_to_live() == 0 {
+        eprintln!("Time exceeded (TTL=0)");
+        icmp::time_exceeded(TimeExceededCode::TimeToLive, &data[..]);
+        return;
+    }
+
+    let dst = ipv4_hdr.dst_address();
+    let src = ipv4_hdr.src_address();
+    // ブロードキャストやマルチキャストを送信元に持つパケットは捨てる (RFC 1122 3.2.1.3)
+    if src.is_broadcast() || src.is_multicast() {
+        eprintln!("IP invalid source address SRC={}", src);
+        return;
+    }
+    let interfaces = dev
+        .get_interfaces(NetInterfaceFamily::Ip)
+        .into_iter()
+        .filter_map(|interface| match interface {
+            NetInterfaceType::Ip(ip_interface) => Some(ip_interface),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let local = if dst.is_multicast() {
+        // マルチキャストの経路制御はしないので、参加していないグループ宛ては転送しない
+        if !igmp::accepts(dev, dst) {
+            return;
+        }
+        interfaces
+            .first()
+            .map_or(IP_ADDRESS_ANY, |ip_interface| ip_interface.unicast)
+    } else {
+        match classify(&interfaces, dst) {
+            Ipv4Delivery::Unicast(local) | Ipv4Delivery::Broadcast(local) => local,
+            Ipv4Delivery::Refused => {
+                eprintln!("IP directed broadcast refused DEV={} DST={}", dev.name, dst);
+                return;
+            }
+            Ipv4Delivery::NotLocal => {
+                if is_forwarding() {
+                    forward::forward(&data[..ipv4_hdr.total_length() as usize], dev);
+                }
+                return;
+            }
+        }
+    };
+
+    if ipv4_hdr.more_fragments() || ipv4_hdr.offset() > 0 {
+        eprintln!("fragment is not supported");
+        return;
+    }
+
+    let mut packet = Cow::Borrowed(&data[..ipv4_hdr.total_length() as usize]);
+    if ipv4_hdr.header_length() > IP_HEADER_SIZE_MIN as u8 {
+        match input_options(&ipv4_hdr, local) {
+            Ok(Some(options)) => {
+                let mut modified = packet.to_vec();
+                let mut header = Ipv4Header::new_unchecked(&mut modified[..]);
+                header.options_mut()[..options.len()].copy_from_slice(&options);
+                header.fill_checksum();
+                packet = Cow::Owned(modified);
+            }
+            Ok(None) => (),
+            Err(e) if e.kind == Ipv4OptionErrorKind::SourceRouteFailed && is_forwarding() => {
+                forward::source_route(&packet, dev);
+                return;
+            }
+            Err(e) if e.kind == Ipv4OptionErrorKind::SourceRouteFailed => {
+                eprintln!("IP option error: {}", e.kind);
+                icmp::destination_unreachable(UnreachableCode::SourceRouteFailed, &packet);
+                return;
+            }
+            Err(e) => {
+                eprintln!("IP option error: {} OFFSET={}", e.kind, e.offset);
+                icmp::parameter_problem((IP_HEADER_SIZE_MIN as usize + e.offset) as u8, &packet);
+                return;
+            }
+        }
+    }
+    let ipv4_hdr = Ipv4Header::new_unchecked(&packet[..]);
+
+    eprintln!(
+        "IP input DEV={} PROTOCOL={} ({}) TOTAL={} ",
+        dev.name,
+        ipv4_hdr.protocol(),
+        ipv4_hdr.protocol_number(),
+        ipv4_hdr.total_length()
+    );
+    let _ = dump(&packet[..]);
+
+    // raw ソケットには上位プロトコルの処理とは別に複製を渡す
+    let raw_delivered = raw::input(&packet, dev);
+    match protocol_handler(ipv4_hdr.protocol_number()) {
+        Some(handler) => handler(&packet, dev),
+        None if raw_delivered => (),
+        None => {
+            eprintln!(
+                "IP protocol is not registered PROTOCOL={}",
+                ipv4_hdr.protocol_number()
+            );
+            icmp::destination_unreachable(UnreachableCode::Protocol, &packet);
+        }
+    }
+}
+
+/// 受信したパケットを自分宛てとして受け取るか
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ipv4Delivery {
+    /// インターフェースのアドレス宛て
+    Unicast(Ipv4Address),
+    /// ブロードキャスト宛て。受け取ったことにするインターフェースのアドレスを持つ
+    Broadcast(Ipv4Address),
+    /// directed broadcast を受け取らない設定のインターフェースのブロードキャスト宛て
+    Refused,
+    /// 自分宛てではない
+    NotLocal,
+}
+
+/// 受信したデバイスのインターフェースから宛先の扱いを決める (マルチキャストは除く)
+/// 1. どれかのインターフェースのアドレス宛てならそのインターフェースで受け取る
+/// 2. 255.255.255.255 はデバイスの最初のインターフェースで受け取る。アドレスが未設定でも
+///    DHCP の応答を受け取れるよう 0.0.0.0 で受け取る
+/// 3. サブネットのブロードキャスト宛ては、それを許すインターフェースがあればそこで受け取る
+pub fn classify(interfaces: &[IpInterface], dst: Ipv4Address) -> Ipv4Delivery {
+    if let Some(ip_interface) = interfaces.iter().find(|i| i.unicast == dst) {
+        return Ipv4Delivery::Unicast(ip_interface.unicast);
+    }
+    if dst.is_broadcast() {
+        return Ipv4Delivery::Broadcast(
+            interfaces
+                .first()
+                .map_or(IP_ADDRESS_ANY, |ip_interface| ip_interface.unicast),
+        );
+    }
+    let mut subnets = interfaces
+        .iter()
+        .filter(|i| i.has_broadcast() && i.broadcast == dst)
+        .peekable();
+    if subnets.peek().is_none() {
+        return Ipv4Delivery::NotLocal;
+    }
+    match subnets.find(|i| i.directed_broadcast) {
+        Some(ip_interface) => Ipv4Delivery::Broadcast(ip_interface.unicast),
+        None => Ipv4Delivery::Refused,
+    }
+}
+
+/// 255.255.255.255 か、登録されたどれかのインターフェースのサブネットのブロードキャストか
+/// ICMP のエラーや応答を返さない宛先の判定に使う
+pub fn is_broadcast(address: Ipv4Address) -> bool {
+    if address.is_broadcast() {
+        return true;
+    }
+    let interfaces = IP_INTERFACES.lock();
+    let found = interfaces
+        .iter()
+        .any(|entry| entry.has_broadcast() && entry.broadcast == address);
+    found
+}
+
+/// 上位プロトコルのハンドラ。ヘッダを含むパケット全体と受信したデバイスを受け取る
+pub type Ipv4ProtocolHandler = fn(&[u8], &'static NetDevice);
+
+struct Ipv4ProtocolEntry {
+    protocol: u8,
+    handler: Ipv4ProtocolHandler,
+}
+
+lazy_static! {
+    static ref IP_PROTOCOLS: Mutex<Vec<Ipv4ProtocolEntry>> = Mutex::new(Vec::new());
+}
+
+pub fn protocol_register(protocol: u8, handler: Ipv4ProtocolHandler) -> Result<(), Ipv4Error> {
+    let mut protocols = IP_PROTOCOLS.lock().unwrap();
+    if protocols.iter().any(|p| p.protocol == protocol) {
+        eprintln!("IP protocol is already registered PROTOCOL={}", protocol);
+        return Err(Ipv4Error::new(Ipv4ErrorKind::AlreadyRegistered));
+    }
+    protocols.push(Ipv4ProtocolEntry { protocol, handler });
+    println!(
+        "IP protocol registered PROTOCOL={} ({})",
+        Protocol::from_u8(protocol),
+        protocol
+    );
+    Ok(())
+}
+
+fn protocol_handler(protocol: u8) -> Option<Ipv4ProtocolHandler> {
+    let protocols = IP_PROTOCOLS.lock().unwrap();
+    protocols
+        .iter()
+        .find(|p| p.protocol == protocol)
+        .map(|p| p.handler)
+}
+
+/// 自分宛てのパケットのオプションを処理する
+/// 書き換えが必要ならエンコードし直したオプションを返す
+fn input_options<T: AsRef<[u8]>>(
+    ipv4_hdr: &Ipv4Header<T>,
+    local: Ipv4Address,
+) -> Result<Option<Vec<u8>>, Ipv4OptionError> {
+    let mut options = option::parse(ipv4_hdr.options())?;
+    let mut modified = false;
+    for option in options.iter_mut() {
+        match option {
+            Ipv4Option::Timestamp { .. } => {
+                option.stamp(local, option::timestamp_now());
+                modified = true;
+            }
+            // 経路が残っているなら次の経由地へ転送しなければならないが、ホストとしては受け取らない
+            Ipv4Option::LooseSourceRoute { .. } | Ipv4Option::StrictSourceRoute { .. }
+                if option.next_route_slot().is_some() =>
+            {
+                return Err(Ipv4OptionError::new(
+                    Ipv4OptionErrorKind::SourceRouteFailed,
+                    0,
+                ));
+            }
+            _ => (),
+        }
+    }
+    if modified {
+        Ok(Some(option::emit(&options)))
+    } else {
+        Ok(None)
+    }
+}
+
+static ID: AtomicU16 = AtomicU16::new(128);
+
+fn generate_id() -> u16 {
+    ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug)]
+pub struct Ipv4Error {
+    pub kind: Ipv4ErrorKind,
+}
+
+impl Ipv4Error {
+    pub fn new(kind: Ipv4ErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ipv4ErrorKind {
+    AlreadyRegistered,
+    NoRoute,
+    NoInterface,
+    NoDevice,
+    InvalidHeader,
+    TooLong,
+    TransmitError,
+}
+
+/// src が 0.0.0.0 なら宛先に合わせて送信元アドレスを選ぶ
+pub fn output(
+    protocol: u8,
+    data: &[u8],
+    src: Ipv4Address,
+    dst: Ipv4Address,
+) -> Result<usize, Ipv4Error> {
+    output_with_options(protocol, data, src, dst, &[])
+}
+
+/// オプションを付けて送信する
+/// ソースルートを使うときは dst に最初の経由地を、オプションの route に残りの経路と最終的な宛先を入れる
+pub fn output_with_options(
+    protocol: u8,
+    data: &[u8],
+    src: Ipv4Address,
+    dst: Ipv4Address,
+    options: &[Ipv4Option],
+) -> Result<usize, Ipv4Error> {
+    output_with_flags(protocol, data, src, dst, options, 0)
+}
+
+/// フラグを指定して送信する
+/// IP_FLAG_DF を立てると PMTU を超えるパケットは分割せずに TooLong を返すので、
+/// 呼び出し側は pmtu::path_mtu に合わせて送り直す
+pub fn output_with_flags(
+    protocol: u8,
+    data: &[u8],
+    src: Ipv4Address,
+    dst: Ipv4Address,
+    options: &[Ipv4Option],
+    flags: u16,
+) -> Result<usize, Ipv4Error> {
+    let (interface, dev) = select_interface(src, dst)?;
+    output_device(dev, protocol, data, interface.unicast, dst, options, flags)
+}
+
+/// src が 0.0.0.0 なら経路表で、そうでなければ src を持つインターフェースを選ぶ
+fn select_interface(
+    src: Ipv4Address,
+    dst: Ipv4Address,
+) -> Result<(Box<IpInterface>, &'static NetDevice), Ipv4Error> {
+    let interface = if src.is_unspecified() {
+        match route::lookup(dst) {
+            Some(route) => Some(route.interface),
+            None => IpInterface::select_source(dst),
+        }
+    } else {
+        IpInterface::select(src)
+    };
+    let interface = match interface {
+        Some(interface) => interface,
+        None => {
+            eprintln!("IP interface not found SRC={} DST={}", src, dst);
+            return Err(Ipv4Error::new(Ipv4ErrorKind::NoRoute));
+        }
+    };
+    match interface.device() {
+        Some(dev) => Ok((interface, dev)),
+        None => {
+            eprintln!("device not found ADDR={}", interface.unicast);
+            Err(Ipv4Error::new(Ipv4ErrorKind::NoDevice))
+        }
+    }
+}
+
+/// 呼び出し側が作ったヘッダ付きのパケットを送る (IP_HDRINCL 相当)
+/// 全長とチェックサムは埋め直し、ID と送信元アドレスは 0 のときだけ埋める
+/// 送信元アドレスは偽れるので、経路は宛先だけで選ぶ
+pub fn output_packet(packet: &[u8]) -> Result<usize, Ipv4Error> {
+    if packet.len() > IP_TOTAL_SIZE_MAX as usize {
+        return Err(Ipv4Error::new(Ipv4ErrorKind::TooLong));
+    }
+    if packet.len() < IP_HEADER_SIZE_MIN as usize {
+        eprintln!("IP header is too short LEN={}", packet.len());
+        return Err(Ipv4Error::new(Ipv4ErrorKind::InvalidHeader));
+    }
+    let mut packet = packet.to_vec();
+    let total_length = packet.len() as u16;
+    Ipv4Header::new_unchecked(&mut packet[..]).set_total_length(total_length);
+    let mut header = Ipv4Header::new_checked(&mut packet[..]).map_err(|e| {
+        eprintln!("IP header error: {}", e.kind);
+        Ipv4Error::new(Ipv4ErrorKind::InvalidHeader)
+    })?;
+    let dst = header.dst_address();
+    let (interface, dev) = select_interface(IP_ADDRESS_ANY, dst)?;
+    if header.src_address().is_unspecified() {
+        header.set_src_address(interface.unicast);
+    }
+    if header.id() == 0 {
+        header.set_id(generate_id());
+    }
+    header.fill_checksum();
+
+    eprintln!(
+        "IP output DEV={} PROTOCOL={} ({}) SRC={} DST={} TOTAL={} (header included)",
+        dev.name,
+        header.protocol(),
+        header.protocol_number(),
+        header.src_address(),
+        dst,
+        total_length
+    );
+    transmit(
+        dev,
+        &packet,
+        next_hop(dev, dst),
+        pmtu::path_mtu(dst, dev.mtu),
+    )?;
+    Ok(packet.len())
+}
+
+/// 経路表を使わずに dev から送信する
+/// アドレスが決まる前の DHCP のように src が 0.0.0.0 のまま送るときに使う
+pub fn output_device(
+    dev: &NetDevice,
+    protocol: u8,
+    data: &[u8],
+    src: Ipv4Address,
+    dst: Ipv4Address,
+    options: &[Ipv4Option],
+    flags: u16,
+) -> Result<usize, Ipv4Error> {
+    // マルチキャストは既定で同じリンクの中だけに届ける (RFC 1112 6.1)
+    let ttl = if dst.is_multicast() {
+        IP_MULTICAST_TTL_DEFAULT
+    } else {
+        IP_TTL_DEFAULT
+    };
+    let packet = Ipv4HeaderBuilder::new(protocol, src, dst)
+        .id(generate_id())
+        .time_to_live(ttl)
+        .flags(flags)
+        .options(&option::emit(options))
+        .build(data)
+        .map_err(|e| {
+            eprintln!("IP header build error: {}", e.kind);
+            Ipv4Error::new(Ipv4ErrorKind::InvalidHeader)
+        })?;
+
+    eprintln!(
+        "IP output DEV={} PROTOCOL={} SRC={} DST={} TOTAL={}",
+        dev.name,
+        Protocol::from_u8(protocol),
+        src,
+        dst,
+        packet.len()
+    );
+    transmit(
+        dev,
+        &packet,
+        next_hop(dev, dst),
+        pmtu::path_mtu(dst, dev.mtu),
+    )?;
+    Ok(data.len())
+}
+
+/// TCP や UDP のチェックサムに含める疑似ヘッダの和
+pub fn pseudo_header_sum(src: Ipv4Address, dst: Ipv4Address, protocol: u8, length: u16) -> u32 {
+    let src = src.octets();
+    let dst = dst.octets();
+    u16::from_be_bytes([src[0], src[1]]) as u32
+        + u16::from_be_bytes([src[2], src[3]]) as u32
+        + u16::from_be_bytes([dst[0], dst[1]]) as u32
+        + u16::from_be_bytes([dst[2], dst[3]]) as u32
+        + protocol as u32
+        + length as u32
+}
+
+/// dev から dst へ送るときにリンク層で渡す相手。dev から出る経路にゲートウェイがあればそれを使う
+fn next_hop(dev: &NetDevice, dst: Ipv4Address) -> Ipv4Address {
+    if dst.is_multicast() || is_broadcast(dst) {
+        return dst;
+    }
+    match route::lookup(dst) {
+        Some(route)
+            if route
+                .interface
+                .device()
+                .is_some_and(|out| out.name == dev.name) =>
+        {
+            route.gateway_for(dst)
+        }
+        _ => dst,
+    }
+}
+
+/// next_hop のリンク層アドレス。マルチキャストは対応する MAC アドレスになり、
+/// それ以外は近隣テーブルを引く。見つからなければ決めずにドライバに任せる
+fn resolve(dev: &NetDevice, next_hop: Ipv4Address) -> Option<MacAddress> {
+    if dev.flags & NetDeviceFlag::NeedArp as u16 == 0 {
+        return None;
+    }
+    if next_hop.is_multicast() {
+        return Some(ethernet::ipv4_multicast_mac(next_hop));
+    }
+    neighbor::lookup(&dev.name, next_hop)
+}
+
+/// mtu に合わせて分割してから next_hop のリンク層アドレスへ送出する
+fn transmit(
+    dev: &NetDevice,
+    packet: &[u8],
+    next_hop: Ipv4Address,
+    mtu: u16,
+) -> Result<(), Ipv4Error> {
+    let mut hwaddr = [0; HARDWARE_ADDRESS_LENGTH];
+    let hwaddr = match resolve(dev, next_hop) {
+        Some(mac) => {
+            hwaddr[..MAC_LENGTH].copy_from_slice(&mac.octets());
+            hwaddr.as_mut_ptr()
+        }
+        None => ptr::null_mut(),
+    };
+    for fragment in fragment(packet, mtu as usize)? {
+        if dev
+            .output(NetProtocolType::Ip as u16, &fragment, hwaddr)
+            .is_err()
+        {
+            return Err(Ipv4Error::new(Ipv4ErrorKind::TransmitError));
+        }
+    }
+    Ok(())
+}
+
+/// MTU を超えるパケットをフラグメントに分ける
+/// 2 つ目以降のフラグメントにはコピーフラグの立ったオプションだけを載せる
+/// DF が立っていれば分割せずにエラーを返す
+pub fn fragment(packet: &[u8], mtu: usize) -> Result<Vec<Vec<u8>>, Ipv4Error> {
+    if packet.len() <= mtu {
+        return Ok(vec![packet.to_vec()]);
+    }
+    let ipv4_hdr = Ipv4Header::new_checked(packet)
+        .map_err(|_| Ipv4Error::new(Ipv4ErrorKind::InvalidHeader))?;
+    if ipv4_hdr.dont_fragment() {
+        eprintln!(
+            "IP packet too long and DF is set MTU={} SIZE={}",
+            mtu,
+            packet.len()
+        );
+        return Err(Ipv4Error::new(Ipv4ErrorKind::TooLong));
+    }
+
+    let first_header = ipv4_hdr.header_bytes().to_vec();
+    let mut rest_header = first_header[..IP_HEADER_SIZE_MIN as usize].to_vec();
+    if let Ok(options) = option::parse(ipv4_hdr.options()) {
+        let copied: Vec<Ipv4Option> = options.into_iter().filter(|o| o.is_copied()).collect();
+        rest_header.extend_from_slice(&option::emit(&copied));
+        while rest_header.len() % 4 != 0 {
+            rest_header.push(0);
+        }
+    }
+
+    let payload = ipv4_hdr.payload();
+    let base_offset = ipv4_hdr.offset() as usize * 8;
+    let more = ipv4_hdr.more_fragments();
+    let mut fragments = Vec::new();
+    let mut position = 0;
+    while position < payload.len() {
+        let header = if position == 0 {
+            &first_header
+        } else {
+            &rest_header
+        };
+        // フラグメントのデータ長は最後以外 8 の倍数でなければならない
+        let room = mtu.saturating_sub(header.len()) & !0b111;
+        if room == 0 {
+            return Err(Ipv4Error::new(Ipv4ErrorKind::TooLong));
+        }
+        let end = payload.len().min(position + room);
+        let mut fragment = header.clone();
+        fragment.extend_from_slice(&payload[position..end]);
+        {
+            let mut fragment_hdr = Ipv4Header::new_unchecked(&mut fragment[..]);
+            let total_length = fragment_hdr.as_bytes().len() as u16;
+            fragment_hdr.set_header_length(header.len() as u8);
+            fragment_hdr.set_total_length(total_length);
+            let mut flags = fragment_hdr.flags() & !IP_FLAG_MF;
+            if end < payload.len() || more {
+                flags |= IP_FLAG_MF;
+            }
+            fragment_hdr.set_flags(flags);
+            fragment_hdr.set_offset(((base_offset + position) / 8) as u16);
+            fragment_hdr.fill_checksum();
+        }
+        fragments.push(fragment);
+        position = end;
+    }
+    Ok(fragments)
+}
+
+pub fn init() {
+    pmtu::init();
+    let r = NetProtocol::register(NetProtocolType::Ip as u16, input);
+    match r {
+        Ok(()) => (),
+        Err(e) => match e.kind {
+            NetProtocolErrorKind::AlreadyRegistered => (),
+        },
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::Mutex;
+
+use super::Ipv4Address;
+use crate::ethernet::MacAddress;
+
+/// ARP を実装するまでの静的な近隣テーブル (arp -s 相当)
+struct NeighborEntry {
+    dev: String,
+    address: Ipv4Address,
+    mac: MacAddress,
+}
+
+lazy_static! {
+    static ref NEIGHBORS: Mutex<Vec<NeighborEntry>> = Mutex::new(Vec::new());
+}
+
+/// dev の先にいる address の MAC アドレスを登録する。同じアドレスは上書きする
+pub fn add(dev: &str, address: Ipv4Address, mac: MacAddress) {
+    let mut neighbors = NEIGHBORS.lock().unwrap();
+    match neighbors
+        .iter_mut()
+        .find(|entry| entry.dev == dev && entry.address == address)
+    {
+        Some(entry) => entry.mac = mac,
+        None => neighbors.push(NeighborEntry {
+            dev: dev.to_string(),
+            address,
+            mac,
+        }),
+    }
+}
+
+pub fn remove(dev: &str, address: Ipv4Address) {
+    NEIGHBORS
+        .lock()
+        .unwrap()
+        .retain(|entry| entry.dev != dev || entry.address != address);
+}
+
+pub fn lookup(dev: &str, address: Ipv4Address) -> Option<MacAddress> {
+    NEIGHBORS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|entry| entry.dev == dev && entry.address == address)
+        .map(|entry| entry.mac)
+}
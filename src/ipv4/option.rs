@@ -0,0 +1,463 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Ipv4Address, IPV4_ADDRESS_SIZE};
+
+pub const IP_OPTION_END: u8 = 0;
+pub const IP_OPTION_NOP: u8 = 1;
+pub const IP_OPTION_RECORD_ROUTE: u8 = 7;
+pub const IP_OPTION_TIMESTAMP: u8 = 68;
+pub const IP_OPTION_LOOSE_SOURCE_ROUTE: u8 = 131;
+pub const IP_OPTION_STRICT_SOURCE_ROUTE: u8 = 137;
+pub const IP_OPTION_ROUTER_ALERT: u8 = 148;
+
+/// タイプの最上位ビット。フラグメントすべてにコピーするオプションに立つ
+pub const IP_OPTION_COPIED: u8 = 0b1000_0000;
+
+/// ルート系オプションのポインタの最小値 (type, length, pointer の直後)
+const ROUTE_POINTER_MIN: u8 = 4;
+/// タイムスタンプのポインタの最小値 (type, length, pointer, oflw/flg の直後)
+const TIMESTAMP_POINTER_MIN: u8 = 5;
+const ROUTER_ALERT_LENGTH: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TimestampFlag {
+    /// タイムスタンプのみ
+    TimestampOnly = 0,
+    /// アドレスとタイムスタンプの組
+    AddressAndTimestamp = 1,
+    /// 送信元が指定したアドレスのノードだけが記録する
+    Prespecified = 3,
+}
+
+impl TimestampFlag {
+    pub fn from_u8(u: u8) -> Option<TimestampFlag> {
+        match u {
+            0 => Some(TimestampFlag::TimestampOnly),
+            1 => Some(TimestampFlag::AddressAndTimestamp),
+            3 => Some(TimestampFlag::Prespecified),
+            _ => None,
+        }
+    }
+
+    fn entry_size(&self) -> usize {
+        match self {
+            TimestampFlag::TimestampOnly => 4,
+            _ => IPV4_ADDRESS_SIZE + 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampEntry {
+    pub address: Option<Ipv4Address>,
+    pub timestamp: u32,
+}
+
+/// RFC 791 で定義されているオプションとルータアラート (RFC 2113)
+/// ルート系オプションの route はまだ記録されていない枠も含めたすべての枠を持つ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ipv4Option {
+    EndOfList,
+    NoOperation,
+    RecordRoute {
+        pointer: u8,
+        route: Vec<Ipv4Address>,
+    },
+    Timestamp {
+        pointer: u8,
+        overflow: u8,
+        flag: TimestampFlag,
+        entries: Vec<TimestampEntry>,
+    },
+    LooseSourceRoute {
+        pointer: u8,
+        route: Vec<Ipv4Address>,
+    },
+    StrictSourceRoute {
+        pointer: u8,
+        route: Vec<Ipv4Address>,
+    },
+    RouterAlert(u16),
+    Unknown {
+        kind: u8,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug)]
+pub struct Ipv4OptionError {
+    pub kind: Ipv4OptionErrorKind,
+    /// オプション領域の先頭からの問題のあるバイトの位置
+    pub offset: usize,
+}
+
+impl Ipv4OptionError {
+    pub fn new(kind: Ipv4OptionErrorKind, offset: usize) -> Self {
+        Self { kind, offset }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ipv4OptionErrorKind {
+    Truncated,
+    InvalidLength,
+    InvalidPointer,
+    InvalidFlag,
+    Duplicated,
+    SourceRouteFailed,
+}
+
+impl fmt::Display for Ipv4OptionErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Ipv4OptionErrorKind::Truncated => "option truncated",
+            Ipv4OptionErrorKind::InvalidLength => "invalid option length",
+            Ipv4OptionErrorKind::InvalidPointer => "invalid option pointer",
+            Ipv4OptionErrorKind::InvalidFlag => "invalid timestamp flag",
+            Ipv4OptionErrorKind::Duplicated => "option duplicated",
+            Ipv4OptionErrorKind::SourceRouteFailed => "source route failed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl Ipv4Option {
+    /// 空の枠を slots 個持つ Record Route を作る
+    pub fn record_route(slots: usize) -> Self {
+        Ipv4Option::RecordRoute {
+            pointer: ROUTE_POINTER_MIN,
+            route: vec![Ipv4Address::from_u32(0); slots],
+        }
+    }
+
+    /// 空の枠を slots 個持つ Timestamp を作る
+    pub fn timestamp(flag: TimestampFlag, slots: usize) -> Self {
+        let address = match flag {
+            TimestampFlag::TimestampOnly => None,
+            _ => Some(Ipv4Address::from_u32(0)),
+        };
+        Ipv4Option::Timestamp {
+            pointer: TIMESTAMP_POINTER_MIN,
+            overflow: 0,
+            flag,
+            entries: vec![
+                TimestampEntry {
+                    address,
+                    timestamp: 0,
+                };
+                slots
+            ],
+        }
+    }
+
+    pub fn kind(&self) -> u8 {
+        match self {
+            Ipv4Option::EndOfList => IP_OPTION_END,
+            Ipv4Option::NoOperation => IP_OPTION_NOP,
+            Ipv4Option::RecordRoute { .. } => IP_OPTION_RECORD_ROUTE,
+            Ipv4Option::Timestamp { .. } => IP_OPTION_TIMESTAMP,
+            Ipv4Option::LooseSourceRoute { .. } => IP_OPTION_LOOSE_SOURCE_ROUTE,
+            Ipv4Option::StrictSourceRoute { .. } => IP_OPTION_STRICT_SOURCE_ROUTE,
+            Ipv4Option::RouterAlert(_) => IP_OPTION_ROUTER_ALERT,
+            Ipv4Option::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    /// フラグメントにコピーするべきオプションか
+    pub fn is_copied(&self) -> bool {
+        self.kind() & IP_OPTION_COPIED > 0
+    }
+
+    /// エンコードしたときのバイト数
+    pub fn length(&self) -> usize {
+        match self {
+            Ipv4Option::EndOfList | Ipv4Option::NoOperation => 1,
+            Ipv4Option::RecordRoute { route, .. }
+            | Ipv4Option::LooseSourceRoute { route, .. }
+            | Ipv4Option::StrictSourceRoute { route, .. } => 3 + route.len() * IPV4_ADDRESS_SIZE,
+            Ipv4Option::Timestamp { flag, entries, .. } => 4 + entries.len() * flag.entry_size(),
+            Ipv4Option::RouterAlert(_) => ROUTER_ALERT_LENGTH as usize,
+            Ipv4Option::Unknown { data, .. } => 2 + data.len(),
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Ipv4Option::EndOfList | Ipv4Option::NoOperation => buf.push(self.kind()),
+            Ipv4Option::RecordRoute { pointer, route }
+            | Ipv4Option::LooseSourceRoute { pointer, route }
+            | Ipv4Option::StrictSourceRoute { pointer, route } => {
+                buf.push(self.kind());
+                buf.push(self.length() as u8);
+                buf.push(*pointer);
+                for address in route {
+                    buf.extend_from_slice(&address.octets());
+                }
+            }
+            Ipv4Option::Timestamp {
+                pointer,
+                overflow,
+                flag,
+                entries,
+            } => {
+                buf.push(self.kind());
+                buf.push(self.length() as u8);
+                buf.push(*pointer);
+                buf.push((overflow << 4) | (*flag as u8));
+                for entry in entries {
+                    if *flag != TimestampFlag::TimestampOnly {
+                        let address = entry.address.unwrap_or_else(|| Ipv4Address::from_u32(0));
+                        buf.extend_from_slice(&address.octets());
+                    }
+                    buf.extend_from_slice(&entry.timestamp.to_be_bytes());
+                }
+            }
+            Ipv4Option::RouterAlert(value) => {
+                buf.push(self.kind());
+                buf.push(ROUTER_ALERT_LENGTH);
+                buf.extend_from_slice(&value.to_be_bytes());
+            }
+            Ipv4Option::Unknown { kind, data } => {
+                buf.push(*kind);
+                buf.push(self.length() as u8);
+                buf.extend_from_slice(data);
+            }
+        }
+    }
+
+    /// ルート系オプションでまだ辿っていない (記録していない) 枠があれば返す
+    pub fn next_route_slot(&self) -> Option<usize> {
+        match self {
+            Ipv4Option::RecordRoute { pointer, route }
+            | Ipv4Option::LooseSourceRoute { pointer, route }
+            | Ipv4Option::StrictSourceRoute { pointer, route } => {
+                let index = (*pointer - ROUTE_POINTER_MIN) as usize / IPV4_ADDRESS_SIZE;
+                if index < route.len() {
+                    Some(index)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Record Route の空き枠に address を記録する。空きがなければ何もしない
+    pub fn record(&mut self, address: Ipv4Address) -> bool {
+        let index = match self.next_route_slot() {
+            Some(index) => index,
+            None => return false,
+        };
+        match self {
+            Ipv4Option::RecordRoute { pointer, route }
+            | Ipv4Option::LooseSourceRoute { pointer, route }
+            | Ipv4Option::StrictSourceRoute { pointer, route } => {
+                route[index] = address;
+                *pointer += IPV4_ADDRESS_SIZE as u8;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Timestamp に address の時刻を記録する
+    /// 空きがなければオーバーフローカウンタを進める (4bit で飽和する)
+    pub fn stamp(&mut self, address: Ipv4Address, timestamp: u32) -> bool {
+        if let Ipv4Option::Timestamp {
+            pointer,
+            overflow,
+            flag,
+            entries,
+        } = self
+        {
+            let index = (*pointer - TIMESTAMP_POINTER_MIN) as usize / flag.entry_size();
+            if index >= entries.len() {
+                if *overflow < 0b1111 {
+                    *overflow += 1;
+                }
+                return false;
+            }
+            let entry = &mut entries[index];
+            match flag {
+                TimestampFlag::TimestampOnly => (),
+                TimestampFlag::AddressAndTimestamp => entry.address = Some(address),
+                TimestampFlag::Prespecified => {
+                    if entry.address != Some(address) {
+                        return false;
+                    }
+                }
+            }
+            entry.timestamp = timestamp;
+            *pointer += flag.entry_size() as u8;
+            return true;
+        }
+        false
+    }
+}
+
+/// オプション領域をデコードする
+/// End of Option List 以降のパディングは読まない
+pub fn parse(data: &[u8]) -> Result<Vec<Ipv4Option>, Ipv4OptionError> {
+    let mut options = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let kind = data[i];
+        match kind {
+            IP_OPTION_END => {
+                options.push(Ipv4Option::EndOfList);
+                break;
+            }
+            IP_OPTION_NOP => {
+                options.push(Ipv4Option::NoOperation);
+                i += 1;
+                continue;
+            }
+            _ => (),
+        }
+        if i + 1 >= data.len() {
+            return Err(Ipv4OptionError::new(Ipv4OptionErrorKind::Truncated, i));
+        }
+        let length = data[i + 1] as usize;
+        if length < 2 {
+            return Err(Ipv4OptionError::new(
+                Ipv4OptionErrorKind::InvalidLength,
+                i + 1,
+            ));
+        }
+        if i + length > data.len() {
+            return Err(Ipv4OptionError::new(Ipv4OptionErrorKind::Truncated, i + 1));
+        }
+        let body = &data[i..i + length];
+        let option = match kind {
+            IP_OPTION_RECORD_ROUTE
+            | IP_OPTION_LOOSE_SOURCE_ROUTE
+            | IP_OPTION_STRICT_SOURCE_ROUTE => {
+                let (pointer, route) = parse_route(body, i)?;
+                match kind {
+                    IP_OPTION_RECORD_ROUTE => Ipv4Option::RecordRoute { pointer, route },
+                    IP_OPTION_LOOSE_SOURCE_ROUTE => Ipv4Option::LooseSourceRoute { pointer, route },
+                    _ => Ipv4Option::StrictSourceRoute { pointer, route },
+                }
+            }
+            IP_OPTION_TIMESTAMP => parse_timestamp(body, i)?,
+            IP_OPTION_ROUTER_ALERT => {
+                if length != ROUTER_ALERT_LENGTH as usize {
+                    return Err(Ipv4OptionError::new(
+                        Ipv4OptionErrorKind::InvalidLength,
+                        i + 1,
+                    ));
+                }
+                Ipv4Option::RouterAlert(u16::from_be_bytes([body[2], body[3]]))
+            }
+            _ => Ipv4Option::Unknown {
+                kind,
+                data: body[2..].to_vec(),
+            },
+        };
+        // 同じ種類のオプションは 1 つしか持てない (RFC 1122 3.2.1.8)
+        if options.iter().any(|o: &Ipv4Option| o.kind() == kind) {
+            return Err(Ipv4OptionError::new(Ipv4OptionErrorKind::Duplicated, i));
+        }
+        options.push(option);
+        i += length;
+    }
+    Ok(options)
+}
+
+/// オプションをエンコードする。4 バイト境界までのパディングは含めない
+pub fn emit(options: &[Ipv4Option]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for option in options {
+        option.encode(&mut buf);
+    }
+    buf
+}
+
+fn parse_route(body: &[u8], offset: usize) -> Result<(u8, Vec<Ipv4Address>), Ipv4OptionError> {
+    if body.len() < 3 || !(body.len() - 3).is_multiple_of(IPV4_ADDRESS_SIZE) {
+        return Err(Ipv4OptionError::new(
+            Ipv4OptionErrorKind::InvalidLength,
+            offset + 1,
+        ));
+    }
+    let pointer = body[2];
+    // ポインタは 4 以上で、枠の境界を指していなければならない (length + 1 は記録済み)
+    if pointer < ROUTE_POINTER_MIN
+        || !((pointer - ROUTE_POINTER_MIN) as usize).is_multiple_of(IPV4_ADDRESS_SIZE)
+        || pointer as usize > body.len() + 1
+    {
+        return Err(Ipv4OptionError::new(
+            Ipv4OptionErrorKind::InvalidPointer,
+            offset + 2,
+        ));
+    }
+    let route = body[3..]
+        .chunks_exact(IPV4_ADDRESS_SIZE)
+        .map(|c| Ipv4Address::new(c[0], c[1], c[2], c[3]))
+        .collect();
+    Ok((pointer, route))
+}
+
+fn parse_timestamp(body: &[u8], offset: usize) -> Result<Ipv4Option, Ipv4OptionError> {
+    if body.len() < 4 {
+        return Err(Ipv4OptionError::new(
+            Ipv4OptionErrorKind::InvalidLength,
+            offset + 1,
+        ));
+    }
+    let flag = match TimestampFlag::from_u8(body[3] & 0b1111) {
+        Some(flag) => flag,
+        None => {
+            return Err(Ipv4OptionError::new(
+                Ipv4OptionErrorKind::InvalidFlag,
+                offset + 3,
+            ))
+        }
+    };
+    let entry_size = flag.entry_size();
+    if !(body.len() - 4).is_multiple_of(entry_size) {
+        return Err(Ipv4OptionError::new(
+            Ipv4OptionErrorKind::InvalidLength,
+            offset + 1,
+        ));
+    }
+    let pointer = body[2];
+    if pointer < TIMESTAMP_POINTER_MIN
+        || !((pointer - TIMESTAMP_POINTER_MIN) as usize).is_multiple_of(entry_size)
+        || pointer as usize > body.len() + 1
+    {
+        return Err(Ipv4OptionError::new(
+            Ipv4OptionErrorKind::InvalidPointer,
+            offset + 2,
+        ));
+    }
+    let entries = body[4..]
+        .chunks_exact(entry_size)
+        .map(|c| match flag {
+            TimestampFlag::TimestampOnly => TimestampEntry {
+                address: None,
+                timestamp: u32::from_be_bytes([c[0], c[1], c[2], c[3]]),
+            },
+            _ => TimestampEntry {
+                address: Some(Ipv4Address::new(c[0], c[1], c[2], c[3])),
+                timestamp: u32::from_be_bytes([c[4], c[5], c[6], c[7]]),
+            },
+        })
+        .collect();
+    Ok(Ipv4Option::Timestamp {
+        pointer,
+        overflow: body[3] >> 4,
+        flag,
+        entries,
+    })
+}
+
+/// タイムスタンプオプションに記録する UT の 0 時からのミリ秒
+pub fn timestamp_now() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (now.as_millis() % (24 * 60 * 60 * 1000)) as u32
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{Ipv4Address, IP_HEADER_SIZE_MIN};
+use crate::net::NetTimer;
+
+/// すべてのホストが扱えなければならない最小の MTU (RFC 791)
+pub const PMTU_MIN: u16 = 68;
+
+/// 減らした PMTU を捨てて大きな値を試し直すまでの時間 (RFC 1191 の推奨値)
+pub const PMTU_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+const PMTU_AGING_INTERVAL: Duration = Duration::from_secs(60);
+
+const TCP_HEADER_SIZE_MIN: u16 = 20;
+
+/// Next-Hop MTU を返さない古いルータ向けの推定値 (RFC 1191 section 7)
+const PMTU_PLATEAUS: [u16; 11] = [
+    65535, 32000, 17914, 8166, 4352, 2002, 1492, 1006, 508, 296, PMTU_MIN,
+];
+
+struct PmtuEntry {
+    mtu: u16,
+    updated: Instant,
+}
+
+lazy_static! {
+    static ref PMTU_CACHE: Mutex<HashMap<Ipv4Address, PmtuEntry>> = Mutex::new(HashMap::new());
+}
+
+/// Fragmentation Needed を受けて宛先の PMTU を下げる
+/// next_hop_mtu が 0 なら送ったパケットの全長から一段小さい値を推定する
+pub fn update(dst: Ipv4Address, next_hop_mtu: u16, original_total_length: u16) {
+    update_at(dst, next_hop_mtu, original_total_length, Instant::now());
+}
+
+/// 時刻を指定する update
+pub fn update_at(dst: Ipv4Address, next_hop_mtu: u16, original_total_length: u16, now: Instant) {
+    // 送ったパケットが通らなかったのにそれ以上の MTU を返すのはおかしな通知
+    if next_hop_mtu != 0 && next_hop_mtu >= original_total_length {
+        eprintln!(
+            "PMTU bogus report DST={} MTU={} TOTAL={}",
+            dst, next_hop_mtu, original_total_length
+        );
+        return;
+    }
+    let mtu = if next_hop_mtu == 0 {
+        PMTU_PLATEAUS
+            .iter()
+            .copied()
+            .find(|plateau| *plateau < original_total_length)
+            .unwrap_or(PMTU_MIN)
+    } else {
+        next_hop_mtu
+    }
+    .max(PMTU_MIN);
+
+    let mut cache = PMTU_CACHE.lock().unwrap();
+    if let Some(entry) = cache.get(&dst) {
+        // 有効なうちは PMTU を増やす方向の通知は無視する。期限切れなら学び直す
+        if entry.mtu <= mtu && now.saturating_duration_since(entry.updated) < PMTU_TIMEOUT {
+            return;
+        }
+    }
+    eprintln!("PMTU updated DST={} MTU={}", dst, mtu);
+    cache.insert(dst, PmtuEntry { mtu, updated: now });
+}
+
+pub fn lookup(dst: Ipv4Address) -> Option<u16> {
+    lookup_at(dst, Instant::now())
+}
+
+/// 時刻を指定する lookup。PMTU_TIMEOUT より古いものは無いものとする
+pub fn lookup_at(dst: Ipv4Address, now: Instant) -> Option<u16> {
+    let cache = PMTU_CACHE.lock().unwrap();
+    match cache.get(&dst) {
+        Some(entry) if now.saturating_duration_since(entry.updated) < PMTU_TIMEOUT => {
+            Some(entry.mtu)
+        }
+        _ => None,
+    }
+}
+
+/// 宛先までの PMTU。キャッシュがなければ送出するデバイスの MTU
+pub fn path_mtu(dst: Ipv4Address, dev_mtu: u16) -> u16 {
+    match lookup(dst) {
+        Some(mtu) => mtu.min(dev_mtu),
+        None => dev_mtu,
+    }
+}
+
+/// オプションなしの IP ヘッダと TCP ヘッダを差し引いた TCP の MSS
+pub fn mss(dst: Ipv4Address, dev_mtu: u16) -> u16 {
+    path_mtu(dst, dev_mtu).saturating_sub(IP_HEADER_SIZE_MIN + TCP_HEADER_SIZE_MIN)
+}
+
+pub fn flush() {
+    PMTU_CACHE.lock().unwrap().clear();
+}
+
+fn age() {
+    let mut cache = PMTU_CACHE.lock().unwrap();
+    cache.retain(|dst, entry| {
+        let alive = entry.updated.elapsed() < PMTU_TIMEOUT;
+        if !alive {
+            eprintln!("PMTU expired DST={}", dst);
+        }
+        alive
+    });
+}
+
+pub fn init() {
+    NetTimer::register(PMTU_AGING_INTERVAL, age);
+}
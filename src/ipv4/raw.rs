@@ -0,0 +1,295 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use super::{
+    output, output_packet, IpInterface, Ipv4Address, Ipv4ErrorKind, Ipv4Header, Protocol,
+    IP_ADDRESS_ANY, IP_HEADER_SIZE_MIN, IP_PAYLOAD_SIZE_MAX,
+};
+use crate::net::NetDevice;
+
+const RAW_PCB_SIZE: usize = 16;
+const RAW_QUEUE_LIMIT: usize = 64;
+
+/// IPPROTO_RAW 相当。ヘッダは常に呼び出し側が用意する
+pub const IP_PROTOCOL_RAW: u8 = 255;
+
+#[derive(Debug)]
+pub struct RawError {
+    pub kind: RawErrorKind,
+}
+
+impl RawError {
+    pub fn new(kind: RawErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RawErrorKind {
+    NoSpace,
+    InvalidId,
+    InvalidProtocol,
+    AddressNotAvailable,
+    InvalidHeader,
+    TooLong,
+    OutputError,
+    Timeout,
+}
+
+impl fmt::Display for RawErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RawErrorKind::NoSpace => "no pcb space",
+            RawErrorKind::InvalidId => "invalid id",
+            RawErrorKind::InvalidProtocol => "invalid protocol",
+            RawErrorKind::AddressNotAvailable => "address not available",
+            RawErrorKind::InvalidHeader => "invalid header",
+            RawErrorKind::TooLong => "packet too long",
+            RawErrorKind::OutputError => "output error",
+            RawErrorKind::Timeout => "timeout",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// 受信したパケット。data は IP ヘッダから始まる
+pub struct RawPacket {
+    pub data: Vec<u8>,
+    pub src: Ipv4Address,
+    pub dst: Ipv4Address,
+    pub dev: &'static NetDevice,
+}
+
+struct RawPcb {
+    protocol: u8,
+    local: Ipv4Address,
+    /// 送るデータが IP ヘッダから始まる
+    header_included: bool,
+    queue: VecDeque<RawPacket>,
+    /// poll_recvfrom で待っているタスク
+    wakers: Vec<Waker>,
+}
+
+impl RawPcb {
+    fn new(protocol: u8) -> Self {
+        RawPcb {
+            protocol,
+            local: IP_ADDRESS_ANY,
+            header_included: protocol == IP_PROTOCOL_RAW,
+            queue: VecDeque::new(),
+            wakers: Vec::new(),
+        }
+    }
+
+    fn accepts(&self, protocol: u8, dst: Ipv4Address) -> bool {
+        self.protocol == protocol && (self.local.is_unspecified() || self.local == dst)
+    }
+}
+
+lazy_static! {
+    static ref PCBS: Mutex<Vec<Option<RawPcb>>> =
+        Mutex::new((0..RAW_PCB_SIZE).map(|_| None).collect());
+    static ref PCB_CONDVAR: Condvar = Condvar::new();
+}
+
+fn pcbs() -> MutexGuard<'static, Vec<Option<RawPcb>>> {
+    PCBS.lock().unwrap()
+}
+
+fn pcb_mut(pcbs: &mut [Option<RawPcb>], id: usize) -> Result<&mut RawPcb, RawError> {
+    match pcbs.get_mut(id) {
+        Some(Some(pcb)) => Ok(pcb),
+        _ => Err(RawError::new(RawErrorKind::InvalidId)),
+    }
+}
+
+/// protocol の IP パケットをすべて受け取る pcb を作る。0 は使えない
+pub fn open(protocol: u8) -> Result<usize, RawError> {
+    if protocol == 0 {
+        return Err(RawError::new(RawErrorKind::InvalidProtocol));
+    }
+    let mut pcbs = pcbs();
+    for (id, entry) in pcbs.iter_mut().enumerate() {
+        if entry.is_none() {
+            *entry = Some(RawPcb::new(protocol));
+            return Ok(id);
+        }
+    }
+    eprintln!("RAW pcb is full");
+    Err(RawError::new(RawErrorKind::NoSpace))
+}
+
+pub fn close(id: usize) -> Result<(), RawError> {
+    let wakers = {
+        let mut pcbs = pcbs();
+        match pcbs.get_mut(id) {
+            Some(entry @ Some(_)) => entry.take().unwrap().wakers,
+            _ => return Err(RawError::new(RawErrorKind::InvalidId)),
+        }
+    };
+    PCB_CONDVAR.notify_all();
+    for waker in wakers {
+        waker.wake();
+    }
+    Ok(())
+}
+
+/// 宛先が address のパケットだけを受け取り、送信元にも使う。ポートはない
+pub fn bind(id: usize, address: Ipv4Address) -> Result<(), RawError> {
+    if !address.is_unspecified() && IpInterface::select(address).is_none() {
+        eprintln!("RAW local address not found LOCAL={}", address);
+        return Err(RawError::new(RawErrorKind::AddressNotAvailable));
+    }
+    let mut pcbs = pcbs();
+    pcb_mut(&mut pcbs, id)?.local = address;
+    Ok(())
+}
+
+pub fn local_address(id: usize) -> Result<Ipv4Address, RawError> {
+    let mut pcbs = pcbs();
+    Ok(pcb_mut(&mut pcbs, id)?.local)
+}
+
+pub fn protocol(id: usize) -> Result<u8, RawError> {
+    let mut pcbs = pcbs();
+    Ok(pcb_mut(&mut pcbs, id)?.protocol)
+}
+
+/// IP_HDRINCL 相当。IP_PROTOCOL_RAW では外せない
+pub fn set_header_included(id: usize, included: bool) -> Result<(), RawError> {
+    let mut pcbs = pcbs();
+    let pcb = pcb_mut(&mut pcbs, id)?;
+    if pcb.protocol == IP_PROTOCOL_RAW && !included {
+        return Err(RawError::new(RawErrorKind::InvalidProtocol));
+    }
+    pcb.header_included = included;
+    Ok(())
+}
+
+pub fn header_included(id: usize) -> Result<bool, RawError> {
+    let mut pcbs = pcbs();
+    Ok(pcb_mut(&mut pcbs, id)?.header_included)
+}
+
+/// ヘッダを含めるなら宛先はヘッダのものを使い、0.0.0.0 のときだけ dst で埋める
+pub fn sendto(id: usize, data: &[u8], dst: Ipv4Address) -> Result<usize, RawError> {
+    let (protocol, local, header_included) = {
+        let mut pcbs = pcbs();
+        let pcb = pcb_mut(&mut pcbs, id)?;
+        (pcb.protocol, pcb.local, pcb.header_included)
+    };
+    let result = if header_included {
+        let mut packet = data.to_vec();
+        if packet.len() >= IP_HEADER_SIZE_MIN as usize {
+            let mut header = Ipv4Header::new_unchecked(&mut packet[..]);
+            if header.dst_address().is_unspecified() {
+                header.set_dst_address(dst);
+            }
+        }
+        output_packet(&packet)
+    } else {
+        if data.len() > IP_PAYLOAD_SIZE_MAX as usize {
+            return Err(RawError::new(RawErrorKind::TooLong));
+        }
+        output(protocol, data, local, dst)
+    };
+    result.map_err(|e| {
+        RawError::new(match e.kind {
+            Ipv4ErrorKind::InvalidHeader => RawErrorKind::InvalidHeader,
+            Ipv4ErrorKind::TooLong => RawErrorKind::TooLong,
+            _ => RawErrorKind::OutputError,
+        })
+    })?;
+    Ok(data.len())
+}
+
+/// timeout が None なら届くまで待つ
+pub fn recvfrom(id: usize, timeout: Option<Duration>) -> Result<RawPacket, RawError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut pcbs = pcbs();
+    loop {
+        let pcb = pcb_mut(&mut pcbs, id)?;
+        if let Some(packet) = pcb.queue.pop_front() {
+            return Ok(packet);
+        }
+        pcbs = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(RawError::new(RawErrorKind::Timeout));
+                }
+                PCB_CONDVAR.wait_timeout(pcbs, deadline - now).unwrap().0
+            }
+            None => PCB_CONDVAR.wait(pcbs).unwrap(),
+        };
+    }
+}
+
+/// 届いていなければ waker を登録して Pending を返す。input で起こす
+pub fn poll_recvfrom(id: usize, cx: &mut Context<'_>) -> Poll<Result<RawPacket, RawError>> {
+    let mut pcbs = pcbs();
+    let pcb = match pcb_mut(&mut pcbs, id) {
+        Ok(pcb) => pcb,
+        Err(err) => return Poll::Ready(Err(err)),
+    };
+    if let Some(packet) = pcb.queue.pop_front() {
+        return Poll::Ready(Ok(packet));
+    }
+    if !pcb.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+        pcb.wakers.push(cx.waker().clone());
+    }
+    Poll::Pending
+}
+
+/// 自分宛てのパケットの複製をプロトコル番号の合う pcb すべてに渡す
+/// 受け取る pcb があれば true を返す (プロトコル到達不能を返さない)
+pub(crate) fn input(packet: &[u8], dev: &'static NetDevice) -> bool {
+    let ipv4_hdr = Ipv4Header::new_unchecked(packet);
+    let protocol = ipv4_hdr.protocol_number();
+    let src = ipv4_hdr.src_address();
+    let dst = ipv4_hdr.dst_address();
+
+    let mut delivered = false;
+    let mut wakers = Vec::new();
+    {
+        let mut pcbs = pcbs();
+        for pcb in pcbs
+            .iter_mut()
+            .flatten()
+            .filter(|pcb| pcb.accepts(protocol, dst))
+        {
+            delivered = true;
+            if pcb.queue.len() >= RAW_QUEUE_LIMIT {
+                eprintln!("RAW queue is full PROTOCOL={}", protocol);
+                continue;
+            }
+            pcb.queue.push_back(RawPacket {
+                data: packet.to_vec(),
+                src,
+                dst,
+                dev,
+            });
+            wakers.append(&mut pcb.wakers);
+        }
+    }
+    if delivered {
+        eprintln!(
+            "RAW input DEV={} PROTOCOL={} ({}) SRC={} DST={} LEN={}",
+            dev.name,
+            Protocol::from_u8(protocol),
+            protocol,
+            src,
+            dst,
+            packet.len()
+        );
+        PCB_CONDVAR.notify_all();
+    }
+    // ロックを外してから起こす
+    for waker in wakers {
+        waker.wake();
+    }
+    delivered
+}
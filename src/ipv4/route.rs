@@ -0,0 +1,113 @@
+use std::sync::Mutex;
+
+use super::{IpInterface, Ipv4Address, Ipv4Error, Ipv4ErrorKind, Ipv4Network, IP_INTERFACES};
+
+/// 経路表のエントリ
+/// nexthop が None なら宛先はインターフェースと同じリンク上にある
+#[derive(Clone)]
+pub struct Ipv4Route {
+    pub network: Ipv4Network,
+    pub nexthop: Option<Ipv4Address>,
+    pub interface: Box<IpInterface>,
+}
+
+impl Ipv4Route {
+    /// 宛先へ送るときに次に渡す相手のアドレス
+    pub fn gateway_for(&self, dst: Ipv4Address) -> Ipv4Address {
+        self.nexthop.unwrap_or(dst)
+    }
+}
+
+lazy_static! {
+    static ref ROUTES: Mutex<Vec<Ipv4Route>> = Mutex::new(Vec::new());
+}
+
+/// 静的経路を追加する。interface には送出するインターフェースのユニキャストアドレスを指定する
+pub fn add(
+    network: Ipv4Network,
+    nexthop: Option<Ipv4Address>,
+    interface: Ipv4Address,
+) -> Result<(), Ipv4Error> {
+    let interface = match IpInterface::select(interface) {
+        Some(interface) => interface,
+        None => {
+            eprintln!("IP interface not found ADDR={}", interface);
+            return Err(Ipv4Error::new(Ipv4ErrorKind::NoInterface));
+        }
+    };
+    // ホスト部を落として登録する
+    let network = Ipv4Network::new(network.network(), network.prefix())
+        .map_err(|_| Ipv4Error::new(Ipv4ErrorKind::NoRoute))?;
+    let mut routes = ROUTES.lock().unwrap();
+    routes.retain(|r| r.network != network);
+    eprintln!(
+        "route added NETWORK={} NEXTHOP={} IFACE={}",
+        network,
+        nexthop.map_or_else(|| String::from("(on-link)"), |n| n.to_string()),
+        interface.unicast
+    );
+    routes.push(Ipv4Route {
+        network,
+        nexthop,
+        interface,
+    });
+    Ok(())
+}
+
+pub fn set_default_gateway(gateway: Ipv4Address, interface: Ipv4Address) -> Result<(), Ipv4Error> {
+    let default = Ipv4Network::new(Ipv4Address::from_u32(0), 0)
+        .map_err(|_| Ipv4Error::new(Ipv4ErrorKind::NoRoute))?;
+    add(default, Some(gateway), interface)
+}
+
+pub fn delete(network: Ipv4Network) {
+    let mut routes = ROUTES.lock().unwrap();
+    routes.retain(|r| {
+        r.network.network() != network.network() || r.network.prefix() != network.prefix()
+    });
+}
+
+/// network への経路が nexthop と interface で登録したものであるときだけ消す
+/// 後から別の経路に置き換えられていれば何もしない
+pub fn delete_exact(network: Ipv4Network, nexthop: Option<Ipv4Address>, interface: Ipv4Address) {
+    let mut routes = ROUTES.lock().unwrap();
+    routes.retain(|r| {
+        r.network.network() != network.network()
+            || r.network.prefix() != network.prefix()
+            || r.nexthop != nexthop
+            || r.interface.unicast != interface
+    });
+}
+
+/// 登録されている静的経路とインターフェースの直結ネットワークから最長一致で経路を選ぶ
+pub fn lookup(dst: Ipv4Address) -> Option<Ipv4Route> {
+    let mut candidates: Vec<Ipv4Route> = Vec::new();
+    {
+        let interfaces = IP_INTERFACES.lock();
+        for interface in interfaces.items.iter() {
+            candidates.push(Ipv4Route {
+                network: interface.network(),
+                nexthop: None,
+                interface: interface.clone(),
+            });
+        }
+    }
+    {
+        let routes = ROUTES.lock().unwrap();
+        candidates.extend(routes.iter().cloned());
+    }
+
+    let mut selected: Option<Ipv4Route> = None;
+    for candidate in candidates {
+        if !candidate.network.contains(dst) {
+            continue;
+        }
+        if let Some(current) = &selected {
+            if candidate.network.prefix() <= current.network.prefix() {
+                continue;
+            }
+        }
+        selected = Some(candidate);
+    }
+    selected
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+
+use super::{Ipv6Address, IPV6_ADDRESS_SIZE, IPV6_HEADER_SIZE, IPV6_VERSION};
+
+pub const IPV6_HOP_LIMIT_DEFAULT: u8 = 64;
+
+#[derive(Debug)]
+pub struct Ipv6HeaderError {
+    pub kind: Ipv6HeaderErrorKind,
+}
+
+impl Ipv6HeaderError {
+    pub fn new(kind: Ipv6HeaderErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ipv6HeaderErrorKind {
+    TooShort,
+    Version,
+    PayloadLength,
+}
+
+impl fmt::Display for Ipv6HeaderErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Ipv6HeaderErrorKind::TooShort => "too short",
+            Ipv6HeaderErrorKind::Version => "version error",
+            Ipv6HeaderErrorKind::PayloadLength => "payload length error",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// バイト列の上に被せて IPv6 固定ヘッダ (RFC 8200 3) を読み書きするビュー
+pub struct Ipv6Header<T: AsRef<[u8]>> {
+    buffer: T,
+}
+
+impl<T: AsRef<[u8]>> Ipv6Header<T> {
+    /// 長さを検査しない。new_checked を通したバッファか自分で組み立てたバッファに使う
+    pub fn new_unchecked(buffer: T) -> Self {
+        Ipv6Header { buffer }
+    }
+
+    pub fn new_checked(buffer: T) -> Result<Self, Ipv6HeaderError> {
+        let len = buffer.as_ref().len();
+        if len < IPV6_HEADER_SIZE {
+            return Err(Ipv6HeaderError::new(Ipv6HeaderErrorKind::TooShort));
+        }
+        let header = Ipv6Header { buffer };
+        if header.version() != IPV6_VERSION {
+            return Err(Ipv6HeaderError::new(Ipv6HeaderErrorKind::Version));
+        }
+        if IPV6_HEADER_SIZE + header.payload_length() as usize > len {
+            return Err(Ipv6HeaderError::new(Ipv6HeaderErrorKind::PayloadLength));
+        }
+        Ok(header)
+    }
+
+    fn data(&self) -> &[u8] {
+        self.buffer.as_ref()
+    }
+
+    pub fn version(&self) -> u8 {
+        self.data()[0] >> 4
+    }
+
+    pub fn traffic_class(&self) -> u8 {
+        (self.data()[0] << 4) | (self.data()[1] >> 4)
+    }
+
+    pub fn flow_label(&self) -> u32 {
+        let data = self.data();
+        ((data[1] as u32 & 0x0f) << 16) | ((data[2] as u32) << 8) | data[3] as u32
+    }
+
+    pub fn payload_length(&self) -> u16 {
+        u16::from_be_bytes([self.data()[4], self.data()[5]])
+    }
+
+    pub fn next_header(&self) -> u8 {
+        self.data()[6]
+    }
+
+    pub fn hop_limit(&self) -> u8 {
+        self.data()[7]
+    }
+
+    pub fn src_address(&self) -> Ipv6Address {
+        self.address(8)
+    }
+
+    pub fn dst_address(&self) -> Ipv6Address {
+        self.address(24)
+    }
+
+    /// ヘッダと payload length 分のペイロード
+    pub fn packet_bytes(&self) -> &[u8] {
+        &self.data()[..IPV6_HEADER_SIZE + self.payload_length() as usize]
+    }
+
+    /// 拡張ヘッダを含む固定ヘッダの後ろ
+    pub fn payload(&self) -> &[u8] {
+        &self.data()[IPV6_HEADER_SIZE..IPV6_HEADER_SIZE + self.payload_length() as usize]
+    }
+
+    fn address(&self, index: usize) -> Ipv6Address {
+        let mut octets = [0; IPV6_ADDRESS_SIZE];
+        octets.copy_from_slice(&self.data()[index..index + IPV6_ADDRESS_SIZE]);
+        Ipv6Address::from(octets)
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> Ipv6Header<T> {
+    fn data_mut(&mut self) -> &mut [u8] {
+        self.buffer.as_mut()
+    }
+
+    pub fn set_version_traffic_class_flow_label(&mut self, traffic_class: u8, flow_label: u32) {
+        let data = self.data_mut();
+        data[0] = (IPV6_VERSION << 4) | (traffic_class >> 4);
+        data[1] = (traffic_class << 4) | ((flow_label >> 16) as u8 & 0x0f);
+        data[2] = (flow_label >> 8) as u8;
+        data[3] = flow_label as u8;
+    }
+
+    pub fn set_payload_length(&mut self, length: u16) {
+        self.data_mut()[4..6].copy_from_slice(&length.to_be_bytes());
+    }
+
+    pub fn set_next_header(&mut self, next_header: u8) {
+        self.data_mut()[6] = next_header;
+    }
+
+    pub fn set_hop_limit(&mut self, hop_limit: u8) {
+        self.data_mut()[7] = hop_limit;
+    }
+
+    pub fn set_src_address(&mut self, address: Ipv6Address) {
+        self.data_mut()[8..24].copy_from_slice(&address.octets());
+    }
+
+    pub fn set_dst_address(&mut self, address: Ipv6Address) {
+        self.data_mut()[24..40].copy_from_slice(&address.octets());
+    }
+}
+
+/// 送信するパケットを組み立てる
+pub struct Ipv6HeaderBuilder {
+    traffic_class: u8,
+    flow_label: u32,
+    next_header: u8,
+    hop_limit: u8,
+    src: Ipv6Address,
+    dst: Ipv6Address,
+}
+
+impl Ipv6HeaderBuilder {
+    pub fn new(next_header: u8, src: Ipv6Address, dst: Ipv6Address) -> Self {
+        Ipv6HeaderBuilder {
+            traffic_class: 0,
+            flow_label: 0,
+            next_header,
+            hop_limit: IPV6_HOP_LIMIT_DEFAULT,
+            src,
+            dst,
+        }
+    }
+
+    pub fn traffic_class(mut self, traffic_class: u8) -> Self {
+        self.traffic_class = traffic_class;
+        self
+    }
+
+    /// 下位 20 ビットだけを使う
+    pub fn flow_label(mut self, flow_label: u32) -> Self {
+        self.flow_label = flow_label & 0x000f_ffff;
+        self
+    }
+
+    pub fn hop_limit(mut self, hop_limit: u8) -> Self {
+        self.hop_limit = hop_limit;
+        self
+    }
+
+    pub fn build(&self, payload: &[u8]) -> Result<Vec<u8>, Ipv6HeaderError> {
+        if payload.len() > u16::MAX as usize {
+            return Err(Ipv6HeaderError::new(Ipv6HeaderErrorKind::PayloadLength));
+        }
+        let mut packet = vec![0; IPV6_HEADER_SIZE + payload.len()];
+        {
+            let mut header = Ipv6Header::new_unchecked(&mut packet[..]);
+            header.set_version_traffic_class_flow_label(self.traffic_class, self.flow_label);
+            header.set_payload_length(payload.len() as u16);
+            header.set_next_header(self.next_header);
+            header.set_hop_limit(self.hop_limit);
+            header.set_src_address(self.src);
+            header.set_dst_address(self.dst);
+        }
+        packet[IPV6_HEADER_SIZE..].copy_from_slice(payload);
+        Ok(packet)
+    }
+}
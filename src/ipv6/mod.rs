@@ -0,0 +1,683 @@
+use std::fmt;
+use std::net::{AddrParseError, Ipv6Addr};
+use std::ptr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::icmpv6::{self, Icmpv6ParameterProblemCode};
+use crate::ipv4::Protocol;
+use crate::net::{
+    NetDevice, NetDeviceErrorKind, NetInterface, NetInterfaceFamily, NetInterfaceType, NetProtocol,
+    NetProtocolErrorKind, NetProtocolType, HARDWARE_ADDRESS_LENGTH, NET_DEVICES,
+};
+
+pub mod header;
+pub mod nd;
+pub mod slaac;
+
+pub use header::{
+    Ipv6Header, Ipv6HeaderBuilder, Ipv6HeaderError, Ipv6HeaderErrorKind, IPV6_HOP_LIMIT_DEFAULT,
+};
+
+pub const IPV6_VERSION: u8 = 6;
+pub const IPV6_HEADER_SIZE: usize = 40;
+pub const IPV6_ADDRESS_SIZE: usize = 16;
+pub const IPV6_PREFIX_LENGTH_MAX: u8 = 128;
+/// すべてのリンクが運べなければならない最小の MTU (RFC 8200 5)
+pub const IPV6_MTU_MIN: u16 = 1280;
+
+/// Next Header の値 (IPv4 のプロトコル番号と同じ空間)
+pub const IPV6_NEXT_HEADER_HOP_BY_HOP: u8 = 0;
+pub const IPV6_NEXT_HEADER_ROUTING: u8 = 43;
+pub const IPV6_NEXT_HEADER_FRAGMENT: u8 = 44;
+pub const IPV6_NEXT_HEADER_ICMPV6: u8 = 58;
+pub const IPV6_NEXT_HEADER_NO_NEXT: u8 = 59;
+pub const IPV6_NEXT_HEADER_DESTINATION: u8 = 60;
+
+/// ネットワークバイトオーダーで保持する IPv6 アドレス
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ipv6Address([u8; IPV6_ADDRESS_SIZE]);
+
+pub const IPV6_ADDRESS_UNSPECIFIED: Ipv6Address = Ipv6Address([0; IPV6_ADDRESS_SIZE]);
+pub const IPV6_ADDRESS_LOOPBACK: Ipv6Address =
+    Ipv6Address([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+/// ff02::1
+pub const IPV6_ADDRESS_ALL_NODES: Ipv6Address =
+    Ipv6Address([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+/// ff02::2
+pub const IPV6_ADDRESS_ALL_ROUTERS: Ipv6Address =
+    Ipv6Address([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+impl fmt::Display for Ipv6Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // :: による省略は std の表記に合わせる (RFC 5952)
+        write!(f, "{}", Ipv6Addr::from(self.0))
+    }
+}
+
+impl FromStr for Ipv6Address {
+    type Err = AddrParseError;
+
+    fn from_str(address_str: &str) -> Result<Self, Self::Err> {
+        Ok(Ipv6Address::from(Ipv6Addr::from_str(address_str)?))
+    }
+}
+
+impl From<Ipv6Addr> for Ipv6Address {
+    fn from(addr: Ipv6Addr) -> Self {
+        Ipv6Address(addr.octets())
+    }
+}
+
+impl From<Ipv6Address> for Ipv6Addr {
+    fn from(addr: Ipv6Address) -> Self {
+        Ipv6Addr::from(addr.0)
+    }
+}
+
+impl From<[u8; IPV6_ADDRESS_SIZE]> for Ipv6Address {
+    fn from(octets: [u8; IPV6_ADDRESS_SIZE]) -> Self {
+        Ipv6Address(octets)
+    }
+}
+
+impl Ipv6Address {
+    /// 16 ビットずつ 8 つの値から作る
+    pub fn from_segments(segments: [u16; 8]) -> Self {
+        let mut octets = [0; IPV6_ADDRESS_SIZE];
+        for (i, segment) in segments.iter().enumerate() {
+            octets[i * 2..i * 2 + 2].copy_from_slice(&segment.to_be_bytes());
+        }
+        Ipv6Address(octets)
+    }
+
+    pub fn from_u128(u: u128) -> Self {
+        Ipv6Address(u.to_be_bytes())
+    }
+
+    pub fn to_u128(&self) -> u128 {
+        u128::from_be_bytes(self.0)
+    }
+
+    pub fn octets(&self) -> [u8; IPV6_ADDRESS_SIZE] {
+        self.0
+    }
+
+    pub fn segments(&self) -> [u16; 8] {
+        let mut segments = [0; 8];
+        for (i, segment) in segments.iter_mut().enumerate() {
+            *segment = u16::from_be_bytes([self.0[i * 2], self.0[i * 2 + 1]]);
+        }
+        segments
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == IPV6_ADDRESS_UNSPECIFIED
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        *self == IPV6_ADDRESS_LOOPBACK
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] == 0xff
+    }
+
+    /// fe80::/10
+    pub fn is_link_local(&self) -> bool {
+        self.0[0] == 0xfe && self.0[1] & 0xc0 == 0x80
+    }
+
+    /// 要請ノードマルチキャストアドレス ff02::1:ffXX:XXXX (RFC 4291 2.7.1)
+    pub fn solicited_node(&self) -> Ipv6Address {
+        let mut octets = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, 0, 0, 0];
+        octets[13..].copy_from_slice(&self.0[13..]);
+        Ipv6Address(octets)
+    }
+}
+
+#[derive(Debug)]
+pub struct Ipv6NetworkError {
+    pub kind: Ipv6NetworkErrorKind,
+}
+
+impl Ipv6NetworkError {
+    pub fn new(kind: Ipv6NetworkErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug)]
+pub enum Ipv6NetworkErrorKind {
+    InvalidAddress,
+    InvalidPrefix,
+}
+
+/// アドレスとプレフィックス長の組 (例: 2001:db8::1/64)
+/// アドレスはインターフェース ID を含んだまま保持する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ipv6Network {
+    address: Ipv6Address,
+    prefix: u8,
+}
+
+impl Ipv6Network {
+    pub fn new(address: Ipv6Address, prefix: u8) -> Result<Self, Ipv6NetworkError> {
+        if prefix > IPV6_PREFIX_LENGTH_MAX {
+            return Err(Ipv6NetworkError::new(Ipv6NetworkErrorKind::InvalidPrefix));
+        }
+        Ok(Ipv6Network { address, prefix })
+    }
+
+    pub fn address(&self) -> Ipv6Address {
+        self.address
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn network(&self) -> Ipv6Address {
+        Ipv6Address::from_u128(self.address.to_u128() & self.mask())
+    }
+
+    pub fn contains(&self, address: Ipv6Address) -> bool {
+        (address.to_u128() & self.mask()) == self.network().to_u128()
+    }
+
+    fn mask(&self) -> u128 {
+        u128::MAX
+            .checked_shl((IPV6_PREFIX_LENGTH_MAX - self.prefix) as u32)
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Ipv6Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+impl FromStr for Ipv6Network {
+    type Err = Ipv6NetworkError;
+
+    fn from_str(network_str: &str) -> Result<Self, Self::Err> {
+        let mut split = network_str.splitn(2, '/');
+        let address = split
+            .next()
+            .and_then(|s| Ipv6Address::from_str(s).ok())
+            .ok_or_else(|| Ipv6NetworkError::new(Ipv6NetworkErrorKind::InvalidAddress))?;
+        let prefix = match split.next() {
+            Some(s) => s
+                .parse::<u8>()
+                .map_err(|_| Ipv6NetworkError::new(Ipv6NetworkErrorKind::InvalidPrefix))?,
+            None => IPV6_PREFIX_LENGTH_MAX,
+        };
+        Ipv6Network::new(address, prefix)
+    }
+}
+
+lazy_static! {
+    static ref IPV6_INTERFACES: Mutex<Vec<Ipv6Interface>> = Mutex::new(Vec::new());
+}
+
+#[derive(Clone)]
+pub struct Ipv6Interface {
+    pub net_interface: NetInterface,
+    pub unicast: Ipv6Address,
+    pub prefix: u8,
+}
+
+impl Ipv6Interface {
+    /// 2001:db8::1/64 のような表記から作る。プレフィックス長を省くと /128
+    pub fn alloc(network: &str) -> Option<Box<Self>> {
+        match Ipv6Network::from_str(network) {
+            Ok(network) => Some(Ipv6Interface::from_network(network)),
+            Err(_) => {
+                eprintln!("Invalid IPv6 network");
+                None
+            }
+        }
+    }
+
+    pub fn from_network(network: Ipv6Network) -> Box<Self> {
+        let mut interface = Ipv6Interface::default();
+        interface.net_interface.family = NetInterfaceFamily::Ipv6;
+        interface.unicast = network.address();
+        interface.prefix = network.prefix();
+        Box::new(interface)
+    }
+
+    pub fn register(interface: Box<Self>, dev: &NetDevice) -> Result<(), Ipv6Error> {
+        let iface = interface.clone();
+        if let Err(e) = dev.add_interface(NetInterfaceType::Ipv6(*interface)) {
+            return match e.kind {
+                NetDeviceErrorKind::AlreadyRegistered => Ok(()),
+                _ => {
+                    eprintln!("add interface is failed");
+                    Err(Ipv6Error::new(Ipv6ErrorKind::NoInterface))
+                }
+            };
+        }
+        IPV6_INTERFACES.lock().unwrap().push(*iface);
+        Ok(())
+    }
+
+    /// デバイスから unicast のインターフェースを取り除く
+    pub fn unregister(unicast: Ipv6Address, dev: &NetDevice) -> Result<(), Ipv6Error> {
+        if dev.remove_ipv6_interface(unicast).is_none() {
+            eprintln!("interface not found DEV={} ADDR={}", dev.name, unicast);
+            return Err(Ipv6Error::new(Ipv6ErrorKind::NoInterface));
+        }
+        IPV6_INTERFACES
+            .lock()
+            .unwrap()
+            .retain(|entry| entry.unicast != unicast);
+        Ok(())
+    }
+
+    pub fn select(address: Ipv6Address) -> Option<Box<Ipv6Interface>> {
+        let interfaces = IPV6_INTERFACES.lock().unwrap();
+        interfaces
+            .iter()
+            .find(|entry| entry.unicast == address)
+            .map(|entry| Box::new(entry.clone()))
+    }
+
+    /// 宛先と共通するプレフィックスが最長のアドレスを送信元に選ぶ (RFC 6724 の規則 8 の簡略版)
+    /// リンクローカル宛てにはリンクローカルアドレスを使う
+    pub fn select_source(dst: Ipv6Address) -> Option<Box<Ipv6Interface>> {
+        Ipv6Interface::select_source_at(dst, Instant::now())
+    }
+
+    /// now の時点で優先寿命が切れたアドレスは、他に使えるものがあれば選ばない (RFC 6724 の規則 3)
+    pub fn select_source_at(dst: Ipv6Address, now: Instant) -> Option<Box<Ipv6Interface>> {
+        let deprecated = slaac::deprecated_addresses(now);
+        let interfaces = IPV6_INTERFACES.lock().unwrap();
+        let candidates = || {
+            interfaces
+                .iter()
+                .filter(|entry| entry.unicast.is_link_local() == dst.is_link_local())
+        };
+        let common_prefix =
+            |entry: &&Ipv6Interface| (entry.unicast.to_u128() ^ dst.to_u128()).leading_zeros();
+        candidates()
+            .filter(|entry| !deprecated.contains(&entry.unicast))
+            .max_by_key(common_prefix)
+            .or_else(|| candidates().max_by_key(common_prefix))
+            .or_else(|| interfaces.iter().next())
+            .map(|entry| Box::new(entry.clone()))
+    }
+
+    /// このインターフェースが登録されているデバイスを探す
+    pub fn device(&self) -> Option<&'static NetDevice> {
+        let devices = NET_DEVICES.lock();
+        for dev in devices.items.iter() {
+            let dev: &'static NetDevice = dev;
+            for interface in dev.get_interfaces(NetInterfaceFamily::Ipv6) {
+                if let NetInterfaceType::Ipv6(interface) = interface {
+                    if interface.unicast == self.unicast {
+                        return Some(dev);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    pub fn network(&self) -> Ipv6Network {
+        Ipv6Network {
+            address: self.unicast,
+            prefix: self.prefix,
+        }
+    }
+
+    pub fn contains(&self, address: Ipv6Address) -> bool {
+        self.network().contains(address)
+    }
+}
+
+impl Default for Ipv6Interface {
+    fn default() -> Self {
+        Self {
+            net_interface: NetInterface::default(),
+            unicast: IPV6_ADDRESS_UNSPECIFIED,
+            prefix: IPV6_PREFIX_LENGTH_MAX,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Ipv6Error {
+    pub kind: Ipv6ErrorKind,
+}
+
+impl Ipv6Error {
+    pub fn new(kind: Ipv6ErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ipv6ErrorKind {
+    AlreadyRegistered,
+    NoInterface,
+    NoDevice,
+    InvalidHeader,
+    TooLong,
+    TransmitError,
+}
+
+/// 上位プロトコルのハンドラ
+/// IPv6 ヘッダを含むパケット全体、上位ヘッダの開始位置、受信したデバイスを受け取る
+pub type Ipv6ProtocolHandler = fn(&[u8], usize, &'static NetDevice);
+
+struct Ipv6ProtocolEntry {
+    next_header: u8,
+    handler: Ipv6ProtocolHandler,
+}
+
+lazy_static! {
+    static ref IPV6_PROTOCOLS: Mutex<Vec<Ipv6ProtocolEntry>> = Mutex::new(Vec::new());
+}
+
+pub fn protocol_register(next_header: u8, handler: Ipv6ProtocolHandler) -> Result<(), Ipv6Error> {
+    let mut protocols = IPV6_PROTOCOLS.lock().unwrap();
+    if protocols.iter().any(|p| p.next_header == next_header) {
+        eprintln!("IPv6 protocol is already registered NEXT={}", next_header);
+        return Err(Ipv6Error::new(Ipv6ErrorKind::AlreadyRegistered));
+    }
+    protocols.push(Ipv6ProtocolEntry {
+        next_header,
+        handler,
+    });
+    println!("IPv6 protocol registered NEXT={}", next_header);
+    Ok(())
+}
+
+fn protocol_handler(next_header: u8) -> Option<Ipv6ProtocolHandler> {
+    let protocols = IPV6_PROTOCOLS.lock().unwrap();
+    protocols
+        .iter()
+        .find(|p| p.next_header == next_header)
+        .map(|p| p.handler)
+}
+
+/// 拡張ヘッダを読み飛ばし、上位プロトコルの Next Header と開始位置を返す
+/// フラグメントヘッダと、経由地が残っている経路ヘッダは扱わない
+pub fn upper_layer(packet: &[u8]) -> Result<(u8, usize), Ipv6ErrorKind> {
+    walk(packet).map(|(next_header, offset, _)| (next_header, offset))
+}
+
+/// upper_layer に加えて、最後の Next Header フィールドの位置を返す
+/// 未知の Next Header を Parameter Problem で知らせるときのポインタに使う
+fn walk(packet: &[u8]) -> Result<(u8, usize, usize), Ipv6ErrorKind> {
+    let header = Ipv6Header::new_checked(packet).map_err(|_| Ipv6ErrorKind::InvalidHeader)?;
+    let packet = header.packet_bytes();
+    let mut next_header = header.next_header();
+    let mut offset = IPV6_HEADER_SIZE;
+    let mut pointer = 6;
+    loop {
+        match next_header {
+            IPV6_NEXT_HEADER_HOP_BY_HOP
+            | IPV6_NEXT_HEADER_DESTINATION
+            | IPV6_NEXT_HEADER_ROUTING => {
+                let ext = packet
+                    .get(offset..offset + 8)
+                    .ok_or(Ipv6ErrorKind::InvalidHeader)?;
+                let length = (ext[1] as usize + 1) * 8;
+                if offset + length > packet.len() {
+                    return Err(Ipv6ErrorKind::InvalidHeader);
+                }
+                // segments left が 0 でない経路ヘッダは転送しなければならない
+                if next_header == IPV6_NEXT_HEADER_ROUTING && ext[3] != 0 {
+                    return Err(Ipv6ErrorKind::InvalidHeader);
+                }
+                next_header = ext[0];
+                pointer = offset;
+                offset += length;
+            }
+            IPV6_NEXT_HEADER_FRAGMENT => return Err(Ipv6ErrorKind::InvalidHeader),
+            _ => return Ok((next_header, offset, pointer)),
+        }
+    }
+}
+
+/// 受信したデバイスのアドレス宛て、全ノード宛て、要請ノードマルチキャスト宛てを受け取る
+/// 重複検出中のアドレスの要請ノードマルチキャスト宛ても受け取る
+fn accepts(dev: &NetDevice, dst: Ipv6Address) -> bool {
+    if dst == IPV6_ADDRESS_ALL_NODES || nd::is_tentative_group(dev, dst) {
+        return true;
+    }
+    dev.get_interfaces(NetInterfaceFamily::Ipv6)
+        .into_iter()
+        .any(|interface| match interface {
+            NetInterfaceType::Ipv6(interface) => {
+                interface.unicast == dst || interface.unicast.solicited_node() == dst
+            }
+            _ => false,
+        })
+}
+
+pub fn input(data: &Vec<u8>, dev: &'static NetDevice) {
+    let ipv6_hdr = match Ipv6Header::new_checked(&data[..]) {
+        Ok(hdr) => hdr,
+        Err(e) => {
+            eprintln!("IPv6 input error: {}, length={}", e.kind, data.len());
+            return;
+        }
+    };
+    let dst = ipv6_hdr.dst_address();
+    if !accepts(dev, dst) {
+        // 転送はしない
+        return;
+    }
+    let packet = ipv6_hdr.packet_bytes();
+    let (next_header, offset, pointer) = match walk(packet) {
+        Ok(upper) => upper,
+        Err(_) => {
+            eprintln!("IPv6 extension header is not supported");
+            return;
+        }
+    };
+
+    eprintln!(
+        "IPv6 input DEV={} NEXT={} SRC={} DST={} LEN={}",
+        dev.name,
+        next_header,
+        ipv6_hdr.src_address(),
+        dst,
+        ipv6_hdr.payload_length()
+    );
+
+    match protocol_handler(next_header) {
+        Some(handler) => handler(packet, offset, dev),
+        None if next_header == IPV6_NEXT_HEADER_NO_NEXT => (),
+        None => {
+            eprintln!(
+                "IPv6 protocol is not registered NEXT={} ({})",
+                next_header,
+                Protocol::from_u8(next_header)
+            );
+            icmpv6::parameter_problem(
+                Icmpv6ParameterProblemCode::NextHeader,
+                pointer as u32,
+                packet,
+            );
+        }
+    }
+}
+
+/// src が未指定なら宛先に合わせて送信元アドレスを選ぶ
+pub fn output(
+    next_header: u8,
+    data: &[u8],
+    src: Ipv6Address,
+    dst: Ipv6Address,
+) -> Result<usize, Ipv6Error> {
+    let interface = if src.is_unspecified() {
+        Ipv6Interface::select_source(dst)
+    } else {
+        Ipv6Interface::select(src)
+    };
+    let interface = match interface {
+        Some(interface) => interface,
+        None => {
+            eprintln!("IPv6 interface not found SRC={} DST={}", src, dst);
+            return Err(Ipv6Error::new(Ipv6ErrorKind::NoInterface));
+        }
+    };
+    let dev = match interface.device() {
+        Some(dev) => dev,
+        None => {
+            eprintln!("IPv6 device not found ADDR={}", interface.unicast);
+            return Err(Ipv6Error::new(Ipv6ErrorKind::NoDevice));
+        }
+    };
+    output_device(
+        dev,
+        next_header,
+        data,
+        interface.unicast,
+        dst,
+        IPV6_HOP_LIMIT_DEFAULT,
+    )
+}
+
+/// 経路を引かずに dev から送る。src は呼び出し側が決める (未指定アドレスでもよい)
+/// アドレス解決が必要なデバイスでは近隣キャッシュを引き、解決するまでパケットを溜める
+pub fn output_device(
+    dev: &NetDevice,
+    next_header: u8,
+    data: &[u8],
+    src: Ipv6Address,
+    dst: Ipv6Address,
+    hop_limit: u8,
+) -> Result<usize, Ipv6Error> {
+    let packet = build_packet(dev, next_header, data, src, dst, hop_limit)?;
+    if !nd::needs_resolution(dev) {
+        transmit(dev, &packet, &[])?;
+        return Ok(data.len());
+    }
+    let next_hop = next_hop(dev, dst);
+    match nd::resolve(dev, next_hop, &packet) {
+        Some(hwaddr) => transmit(dev, &packet, &hwaddr)?,
+        None => eprintln!("IPv6 waiting for neighbor resolution NEXT_HOP={}", next_hop),
+    }
+    Ok(data.len())
+}
+
+/// リンク上にない宛先にはデフォルトルータを経由して送る
+fn next_hop(dev: &NetDevice, dst: Ipv6Address) -> Ipv6Address {
+    if dst.is_multicast() || dst.is_link_local() || slaac::is_on_link(&dev.name, dst) {
+        return dst;
+    }
+    let on_link = dev
+        .get_interfaces(NetInterfaceFamily::Ipv6)
+        .into_iter()
+        .any(|interface| match interface {
+            NetInterfaceType::Ipv6(interface) => interface.contains(dst),
+            _ => false,
+        });
+    if on_link {
+        return dst;
+    }
+    slaac::default_router(&dev.name).unwrap_or(dst)
+}
+
+/// 近隣キャッシュを引かずに hwaddr へ送る
+pub fn output_link(
+    dev: &NetDevice,
+    next_header: u8,
+    data: &[u8],
+    src: Ipv6Address,
+    dst: Ipv6Address,
+    hop_limit: u8,
+    hwaddr: &[u8],
+) -> Result<usize, Ipv6Error> {
+    let packet = build_packet(dev, next_header, data, src, dst, hop_limit)?;
+    transmit(dev, &packet, hwaddr)?;
+    Ok(data.len())
+}
+
+fn build_packet(
+    dev: &NetDevice,
+    next_header: u8,
+    data: &[u8],
+    src: Ipv6Address,
+    dst: Ipv6Address,
+    hop_limit: u8,
+) -> Result<Vec<u8>, Ipv6Error> {
+    let packet = Ipv6HeaderBuilder::new(next_header, src, dst)
+        .hop_limit(hop_limit)
+        .build(data)
+        .map_err(|e| {
+            eprintln!("IPv6 header build error: {}", e.kind);
+            Ipv6Error::new(Ipv6ErrorKind::InvalidHeader)
+        })?;
+    // 途中で分割されない IPv6 では送信元が MTU に収める。フラグメントヘッダはまだ付けられない
+    if packet.len() > dev.mtu as usize {
+        eprintln!(
+            "IPv6 packet too long DEV={} LEN={} MTU={}",
+            dev.name,
+            packet.len(),
+            dev.mtu
+        );
+        return Err(Ipv6Error::new(Ipv6ErrorKind::TooLong));
+    }
+
+    eprintln!(
+        "IPv6 output DEV={} NEXT={} SRC={} DST={} LEN={}",
+        dev.name,
+        next_header,
+        src,
+        dst,
+        data.len()
+    );
+    Ok(packet)
+}
+
+/// 組み立て済みのパケットを送る。hwaddr が空ならリンク層の宛先を渡さない
+fn transmit(dev: &NetDevice, packet: &[u8], hwaddr: &[u8]) -> Result<(), Ipv6Error> {
+    let mut dst = [0; HARDWARE_ADDRESS_LENGTH];
+    let dst = if hwaddr.is_empty() {
+        ptr::null_mut()
+    } else {
+        let length = hwaddr.len().min(HARDWARE_ADDRESS_LENGTH);
+        dst[..length].copy_from_slice(&hwaddr[..length]);
+        dst.as_mut_ptr()
+    };
+    if dev
+        .output(NetProtocolType::Ipv6 as u16, packet, dst)
+        .is_err()
+    {
+        return Err(Ipv6Error::new(Ipv6ErrorKind::TransmitError));
+    }
+    Ok(())
+}
+
+/// ICMPv6 や TCP/UDP のチェックサムに含める疑似ヘッダの和 (RFC 8200 8.1)
+pub fn pseudo_header_sum(src: Ipv6Address, dst: Ipv6Address, next_header: u8, length: u32) -> u32 {
+    let mut sum: u32 = 0;
+    for address in [src, dst].iter() {
+        for segment in address.segments().iter() {
+            sum += *segment as u32;
+        }
+    }
+    sum + (length >> 16) + (length & 0xffff) + next_header as u32
+}
+
+pub fn init() {
+    let r = NetProtocol::register(NetProtocolType::Ipv6 as u16, input);
+    match r {
+        Ok(()) => {
+            nd::init();
+            slaac::init();
+        }
+        Err(e) => match e.kind {
+            NetProtocolErrorKind::AlreadyRegistered => (),
+        },
+    }
+}
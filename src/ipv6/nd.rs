@@ -0,0 +1,860 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{
+    Ipv6Address, Ipv6Header, Ipv6Interface, Ipv6Network, IPV6_ADDRESS_ALL_NODES, IPV6_ADDRESS_SIZE,
+    IPV6_ADDRESS_UNSPECIFIED, IPV6_NEXT_HEADER_ICMPV6,
+};
+use crate::ethernet::MAC_LENGTH;
+use crate::icmpv6::{self, Icmpv6Message, Icmpv6Type};
+use crate::net::{
+    device_by_name, NetDevice, NetDeviceFlag, NetInterfaceFamily, NetInterfaceType, NetTimer,
+};
+
+/// ND のメッセージはリンクの外から届いてはならないので、ホップリミットは 255 で送受信する
+pub const ND_HOP_LIMIT: u8 = 255;
+
+pub const ND_OPTION_SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+pub const ND_OPTION_TARGET_LINK_LAYER_ADDRESS: u8 = 2;
+pub const ND_OPTION_PREFIX_INFORMATION: u8 = 3;
+pub const ND_OPTION_MTU: u8 = 5;
+
+/// Prefix Information オプションのフラグ (RFC 4861 4.6.2)
+pub const ND_PREFIX_FLAG_ON_LINK: u8 = 0x80;
+pub const ND_PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+/// Prefix Information オプションの値の長さ (型と長さの 2 オクテットを除く)
+const ND_PREFIX_INFORMATION_SIZE: usize = 30;
+
+/// Neighbor Advertisement のフラグ (RFC 4861 4.4)
+pub const ND_FLAG_ROUTER: u32 = 0x8000_0000;
+pub const ND_FLAG_SOLICITED: u32 = 0x4000_0000;
+pub const ND_FLAG_OVERRIDE: u32 = 0x2000_0000;
+
+/// RFC 4861 10 の既定値
+pub const MAX_MULTICAST_SOLICIT: u32 = 3;
+pub const MAX_UNICAST_SOLICIT: u32 = 3;
+pub const RETRANS_TIMER: Duration = Duration::from_secs(1);
+pub const REACHABLE_TIME: Duration = Duration::from_secs(30);
+pub const DELAY_FIRST_PROBE_TIME: Duration = Duration::from_secs(5);
+/// DAD で送る Neighbor Solicitation の数 (RFC 4862 5.1)
+pub const DUP_ADDR_DETECT_TRANSMITS: u32 = 1;
+
+/// 解決待ちの間に溜めておくパケットの数
+const ND_PENDING_MAX: usize = 8;
+/// Neighbor Solicitation/Advertisement の固定部 (予約領域の後ろのターゲットアドレス)
+const ND_TARGET_SIZE: usize = IPV6_ADDRESS_SIZE;
+
+/// 近隣キャッシュのエントリの到達可能性の状態 (RFC 4861 7.3.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighborState {
+    Incomplete,
+    Reachable,
+    Stale,
+    Delay,
+    Probe,
+}
+
+impl fmt::Display for NeighborState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            NeighborState::Incomplete => "INCOMPLETE",
+            NeighborState::Reachable => "REACHABLE",
+            NeighborState::Stale => "STALE",
+            NeighborState::Delay => "DELAY",
+            NeighborState::Probe => "PROBE",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// ルータ広告で配られるプレフィックス。寿命は秒で 0xffffffff は無期限
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NdPrefixInformation {
+    pub prefix_length: u8,
+    pub on_link: bool,
+    pub autonomous: bool,
+    pub valid_lifetime: u32,
+    pub preferred_lifetime: u32,
+    pub prefix: Ipv6Address,
+}
+
+impl NdPrefixInformation {
+    fn encode(&self) -> Vec<u8> {
+        let mut value = vec![self.prefix_length, 0];
+        if self.on_link {
+            value[1] |= ND_PREFIX_FLAG_ON_LINK;
+        }
+        if self.autonomous {
+            value[1] |= ND_PREFIX_FLAG_AUTONOMOUS;
+        }
+        value.extend_from_slice(&self.valid_lifetime.to_be_bytes());
+        value.extend_from_slice(&self.preferred_lifetime.to_be_bytes());
+        value.extend_from_slice(&[0; 4]);
+        value.extend_from_slice(&self.prefix.octets());
+        value
+    }
+
+    fn parse(value: &[u8]) -> Option<Self> {
+        if value.len() != ND_PREFIX_INFORMATION_SIZE {
+            return None;
+        }
+        let mut prefix = [0; IPV6_ADDRESS_SIZE];
+        prefix.copy_from_slice(&value[14..30]);
+        Some(NdPrefixInformation {
+            prefix_length: value[0],
+            on_link: value[1] & ND_PREFIX_FLAG_ON_LINK != 0,
+            autonomous: value[1] & ND_PREFIX_FLAG_AUTONOMOUS != 0,
+            valid_lifetime: u32::from_be_bytes([value[2], value[3], value[4], value[5]]),
+            preferred_lifetime: u32::from_be_bytes([value[6], value[7], value[8], value[9]]),
+            prefix: Ipv6Address::from(prefix),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NdOption {
+    SourceLinkLayerAddress(Vec<u8>),
+    TargetLinkLayerAddress(Vec<u8>),
+    PrefixInformation(NdPrefixInformation),
+    Mtu(u32),
+    Unknown(u8, Vec<u8>),
+}
+
+impl NdOption {
+    /// 8 オクテット単位の長さに合わせて 0 で埋める
+    pub fn encode(&self) -> Vec<u8> {
+        let (option_type, value) = match self {
+            NdOption::SourceLinkLayerAddress(value) => {
+                (ND_OPTION_SOURCE_LINK_LAYER_ADDRESS, value.clone())
+            }
+            NdOption::TargetLinkLayerAddress(value) => {
+                (ND_OPTION_TARGET_LINK_LAYER_ADDRESS, value.clone())
+            }
+            NdOption::PrefixInformation(info) => (ND_OPTION_PREFIX_INFORMATION, info.encode()),
+            NdOption::Mtu(mtu) => {
+                let mut value = vec![0, 0];
+                value.extend_from_slice(&mtu.to_be_bytes());
+                (ND_OPTION_MTU, value)
+            }
+            NdOption::Unknown(option_type, value) => (*option_type, value.clone()),
+        };
+        let units = (2 + value.len()).div_ceil(8);
+        let mut data = vec![0; units * 8];
+        data[0] = option_type;
+        data[1] = units as u8;
+        data[2..2 + value.len()].copy_from_slice(&value);
+        data
+    }
+
+    /// 長さが 0 のオプションを含むメッセージは捨てなければならない (RFC 4861 4.6)
+    pub fn parse_all(data: &[u8]) -> Option<Vec<NdOption>> {
+        let mut options = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            if rest.len() < 2 || rest[1] == 0 {
+                return None;
+            }
+            let length = rest[1] as usize * 8;
+            if length > rest.len() {
+                return None;
+            }
+            let value = rest[2..length].to_vec();
+            options.push(match rest[0] {
+                ND_OPTION_SOURCE_LINK_LAYER_ADDRESS => NdOption::SourceLinkLayerAddress(value),
+                ND_OPTION_TARGET_LINK_LAYER_ADDRESS => NdOption::TargetLinkLayerAddress(value),
+                ND_OPTION_PREFIX_INFORMATION => match NdPrefixInformation::parse(&value) {
+                    Some(info) => NdOption::PrefixInformation(info),
+                    None => NdOption::Unknown(ND_OPTION_PREFIX_INFORMATION, value),
+                },
+                ND_OPTION_MTU if value.len() == 6 => {
+                    NdOption::Mtu(u32::from_be_bytes([value[2], value[3], value[4], value[5]]))
+                }
+                option_type => NdOption::Unknown(option_type, value),
+            });
+            rest = &rest[length..];
+        }
+        Some(options)
+    }
+}
+
+/// リンク層アドレスのオプションから、デバイスのアドレス長の分だけ取り出す
+pub fn link_layer_address(options: &[NdOption], source: bool, length: usize) -> Option<Vec<u8>> {
+    options.iter().find_map(|option| match option {
+        NdOption::SourceLinkLayerAddress(value) if source && value.len() >= length => {
+            Some(value[..length].to_vec())
+        }
+        NdOption::TargetLinkLayerAddress(value) if !source && value.len() >= length => {
+            Some(value[..length].to_vec())
+        }
+        _ => None,
+    })
+}
+
+/// マルチキャストアドレスに対応する Ethernet アドレス 33:33:xx:xx:xx:xx (RFC 2464 7)
+pub fn multicast_hwaddr(address: Ipv6Address) -> [u8; MAC_LENGTH] {
+    let octets = address.octets();
+    [0x33, 0x33, octets[12], octets[13], octets[14], octets[15]]
+}
+
+#[derive(Debug, Clone)]
+pub struct NeighborEntry {
+    pub dev: String,
+    pub address: Ipv6Address,
+    pub hwaddr: Vec<u8>,
+    pub state: NeighborState,
+    probes: u32,
+    /// 状態ごとの次の処理の時刻 (再送、REACHABLE の期限、DELAY の期限)
+    timer: Instant,
+    pending: Vec<Vec<u8>>,
+}
+
+/// resolve の結果
+#[derive(Debug, PartialEq, Eq)]
+pub enum NeighborLookup {
+    Resolved(Vec<u8>),
+    /// パケットを溜めた。Neighbor Solicitation はすでに送ってある
+    Queued,
+    /// パケットを溜めた。新しいエントリなので Neighbor Solicitation を送る
+    Solicit,
+}
+
+/// 期限が来たエントリに送る Neighbor Solicitation
+#[derive(Debug, PartialEq, Eq)]
+pub struct NeighborProbe {
+    pub dev: String,
+    pub target: Ipv6Address,
+    /// Some ならユニキャストで確認する
+    pub hwaddr: Option<Vec<u8>>,
+}
+
+/// 近隣キャッシュ (RFC 4861 7.3)。時刻は呼び出し側が渡す
+#[derive(Default)]
+pub struct NeighborCache {
+    entries: Vec<NeighborEntry>,
+}
+
+impl NeighborCache {
+    pub fn new() -> Self {
+        NeighborCache {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn entries(&self) -> &[NeighborEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, dev: &str, address: Ipv6Address) -> Option<&NeighborEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.dev == dev && entry.address == address)
+    }
+
+    fn get_mut(&mut self, dev: &str, address: Ipv6Address) -> Option<&mut NeighborEntry> {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.dev == dev && entry.address == address)
+    }
+
+    /// 送信のためにリンク層アドレスを引く。解決できなければ packet を溜める (RFC 4861 7.3.3)
+    pub fn resolve(
+        &mut self,
+        dev: &str,
+        address: Ipv6Address,
+        packet: Vec<u8>,
+        now: Instant,
+    ) -> NeighborLookup {
+        let entry = match self.get_mut(dev, address) {
+            Some(entry) => entry,
+            None => {
+                self.entries.push(NeighborEntry {
+                    dev: dev.to_string(),
+                    address,
+                    hwaddr: Vec::new(),
+                    state: NeighborState::Incomplete,
+                    probes: 1,
+                    timer: now + RETRANS_TIMER,
+                    pending: vec![packet],
+                });
+                return NeighborLookup::Solicit;
+            }
+        };
+        match entry.state {
+            NeighborState::Incomplete => {
+                if entry.pending.len() >= ND_PENDING_MAX {
+                    entry.pending.remove(0);
+                }
+                entry.pending.push(packet);
+                return NeighborLookup::Queued;
+            }
+            NeighborState::Reachable if now >= entry.timer => {
+                entry.state = NeighborState::Delay;
+                entry.timer = now + DELAY_FIRST_PROBE_TIME;
+            }
+            NeighborState::Stale => {
+                entry.state = NeighborState::Delay;
+                entry.timer = now + DELAY_FIRST_PROBE_TIME;
+            }
+            _ => (),
+        }
+        NeighborLookup::Resolved(entry.hwaddr.clone())
+    }
+
+    /// Source Link-Layer Address オプション付きの要請を受けたときの更新 (RFC 4861 7.2.3)
+    /// 解決を待っていたパケットを返す
+    pub fn learn(
+        &mut self,
+        dev: &str,
+        address: Ipv6Address,
+        hwaddr: &[u8],
+        now: Instant,
+    ) -> Vec<Vec<u8>> {
+        let entry = match self.get_mut(dev, address) {
+            Some(entry) => entry,
+            None => {
+                self.entries.push(NeighborEntry {
+                    dev: dev.to_string(),
+                    address,
+                    hwaddr: hwaddr.to_vec(),
+                    state: NeighborState::Stale,
+                    probes: 0,
+                    timer: now,
+                    pending: Vec::new(),
+                });
+                return Vec::new();
+            }
+        };
+        if entry.state == NeighborState::Incomplete || entry.hwaddr != hwaddr {
+            entry.hwaddr = hwaddr.to_vec();
+            entry.state = NeighborState::Stale;
+            entry.timer = now;
+        }
+        std::mem::take(&mut entry.pending)
+    }
+
+    /// Neighbor Advertisement を受けたときの更新 (RFC 4861 7.2.5)
+    /// 解決を待っていたパケットを返す
+    pub fn advertise(
+        &mut self,
+        dev: &str,
+        address: Ipv6Address,
+        hwaddr: Option<&[u8]>,
+        flags: u32,
+        now: Instant,
+    ) -> Vec<Vec<u8>> {
+        let solicited = flags & ND_FLAG_SOLICITED != 0;
+        let override_flag = flags & ND_FLAG_OVERRIDE != 0;
+        let entry = match self.get_mut(dev, address) {
+            Some(entry) => entry,
+            // 要請していない相手の通知でエントリは作らない
+            None => return Vec::new(),
+        };
+        if entry.state == NeighborState::Incomplete {
+            let hwaddr = match hwaddr {
+                Some(hwaddr) => hwaddr,
+                None => return Vec::new(),
+            };
+            entry.hwaddr = hwaddr.to_vec();
+            entry.probes = 0;
+            if solicited {
+                entry.state = NeighborState::Reachable;
+                entry.timer = now + REACHABLE_TIME;
+            } else {
+                entry.state = NeighborState::Stale;
+                entry.timer = now;
+            }
+            return std::mem::take(&mut entry.pending);
+        }
+
+        let changed = hwaddr.is_some_and(|hwaddr| hwaddr != entry.hwaddr.as_slice());
+        if !override_flag && changed {
+            // 上書きしない通知で別のアドレスが届いたら、今のアドレスを疑うだけにする
+            if entry.state == NeighborState::Reachable {
+                entry.state = NeighborState::Stale;
+                entry.timer = now;
+            }
+            return Vec::new();
+        }
+        if let Some(hwaddr) = hwaddr {
+            entry.hwaddr = hwaddr.to_vec();
+        }
+        if solicited {
+            entry.state = NeighborState::Reachable;
+            entry.timer = now + REACHABLE_TIME;
+            entry.probes = 0;
+        } else if changed {
+            entry.state = NeighborState::Stale;
+            entry.timer = now;
+        }
+        Vec::new()
+    }
+
+    /// 上位層が相手との疎通を確認できたときに REACHABLE に戻す (RFC 4861 7.3.1)
+    pub fn confirm(&mut self, address: Ipv6Address, now: Instant) {
+        for entry in self.entries.iter_mut() {
+            if entry.address == address && entry.state != NeighborState::Incomplete {
+                entry.state = NeighborState::Reachable;
+                entry.timer = now + REACHABLE_TIME;
+                entry.probes = 0;
+            }
+        }
+    }
+
+    /// 期限の来たエントリの状態を進め、送るべき Neighbor Solicitation を返す
+    /// 解決できずに諦めたエントリは溜めていたパケットごと捨てる
+    pub fn tick(&mut self, now: Instant) -> Vec<NeighborProbe> {
+        let mut probes = Vec::new();
+        self.entries.retain_mut(|entry| {
+            if now < entry.timer {
+                return true;
+            }
+            match entry.state {
+                NeighborState::Incomplete | NeighborState::Probe => {
+                    let limit = if entry.state == NeighborState::Incomplete {
+                        MAX_MULTICAST_SOLICIT
+                    } else {
+                        MAX_UNICAST_SOLICIT
+                    };
+                    if entry.probes >= limit {
+                        eprintln!(
+                            "neighbor unreachable DEV={} ADDR={} DROP={}",
+                            entry.dev,
+                            entry.address,
+                            entry.pending.len()
+                        );
+                        return false;
+                    }
+                    entry.probes += 1;
+                    entry.timer = now + RETRANS_TIMER;
+                    probes.push(NeighborProbe {
+                        dev: entry.dev.clone(),
+                        target: entry.address,
+                        hwaddr: match entry.state {
+                            NeighborState::Probe => Some(entry.hwaddr.clone()),
+                            _ => None,
+                        },
+                    });
+                }
+                NeighborState::Reachable => {
+                    entry.state = NeighborState::Stale;
+                }
+                NeighborState::Delay => {
+                    entry.state = NeighborState::Probe;
+                    entry.probes = 1;
+                    entry.timer = now + RETRANS_TIMER;
+                    probes.push(NeighborProbe {
+                        dev: entry.dev.clone(),
+                        target: entry.address,
+                        hwaddr: Some(entry.hwaddr.clone()),
+                    });
+                }
+                NeighborState::Stale => (),
+            }
+            true
+        });
+        probes
+    }
+
+    pub fn remove_device(&mut self, dev: &str) {
+        self.entries.retain(|entry| entry.dev != dev);
+    }
+}
+
+/// 重複アドレス検出 (RFC 4862 5.4) の状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DadState {
+    /// 検出中でまだ使えない
+    Tentative,
+    /// 重複が見つからずインターフェースに登録した
+    Preferred,
+    /// 他のノードが使っていたので登録しなかった
+    Duplicated,
+}
+
+impl fmt::Display for DadState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DadState::Tentative => "tentative",
+            DadState::Preferred => "preferred",
+            DadState::Duplicated => "duplicated",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+struct DadEntry {
+    dev: String,
+    network: Ipv6Network,
+    state: DadState,
+    transmits: u32,
+    timer: Instant,
+}
+
+lazy_static! {
+    static ref NEIGHBOR_CACHE: Mutex<NeighborCache> = Mutex::new(NeighborCache::new());
+    static ref DAD_ENTRIES: Mutex<Vec<DadEntry>> = Mutex::new(Vec::new());
+}
+
+pub fn neighbors() -> Vec<NeighborEntry> {
+    NEIGHBOR_CACHE.lock().unwrap().entries().to_vec()
+}
+
+pub fn confirm(address: Ipv6Address) {
+    NEIGHBOR_CACHE
+        .lock()
+        .unwrap()
+        .confirm(address, Instant::now());
+}
+
+pub fn needs_resolution(dev: &NetDevice) -> bool {
+    dev.flags & NetDeviceFlag::NeedArp as u16 != 0
+}
+
+/// dst 宛てのパケットを送るためのリンク層アドレスを返す
+/// 解決中なら packet を溜めて None を返し、解決できたときに flush で送る
+pub fn resolve(dev: &NetDevice, dst: Ipv6Address, packet: &[u8]) -> Option<Vec<u8>> {
+    if dst.is_multicast() {
+        return Some(multicast_hwaddr(dst).to_vec());
+    }
+    let lookup =
+        NEIGHBOR_CACHE
+            .lock()
+            .unwrap()
+            .resolve(&dev.name, dst, packet.to_vec(), Instant::now());
+    match lookup {
+        NeighborLookup::Resolved(hwaddr) => Some(hwaddr),
+        NeighborLookup::Queued => None,
+        NeighborLookup::Solicit => {
+            send_solicitation(dev, dst, None);
+            None
+        }
+    }
+}
+
+/// 要請や広告の Source Link-Layer Address オプションでキャッシュを更新する
+pub fn learn(dev: &NetDevice, address: Ipv6Address, hwaddr: &[u8]) {
+    let pending = NEIGHBOR_CACHE
+        .lock()
+        .unwrap()
+        .learn(&dev.name, address, hwaddr, Instant::now());
+    flush(dev, hwaddr, pending);
+}
+
+fn flush(dev: &NetDevice, hwaddr: &[u8], packets: Vec<Vec<u8>>) {
+    for packet in packets {
+        let _ = super::transmit(dev, &packet, hwaddr);
+    }
+}
+
+/// デバイスのアドレスからリンクローカルを優先して送信元を選ぶ
+fn device_source(dev: &NetDevice) -> Option<Ipv6Address> {
+    let mut addresses = dev
+        .get_interfaces(NetInterfaceFamily::Ipv6)
+        .into_iter()
+        .filter_map(|interface| match interface {
+            NetInterfaceType::Ipv6(interface) => Some(interface.unicast),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    addresses.sort_by_key(|address| !address.is_link_local());
+    addresses.into_iter().next()
+}
+
+fn hwaddr(dev: &NetDevice) -> Vec<u8> {
+    dev.hwaddr[..dev.address_length as usize].to_vec()
+}
+
+/// target の解決や確認のための要請を送る。hwaddr を渡すとユニキャストで送る
+fn send_solicitation(dev: &NetDevice, target: Ipv6Address, hwaddr: Option<&[u8]>) {
+    let src = match device_source(dev) {
+        Some(src) => src,
+        None => {
+            eprintln!("ND no source address DEV={}", dev.name);
+            return;
+        }
+    };
+    let dst = match hwaddr {
+        Some(_) => target,
+        None => target.solicited_node(),
+    };
+    let mut data = target.octets().to_vec();
+    data.extend(NdOption::SourceLinkLayerAddress(self::hwaddr(dev)).encode());
+    let message = icmpv6::build(Icmpv6Type::NeighborSolicitation, 0, 0, &data, src, dst);
+    eprintln!(
+        "ND solicitation output DEV={} TARGET={} DST={}",
+        dev.name, target, dst
+    );
+    let _ = match hwaddr {
+        Some(hwaddr) => super::output_link(
+            dev,
+            IPV6_NEXT_HEADER_ICMPV6,
+            &message,
+            src,
+            dst,
+            ND_HOP_LIMIT,
+            hwaddr,
+        ),
+        None => super::output_device(
+            dev,
+            IPV6_NEXT_HEADER_ICMPV6,
+            &message,
+            src,
+            dst,
+            ND_HOP_LIMIT,
+        ),
+    };
+}
+
+/// DAD の要請は送信元が未指定アドレスで、リンク層アドレスのオプションを付けない
+fn send_dad_solicitation(dev: &NetDevice, target: Ipv6Address) {
+    let dst = target.solicited_node();
+    let message = icmpv6::build(
+        Icmpv6Type::NeighborSolicitation,
+        0,
+        0,
+        &target.octets(),
+        IPV6_ADDRESS_UNSPECIFIED,
+        dst,
+    );
+    eprintln!(
+        "ND DAD solicitation output DEV={} TARGET={}",
+        dev.name, target
+    );
+    let _ = super::output_device(
+        dev,
+        IPV6_NEXT_HEADER_ICMPV6,
+        &message,
+        IPV6_ADDRESS_UNSPECIFIED,
+        dst,
+        ND_HOP_LIMIT,
+    );
+}
+
+fn send_advertisement(dev: &NetDevice, target: Ipv6Address, dst: Ipv6Address, flags: u32) {
+    let mut data = target.octets().to_vec();
+    data.extend(NdOption::TargetLinkLayerAddress(hwaddr(dev)).encode());
+    let message = icmpv6::build(
+        Icmpv6Type::NeighborAdvertisement,
+        0,
+        flags,
+        &data,
+        target,
+        dst,
+    );
+    eprintln!(
+        "ND advertisement output DEV={} TARGET={} DST={}",
+        dev.name, target, dst
+    );
+    let _ = super::output_device(
+        dev,
+        IPV6_NEXT_HEADER_ICMPV6,
+        &message,
+        target,
+        dst,
+        ND_HOP_LIMIT,
+    );
+}
+
+fn owns(dev: &NetDevice, address: Ipv6Address) -> bool {
+    dev.get_interfaces(NetInterfaceFamily::Ipv6)
+        .into_iter()
+        .any(|interface| match interface {
+            NetInterfaceType::Ipv6(interface) => interface.unicast == address,
+            _ => false,
+        })
+}
+
+pub fn input(
+    ipv6_hdr: &Ipv6Header<&[u8]>,
+    message: &Icmpv6Message<&[u8]>,
+    dev: &'static NetDevice,
+) {
+    let src = ipv6_hdr.src_address();
+    let dst = ipv6_hdr.dst_address();
+    let data = message.data();
+    // RFC 4861 7.1.1 / 7.1.2 の検査
+    if ipv6_hdr.hop_limit() != ND_HOP_LIMIT || message.code() != 0 || data.len() < ND_TARGET_SIZE {
+        eprintln!("ND invalid message SRC={}", src);
+        return;
+    }
+    let mut octets = [0; IPV6_ADDRESS_SIZE];
+    octets.copy_from_slice(&data[..ND_TARGET_SIZE]);
+    let target = Ipv6Address::from(octets);
+    if target.is_multicast() {
+        return;
+    }
+    let options = match NdOption::parse_all(&data[ND_TARGET_SIZE..]) {
+        Some(options) => options,
+        None => {
+            eprintln!("ND invalid option SRC={}", src);
+            return;
+        }
+    };
+    let length = dev.address_length as usize;
+
+    match message.message_type() {
+        Icmpv6Type::NeighborSolicitation => {
+            let source_hwaddr = link_layer_address(&options, true, length);
+            if src.is_unspecified() {
+                if dst != target.solicited_node() || source_hwaddr.is_some() {
+                    return;
+                }
+                if dad_conflict(&dev.name, target) {
+                    return;
+                }
+            }
+            if !owns(dev, target) {
+                return;
+            }
+            if src.is_unspecified() {
+                // DAD 中のノードへの応答は全ノード宛てに送る
+                send_advertisement(dev, target, IPV6_ADDRESS_ALL_NODES, ND_FLAG_OVERRIDE);
+                return;
+            }
+            if let Some(source_hwaddr) = source_hwaddr {
+                learn(dev, src, &source_hwaddr);
+            }
+            send_advertisement(dev, target, src, ND_FLAG_SOLICITED | ND_FLAG_OVERRIDE);
+        }
+        Icmpv6Type::NeighborAdvertisement => {
+            let flags = message.values();
+            if dst.is_multicast() && flags & ND_FLAG_SOLICITED != 0 {
+                return;
+            }
+            if dad_conflict(&dev.name, target) {
+                return;
+            }
+            let target_hwaddr = link_layer_address(&options, false, length);
+            let pending = NEIGHBOR_CACHE.lock().unwrap().advertise(
+                &dev.name,
+                target,
+                target_hwaddr.as_deref(),
+                flags,
+                Instant::now(),
+            );
+            if let Some(target_hwaddr) = target_hwaddr {
+                flush(dev, &target_hwaddr, pending);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// 検出中のアドレスが他のノードに使われていることがわかったら登録をやめる
+fn dad_conflict(dev: &str, target: Ipv6Address) -> bool {
+    let mut entries = DAD_ENTRIES.lock().unwrap();
+    match entries.iter_mut().find(|entry| {
+        entry.dev == dev && entry.network.address() == target && entry.state == DadState::Tentative
+    }) {
+        Some(entry) => {
+            entry.state = DadState::Duplicated;
+            eprintln!("ND duplicate address detected DEV={} ADDR={}", dev, target);
+            true
+        }
+        None => false,
+    }
+}
+
+/// network のアドレスの重複検出を始め、重複が無ければデバイスに登録する
+/// アドレス解決をしないデバイスではすぐに登録する
+pub fn dad_start(network: Ipv6Network, dev: &NetDevice) {
+    let address = network.address();
+    if !needs_resolution(dev) {
+        if Ipv6Interface::register(Ipv6Interface::from_network(network), dev).is_ok() {
+            set_dad_state(dev, network, DadState::Preferred);
+        }
+        return;
+    }
+    {
+        let mut entries = DAD_ENTRIES.lock().unwrap();
+        entries.retain(|entry| entry.network.address() != address);
+        entries.push(DadEntry {
+            dev: dev.name.clone(),
+            network,
+            state: DadState::Tentative,
+            transmits: 1,
+            timer: Instant::now() + RETRANS_TIMER,
+        });
+    }
+    send_dad_solicitation(dev, address);
+}
+
+fn set_dad_state(dev: &NetDevice, network: Ipv6Network, state: DadState) {
+    let mut entries = DAD_ENTRIES.lock().unwrap();
+    entries.retain(|entry| entry.network.address() != network.address());
+    entries.push(DadEntry {
+        dev: dev.name.clone(),
+        network,
+        state,
+        transmits: 0,
+        timer: Instant::now(),
+    });
+}
+
+pub fn dad_state(address: Ipv6Address) -> Option<DadState> {
+    DAD_ENTRIES
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|entry| entry.network.address() == address)
+        .map(|entry| entry.state)
+}
+
+/// 検出中のアドレスの要請ノードマルチキャスト宛ても受け取らなければならない
+pub fn is_tentative_group(dev: &NetDevice, dst: Ipv6Address) -> bool {
+    DAD_ENTRIES.lock().unwrap().iter().any(|entry| {
+        entry.dev == dev.name
+            && entry.state == DadState::Tentative
+            && entry.network.address().solicited_node() == dst
+    })
+}
+
+fn dad_timer(now: Instant) {
+    let mut solicit = Vec::new();
+    let mut register = Vec::new();
+    {
+        let mut entries = DAD_ENTRIES.lock().unwrap();
+        for entry in entries.iter_mut() {
+            if entry.state != DadState::Tentative || now < entry.timer {
+                continue;
+            }
+            if entry.transmits < DUP_ADDR_DETECT_TRANSMITS {
+                entry.transmits += 1;
+                entry.timer = now + RETRANS_TIMER;
+                solicit.push((entry.dev.clone(), entry.network.address()));
+            } else {
+                entry.state = DadState::Preferred;
+                register.push((entry.dev.clone(), entry.network));
+            }
+        }
+    }
+    for (dev, target) in solicit {
+        if let Some(dev) = device_by_name(&dev) {
+            send_dad_solicitation(dev, target);
+        }
+    }
+    for (dev, network) in register {
+        if let Some(dev) = device_by_name(&dev) {
+            println!("ND address is unique DEV={} ADDR={}", dev.name, network);
+            if Ipv6Interface::register(Ipv6Interface::from_network(network), dev).is_err() {
+                eprintln!("ND register failed ADDR={}", network);
+            }
+        }
+    }
+}
+
+fn timer() {
+    let now = Instant::now();
+    let probes = NEIGHBOR_CACHE.lock().unwrap().tick(now);
+    for probe in probes {
+        if let Some(dev) = device_by_name(&probe.dev) {
+            send_solicitation(dev, probe.target, probe.hwaddr.as_deref());
+        }
+    }
+    dad_timer(now);
+}
+
+pub fn init() {
+    NetTimer::register(RETRANS_TIMER, timer);
+}
@@ -0,0 +1,490 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::nd::{self, DadState, NdOption, NdPrefixInformation, NeighborState, ND_HOP_LIMIT};
+use super::{
+    Ipv6Address, Ipv6Header, Ipv6Interface, Ipv6Network, IPV6_ADDRESS_ALL_ROUTERS,
+    IPV6_NEXT_HEADER_ICMPV6,
+};
+use crate::ethernet::MAC_LENGTH;
+use crate::icmpv6::{self, Icmpv6Message, Icmpv6Type};
+use crate::net::{device_by_name, NetDevice, NetDeviceType, NetTimer};
+
+/// RFC 4861 10 のホスト側の既定値
+pub const MAX_RTR_SOLICITATIONS: u32 = 3;
+pub const RTR_SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+/// インターフェース ID の長さ。これ以外の長さのプレフィックスからはアドレスを作らない
+pub const SLAAC_PREFIX_LENGTH: u8 = 64;
+/// 有効期間を短くする広告を受け入れる下限 (RFC 4862 5.5.3 (e) の 2 時間)
+const VALID_LIFETIME_GUARD: Duration = Duration::from_secs(2 * 60 * 60);
+/// 寿命の無期限を表す値
+const INFINITE_LIFETIME: u32 = 0xffff_ffff;
+/// Router Advertisement の ICMPv6 ヘッダの後ろの固定部 (Reachable Time と Retrans Timer)
+const RA_FIXED_SIZE: usize = 8;
+
+/// 寿命の秒数を期間にする。None は無期限
+pub fn lifetime(seconds: u32) -> Option<Duration> {
+    match seconds {
+        INFINITE_LIFETIME => None,
+        seconds => Some(Duration::from_secs(seconds as u64)),
+    }
+}
+
+/// MAC アドレスから修正 EUI-64 のインターフェース ID を作る (RFC 4291 付録 A)
+pub fn interface_id(hwaddr: &[u8; MAC_LENGTH]) -> [u8; 8] {
+    [
+        hwaddr[0] ^ 0x02,
+        hwaddr[1],
+        hwaddr[2],
+        0xff,
+        0xfe,
+        hwaddr[3],
+        hwaddr[4],
+        hwaddr[5],
+    ]
+}
+
+/// プレフィックスの上位 64 ビットとインターフェース ID を合わせる
+pub fn address_from_prefix(prefix: Ipv6Address, interface_id: &[u8; 8]) -> Ipv6Address {
+    let mut octets = prefix.octets();
+    octets[8..].copy_from_slice(interface_id);
+    Ipv6Address::from(octets)
+}
+
+/// fe80::/64 のリンクローカルアドレス
+pub fn link_local(interface_id: &[u8; 8]) -> Ipv6Address {
+    address_from_prefix(
+        Ipv6Address::from_segments([0xfe80, 0, 0, 0, 0, 0, 0, 0]),
+        interface_id,
+    )
+}
+
+/// 既存のアドレスに届いた有効期間から新しい残り時間を決める (RFC 4862 5.5.3 (e))
+/// 偽の広告で短くされないように、2 時間を切る短縮は 2 時間までにとどめる
+pub fn update_valid_lifetime(
+    received: Option<Duration>,
+    remaining: Option<Duration>,
+) -> Option<Duration> {
+    let received = received?;
+    let longer = remaining.is_some_and(|remaining| received > remaining);
+    if received > VALID_LIFETIME_GUARD || longer {
+        return Some(received);
+    }
+    match remaining {
+        Some(remaining) if remaining <= VALID_LIFETIME_GUARD => Some(remaining),
+        _ => Some(VALID_LIFETIME_GUARD),
+    }
+}
+
+/// 受信した Router Advertisement (RFC 4861 4.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouterAdvertisement {
+    pub cur_hop_limit: u8,
+    pub managed: bool,
+    pub other: bool,
+    /// 秒。0 ならデフォルトルータとして使わない
+    pub router_lifetime: u16,
+    pub reachable_time: u32,
+    pub retrans_timer: u32,
+    pub options: Vec<NdOption>,
+}
+
+impl RouterAdvertisement {
+    /// values は ICMPv6 ヘッダの後半 4 バイト、data はその後ろ
+    pub fn parse(values: u32, data: &[u8]) -> Option<Self> {
+        if data.len() < RA_FIXED_SIZE {
+            return None;
+        }
+        let options = NdOption::parse_all(&data[RA_FIXED_SIZE..])?;
+        Some(RouterAdvertisement {
+            cur_hop_limit: (values >> 24) as u8,
+            managed: values & 0x0080_0000 != 0,
+            other: values & 0x0040_0000 != 0,
+            router_lifetime: values as u16,
+            reachable_time: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            retrans_timer: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            options,
+        })
+    }
+
+    pub fn prefixes(&self) -> impl Iterator<Item = &NdPrefixInformation> {
+        self.options.iter().filter_map(|option| match option {
+            NdOption::PrefixInformation(info) => Some(info),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefaultRouter {
+    pub dev: String,
+    pub address: Ipv6Address,
+    pub expires: Instant,
+}
+
+/// デフォルトルータリスト (RFC 4861 6.3.4)。時刻は呼び出し側が渡す
+#[derive(Default)]
+pub struct DefaultRouterList {
+    routers: Vec<DefaultRouter>,
+}
+
+impl DefaultRouterList {
+    pub fn new() -> Self {
+        DefaultRouterList {
+            routers: Vec::new(),
+        }
+    }
+
+    pub fn routers(&self) -> &[DefaultRouter] {
+        &self.routers
+    }
+
+    /// 寿命 0 の広告はルータをリストから外す
+    pub fn update(&mut self, dev: &str, address: Ipv6Address, lifetime: Duration, now: Instant) {
+        if lifetime.is_zero() {
+            self.routers
+                .retain(|router| !(router.dev == dev && router.address == address));
+            return;
+        }
+        let expires = now + lifetime;
+        match self
+            .routers
+            .iter_mut()
+            .find(|router| router.dev == dev && router.address == address)
+        {
+            Some(router) => router.expires = expires,
+            None => self.routers.push(DefaultRouter {
+                dev: dev.to_string(),
+                address,
+                expires,
+            }),
+        }
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        self.routers.retain(|router| router.expires > now);
+    }
+
+    /// 到達できそうなルータを優先し、無ければ先頭のルータを使う (RFC 4861 6.3.6)
+    pub fn select<F>(&self, dev: &str, reachable: F) -> Option<Ipv6Address>
+    where
+        F: Fn(&DefaultRouter) -> bool,
+    {
+        let mut candidates = self.routers.iter().filter(|router| router.dev == dev);
+        let first = candidates.clone().next();
+        candidates
+            .find(|router| reachable(router))
+            .or(first)
+            .map(|router| router.address)
+    }
+}
+
+struct OnLinkPrefix {
+    dev: String,
+    network: Ipv6Network,
+    expires: Option<Instant>,
+}
+
+/// プレフィックスから作ったアドレス
+struct AutoconfAddress {
+    dev: String,
+    network: Ipv6Network,
+    valid_until: Option<Instant>,
+    preferred_until: Option<Instant>,
+    deprecated: bool,
+}
+
+/// 自動設定を行うデバイス
+struct AutoconfDevice {
+    dev: String,
+    interface_id: [u8; 8],
+    solicitations: u32,
+    next_solicitation: Instant,
+    advertised: bool,
+}
+
+lazy_static! {
+    static ref DEVICES: Mutex<Vec<AutoconfDevice>> = Mutex::new(Vec::new());
+    static ref ROUTERS: Mutex<DefaultRouterList> = Mutex::new(DefaultRouterList::new());
+    static ref PREFIXES: Mutex<Vec<OnLinkPrefix>> = Mutex::new(Vec::new());
+    static ref ADDRESSES: Mutex<Vec<AutoconfAddress>> = Mutex::new(Vec::new());
+}
+
+fn deadline(now: Instant, lifetime: Option<Duration>) -> Option<Instant> {
+    lifetime.map(|lifetime| now + lifetime)
+}
+
+/// Ethernet デバイスを開いたときにリンクローカルアドレスの設定を始める
+pub fn device_open(dev: &NetDevice) {
+    if NetDeviceType::from_u16(dev.device_type) != NetDeviceType::Ethernet
+        || dev.address_length as usize != MAC_LENGTH
+    {
+        return;
+    }
+    let mut hwaddr = [0; MAC_LENGTH];
+    hwaddr.copy_from_slice(&dev.hwaddr[..MAC_LENGTH]);
+    let interface_id = interface_id(&hwaddr);
+    {
+        let mut devices = DEVICES.lock().unwrap();
+        devices.retain(|entry| entry.dev != dev.name);
+        devices.push(AutoconfDevice {
+            dev: dev.name.clone(),
+            interface_id,
+            solicitations: 0,
+            next_solicitation: Instant::now(),
+            advertised: false,
+        });
+    }
+    let address = link_local(&interface_id);
+    println!("SLAAC link-local DEV={} ADDR={}", dev.name, address);
+    match Ipv6Network::new(address, SLAAC_PREFIX_LENGTH) {
+        Ok(network) => nd::dad_start(network, dev),
+        Err(_) => eprintln!("SLAAC invalid link-local ADDR={}", address),
+    }
+}
+
+/// dev から送るときのデフォルトルータ
+pub fn default_router(dev: &str) -> Option<Ipv6Address> {
+    let neighbors = nd::neighbors();
+    ROUTERS.lock().unwrap().select(dev, |router| {
+        neighbors.iter().any(|entry| {
+            entry.dev == router.dev
+                && entry.address == router.address
+                && entry.state != NeighborState::Incomplete
+        })
+    })
+}
+
+/// now の時点で優先寿命が切れている自動設定のアドレス
+pub fn deprecated_addresses(now: Instant) -> Vec<Ipv6Address> {
+    ADDRESSES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry.preferred_until.is_some_and(|until| until <= now))
+        .map(|entry| entry.network.address())
+        .collect()
+}
+
+/// ルータ広告でリンク上にあると知らされたプレフィックスに含まれるか
+pub fn is_on_link(dev: &str, address: Ipv6Address) -> bool {
+    PREFIXES
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|prefix| prefix.dev == dev && prefix.network.contains(address))
+}
+
+pub fn input(
+    ipv6_hdr: &Ipv6Header<&[u8]>,
+    message: &Icmpv6Message<&[u8]>,
+    dev: &'static NetDevice,
+) {
+    let src = ipv6_hdr.src_address();
+    // RFC 4861 6.1.2 の検査
+    if ipv6_hdr.hop_limit() != ND_HOP_LIMIT || message.code() != 0 || !src.is_link_local() {
+        eprintln!("SLAAC invalid router advertisement SRC={}", src);
+        return;
+    }
+    let advertisement = match RouterAdvertisement::parse(message.values(), message.data()) {
+        Some(advertisement) => advertisement,
+        None => {
+            eprintln!("SLAAC malformed router advertisement SRC={}", src);
+            return;
+        }
+    };
+    println!(
+        "SLAAC router advertisement DEV={} ROUTER={} LIFETIME={}",
+        dev.name, src, advertisement.router_lifetime
+    );
+    let interface_id = {
+        let mut devices = DEVICES.lock().unwrap();
+        match devices.iter_mut().find(|entry| entry.dev == dev.name) {
+            Some(entry) => {
+                entry.advertised = true;
+                entry.interface_id
+            }
+            None => return,
+        }
+    };
+
+    let now = Instant::now();
+    ROUTERS.lock().unwrap().update(
+        &dev.name,
+        src,
+        Duration::from_secs(advertisement.router_lifetime as u64),
+        now,
+    );
+    if let Some(hwaddr) =
+        nd::link_layer_address(&advertisement.options, true, dev.address_length as usize)
+    {
+        nd::learn(dev, src, &hwaddr);
+    }
+    for info in advertisement.prefixes() {
+        process_prefix(dev, info, &interface_id, now);
+    }
+}
+
+/// Prefix Information オプションの処理 (RFC 4861 6.3.4、RFC 4862 5.5.3)
+fn process_prefix(
+    dev: &NetDevice,
+    info: &NdPrefixInformation,
+    interface_id: &[u8; 8],
+    now: Instant,
+) {
+    if info.prefix.is_link_local() {
+        return;
+    }
+    let network = match Ipv6Network::new(info.prefix, info.prefix_length) {
+        Ok(network) => network,
+        Err(_) => return,
+    };
+    let valid = lifetime(info.valid_lifetime);
+    if info.on_link {
+        let mut prefixes = PREFIXES.lock().unwrap();
+        prefixes.retain(|prefix| !(prefix.dev == dev.name && prefix.network == network));
+        if info.valid_lifetime != 0 {
+            prefixes.push(OnLinkPrefix {
+                dev: dev.name.clone(),
+                network,
+                expires: deadline(now, valid),
+            });
+        }
+    }
+    if !info.autonomous || info.preferred_lifetime > info.valid_lifetime {
+        return;
+    }
+    if info.prefix_length != SLAAC_PREFIX_LENGTH {
+        eprintln!(
+            "SLAAC prefix length is not {} PREFIX={}",
+            SLAAC_PREFIX_LENGTH, network
+        );
+        return;
+    }
+
+    let address = address_from_prefix(info.prefix, interface_id);
+    let preferred = lifetime(info.preferred_lifetime);
+    {
+        let mut addresses = ADDRESSES.lock().unwrap();
+        if let Some(entry) = addresses
+            .iter_mut()
+            .find(|entry| entry.network.address() == address)
+        {
+            let remaining = entry
+                .valid_until
+                .map(|until| until.saturating_duration_since(now));
+            entry.valid_until = deadline(now, update_valid_lifetime(valid, remaining));
+            entry.preferred_until = deadline(now, preferred);
+            entry.deprecated = false;
+            return;
+        }
+        if info.valid_lifetime == 0 {
+            return;
+        }
+        let network = match Ipv6Network::new(address, SLAAC_PREFIX_LENGTH) {
+            Ok(network) => network,
+            Err(_) => return,
+        };
+        addresses.push(AutoconfAddress {
+            dev: dev.name.clone(),
+            network,
+            valid_until: deadline(now, valid),
+            preferred_until: deadline(now, preferred),
+            deprecated: false,
+        });
+    }
+    println!("SLAAC address DEV={} ADDR={}", dev.name, address);
+    if let Ok(network) = Ipv6Network::new(address, SLAAC_PREFIX_LENGTH) {
+        nd::dad_start(network, dev);
+    }
+}
+
+/// リンクローカルアドレスが使えるようになったらルータ要請を送る (RFC 4861 6.3.7)
+fn send_solicitation(dev: &NetDevice, src: Ipv6Address) {
+    let hwaddr = dev.hwaddr[..dev.address_length as usize].to_vec();
+    let data = NdOption::SourceLinkLayerAddress(hwaddr).encode();
+    let message = icmpv6::build(
+        Icmpv6Type::RouterSolicitation,
+        0,
+        0,
+        &data,
+        src,
+        IPV6_ADDRESS_ALL_ROUTERS,
+    );
+    println!("SLAAC router solicitation output DEV={}", dev.name);
+    let _ = super::output_device(
+        dev,
+        IPV6_NEXT_HEADER_ICMPV6,
+        &message,
+        src,
+        IPV6_ADDRESS_ALL_ROUTERS,
+        ND_HOP_LIMIT,
+    );
+}
+
+fn timer() {
+    let now = Instant::now();
+    ROUTERS.lock().unwrap().expire(now);
+    PREFIXES
+        .lock()
+        .unwrap()
+        .retain(|prefix| prefix.expires.is_none_or(|expires| expires > now));
+
+    let mut expired = Vec::new();
+    {
+        let mut addresses = ADDRESSES.lock().unwrap();
+        for entry in addresses.iter_mut() {
+            let deprecated = entry.preferred_until.is_some_and(|until| until <= now);
+            if deprecated && !entry.deprecated {
+                // 優先寿命が切れたアドレスも有効期間中は受信に使える
+                println!("SLAAC address deprecated ADDR={}", entry.network);
+                entry.deprecated = true;
+            }
+        }
+        addresses.retain(|entry| {
+            if entry.valid_until.is_some_and(|until| until <= now) {
+                expired.push((entry.dev.clone(), entry.network.address()));
+                return false;
+            }
+            true
+        });
+    }
+    for (dev, address) in expired {
+        println!("SLAAC address expired DEV={} ADDR={}", dev, address);
+        if nd::dad_state(address) != Some(DadState::Preferred) {
+            continue;
+        }
+        if let Some(dev) = device_by_name(&dev) {
+            let _ = Ipv6Interface::unregister(address, dev);
+        }
+    }
+
+    let mut solicit = Vec::new();
+    {
+        let mut devices = DEVICES.lock().unwrap();
+        for entry in devices.iter_mut() {
+            if entry.advertised
+                || entry.solicitations >= MAX_RTR_SOLICITATIONS
+                || now < entry.next_solicitation
+            {
+                continue;
+            }
+            let address = link_local(&entry.interface_id);
+            if nd::dad_state(address) != Some(DadState::Preferred) {
+                continue;
+            }
+            entry.solicitations += 1;
+            entry.next_solicitation = now + RTR_SOLICITATION_INTERVAL;
+            solicit.push((entry.dev.clone(), address));
+        }
+    }
+    for (dev, src) in solicit {
+        if let Some(dev) = device_by_name(&dev) {
+            send_solicitation(dev, src);
+        }
+    }
+}
+
+pub fn init() {
+    NetTimer::register(Duration::from_secs(1), timer);
+}
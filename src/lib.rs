@@ -2,8 +2,17 @@
 extern crate lazy_static;
 
 pub mod device;
+pub mod dhcp;
+pub mod dns;
 pub mod ethernet;
+pub mod icmp;
+pub mod icmpv6;
+pub mod igmp;
 pub mod ipv4;
+pub mod ipv6;
 pub mod net;
 pub mod packet;
+pub mod socket;
+pub mod tcp;
+pub mod udp;
 pub mod utils;
@@ -5,9 +5,15 @@ use std::sync::{
     Arc, Mutex, MutexGuard,
 };
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::ipv4;
+use crate::icmp;
+use crate::icmpv6;
+use crate::igmp;
+use crate::ipv4::{self, Ipv4Address};
+use crate::ipv6::{self, Ipv6Address};
+use crate::tcp;
+use crate::udp;
 
 #[repr(u16)]
 pub enum NetProtocolType {
@@ -19,7 +25,6 @@ pub enum NetProtocolType {
 
 impl NetProtocolType {
     pub fn from_u16(u: u16) -> NetProtocolType {
-        let u = u & 0xfffe;
         match u {
             0x0800 => NetProtocolType::Ip,
             0x0806 => NetProtocolType::Arp,
@@ -126,6 +131,7 @@ impl NetProtocol {
     ) -> Result<(), NetProtocolError> {
         {
             let mut protocols = PROTOCOLS.lock();
+            let mut found = false;
             for protocol in protocols.iter_mut() {
                 if protocol.protocol_type == protocol_type {
                     {
@@ -138,8 +144,14 @@ impl NetProtocol {
                             })
                         };
                     }
+                    found = true;
                 }
             }
+            if !found {
+                // 受け取り手がいないので解放して捨てる
+                drop(unsafe { Vec::from_raw_parts(data as *mut u8, size, size) });
+                return Ok(());
+            }
         }
         println!(
             "Queue pushed DEV={} TYPE={}:{:04x} SIZE={}",
@@ -150,6 +162,21 @@ impl NetProtocol {
         );
         Ok(())
     }
+
+    /// 各プロトコルのキューから 1 つずつ取り出してハンドラに渡し、渡した数を返す
+    pub fn poll() -> usize {
+        let mut count = 0;
+        let mut protocols = PROTOCOLS.lock();
+        for protocol in protocols.iter_mut() {
+            let mut queue = protocol.queue.lock().unwrap();
+            let entry_option = queue.pop_front();
+            if let Some(entry) = entry_option {
+                (protocol.handler)(&entry.data, entry.dev);
+                count += 1;
+            }
+        }
+        count
+    }
 }
 
 pub struct NetProtocolQueueEntry {
@@ -190,7 +217,7 @@ pub struct NetDevice {
     pub hwaddr: [u8; HARDWARE_ADDRESS_LENGTH],
     pub pb: NetDeviceAddress,
     pub ops: NetDeviceOps,
-    pub interfaces: Vec<Box<NetInterfaceType>>,
+    pub interfaces: Mutex<Vec<Box<NetInterfaceType>>>,
 }
 
 #[derive(PartialEq, Eq)]
@@ -242,8 +269,8 @@ pub enum NetDeviceAddress {
 
 pub type OpenFnPtr = fn(&NetDevice) -> isize;
 pub type CloseFnPtr = fn(&NetDevice) -> isize;
-pub type TransmitFnPtr = fn(&NetDevice, u16, *const u8, usize, *mut u8) -> isize;
-pub type PollFnPtr = fn(&NetDevice) -> isize;
+pub type TransmitFnPtr = fn(&NetDevice, u16, &[u8], *mut u8) -> isize;
+pub type PollFnPtr = fn(&'static NetDevice) -> isize;
 
 pub struct NetDeviceOps {
     pub open: Option<OpenFnPtr>,
@@ -281,6 +308,7 @@ impl NetDevice {
 
         self.flags = self.flags | NetDeviceFlag::Up as u16;
         println!("open device DEV={}", self.name);
+        ipv6::slaac::device_open(self);
         Ok(())
     }
 
@@ -302,10 +330,10 @@ impl NetDevice {
     pub fn output(
         &self,
         net_device_type: u16,
-        data: *const u8,
-        size: usize,
+        data: &[u8],
         dst: *mut u8,
     ) -> Result<(), NetDeviceError> {
+        let size = data.len();
         if !self.is_up() {
             eprintln!("not opened DEV={}", self.name);
             return Err(NetDeviceError::new(NetDeviceErrorKind::OpenError));
@@ -320,7 +348,7 @@ impl NetDevice {
         }
 
         if let Some(transmit) = self.ops.transmit {
-            if transmit(self, net_device_type, data, size, dst) == -1 {
+            if transmit(self, net_device_type, data, dst) == -1 {
                 eprintln!("data transmit failed DEV={} SIZE={}", self.name, size);
                 return Err(NetDeviceError::new(NetDeviceErrorKind::TransmitError));
             }
@@ -335,47 +363,71 @@ impl NetDevice {
         );
     }
 
-    pub fn add_interface(&mut self, interface: NetInterfaceType) -> Result<(), NetDeviceError> {
-        for entry in &self.interfaces {
-            let entry = entry.as_ref();
-            match entry {
-                NetInterfaceType::Ip(entry) => match interface {
-                    NetInterfaceType::Ip(_) => {
-                        eprintln!(
-                            "interface is already exists, DEV={}, FAMILY={}",
-                            self.name, entry.net_interface.family
-                        );
-                        return Err(NetDeviceError::new(NetDeviceErrorKind::AlreadyRegistered));
-                    }
-                    NetInterfaceType::Unknown => {
-                        eprintln!("Unknown Type");
-                        return Err(NetDeviceError::new(NetDeviceErrorKind::UnknownType));
-                    }
-                },
-                NetInterfaceType::Unknown => {
+    /// 登録済みのデバイスにも実行中にインターフェースを追加できる
+    pub fn add_interface(&self, interface: NetInterfaceType) -> Result<(), NetDeviceError> {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        for entry in interfaces.iter() {
+            // 同じデバイスに複数のアドレスを持てるが、同一アドレスの二重登録は拒否する
+            let duplicated = match (entry.as_ref(), &interface) {
+                (NetInterfaceType::Ip(entry), NetInterfaceType::Ip(new)) => {
+                    entry.unicast == new.unicast
+                }
+                (NetInterfaceType::Ipv6(entry), NetInterfaceType::Ipv6(new)) => {
+                    entry.unicast == new.unicast
+                }
+                (_, NetInterfaceType::Unknown) | (NetInterfaceType::Unknown, _) => {
                     eprintln!("Unknown NetInterfaceType");
                     return Err(NetDeviceError::new(NetDeviceErrorKind::UnknownType));
                 }
+                _ => false,
+            };
+            if duplicated {
+                eprintln!(
+                    "interface is already exists, DEV={}, FAMILY={}",
+                    self.name,
+                    interface.family()
+                );
+                return Err(NetDeviceError::new(NetDeviceErrorKind::AlreadyRegistered));
             }
         }
-        self.interfaces.push(Box::new(interface));
+        interfaces.push(Box::new(interface));
         Ok(())
     }
 
+    /// IP アドレスが unicast のインターフェースを取り除く
+    pub fn remove_interface(&self, unicast: Ipv4Address) -> Option<NetInterfaceType> {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        let position = interfaces.iter().position(|entry| match entry.as_ref() {
+            NetInterfaceType::Ip(entry) => entry.unicast == unicast,
+            _ => false,
+        })?;
+        Some(*interfaces.remove(position))
+    }
+
+    /// IPv6 アドレスが unicast のインターフェースを取り除く
+    pub fn remove_ipv6_interface(&self, unicast: Ipv6Address) -> Option<NetInterfaceType> {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        let position = interfaces.iter().position(|entry| match entry.as_ref() {
+            NetInterfaceType::Ipv6(entry) => entry.unicast == unicast,
+            _ => false,
+        })?;
+        Some(*interfaces.remove(position))
+    }
+
+    /// 指定したファミリの最初に登録されたインターフェース (プライマリ) を返す
     pub fn get_interface(&self, family: NetInterfaceFamily) -> Option<NetInterfaceType> {
-        for entry in &self.interfaces {
-            let entry = entry.as_ref();
-            match entry {
-                NetInterfaceType::Ip(entry) => {
-                    if entry.net_interface.family == family {
-                        let entry = (*entry).clone();
-                        return Some(NetInterfaceType::Ip(entry));
-                    }
-                }
-                _ => (),
-            }
-        }
-        None
+        self.get_interfaces(family).into_iter().next()
+    }
+
+    /// 指定したファミリのインターフェースを登録順 (プライマリ, セカンダリ...) にすべて返す
+    pub fn get_interfaces(&self, family: NetInterfaceFamily) -> Vec<NetInterfaceType> {
+        self.interfaces
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| entry.family() == family)
+            .map(|entry| (**entry).clone())
+            .collect()
     }
 }
 
@@ -396,7 +448,7 @@ impl Default for NetDevice {
                 transmit: None,
                 poll: None,
             },
-            interfaces: Vec::new(),
+            interfaces: Mutex::new(Vec::new()),
         }
     }
 }
@@ -419,9 +471,20 @@ impl Default for NetInterface {
 #[derive(Clone)]
 pub enum NetInterfaceType {
     Ip(ipv4::IpInterface),
+    Ipv6(ipv6::Ipv6Interface),
     Unknown,
 }
 
+impl NetInterfaceType {
+    pub fn family(&self) -> NetInterfaceFamily {
+        match self {
+            NetInterfaceType::Ip(interface) => interface.net_interface.family.clone(),
+            NetInterfaceType::Ipv6(interface) => interface.net_interface.family.clone(),
+            NetInterfaceType::Unknown => NetInterfaceFamily::Unknown,
+        }
+    }
+}
+
 #[derive(Eq, PartialEq, Clone)]
 #[repr(usize)]
 pub enum NetInterfaceFamily {
@@ -482,6 +545,19 @@ lazy_static! {
     pub static ref NET_DEVICES: LockableNetDevices = LockableNetDevices::new();
 }
 
+/// タイマーなどで名前だけを覚えているデバイスを探す
+pub fn device_by_name(name: &str) -> Option<&'static NetDevice> {
+    let devices = NET_DEVICES.lock();
+    devices
+        .items
+        .iter()
+        .find(|dev| dev.name == name)
+        .map(|dev| {
+            let dev: &'static NetDevice = dev;
+            dev
+        })
+}
+
 pub struct LockableThreadHandle {
     pub item: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
 }
@@ -514,6 +590,44 @@ lazy_static! {
     pub static ref THREAD: LockableThreadHandle = LockableThreadHandle::new();
 }
 
+pub type TimerHandlerType = fn();
+
+pub struct NetTimer {
+    interval: Duration,
+    last: Instant,
+    handler: TimerHandlerType,
+}
+
+lazy_static! {
+    static ref TIMERS: Mutex<Vec<NetTimer>> = Mutex::new(Vec::new());
+}
+
+impl NetTimer {
+    /// net_thread から interval ごとに handler を呼ぶ
+    pub fn register(interval: Duration, handler: TimerHandlerType) {
+        let mut timers = TIMERS.lock().unwrap();
+        timers.push(NetTimer {
+            interval,
+            last: Instant::now(),
+            handler,
+        });
+        println!("timer registered INTERVAL={:?}", interval);
+    }
+
+    fn expired() -> Vec<TimerHandlerType> {
+        let now = Instant::now();
+        let mut timers = TIMERS.lock().unwrap();
+        let mut handlers = Vec::new();
+        for timer in timers.iter_mut() {
+            if now.duration_since(timer.last) >= timer.interval {
+                timer.last = now;
+                handlers.push(timer.handler);
+            }
+        }
+        handlers
+    }
+}
+
 static TERMINATE: AtomicBool = AtomicBool::new(false);
 
 pub fn net_thread() {
@@ -524,25 +638,17 @@ pub fn net_thread() {
             for dev in devices.iter_mut() {
                 if dev.is_up() {
                     if let Some(poll) = dev.ops.poll {
-                        if poll(dev) != -1 {
+                        if poll(*dev) != -1 {
                             count += 1;
                         }
                     }
                 }
             }
         }
-        {
-            let mut protocols = PROTOCOLS.lock();
-            for protocol in protocols.iter_mut() {
-                {
-                    let mut queue = protocol.queue.lock().unwrap();
-                    let entry_option = queue.pop_front();
-                    if let Some(entry) = entry_option {
-                        let _ = (protocol.handler)(&entry.data, entry.dev);
-                        count += 1;
-                    }
-                }
-            }
+        count += NetProtocol::poll();
+        // ハンドラの中でタイマーを登録できるようにロックを外してから呼ぶ
+        for handler in NetTimer::expired() {
+            handler();
         }
         if count == 0 {
             thread::sleep(Duration::new(0, 1000_0000));
@@ -586,4 +692,10 @@ pub fn net_shutdown() -> Result<(), NetDeviceError> {
 
 pub fn net_init() {
     ipv4::init();
+    ipv6::init();
+    icmp::init();
+    icmpv6::init();
+    igmp::init();
+    udp::init();
+    tcp::init();
 }
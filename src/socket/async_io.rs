@@ -0,0 +1,197 @@
+use std::future::poll_fn;
+use std::io;
+use std::net::{Shutdown, SocketAddr, ToSocketAddrs};
+use std::task::{Context, Poll};
+
+use super::{each_addr, to_endpoint, to_socket_addr, SocketType};
+
+/// std::net::TcpListener と同じく listen の backlog は 128 を指定する
+const LISTEN_BACKLOG: usize = 128;
+
+/// 非同期で使える TCP の接続。ランタイムには依存せず、net_thread が waker を起こす
+/// タイムアウトは持たないので、必要なら呼び出し側のランタイムで掛ける
+/// drop すると close する
+#[derive(Debug)]
+pub struct AsyncTcpStream {
+    fd: usize,
+}
+
+impl AsyncTcpStream {
+    /// to_socket_addrs は名前解決でスレッドを止めるので、解決済みのアドレスだけを受け付ける
+    /// 名前は呼び出し側でブロックしてよい場所で解決してから渡す
+    pub async fn connect(addr: SocketAddr) -> io::Result<AsyncTcpStream> {
+        let endpoint = to_endpoint(&addr)?;
+        let stream = AsyncTcpStream {
+            fd: super::socket(SocketType::Stream)?,
+        };
+        super::connect_start(stream.fd, endpoint)?;
+        poll_fn(|cx| super::poll_connect(stream.fd, cx)).await?;
+        Ok(stream)
+    }
+
+    /// 0 は相手が閉じたことを表す
+    pub fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        super::poll_recvfrom(self.fd, cx, buf).map(|result| Ok(result.map(|(len, _)| len)?))
+    }
+
+    /// 送信バッファに 1 バイトでも積めたら積めた分を返す
+    pub fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        super::poll_send(self.fd, cx, buf).map(|result| Ok(result?))
+    }
+
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_read(cx, buf)).await
+    }
+
+    pub async fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_write(cx, buf)).await
+    }
+
+    pub async fn write_all(&self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let len = self.write(buf).await?;
+            buf = &buf[len..];
+        }
+        Ok(())
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        Ok(super::shutdown(self.fd, how)?)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(to_socket_addr(super::getpeername(self.fd)?))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(to_socket_addr(super::getsockname(self.fd)?))
+    }
+
+    pub fn as_raw_fd(&self) -> usize {
+        self.fd
+    }
+}
+
+impl Drop for AsyncTcpStream {
+    fn drop(&mut self) {
+        let _ = super::close(self.fd);
+    }
+}
+
+/// 非同期で accept できる待ち受けソケット
+#[derive(Debug)]
+pub struct AsyncTcpListener {
+    fd: usize,
+}
+
+impl AsyncTcpListener {
+    /// bind と listen はその場で終わるので async ではない
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<AsyncTcpListener> {
+        each_addr(addr, |endpoint| {
+            let listener = AsyncTcpListener {
+                fd: super::socket(SocketType::Stream)?,
+            };
+            super::bind(listener.fd, endpoint)?;
+            super::listen(listener.fd, LISTEN_BACKLOG)?;
+            Ok(listener)
+        })
+    }
+
+    pub fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<(AsyncTcpStream, SocketAddr)>> {
+        super::poll_accept(self.fd, cx).map(|result| {
+            let (fd, foreign) = result?;
+            Ok((AsyncTcpStream { fd }, to_socket_addr(foreign)))
+        })
+    }
+
+    pub async fn accept(&self) -> io::Result<(AsyncTcpStream, SocketAddr)> {
+        poll_fn(|cx| self.poll_accept(cx)).await
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(to_socket_addr(super::getsockname(self.fd)?))
+    }
+
+    pub fn as_raw_fd(&self) -> usize {
+        self.fd
+    }
+}
+
+impl Drop for AsyncTcpListener {
+    fn drop(&mut self) {
+        let _ = super::close(self.fd);
+    }
+}
+
+/// 非同期で受信できる UDP ソケット。送信は待つことがないのですぐに終わる
+#[derive(Debug)]
+pub struct AsyncUdpSocket {
+    fd: usize,
+}
+
+impl AsyncUdpSocket {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<AsyncUdpSocket> {
+        each_addr(addr, |endpoint| {
+            let socket = AsyncUdpSocket {
+                fd: super::socket(SocketType::Datagram)?,
+            };
+            super::bind(socket.fd, endpoint)?;
+            Ok(socket)
+        })
+    }
+
+    /// connect と同じく解決済みのアドレスだけを受け付ける
+    pub async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        Ok(super::sendto(self.fd, buf, to_endpoint(&addr)?)?)
+    }
+
+    pub fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+        super::poll_recvfrom(self.fd, cx, buf).map(|result| {
+            let (len, foreign) = result?;
+            Ok((len, to_socket_addr(foreign)))
+        })
+    }
+
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        poll_fn(|cx| self.poll_recv_from(cx, buf)).await
+    }
+
+    /// send と recv の相手を決める
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        each_addr(addr, |endpoint| Ok(super::connect(self.fd, endpoint)?))
+    }
+
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        Ok(super::send(self.fd, buf)?)
+    }
+
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let (len, _) = self.recv_from(buf).await?;
+        Ok(len)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(to_socket_addr(super::getpeername(self.fd)?))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(to_socket_addr(super::getsockname(self.fd)?))
+    }
+
+    pub fn as_raw_fd(&self) -> usize {
+        self.fd
+    }
+}
+
+impl Drop for AsyncUdpSocket {
+    fn drop(&mut self) {
+        let _ = super::close(self.fd);
+    }
+}
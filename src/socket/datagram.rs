@@ -0,0 +1,96 @@
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use super::{
+    check_timeout, each_addr, receive_timeout, send_timeout, to_endpoint, to_socket_addr,
+    SocketOption, SocketType,
+};
+
+/// std::net::UdpSocket と同じ使い方ができる UDP ソケット
+/// drop すると close する
+#[derive(Debug)]
+pub struct UdpSocket {
+    fd: usize,
+}
+
+impl UdpSocket {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
+        each_addr(addr, |endpoint| {
+            let socket = UdpSocket {
+                fd: super::socket(SocketType::Datagram)?,
+            };
+            super::bind(socket.fd, endpoint)?;
+            Ok(socket)
+        })
+    }
+
+    /// 最初に解決できたアドレスに送る
+    pub fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> io::Result<usize> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no addresses to send data to")
+        })?;
+        Ok(super::sendto(self.fd, buf, to_endpoint(&addr)?)?)
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (len, foreign) = super::recvfrom(self.fd, buf)?;
+        Ok((len, to_socket_addr(foreign)))
+    }
+
+    /// send と recv の相手を決める
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        each_addr(addr, |endpoint| Ok(super::connect(self.fd, endpoint)?))
+    }
+
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        Ok(super::send(self.fd, buf)?)
+    }
+
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(super::recv(self.fd, buf)?)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(to_socket_addr(super::getpeername(self.fd)?))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(to_socket_addr(super::getsockname(self.fd)?))
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        check_timeout(timeout)?;
+        Ok(super::setsockopt(
+            self.fd,
+            SocketOption::ReceiveTimeout(timeout),
+        )?)
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        check_timeout(timeout)?;
+        Ok(super::setsockopt(
+            self.fd,
+            SocketOption::SendTimeout(timeout),
+        )?)
+    }
+
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        receive_timeout(self.fd)
+    }
+
+    pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        send_timeout(self.fd)
+    }
+
+    /// ソケット層のディスクリプタ
+    pub fn as_raw_fd(&self) -> usize {
+        self.fd
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let _ = super::close(self.fd);
+    }
+}
@@ -0,0 +1,726 @@
+use std::fmt;
+use std::io;
+use std::net::{Shutdown, SocketAddr, SocketAddrV4, ToSocketAddrs};
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::ipv4::raw::{self, RawError, RawErrorKind, RawPacket};
+use crate::ipv4::{Ipv4Endpoint, IP_ADDRESS_ANY};
+use crate::tcp::{self, CongestionAlgorithm, TcpError, TcpErrorKind, TcpKeepalive, TcpState};
+use crate::udp::{self, UdpDatagram, UdpError, UdpErrorKind};
+
+mod async_io;
+mod datagram;
+mod stream;
+
+pub use async_io::{AsyncTcpListener, AsyncTcpStream, AsyncUdpSocket};
+pub use datagram::UdpSocket;
+pub use stream::{Incoming, TcpListener, TcpStream};
+
+/// 同時に開けるソケットの数
+pub const SOCKET_TABLE_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketType {
+    /// TCP (SOCK_STREAM)
+    Stream,
+    /// UDP (SOCK_DGRAM)
+    Datagram,
+    /// 指定した番号の IP パケットをそのまま扱う (SOCK_RAW)。ポートは常に 0
+    Raw(u8),
+}
+
+/// setsockopt で変えられる設定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketOption {
+    /// SO_RCVTIMEO 相当。accept と recv の待ち時間。None なら待ち続ける
+    ReceiveTimeout(Option<Duration>),
+    /// SO_SNDTIMEO 相当。connect と send の待ち時間。None なら待ち続ける
+    SendTimeout(Option<Duration>),
+    /// TCP_CONGESTION 相当。TCP だけで使える
+    CongestionControl(CongestionAlgorithm),
+    /// SO_KEEPALIVE と TCP_KEEPIDLE、TCP_KEEPINTVL、TCP_KEEPCNT を合わせたもの。None なら送らない
+    KeepAlive(Option<TcpKeepalive>),
+    /// IP_HDRINCL 相当。raw ソケットだけで使える
+    HeaderIncluded(bool),
+}
+
+/// getsockopt で読み出す設定の名前
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketOptionName {
+    ReceiveTimeout,
+    SendTimeout,
+    CongestionControl,
+    KeepAlive,
+    HeaderIncluded,
+}
+
+#[derive(Debug)]
+pub struct SocketError {
+    pub kind: SocketErrorKind,
+}
+
+impl SocketError {
+    pub fn new(kind: SocketErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketErrorKind {
+    BadDescriptor,
+    TooManySockets,
+    NoBuffers,
+    InvalidArgument,
+    NotSupported,
+    ProtocolNotSupported,
+    AddressInUse,
+    AddressNotAvailable,
+    NotConnected,
+    AlreadyConnected,
+    ConnectionRefused,
+    ConnectionReset,
+    NetworkUnreachable,
+    TimedOut,
+    MessageTooLong,
+    BrokenPipe,
+}
+
+impl SocketErrorKind {
+    /// 対応する Linux の errno
+    pub fn errno(&self) -> i32 {
+        match self {
+            SocketErrorKind::BadDescriptor => 9,
+            SocketErrorKind::TooManySockets => 24,
+            SocketErrorKind::NoBuffers => 105,
+            SocketErrorKind::InvalidArgument => 22,
+            SocketErrorKind::NotSupported => 95,
+            SocketErrorKind::ProtocolNotSupported => 93,
+            SocketErrorKind::AddressInUse => 98,
+            SocketErrorKind::AddressNotAvailable => 99,
+            SocketErrorKind::NotConnected => 107,
+            SocketErrorKind::AlreadyConnected => 106,
+            SocketErrorKind::ConnectionRefused => 111,
+            SocketErrorKind::ConnectionReset => 104,
+            SocketErrorKind::NetworkUnreachable => 101,
+            SocketErrorKind::TimedOut => 110,
+            SocketErrorKind::MessageTooLong => 90,
+            SocketErrorKind::BrokenPipe => 32,
+        }
+    }
+}
+
+impl fmt::Display for SocketErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SocketErrorKind::BadDescriptor => "bad socket descriptor",
+            SocketErrorKind::TooManySockets => "too many sockets",
+            SocketErrorKind::NoBuffers => "no buffer space",
+            SocketErrorKind::InvalidArgument => "invalid argument",
+            SocketErrorKind::NotSupported => "operation not supported",
+            SocketErrorKind::ProtocolNotSupported => "protocol not supported",
+            SocketErrorKind::AddressInUse => "address in use",
+            SocketErrorKind::AddressNotAvailable => "address not available",
+            SocketErrorKind::NotConnected => "not connected",
+            SocketErrorKind::AlreadyConnected => "already connected",
+            SocketErrorKind::ConnectionRefused => "connection refused",
+            SocketErrorKind::ConnectionReset => "connection reset",
+            SocketErrorKind::NetworkUnreachable => "network unreachable",
+            SocketErrorKind::TimedOut => "timed out",
+            SocketErrorKind::MessageTooLong => "message too long",
+            SocketErrorKind::BrokenPipe => "broken pipe",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl From<UdpError> for SocketError {
+    fn from(e: UdpError) -> Self {
+        let kind = match e.kind {
+            UdpErrorKind::NoSpace => SocketErrorKind::NoBuffers,
+            UdpErrorKind::InvalidId | UdpErrorKind::Closed => SocketErrorKind::BadDescriptor,
+            UdpErrorKind::AddressInUse => SocketErrorKind::AddressInUse,
+            UdpErrorKind::AddressNotAvailable | UdpErrorKind::NoPortAvailable => {
+                SocketErrorKind::AddressNotAvailable
+            }
+            UdpErrorKind::TooLong => SocketErrorKind::MessageTooLong,
+            UdpErrorKind::OutputError => SocketErrorKind::NetworkUnreachable,
+            UdpErrorKind::Timeout => SocketErrorKind::TimedOut,
+        };
+        SocketError::new(kind)
+    }
+}
+
+impl From<RawError> for SocketError {
+    fn from(e: RawError) -> Self {
+        let kind = match e.kind {
+            RawErrorKind::NoSpace => SocketErrorKind::NoBuffers,
+            RawErrorKind::InvalidId => SocketErrorKind::BadDescriptor,
+            RawErrorKind::InvalidProtocol => SocketErrorKind::ProtocolNotSupported,
+            RawErrorKind::AddressNotAvailable => SocketErrorKind::AddressNotAvailable,
+            RawErrorKind::InvalidHeader => SocketErrorKind::InvalidArgument,
+            RawErrorKind::TooLong => SocketErrorKind::MessageTooLong,
+            RawErrorKind::OutputError => SocketErrorKind::NetworkUnreachable,
+            RawErrorKind::Timeout => SocketErrorKind::TimedOut,
+        };
+        SocketError::new(kind)
+    }
+}
+
+impl From<TcpError> for SocketError {
+    fn from(e: TcpError) -> Self {
+        let kind = match e.kind {
+            TcpErrorKind::NoSpace => SocketErrorKind::NoBuffers,
+            TcpErrorKind::InvalidId => SocketErrorKind::BadDescriptor,
+            TcpErrorKind::AddressInUse => SocketErrorKind::AddressInUse,
+            TcpErrorKind::AddressNotAvailable | TcpErrorKind::NoPortAvailable => {
+                SocketErrorKind::AddressNotAvailable
+            }
+            TcpErrorKind::InvalidState => SocketErrorKind::InvalidArgument,
+            TcpErrorKind::NotConnected => SocketErrorKind::NotConnected,
+            TcpErrorKind::NoRoute => SocketErrorKind::NetworkUnreachable,
+            TcpErrorKind::ConnectionRefused => SocketErrorKind::ConnectionRefused,
+            TcpErrorKind::ConnectionReset => SocketErrorKind::ConnectionReset,
+            TcpErrorKind::Timeout => SocketErrorKind::TimedOut,
+            TcpErrorKind::Closed => SocketErrorKind::BrokenPipe,
+        };
+        SocketError::new(kind)
+    }
+}
+
+impl From<SocketError> for io::Error {
+    fn from(e: SocketError) -> Self {
+        let kind = match e.kind {
+            SocketErrorKind::InvalidArgument => io::ErrorKind::InvalidInput,
+            SocketErrorKind::NotSupported => io::ErrorKind::Unsupported,
+            SocketErrorKind::AddressInUse => io::ErrorKind::AddrInUse,
+            SocketErrorKind::AddressNotAvailable => io::ErrorKind::AddrNotAvailable,
+            SocketErrorKind::NotConnected => io::ErrorKind::NotConnected,
+            SocketErrorKind::ConnectionRefused => io::ErrorKind::ConnectionRefused,
+            SocketErrorKind::ConnectionReset => io::ErrorKind::ConnectionReset,
+            SocketErrorKind::NetworkUnreachable => io::ErrorKind::NetworkUnreachable,
+            SocketErrorKind::TimedOut => io::ErrorKind::TimedOut,
+            SocketErrorKind::BrokenPipe => io::ErrorKind::BrokenPipe,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e.kind.to_string())
+    }
+}
+
+/// このスタックは IPv4 の UDP、TCP、raw だけを扱う
+fn to_endpoint(addr: &SocketAddr) -> io::Result<Ipv4Endpoint> {
+    match addr {
+        SocketAddr::V4(addr) => Ok(Ipv4Endpoint::from(*addr)),
+        SocketAddr::V6(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "IPv6 sockets are not supported",
+        )),
+    }
+}
+
+fn to_socket_addr(endpoint: Ipv4Endpoint) -> SocketAddr {
+    SocketAddr::V4(SocketAddrV4::from(endpoint))
+}
+
+/// std::net と同じく、解決できたアドレスを順に試して最後のエラーを返す
+fn each_addr<A, F, T>(addr: A, mut f: F) -> io::Result<T>
+where
+    A: ToSocketAddrs,
+    F: FnMut(Ipv4Endpoint) -> io::Result<T>,
+{
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        match to_endpoint(&addr).and_then(&mut f) {
+            Ok(value) => return Ok(value),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+/// std::net と同じく 0 秒のタイムアウトは受け付けない
+fn check_timeout(timeout: Option<Duration>) -> io::Result<()> {
+    if timeout == Some(Duration::ZERO) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot set a 0 duration timeout",
+        ));
+    }
+    Ok(())
+}
+
+fn receive_timeout(fd: usize) -> io::Result<Option<Duration>> {
+    match getsockopt(fd, SocketOptionName::ReceiveTimeout)? {
+        SocketOption::ReceiveTimeout(timeout) => Ok(timeout),
+        _ => unreachable!(),
+    }
+}
+
+fn send_timeout(fd: usize) -> io::Result<Option<Duration>> {
+    match getsockopt(fd, SocketOptionName::SendTimeout)? {
+        SocketOption::SendTimeout(timeout) => Ok(timeout),
+        _ => unreachable!(),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Socket {
+    socket_type: SocketType,
+    /// UDP か TCP か raw の pcb の id
+    id: usize,
+    /// UDP と raw で connect した相手
+    peer: Option<Ipv4Endpoint>,
+    receive_timeout: Option<Duration>,
+    send_timeout: Option<Duration>,
+    /// shutdown で受信側を閉じた
+    read_shutdown: bool,
+}
+
+lazy_static! {
+    static ref SOCKETS: Mutex<Vec<Option<Socket>>> =
+        Mutex::new((0..SOCKET_TABLE_SIZE).map(|_| None).collect());
+}
+
+fn sockets() -> MutexGuard<'static, Vec<Option<Socket>>> {
+    SOCKETS.lock().unwrap()
+}
+
+/// 待っている間に他のスレッドが使えるように複製して返す
+fn get(fd: usize) -> Result<Socket, SocketError> {
+    match sockets().get(fd) {
+        Some(Some(socket)) => Ok(*socket),
+        _ => Err(SocketError::new(SocketErrorKind::BadDescriptor)),
+    }
+}
+
+fn update<F: FnOnce(&mut Socket)>(fd: usize, f: F) -> Result<(), SocketError> {
+    match sockets().get_mut(fd) {
+        Some(Some(socket)) => {
+            f(socket);
+            Ok(())
+        }
+        _ => Err(SocketError::new(SocketErrorKind::BadDescriptor)),
+    }
+}
+
+fn insert(socket: Socket) -> Result<usize, SocketError> {
+    let mut sockets = sockets();
+    match sockets.iter().position(|entry| entry.is_none()) {
+        Some(fd) => {
+            sockets[fd] = Some(socket);
+            Ok(fd)
+        }
+        None => Err(SocketError::new(SocketErrorKind::TooManySockets)),
+    }
+}
+
+fn close_pcb(socket_type: SocketType, id: usize) -> Result<(), SocketError> {
+    match socket_type {
+        SocketType::Stream => tcp::close(id)?,
+        SocketType::Datagram => udp::close(id)?,
+        SocketType::Raw(_) => raw::close(id)?,
+    }
+    Ok(())
+}
+
+/// ソケットを作ってディスクリプタを返す
+pub fn socket(socket_type: SocketType) -> Result<usize, SocketError> {
+    let id = match socket_type {
+        SocketType::Stream => tcp::open()?,
+        SocketType::Datagram => udp::open()?,
+        SocketType::Raw(protocol) => raw::open(protocol)?,
+    };
+    let socket = Socket {
+        socket_type,
+        id,
+        peer: None,
+        receive_timeout: None,
+        send_timeout: None,
+        read_shutdown: false,
+    };
+    insert(socket).inspect_err(|_| {
+        let _ = close_pcb(socket_type, id);
+    })
+}
+
+pub fn setsockopt(fd: usize, option: SocketOption) -> Result<(), SocketError> {
+    match option {
+        SocketOption::ReceiveTimeout(timeout) => {
+            update(fd, |socket| socket.receive_timeout = timeout)
+        }
+        SocketOption::SendTimeout(timeout) => update(fd, |socket| socket.send_timeout = timeout),
+        SocketOption::CongestionControl(algorithm) => {
+            let socket = stream(fd)?;
+            Ok(tcp::set_congestion_control(socket.id, algorithm)?)
+        }
+        SocketOption::KeepAlive(keepalive) => {
+            let socket = stream(fd)?;
+            Ok(tcp::set_keepalive(socket.id, keepalive)?)
+        }
+        SocketOption::HeaderIncluded(included) => {
+            let socket = raw_socket(fd)?;
+            Ok(raw::set_header_included(socket.id, included)?)
+        }
+    }
+}
+
+pub fn getsockopt(fd: usize, name: SocketOptionName) -> Result<SocketOption, SocketError> {
+    let socket = get(fd)?;
+    Ok(match name {
+        SocketOptionName::ReceiveTimeout => SocketOption::ReceiveTimeout(socket.receive_timeout),
+        SocketOptionName::SendTimeout => SocketOption::SendTimeout(socket.send_timeout),
+        SocketOptionName::CongestionControl => {
+            let socket = stream(fd)?;
+            SocketOption::CongestionControl(tcp::congestion_control(socket.id)?)
+        }
+        SocketOptionName::KeepAlive => {
+            let socket = stream(fd)?;
+            SocketOption::KeepAlive(tcp::keepalive(socket.id)?)
+        }
+        SocketOptionName::HeaderIncluded => {
+            let socket = raw_socket(fd)?;
+            SocketOption::HeaderIncluded(raw::header_included(socket.id)?)
+        }
+    })
+}
+
+/// TCP のソケットだけを受け付ける
+fn stream(fd: usize) -> Result<Socket, SocketError> {
+    let socket = get(fd)?;
+    if socket.socket_type != SocketType::Stream {
+        return Err(SocketError::new(SocketErrorKind::NotSupported));
+    }
+    Ok(socket)
+}
+
+/// raw ソケットだけを受け付ける
+fn raw_socket(fd: usize) -> Result<Socket, SocketError> {
+    let socket = get(fd)?;
+    if !matches!(socket.socket_type, SocketType::Raw(_)) {
+        return Err(SocketError::new(SocketErrorKind::NotSupported));
+    }
+    Ok(socket)
+}
+
+pub fn bind(fd: usize, local: Ipv4Endpoint) -> Result<(), SocketError> {
+    let socket = get(fd)?;
+    match socket.socket_type {
+        SocketType::Stream => tcp::bind(socket.id, local)?,
+        SocketType::Datagram => udp::bind(socket.id, local)?,
+        SocketType::Raw(_) => raw::bind(socket.id, local.address)?,
+    }
+    Ok(())
+}
+
+/// TCP では接続が確立するまで待つ。UDP と raw では送り先の既定値と受け取る相手を決める
+pub fn connect(fd: usize, foreign: Ipv4Endpoint) -> Result<(), SocketError> {
+    let socket = get(fd)?;
+    match socket.socket_type {
+        SocketType::Stream => {
+            if tcp::state(socket.id)? != TcpState::Closed {
+                return Err(SocketError::new(SocketErrorKind::AlreadyConnected));
+            }
+            tcp::connect(socket.id, foreign, socket.send_timeout)?;
+            Ok(())
+        }
+        SocketType::Datagram | SocketType::Raw(_) => {
+            update(fd, |socket| socket.peer = Some(foreign))
+        }
+    }
+}
+
+pub fn listen(fd: usize, backlog: usize) -> Result<(), SocketError> {
+    let socket = get(fd)?;
+    match socket.socket_type {
+        SocketType::Stream => Ok(tcp::listen(socket.id, backlog)?),
+        SocketType::Datagram | SocketType::Raw(_) => {
+            Err(SocketError::new(SocketErrorKind::NotSupported))
+        }
+    }
+}
+
+/// 確立した接続を新しいディスクリプタとして返す。タイムアウトは元のソケットから引き継ぐ
+pub fn accept(fd: usize) -> Result<(usize, Ipv4Endpoint), SocketError> {
+    let socket = get(fd)?;
+    if socket.socket_type != SocketType::Stream {
+        return Err(SocketError::new(SocketErrorKind::NotSupported));
+    }
+    let id = tcp::accept(socket.id, socket.receive_timeout)?;
+    insert_accepted(socket, id)
+}
+
+fn insert_accepted(socket: Socket, id: usize) -> Result<(usize, Ipv4Endpoint), SocketError> {
+    let foreign = tcp::foreign_endpoint(id)?;
+    let accepted = Socket {
+        id,
+        peer: None,
+        ..socket
+    };
+    match insert(accepted) {
+        Ok(fd) => Ok((fd, foreign)),
+        Err(e) => {
+            let _ = tcp::abort(id);
+            let _ = tcp::close(id);
+            Err(e)
+        }
+    }
+}
+
+/// 送ったバイト数を返す
+pub fn send(fd: usize, data: &[u8]) -> Result<usize, SocketError> {
+    let socket = get(fd)?;
+    match socket.socket_type {
+        SocketType::Stream => Ok(tcp::send(socket.id, data, socket.send_timeout)?),
+        SocketType::Datagram => match socket.peer {
+            Some(peer) => Ok(udp::sendto(socket.id, data, peer)?),
+            None => Err(SocketError::new(SocketErrorKind::NotConnected)),
+        },
+        SocketType::Raw(_) => match socket.peer {
+            Some(peer) => Ok(raw::sendto(socket.id, data, peer.address)?),
+            None => Err(SocketError::new(SocketErrorKind::NotConnected)),
+        },
+    }
+}
+
+/// TCP では foreign を無視して接続先に送る。raw ではポートを見ない
+pub fn sendto(fd: usize, data: &[u8], foreign: Ipv4Endpoint) -> Result<usize, SocketError> {
+    let socket = get(fd)?;
+    match socket.socket_type {
+        SocketType::Stream => send(fd, data),
+        SocketType::Datagram => Ok(udp::sendto(socket.id, data, foreign)?),
+        SocketType::Raw(_) => Ok(raw::sendto(socket.id, data, foreign.address)?),
+    }
+}
+
+/// 受け取ったバイト数を返す。TCP で相手が閉じていれば 0
+pub fn recv(fd: usize, buf: &mut [u8]) -> Result<usize, SocketError> {
+    recvfrom(fd, buf).map(|(len, _)| len)
+}
+
+/// UDP と raw では buf に収まらない部分は捨てる。raw では IP ヘッダから書き込む
+pub fn recvfrom(fd: usize, buf: &mut [u8]) -> Result<(usize, Ipv4Endpoint), SocketError> {
+    let socket = get(fd)?;
+    if socket.read_shutdown {
+        return Ok((0, shutdown_foreign(&socket)?));
+    }
+    match socket.socket_type {
+        SocketType::Stream => {
+            let len = tcp::receive(socket.id, buf, socket.receive_timeout)?;
+            Ok((len, tcp::foreign_endpoint(socket.id)?))
+        }
+        SocketType::Datagram => {
+            let deadline = socket
+                .receive_timeout
+                .map(|timeout| Instant::now() + timeout);
+            loop {
+                let timeout =
+                    deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                let datagram = udp::recvfrom(socket.id, timeout)?;
+                if let Some(received) = copy_datagram(&socket, datagram, buf) {
+                    return Ok(received);
+                }
+            }
+        }
+        SocketType::Raw(_) => {
+            let deadline = socket
+                .receive_timeout
+                .map(|timeout| Instant::now() + timeout);
+            loop {
+                let timeout =
+                    deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                let packet = raw::recvfrom(socket.id, timeout)?;
+                if let Some(received) = copy_packet(&socket, packet, buf) {
+                    return Ok(received);
+                }
+            }
+        }
+    }
+}
+
+/// 受信側を閉じたソケットで recv したときに返す相手
+fn shutdown_foreign(socket: &Socket) -> Result<Ipv4Endpoint, SocketError> {
+    Ok(match socket.socket_type {
+        SocketType::Stream => tcp::foreign_endpoint(socket.id)?,
+        SocketType::Datagram | SocketType::Raw(_) => {
+            socket.peer.unwrap_or(Ipv4Endpoint::new(IP_ADDRESS_ANY, 0))
+        }
+    })
+}
+
+/// connect した相手以外からのものは捨てて None を返す
+fn copy_datagram(
+    socket: &Socket,
+    datagram: UdpDatagram,
+    buf: &mut [u8],
+) -> Option<(usize, Ipv4Endpoint)> {
+    if matches!(socket.peer, Some(peer) if peer != datagram.foreign) {
+        return None;
+    }
+    let len = buf.len().min(datagram.data.len());
+    buf[..len].copy_from_slice(&datagram.data[..len]);
+    Some((len, datagram.foreign))
+}
+
+/// connect した相手のアドレス以外からのものは捨てて None を返す
+fn copy_packet(
+    socket: &Socket,
+    packet: RawPacket,
+    buf: &mut [u8],
+) -> Option<(usize, Ipv4Endpoint)> {
+    if matches!(socket.peer, Some(peer) if peer.address != packet.src) {
+        return None;
+    }
+    let len = buf.len().min(packet.data.len());
+    buf[..len].copy_from_slice(&packet.data[..len]);
+    Some((len, Ipv4Endpoint::new(packet.src, 0)))
+}
+
+/// 受信側を閉じると以降の recv は 0 を返し、送信側を閉じると TCP では FIN を送る
+pub fn shutdown(fd: usize, how: Shutdown) -> Result<(), SocketError> {
+    let socket = get(fd)?;
+    match (socket.socket_type, how) {
+        (SocketType::Stream, Shutdown::Write | Shutdown::Both) => tcp::shutdown(socket.id)?,
+        (SocketType::Stream, Shutdown::Read) => {
+            tcp::foreign_endpoint(socket.id)?;
+        }
+        (SocketType::Datagram | SocketType::Raw(_), _) if socket.peer.is_none() => {
+            return Err(SocketError::new(SocketErrorKind::NotConnected));
+        }
+        _ => {}
+    }
+    if matches!(how, Shutdown::Read | Shutdown::Both) {
+        update(fd, |socket| socket.read_shutdown = true)?;
+    }
+    Ok(())
+}
+
+/// ディスクリプタを閉じる。TCP の接続はこの後も FIN のやりとりを続ける
+pub fn close(fd: usize) -> Result<(), SocketError> {
+    let socket = match sockets().get_mut(fd) {
+        Some(entry @ Some(_)) => entry.take().unwrap(),
+        _ => return Err(SocketError::new(SocketErrorKind::BadDescriptor)),
+    };
+    close_pcb(socket.socket_type, socket.id)
+}
+
+pub fn getsockname(fd: usize) -> Result<Ipv4Endpoint, SocketError> {
+    let socket = get(fd)?;
+    match socket.socket_type {
+        SocketType::Stream => Ok(tcp::local_endpoint(socket.id)?),
+        SocketType::Datagram => Ok(udp::local_endpoint(socket.id)?),
+        SocketType::Raw(_) => Ok(Ipv4Endpoint::new(raw::local_address(socket.id)?, 0)),
+    }
+}
+
+pub fn getpeername(fd: usize) -> Result<Ipv4Endpoint, SocketError> {
+    let socket = get(fd)?;
+    match socket.socket_type {
+        SocketType::Stream => Ok(tcp::foreign_endpoint(socket.id)?),
+        SocketType::Datagram | SocketType::Raw(_) => socket
+            .peer
+            .ok_or_else(|| SocketError::new(SocketErrorKind::NotConnected)),
+    }
+}
+
+// 以下は Future から使うための版。待たずに Pending を返し、準備ができたら
+// net_thread が Context の waker を起こす。タイムアウトは見ないので、
+// 必要なら呼び出し側のランタイムで掛ける
+
+pub fn poll_accept(
+    fd: usize,
+    cx: &mut Context<'_>,
+) -> Poll<Result<(usize, Ipv4Endpoint), SocketError>> {
+    let socket = match get(fd) {
+        Ok(socket) => socket,
+        Err(err) => return Poll::Ready(Err(err)),
+    };
+    if socket.socket_type != SocketType::Stream {
+        return Poll::Ready(Err(SocketError::new(SocketErrorKind::NotSupported)));
+    }
+    tcp::poll_accept(socket.id, cx).map(|result| insert_accepted(socket, result?))
+}
+
+/// TCP では SYN を送るだけで確立は待たない。確立は poll_connect で待つ
+pub fn connect_start(fd: usize, foreign: Ipv4Endpoint) -> Result<(), SocketError> {
+    let socket = get(fd)?;
+    match socket.socket_type {
+        SocketType::Stream => {
+            if tcp::state(socket.id)? != TcpState::Closed {
+                return Err(SocketError::new(SocketErrorKind::AlreadyConnected));
+            }
+            Ok(tcp::connect_start(socket.id, foreign)?)
+        }
+        SocketType::Datagram | SocketType::Raw(_) => {
+            update(fd, |socket| socket.peer = Some(foreign))
+        }
+    }
+}
+
+pub fn poll_connect(fd: usize, cx: &mut Context<'_>) -> Poll<Result<(), SocketError>> {
+    let socket = match get(fd) {
+        Ok(socket) => socket,
+        Err(err) => return Poll::Ready(Err(err)),
+    };
+    match socket.socket_type {
+        SocketType::Stream => tcp::poll_connect(socket.id, cx).map_err(SocketError::from),
+        SocketType::Datagram | SocketType::Raw(_) => Poll::Ready(Ok(())),
+    }
+}
+
+/// UDP と raw はいつでも送れるのでそのまま送る
+pub fn poll_send(fd: usize, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize, SocketError>> {
+    let socket = match get(fd) {
+        Ok(socket) => socket,
+        Err(err) => return Poll::Ready(Err(err)),
+    };
+    match socket.socket_type {
+        SocketType::Stream => tcp::poll_send(socket.id, cx, data).map_err(SocketError::from),
+        SocketType::Datagram | SocketType::Raw(_) => Poll::Ready(send(fd, data)),
+    }
+}
+
+pub fn poll_recvfrom(
+    fd: usize,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+) -> Poll<Result<(usize, Ipv4Endpoint), SocketError>> {
+    let socket = match get(fd) {
+        Ok(socket) => socket,
+        Err(err) => return Poll::Ready(Err(err)),
+    };
+    if socket.read_shutdown {
+        return Poll::Ready(shutdown_foreign(&socket).map(|foreign| (0, foreign)));
+    }
+    match socket.socket_type {
+        SocketType::Stream => tcp::poll_receive(socket.id, cx, buf).map(|result| {
+            let len = result?;
+            Ok((len, tcp::foreign_endpoint(socket.id)?))
+        }),
+        SocketType::Datagram => loop {
+            let datagram = match udp::poll_recvfrom(socket.id, cx) {
+                Poll::Ready(Ok(datagram)) => datagram,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err.into())),
+                Poll::Pending => return Poll::Pending,
+            };
+            if let Some(received) = copy_datagram(&socket, datagram, buf) {
+                return Poll::Ready(Ok(received));
+            }
+        },
+        SocketType::Raw(_) => loop {
+            let packet = match raw::poll_recvfrom(socket.id, cx) {
+                Poll::Ready(Ok(packet)) => packet,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err.into())),
+                Poll::Pending => return Poll::Pending,
+            };
+            if let Some(received) = copy_packet(&socket, packet, buf) {
+                return Poll::Ready(Ok(received));
+            }
+        },
+    }
+}
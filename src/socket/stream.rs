@@ -0,0 +1,180 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use super::{
+    check_timeout, each_addr, receive_timeout, send_timeout, to_endpoint, to_socket_addr,
+    SocketOption, SocketType,
+};
+
+/// std::net::TcpListener と同じく listen の backlog は 128 を指定する (実際は TCP_BACKLOG_MAX まで)
+const LISTEN_BACKLOG: usize = 128;
+
+/// std::net::TcpStream と同じ使い方ができる TCP の接続
+/// drop すると close する
+#[derive(Debug)]
+pub struct TcpStream {
+    fd: usize,
+}
+
+impl TcpStream {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+        each_addr(addr, |endpoint| {
+            let stream = TcpStream {
+                fd: super::socket(SocketType::Stream)?,
+            };
+            super::connect(stream.fd, endpoint)?;
+            Ok(stream)
+        })
+    }
+
+    /// timeout は接続するときだけに使い、その後の書き込みには残さない
+    pub fn connect_timeout(addr: &SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        check_timeout(Some(timeout))?;
+        let endpoint = to_endpoint(addr)?;
+        let stream = TcpStream {
+            fd: super::socket(SocketType::Stream)?,
+        };
+        super::setsockopt(stream.fd, SocketOption::SendTimeout(Some(timeout)))?;
+        super::connect(stream.fd, endpoint)?;
+        super::setsockopt(stream.fd, SocketOption::SendTimeout(None))?;
+        Ok(stream)
+    }
+
+    /// ソケット層のディスクリプタ
+    pub fn as_raw_fd(&self) -> usize {
+        self.fd
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(to_socket_addr(super::getpeername(self.fd)?))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(to_socket_addr(super::getsockname(self.fd)?))
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        Ok(super::shutdown(self.fd, how)?)
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        check_timeout(timeout)?;
+        Ok(super::setsockopt(
+            self.fd,
+            SocketOption::ReceiveTimeout(timeout),
+        )?)
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        check_timeout(timeout)?;
+        Ok(super::setsockopt(
+            self.fd,
+            SocketOption::SendTimeout(timeout),
+        )?)
+    }
+
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        receive_timeout(self.fd)
+    }
+
+    pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        send_timeout(self.fd)
+    }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+/// std と同じく共有参照からも読み書きできる
+impl Read for &TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(super::recv(self.fd, buf)?)
+    }
+}
+
+impl Write for &TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(super::send(self.fd, buf)?)
+    }
+
+    /// 送信バッファに積んだものは net_thread が送るので何もしない
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let _ = super::close(self.fd);
+    }
+}
+
+/// std::net::TcpListener と同じ使い方ができる待ち受けソケット
+#[derive(Debug)]
+pub struct TcpListener {
+    fd: usize,
+}
+
+impl TcpListener {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
+        each_addr(addr, |endpoint| {
+            let listener = TcpListener {
+                fd: super::socket(SocketType::Stream)?,
+            };
+            super::bind(listener.fd, endpoint)?;
+            super::listen(listener.fd, LISTEN_BACKLOG)?;
+            Ok(listener)
+        })
+    }
+
+    pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let (fd, foreign) = super::accept(self.fd)?;
+        Ok((TcpStream { fd }, to_socket_addr(foreign)))
+    }
+
+    pub fn incoming(&self) -> Incoming<'_> {
+        Incoming { listener: self }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(to_socket_addr(super::getsockname(self.fd)?))
+    }
+
+    pub fn as_raw_fd(&self) -> usize {
+        self.fd
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let _ = super::close(self.fd);
+    }
+}
+
+/// accept を繰り返す反復子
+#[derive(Debug)]
+pub struct Incoming<'a> {
+    listener: &'a TcpListener,
+}
+
+impl<'a> Iterator for Incoming<'a> {
+    type Item = io::Result<TcpStream>;
+
+    fn next(&mut self) -> Option<io::Result<TcpStream>> {
+        Some(self.listener.accept().map(|(stream, _)| stream))
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::{Duration, Instant};
+
+use super::{CongestionControl, CongestionWindow};
+
+/// W_cubic の係数 C (RFC 9438 4.1)
+const CUBIC_C: f64 = 0.4;
+/// 損失のときの減少率
+const CUBIC_BETA: f64 = 0.7;
+
+/// RFC 9438 の CUBIC。ウィンドウの計算は MSS 単位で行う
+#[derive(Debug)]
+pub struct Cubic {
+    window: CongestionWindow,
+    /// 直前の損失のときのウィンドウ
+    w_max: f64,
+    /// w_max に戻るまでの秒数
+    k: f64,
+    /// 輻輳回避を始めた時刻。損失のたびに測り直す
+    epoch_start: Option<Instant>,
+    /// Reno と同じ増え方をしたときのウィンドウ (RFC 9438 4.3)
+    w_est: f64,
+    /// 1 バイトに満たない増分の繰り越し
+    carry: f64,
+}
+
+impl Cubic {
+    pub fn new(mss: u16) -> Self {
+        Cubic {
+            window: CongestionWindow::new(mss),
+            w_max: 0.0,
+            k: 0.0,
+            epoch_start: None,
+            w_est: 0.0,
+            carry: 0.0,
+        }
+    }
+
+    fn segments(&self) -> f64 {
+        self.window.cwnd as f64 / self.window.mss as f64
+    }
+
+    /// 損失を検出したときに w_max を覚えて新しい ssthresh を返す
+    /// 前回より小さいところで損失したら w_max を下げて他の流れに譲る (RFC 9438 4.7)
+    fn congestion_event(&mut self) -> u32 {
+        let cwnd = self.segments();
+        self.w_max = if cwnd < self.w_max {
+            cwnd * (1.0 + CUBIC_BETA) / 2.0
+        } else {
+            cwnd
+        };
+        self.epoch_start = None;
+        self.carry = 0.0;
+        (self.window.cwnd as f64 * CUBIC_BETA) as u32
+    }
+
+    fn start_epoch(&mut self, now: Instant) {
+        let cwnd = self.segments();
+        self.epoch_start = Some(now);
+        if cwnd < self.w_max {
+            self.k = ((self.w_max - cwnd) / CUBIC_C).cbrt();
+        } else {
+            self.k = 0.0;
+            self.w_max = cwnd;
+        }
+        self.w_est = cwnd;
+    }
+
+    /// 損失から t 秒後の目標ウィンドウ (RFC 9438 式 1)
+    fn w_cubic(&self, t: f64) -> f64 {
+        CUBIC_C * (t - self.k).powi(3) + self.w_max
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn name(&self) -> &'static str {
+        "cubic"
+    }
+
+    fn window(&self) -> &CongestionWindow {
+        &self.window
+    }
+
+    fn window_mut(&mut self) -> &mut CongestionWindow {
+        &mut self.window
+    }
+
+    fn on_ack(&mut self, acked: u32, srtt: Option<Duration>, now: Instant) {
+        if self.window.in_slow_start() {
+            self.window.slow_start(acked);
+            return;
+        }
+        if self.epoch_start.is_none() {
+            self.start_epoch(now);
+        }
+        let t = now.duration_since(self.epoch_start.unwrap()).as_secs_f64();
+        let rtt = srtt.unwrap_or_default().as_secs_f64();
+        let cwnd = self.segments();
+        let acked_segments = acked as f64 / self.window.mss as f64;
+
+        // Reno と同じ速さなら 1 RTT で alpha だけ増える (RFC 9438 式 4)
+        let alpha = 3.0 * (1.0 - CUBIC_BETA) / (1.0 + CUBIC_BETA);
+        self.w_est += alpha * acked_segments / cwnd;
+
+        // 1 RTT 先の目標に向けて増やすが、1 RTT で 1.5 倍を超えない (RFC 9438 4.2)
+        let target = self.w_cubic(t + rtt).clamp(cwnd, cwnd * 1.5);
+        let increase = if self.w_cubic(t) < self.w_est {
+            (self.w_est - cwnd).max(0.0)
+        } else {
+            (target - cwnd) / cwnd * acked_segments
+        };
+        let bytes = increase * self.window.mss as f64 + self.carry;
+        self.carry = bytes.fract();
+        self.window.cwnd = self.window.cwnd.saturating_add(bytes as u32);
+    }
+
+    fn on_fast_retransmit(&mut self, _in_flight: u32, _now: Instant) {
+        let ssthresh = self.congestion_event();
+        self.window.enter_recovery(ssthresh);
+    }
+
+    fn on_timeout(&mut self, _in_flight: u32, _now: Instant) {
+        let ssthresh = self.congestion_event();
+        self.window.ssthresh = ssthresh.max(2 * self.window.mss);
+        self.window.cwnd = self.window.mss;
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+mod cubic;
+mod newreno;
+
+pub use cubic::Cubic;
+pub use newreno::NewReno;
+
+/// 輻輳ウィンドウと slow start の閾値 (RFC 5681)
+/// 値はどちらもバイト単位
+#[derive(Debug, Clone, Copy)]
+pub struct CongestionWindow {
+    pub cwnd: u32,
+    pub ssthresh: u32,
+    pub mss: u32,
+}
+
+impl CongestionWindow {
+    pub fn new(mss: u16) -> Self {
+        let mss = mss as u32;
+        CongestionWindow {
+            cwnd: initial_window(mss),
+            ssthresh: u32::MAX,
+            mss,
+        }
+    }
+
+    pub fn in_slow_start(&self) -> bool {
+        self.cwnd < self.ssthresh
+    }
+
+    /// 確認された分だけ広げる。1 回の ACK で増やすのは 1 MSS まで (RFC 5681 3.1)
+    pub fn slow_start(&mut self, acked: u32) {
+        self.cwnd = self.cwnd.saturating_add(acked.min(self.mss));
+    }
+
+    /// 高速回復に入る。重複 ACK 3 つ分だけ膨らませておく (RFC 6582 3.2)
+    pub fn enter_recovery(&mut self, ssthresh: u32) {
+        self.ssthresh = ssthresh.max(2 * self.mss);
+        self.cwnd = self.ssthresh + 3 * self.mss;
+    }
+}
+
+/// 初期ウィンドウ (RFC 5681 3.1)
+pub fn initial_window(mss: u32) -> u32 {
+    (4 * mss).min((2 * mss).max(4380))
+}
+
+/// 輻輳制御のアルゴリズム
+/// 高速回復中のウィンドウの膨張と収縮は既定の実装を共有し、
+/// 回復の外での増やし方と、損失を検出したときの減らし方をそれぞれが決める
+pub trait CongestionControl: Send {
+    fn name(&self) -> &'static str;
+
+    fn window(&self) -> &CongestionWindow;
+
+    fn window_mut(&mut self) -> &mut CongestionWindow;
+
+    /// 高速回復の外で新しいデータが確認された
+    fn on_ack(&mut self, acked: u32, srtt: Option<Duration>, now: Instant);
+
+    /// 3 つ目の重複 ACK で高速再送した。in_flight は未確認のバイト数
+    fn on_fast_retransmit(&mut self, in_flight: u32, now: Instant);
+
+    /// 再送タイマーが切れた
+    fn on_timeout(&mut self, in_flight: u32, now: Instant);
+
+    fn cwnd(&self) -> u32 {
+        self.window().cwnd
+    }
+
+    fn ssthresh(&self) -> u32 {
+        self.window().ssthresh
+    }
+
+    /// 高速回復中の重複 ACK。出ていったセグメントの分だけ膨らませる
+    fn on_duplicate_ack(&mut self) {
+        let window = self.window_mut();
+        window.cwnd = window.cwnd.saturating_add(window.mss);
+    }
+
+    /// 高速回復中の部分 ACK。確認された分だけ縮めて 1 MSS 戻す (RFC 6582 3.2 step 5)
+    fn on_partial_ack(&mut self, acked: u32) {
+        let window = self.window_mut();
+        window.cwnd = window.cwnd.saturating_sub(acked);
+        if acked >= window.mss {
+            window.cwnd += window.mss;
+        }
+        window.cwnd = window.cwnd.max(window.mss);
+    }
+
+    /// 回復を始めたときの snd.nxt まで確認されて高速回復を抜ける
+    fn on_recovery_exit(&mut self) {
+        let window = self.window_mut();
+        window.cwnd = window.ssthresh;
+    }
+}
+
+/// 選べる輻輳制御。ソケットごとに選ぶか、既定値を変える
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CongestionAlgorithm {
+    NewReno,
+    Cubic,
+}
+
+impl CongestionAlgorithm {
+    pub fn build(self, mss: u16) -> Box<dyn CongestionControl> {
+        match self {
+            CongestionAlgorithm::NewReno => Box::new(NewReno::new(mss)),
+            CongestionAlgorithm::Cubic => Box::new(Cubic::new(mss)),
+        }
+    }
+}
+
+impl fmt::Display for CongestionAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            CongestionAlgorithm::NewReno => "newreno",
+            CongestionAlgorithm::Cubic => "cubic",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+lazy_static! {
+    static ref DEFAULT_ALGORITHM: Mutex<CongestionAlgorithm> =
+        Mutex::new(CongestionAlgorithm::NewReno);
+}
+
+/// 選んでいないソケットで使う輻輳制御
+pub fn default_algorithm() -> CongestionAlgorithm {
+    *DEFAULT_ALGORITHM.lock().unwrap()
+}
+
+/// これから作る接続の既定値を変える。すでにある接続はそのまま
+pub fn set_default_algorithm(algorithm: CongestionAlgorithm) {
+    *DEFAULT_ALGORITHM.lock().unwrap() = algorithm;
+}
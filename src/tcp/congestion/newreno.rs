@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use super::{CongestionControl, CongestionWindow};
+
+/// RFC 5681 の slow start と輻輳回避に RFC 6582 の高速回復を組み合わせたもの
+#[derive(Debug)]
+pub struct NewReno {
+    window: CongestionWindow,
+    /// 輻輳回避中に確認されたバイト数 (RFC 3465)
+    bytes_acked: u32,
+}
+
+impl NewReno {
+    pub fn new(mss: u16) -> Self {
+        NewReno {
+            window: CongestionWindow::new(mss),
+            bytes_acked: 0,
+        }
+    }
+
+    /// 損失が起きたら未確認の半分まで減らす (RFC 5681 式 4)
+    fn reduced_ssthresh(&self, in_flight: u32) -> u32 {
+        (in_flight / 2).max(2 * self.window.mss)
+    }
+}
+
+impl CongestionControl for NewReno {
+    fn name(&self) -> &'static str {
+        "newreno"
+    }
+
+    fn window(&self) -> &CongestionWindow {
+        &self.window
+    }
+
+    fn window_mut(&mut self) -> &mut CongestionWindow {
+        &mut self.window
+    }
+
+    /// 輻輳回避では cwnd 分が確認されるごとに 1 MSS 広げる
+    fn on_ack(&mut self, acked: u32, _srtt: Option<Duration>, _now: Instant) {
+        if self.window.in_slow_start() {
+            self.window.slow_start(acked);
+            return;
+        }
+        self.bytes_acked = self.bytes_acked.saturating_add(acked);
+        if self.bytes_acked >= self.window.cwnd {
+            self.bytes_acked -= self.window.cwnd;
+            self.window.cwnd = self.window.cwnd.saturating_add(self.window.mss);
+        }
+    }
+
+    fn on_fast_retransmit(&mut self, in_flight: u32, _now: Instant) {
+        let ssthresh = self.reduced_ssthresh(in_flight);
+        self.window.enter_recovery(ssthresh);
+        self.bytes_acked = 0;
+    }
+
+    /// 1 MSS から slow start し直す
+    fn on_timeout(&mut self, in_flight: u32, _now: Instant) {
+        self.window.ssthresh = self.reduced_ssthresh(in_flight);
+        self.window.cwnd = self.window.mss;
+        self.bytes_acked = 0;
+    }
+}
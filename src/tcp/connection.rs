@@ -0,0 +1,1272 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::congestion::{self, CongestionControl, CongestionWindow};
+use super::header::{
+    TcpHeader, TCP_FLAG_ACK, TCP_FLAG_FIN, TCP_FLAG_PSH, TCP_FLAG_RST, TCP_FLAG_SYN,
+};
+use super::option::{SackBlock, TcpOptions, TcpTimestamp, TCP_WINDOW_SCALE_MAX};
+use super::retransmit::{
+    RetransmitQueue, RtoEstimator, TCP_DUP_ACK_THRESHOLD, TCP_RETRANSMIT_LIMIT,
+    TCP_RTO_AFTER_SYN_TIMEOUT, TCP_RTO_MAX, TCP_SYN_RETRANSMIT_LIMIT,
+};
+use super::TcpErrorKind;
+use crate::ipv4::Ipv4Endpoint;
+
+/// 送信、受信それぞれのバッファの大きさ
+pub const TCP_BUFFER_SIZE: usize = 65535;
+/// MSS オプションを受け取らなかったときに仮定する値 (RFC 1122 4.2.2.6)
+pub const TCP_DEFAULT_MSS: u16 = 536;
+/// セグメントの最大生存時間
+pub const TCP_MSL: Duration = Duration::from_secs(30);
+/// これより長く相手から届かなければ TS.Recent を信用しない (RFC 7323 5.5)
+pub const TCP_PAWS_IDLE: Duration = Duration::from_secs(24 * 24 * 60 * 60);
+/// Timestamp オプションと揃えるための NOP 2 つの大きさ
+const TCP_TIMESTAMP_OVERHEAD: u16 = 12;
+/// 1 秒あたりに返す RFC 5961 の確かめる ACK の上限
+pub const TCP_CHALLENGE_ACK_LIMIT: u32 = 10;
+/// キープアライブの既定値 (RFC 1122 4.2.3.6)
+pub const TCP_KEEPALIVE_IDLE: Duration = Duration::from_secs(2 * 60 * 60);
+pub const TCP_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(75);
+pub const TCP_KEEPALIVE_PROBES: u32 = 9;
+
+/// 受信バッファを通知し切れるだけのウィンドウスケール
+fn window_scale_for(buffer: usize) -> u8 {
+    let mut shift = 0;
+    while shift < TCP_WINDOW_SCALE_MAX && (u16::MAX as usize) << shift < buffer {
+        shift += 1;
+    }
+    shift
+}
+
+/// SYN で申し出るオプション。相手も申し出たものだけが使われる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpConfig {
+    /// 知らせる MSS。送出するデバイスの MTU から決める
+    pub mss: u16,
+    pub window_scale: bool,
+    pub sack: bool,
+    pub timestamps: bool,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        TcpConfig {
+            mss: TCP_DEFAULT_MSS,
+            window_scale: true,
+            sack: true,
+            timestamps: true,
+        }
+    }
+}
+
+/// キープアライブの設定
+/// idle の間なにも受け取らなければ interval ごとに probes 回まで確かめ、応答がなければ接続を破棄する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    pub idle: Duration,
+    pub interval: Duration,
+    pub probes: u32,
+}
+
+impl Default for TcpKeepalive {
+    fn default() -> Self {
+        TcpKeepalive {
+            idle: TCP_KEEPALIVE_IDLE,
+            interval: TCP_KEEPALIVE_INTERVAL,
+            probes: TCP_KEEPALIVE_PROBES,
+        }
+    }
+}
+
+/// a が b より前にあるか (RFC 1982 の比較)
+pub fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+pub fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+pub fn seq_gt(a: u32, b: u32) -> bool {
+    seq_lt(b, a)
+}
+
+pub fn seq_ge(a: u32, b: u32) -> bool {
+    seq_le(b, a)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl TcpState {
+    /// SYN を交換し終えて順序番号が同期しているか
+    pub fn is_synchronized(&self) -> bool {
+        !matches!(
+            self,
+            TcpState::Closed | TcpState::Listen | TcpState::SynSent | TcpState::SynReceived
+        )
+    }
+}
+
+impl fmt::Display for TcpState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TcpState::Closed => "CLOSED",
+            TcpState::Listen => "LISTEN",
+            TcpState::SynSent => "SYN-SENT",
+            TcpState::SynReceived => "SYN-RECEIVED",
+            TcpState::Established => "ESTABLISHED",
+            TcpState::FinWait1 => "FIN-WAIT-1",
+            TcpState::FinWait2 => "FIN-WAIT-2",
+            TcpState::CloseWait => "CLOSE-WAIT",
+            TcpState::Closing => "CLOSING",
+            TcpState::LastAck => "LAST-ACK",
+            TcpState::TimeWait => "TIME-WAIT",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// ヘッダのうち接続の処理に使う部分とデータ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpSegment {
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub options: TcpOptions,
+    pub data: Vec<u8>,
+}
+
+impl TcpSegment {
+    pub fn from_header<T: AsRef<[u8]>>(header: &TcpHeader<T>) -> Self {
+        TcpSegment {
+            seq: header.seq(),
+            ack: header.ack(),
+            flags: header.flags(),
+            window: header.window(),
+            options: TcpOptions::from_bytes(header.options()),
+            data: header.payload().to_vec(),
+        }
+    }
+
+    /// 順序番号空間で占める長さ (SYN と FIN は 1 つずつ数える)
+    pub fn seq_len(&self) -> u32 {
+        let mut len = self.data.len() as u32;
+        if self.flags & TCP_FLAG_SYN != 0 {
+            len += 1;
+        }
+        if self.flags & TCP_FLAG_FIN != 0 {
+            len += 1;
+        }
+        len
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    fn reset(seq: u32) -> Self {
+        TcpSegment {
+            seq,
+            ack: 0,
+            flags: TCP_FLAG_RST,
+            window: 0,
+            options: TcpOptions::default(),
+            data: Vec::new(),
+        }
+    }
+
+    /// 接続の無いところに届いたセグメントへの RST (RFC 793 3.4)
+    /// RST には RST を返さない
+    pub fn reset_for(&self) -> Option<Self> {
+        if self.has(TCP_FLAG_RST) {
+            return None;
+        }
+        if self.has(TCP_FLAG_ACK) {
+            return Some(TcpSegment::reset(self.ack));
+        }
+        Some(TcpSegment {
+            seq: 0,
+            ack: self.seq.wrapping_add(self.seq_len()),
+            flags: TCP_FLAG_RST | TCP_FLAG_ACK,
+            window: 0,
+            options: TcpOptions::default(),
+            data: Vec::new(),
+        })
+    }
+}
+
+/// 送信側の順序番号変数
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SendSequence {
+    pub una: u32,
+    pub nxt: u32,
+    pub wnd: u32,
+    pub wl1: u32,
+    pub wl2: u32,
+}
+
+/// 受信側の順序番号変数
+/// wnd は最後に通知したウィンドウ
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReceiveSequence {
+    pub nxt: u32,
+    pub wnd: u32,
+}
+
+/// 1 本の接続の状態遷移 (RFC 793 3.9)
+/// 送るべきセグメントを返すだけで、実際の送信は呼び出し側が行う
+pub struct TcpConnection {
+    pub local: Ipv4Endpoint,
+    pub foreign: Ipv4Endpoint,
+    pub state: TcpState,
+    pub iss: u32,
+    pub irs: u32,
+    pub snd: SendSequence,
+    pub rcv: ReceiveSequence,
+    /// 相手に送るセグメントの大きさの上限。オプションの分は含まない
+    pub mss: u16,
+    pub config: TcpConfig,
+    /// 相手も SYN で申し出て使うことになったオプション
+    pub window_scale_ok: bool,
+    pub sack_ok: bool,
+    pub timestamps_ok: bool,
+    /// 受信ウィンドウを右にずらす数と、相手のウィンドウを左にずらす数
+    rcv_wscale: u8,
+    snd_wscale: u8,
+    /// PAWS のために覚えておく相手の TSval (RFC 7323 5.3)
+    ts_recent: u32,
+    ts_recent_age: Instant,
+    last_ack_sent: u32,
+    /// 送る TSval の起点
+    ts_base: Instant,
+    /// 未確認と未送信のデータ。先頭が snd.una にあたる
+    send_buffer: VecDeque<u8>,
+    /// send_buffer のうち送信済みのバイト数
+    sent: usize,
+    receive_buffer: VecDeque<u8>,
+    /// 順序が飛んで届いたデータ。rcv.nxt に追いついたら受信バッファへ移す
+    out_of_order: Vec<(u32, Vec<u8>)>,
+    /// 最後に順序外で受け取ったデータの先頭。SACK の最初のブロックにする (RFC 2018 4)
+    latest_out_of_order: Option<u32>,
+    fin_queued: bool,
+    fin_sent: bool,
+    fin_received: bool,
+    error: Option<TcpErrorKind>,
+    time_wait: Option<Instant>,
+    /// これまでに相手が知らせてきた最大のウィンドウ。古すぎる ACK を見分けるのに使う
+    max_snd_wnd: u32,
+    /// 確かめる ACK を最初に返した時刻と、それから 1 秒の間に返した数
+    challenge_since: Instant,
+    challenge_acks: u32,
+    keepalive: Option<TcpKeepalive>,
+    /// 最後に受け入れたセグメントの時刻
+    last_received: Instant,
+    /// 応答がないまま送ったキープアライブの数
+    keepalive_probes: u32,
+    retransmit: RetransmitQueue,
+    rto: RtoEstimator,
+    /// 再送タイマーが切れる時刻。確認待ちが無ければ止めておく
+    rto_deadline: Option<Instant>,
+    /// 先頭のセグメントを続けて再送した回数
+    retries: u32,
+    dup_acks: u32,
+    syn_retransmitted: bool,
+    /// ゼロウィンドウのプローブを次に送る時刻。窓が開いていれば止めておく (RFC 1122 4.2.2.17)
+    persist_deadline: Option<Instant>,
+    /// 窓が開かないまま送ったプローブの数。間隔を倍にしていくのに使う
+    persist_probes: u32,
+    congestion: Box<dyn CongestionControl>,
+    /// 高速回復を始めたときの snd.nxt。回復中でなければ None (RFC 6582)
+    recover: Option<u32>,
+}
+
+impl TcpConnection {
+    fn new(
+        local: Ipv4Endpoint,
+        foreign: Ipv4Endpoint,
+        iss: u32,
+        state: TcpState,
+        config: TcpConfig,
+    ) -> Self {
+        let now = Instant::now();
+        TcpConnection {
+            local,
+            foreign,
+            state,
+            iss,
+            irs: 0,
+            snd: SendSequence {
+                una: iss,
+                nxt: iss.wrapping_add(1),
+                ..Default::default()
+            },
+            rcv: ReceiveSequence::default(),
+            mss: TCP_DEFAULT_MSS,
+            config,
+            window_scale_ok: config.window_scale,
+            sack_ok: config.sack,
+            timestamps_ok: config.timestamps,
+            rcv_wscale: 0,
+            snd_wscale: 0,
+            ts_recent: 0,
+            ts_recent_age: now,
+            last_ack_sent: 0,
+            ts_base: now,
+            send_buffer: VecDeque::new(),
+            sent: 0,
+            receive_buffer: VecDeque::new(),
+            out_of_order: Vec::new(),
+            latest_out_of_order: None,
+            fin_queued: false,
+            fin_sent: false,
+            fin_received: false,
+            error: None,
+            time_wait: None,
+            max_snd_wnd: 0,
+            challenge_since: now,
+            challenge_acks: 0,
+            keepalive: None,
+            last_received: now,
+            keepalive_probes: 0,
+            retransmit: RetransmitQueue::new(),
+            rto: RtoEstimator::new(),
+            rto_deadline: None,
+            retries: 0,
+            dup_acks: 0,
+            syn_retransmitted: false,
+            persist_deadline: None,
+            persist_probes: 0,
+            congestion: congestion::default_algorithm().build(TCP_DEFAULT_MSS),
+            recover: None,
+        }
+    }
+
+    /// 能動オープン。SYN を返す
+    pub fn connect(local: Ipv4Endpoint, foreign: Ipv4Endpoint, iss: u32) -> (Self, TcpSegment) {
+        TcpConnection::connect_with(local, foreign, iss, TcpConfig::default())
+    }
+
+    pub fn connect_with(
+        local: Ipv4Endpoint,
+        foreign: Ipv4Endpoint,
+        iss: u32,
+        config: TcpConfig,
+    ) -> (Self, TcpSegment) {
+        let mut conn = TcpConnection::new(local, foreign, iss, TcpState::SynSent, config);
+        conn.rcv_wscale = window_scale_for(TCP_BUFFER_SIZE);
+        let syn = conn.segment(iss, TCP_FLAG_SYN, Vec::new());
+        conn.track(&syn);
+        (conn, syn)
+    }
+
+    /// LISTEN 中に受け取った SYN から接続を作る。SYN-ACK を返す
+    pub fn accept(
+        local: Ipv4Endpoint,
+        foreign: Ipv4Endpoint,
+        syn: &TcpSegment,
+        iss: u32,
+    ) -> (Self, TcpSegment) {
+        TcpConnection::accept_with(local, foreign, syn, iss, TcpConfig::default())
+    }
+
+    pub fn accept_with(
+        local: Ipv4Endpoint,
+        foreign: Ipv4Endpoint,
+        syn: &TcpSegment,
+        iss: u32,
+        config: TcpConfig,
+    ) -> (Self, TcpSegment) {
+        let mut conn = TcpConnection::new(local, foreign, iss, TcpState::SynReceived, config);
+        conn.rcv_wscale = window_scale_for(TCP_BUFFER_SIZE);
+        conn.irs = syn.seq;
+        conn.rcv.nxt = syn.seq.wrapping_add(1);
+        conn.negotiate(syn, Instant::now());
+        conn.snd.wnd = syn.window as u32;
+        conn.snd.wl1 = syn.seq;
+        let syn_ack = conn.segment(iss, TCP_FLAG_SYN | TCP_FLAG_ACK, Vec::new());
+        conn.track(&syn_ack);
+        (conn, syn_ack)
+    }
+
+    /// 相手の SYN に付いていたオプションで使うものを決める
+    fn negotiate(&mut self, syn: &TcpSegment, now: Instant) {
+        let options = &syn.options;
+        self.mss = options
+            .mss
+            .unwrap_or(TCP_DEFAULT_MSS)
+            .min(self.config.mss)
+            .max(1);
+        self.window_scale_ok = self.config.window_scale && options.window_scale.is_some();
+        if self.window_scale_ok {
+            self.snd_wscale = options.window_scale.unwrap().min(TCP_WINDOW_SCALE_MAX);
+        } else {
+            self.rcv_wscale = 0;
+        }
+        self.sack_ok = self.config.sack && options.sack_permitted;
+        self.timestamps_ok = self.config.timestamps && options.timestamp.is_some();
+        if let (true, Some(timestamp)) = (self.timestamps_ok, options.timestamp) {
+            self.ts_recent = timestamp.value;
+            self.ts_recent_age = now;
+        }
+        *self.congestion.window_mut() = CongestionWindow::new(self.send_mss());
+    }
+
+    /// PMTU が小さくなったときに呼ぶ。これから作るセグメントを mss に収める
+    pub fn clamp_mss(&mut self, mss: u16) {
+        let mss = mss.max(1);
+        self.config.mss = self.config.mss.min(mss);
+        if mss < self.mss {
+            eprintln!(
+                "TCP MSS clamped LOCAL={} FOREIGN={} MSS={} -> {}",
+                self.local, self.foreign, self.mss, mss
+            );
+            self.mss = mss;
+        }
+    }
+
+    /// 1 つのセグメントに載せるデータの上限。毎回付けるオプションの分を差し引く (RFC 6691)
+    pub fn send_mss(&self) -> u16 {
+        if self.timestamps_ok {
+            self.mss.saturating_sub(TCP_TIMESTAMP_OVERHEAD).max(1)
+        } else {
+            self.mss
+        }
+    }
+
+    /// 今の受信バッファの空き
+    pub fn window(&self) -> u32 {
+        (TCP_BUFFER_SIZE - self.receive_buffer.len()) as u32
+    }
+
+    /// 相手のセグメントのウィンドウ欄を実際の大きさに直す。SYN のものはずらさない
+    fn send_window(&self, segment: &TcpSegment) -> u32 {
+        if segment.has(TCP_FLAG_SYN) {
+            segment.window as u32
+        } else {
+            (segment.window as u32) << self.snd_wscale
+        }
+    }
+
+    /// 送る TSval。ミリ秒で数える
+    fn timestamp(&self) -> u32 {
+        self.iss
+            .wrapping_add(self.ts_base.elapsed().as_millis() as u32)
+    }
+
+    /// 受信済みでまだ読まれていないバイト数
+    pub fn available(&self) -> usize {
+        self.receive_buffer.len()
+    }
+
+    /// send で受け付けられるバイト数
+    pub fn send_space(&self) -> usize {
+        TCP_BUFFER_SIZE - self.send_buffer.len()
+    }
+
+    /// 未確認のデータと FIN がすべて確認されたか
+    pub fn is_idle(&self) -> bool {
+        self.send_buffer.is_empty() && self.snd.una == self.snd.nxt
+    }
+
+    /// 相手の FIN を受け取って受信バッファも空になった
+    pub fn is_eof(&self) -> bool {
+        self.fin_received && self.receive_buffer.is_empty()
+    }
+
+    pub fn error(&self) -> Option<TcpErrorKind> {
+        self.error
+    }
+
+    /// TIME-WAIT に入った時刻
+    pub fn time_wait_since(&self) -> Option<Instant> {
+        match self.state {
+            TcpState::TimeWait => self.time_wait,
+            _ => None,
+        }
+    }
+
+    pub fn keepalive(&self) -> Option<TcpKeepalive> {
+        self.keepalive
+    }
+
+    /// None で止める。最後に受け取ったときから idle を測る
+    pub fn set_keepalive(&mut self, keepalive: Option<TcpKeepalive>) {
+        self.keepalive = keepalive;
+        self.keepalive_probes = 0;
+    }
+
+    /// 今の再送タイムアウト
+    pub fn rto(&self) -> &RtoEstimator {
+        &self.rto
+    }
+
+    /// 再送キューに残っているセグメントの数
+    pub fn unacknowledged(&self) -> usize {
+        self.retransmit.len()
+    }
+
+    pub fn congestion(&self) -> &dyn CongestionControl {
+        self.congestion.as_ref()
+    }
+
+    /// 輻輳制御を差し替える。ウィンドウは初期値からやり直す
+    pub fn set_congestion_control(&mut self, congestion: Box<dyn CongestionControl>) {
+        self.congestion = congestion;
+        self.recover = None;
+    }
+
+    /// 送ったがまだ確認されていないバイト数
+    fn in_flight(&self) -> u32 {
+        self.snd.nxt.wrapping_sub(self.snd.una)
+    }
+
+    /// データを送れる状態か
+    pub fn can_send(&self) -> bool {
+        matches!(
+            self.state,
+            TcpState::SynSent | TcpState::SynReceived | TcpState::Established | TcpState::CloseWait
+        ) && !self.fin_queued
+    }
+
+    fn segment(&mut self, seq: u32, flags: u8, data: Vec<u8>) -> TcpSegment {
+        let syn = flags & TCP_FLAG_SYN != 0;
+        // SYN のウィンドウはずらさない (RFC 7323 2.2)
+        let shift = if syn { 0 } else { self.rcv_wscale };
+        let window = (self.window() >> shift).min(u16::MAX as u32) as u16;
+        self.rcv.wnd = (window as u32) << shift;
+        let ack = if flags & TCP_FLAG_ACK != 0 {
+            self.last_ack_sent = self.rcv.nxt;
+            self.rcv.nxt
+        } else {
+            0
+        };
+        let mut options = TcpOptions::default();
+        if syn {
+            options.mss = Some(self.config.mss);
+            if self.window_scale_ok {
+                options.window_scale = Some(self.rcv_wscale);
+            }
+            options.sack_permitted = self.sack_ok;
+        }
+        if self.timestamps_ok {
+            options.timestamp = Some(TcpTimestamp {
+                value: self.timestamp(),
+                // TSecr は ACK を立てたときだけ意味を持つ
+                echo: if flags & TCP_FLAG_ACK != 0 {
+                    self.ts_recent
+                } else {
+                    0
+                },
+            });
+        }
+        // SACK はデータを運ばないセグメントだけに付ける
+        if self.sack_ok && !syn && data.is_empty() {
+            options.sack = self.sack_blocks();
+        }
+        TcpSegment {
+            seq,
+            ack,
+            flags,
+            window,
+            options,
+            data,
+        }
+    }
+
+    /// 順序外で受け取っている範囲。最後に受け取ったものを含む範囲を先頭にする
+    fn sack_blocks(&self) -> Vec<SackBlock> {
+        let nxt = self.rcv.nxt;
+        let mut ranges: Vec<(u32, u32)> = self
+            .out_of_order
+            .iter()
+            .map(|(seq, data)| (seq.wrapping_sub(nxt), data.len() as u32))
+            .map(|(offset, len)| (offset, offset + len))
+            .collect();
+        ranges.sort_unstable();
+        let mut merged: Vec<(u32, u32)> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        let mut blocks: Vec<SackBlock> = merged
+            .into_iter()
+            .map(|(start, end)| SackBlock {
+                left: nxt.wrapping_add(start),
+                right: nxt.wrapping_add(end),
+            })
+            .collect();
+        if let Some(latest) = self.latest_out_of_order {
+            if let Some(i) = blocks
+                .iter()
+                .position(|block| seq_le(block.left, latest) && seq_lt(latest, block.right))
+            {
+                let block = blocks.remove(i);
+                blocks.insert(0, block);
+            }
+        }
+        // Timestamp と一緒なら 3 つ、そうでなければ 4 つまでしか入らない
+        blocks.truncate(if self.timestamps_ok { 3 } else { 4 });
+        blocks
+    }
+
+    fn ack_segment(&mut self) -> TcpSegment {
+        self.segment(self.snd.nxt, TCP_FLAG_ACK, Vec::new())
+    }
+
+    /// 偽の RST や SYN かもしれないときに相手に確かめる ACK (RFC 5961)
+    /// 数を抑えて、それを超えたら何も返さない (RFC 5961 7)
+    fn challenge_ack(&mut self, now: Instant) -> Option<TcpSegment> {
+        if self.challenge_acks == 0
+            || now.saturating_duration_since(self.challenge_since) >= Duration::from_secs(1)
+        {
+            self.challenge_since = now;
+            self.challenge_acks = 0;
+        }
+        if self.challenge_acks >= TCP_CHALLENGE_ACK_LIMIT {
+            return None;
+        }
+        self.challenge_acks += 1;
+        Some(self.ack_segment())
+    }
+
+    fn set_time_wait(&mut self, now: Instant) {
+        self.state = TcpState::TimeWait;
+        self.time_wait = Some(now);
+    }
+
+    fn set_closed(&mut self, error: Option<TcpErrorKind>) {
+        self.state = TcpState::Closed;
+        if self.error.is_none() {
+            self.error = error;
+        }
+        self.send_buffer.clear();
+        self.sent = 0;
+        self.out_of_order.clear();
+        self.retransmit.clear();
+        self.rto_deadline = None;
+        self.persist_deadline = None;
+    }
+
+    /// 送ったセグメントを再送キューに積み、止まっていればタイマーを動かす (RFC 6298 5.1)
+    fn track(&mut self, segment: &TcpSegment) {
+        if segment.seq_len() == 0 {
+            return;
+        }
+        let now = Instant::now();
+        self.retransmit.push(segment.clone(), now);
+        if self.rto_deadline.is_none() {
+            self.rto_deadline = Some(now + self.rto.rto());
+        }
+    }
+
+    /// snd.una が ack まで進んだときに再送キューとタイマーを更新する (RFC 6298 5.2, 5.3)
+    fn acknowledge(&mut self, ack: u32, now: Instant) {
+        if let Some(rtt) = self.retransmit.acknowledge(ack, now) {
+            self.rto.sample(rtt);
+        }
+        if self.syn_retransmitted && self.state.is_synchronized() {
+            self.syn_retransmitted = false;
+            if self.rto.srtt().is_none() {
+                self.rto.reset(TCP_RTO_AFTER_SYN_TIMEOUT);
+            }
+        }
+        self.retries = 0;
+        self.dup_acks = 0;
+        self.rto_deadline = if self.retransmit.is_empty() {
+            None
+        } else {
+            Some(now + self.rto.rto())
+        };
+    }
+
+    /// 再送キューの先頭をいまの ack とウィンドウで送り直す
+    fn retransmit_first(&mut self) -> Option<TcpSegment> {
+        let seq = self.retransmit.front_mut()?.segment.seq;
+        self.resend(seq)
+    }
+
+    /// SACK で受け取られていないとわかった穴のうち、まだ再送していないものを送り直す (RFC 6675)
+    fn retransmit_hole(&mut self) -> Option<TcpSegment> {
+        let seq = self.retransmit.next_hole_mut()?.segment.seq;
+        self.resend(seq)
+    }
+
+    fn resend(&mut self, seq: u32) -> Option<TcpSegment> {
+        let entry = self.retransmit.find_mut(seq)?;
+        entry.retransmitted = true;
+        let (seq, flags, data) = (
+            entry.segment.seq,
+            entry.segment.flags,
+            entry.segment.data.clone(),
+        );
+        Some(self.segment(seq, flags, data))
+    }
+
+    /// TIME-WAIT で 2MSL 経ったら CLOSED にする
+    /// 再送タイマーが切れていれば先頭のセグメントを再送し、上限を超えたら接続を破棄する
+    /// 相手のウィンドウが 0 のまま止まっていればプローブを、確認待ちがなければキープアライブを送る
+    pub fn tick(&mut self, now: Instant) -> Vec<TcpSegment> {
+        if let (TcpState::TimeWait, Some(since)) = (self.state, self.time_wait) {
+            if now.saturating_duration_since(since) >= TCP_MSL * 2 {
+                self.state = TcpState::Closed;
+            }
+            return Vec::new();
+        }
+        match self.rto_deadline {
+            Some(deadline) if now >= deadline => {}
+            Some(_) => return Vec::new(),
+            None if self.persist_deadline.is_some() => return self.persist_tick(now),
+            None => return self.keepalive_tick(now),
+        }
+        let handshake = matches!(self.state, TcpState::SynSent | TcpState::SynReceived);
+        let limit = if handshake {
+            TCP_SYN_RETRANSMIT_LIMIT
+        } else {
+            TCP_RETRANSMIT_LIMIT
+        };
+        if self.retries >= limit {
+            self.error = Some(TcpErrorKind::Timeout);
+            return self.abort().into_iter().collect();
+        }
+        if !handshake {
+            self.congestion.on_timeout(self.in_flight(), now);
+            self.recover = None;
+            // 相手が SACK したデータを捨てているかもしれないので初めからやり直す
+            self.retransmit.clear_sacked();
+        }
+        self.retries += 1;
+        self.syn_retransmitted |= handshake;
+        // RFC 6298 5.5, 5.6
+        self.rto.backoff();
+        self.rto_deadline = Some(now + self.rto.rto());
+        self.retransmit_first().into_iter().collect()
+    }
+
+    /// 確認済みの最後のバイトを送り直すように見せて相手のウィンドウを尋ねる
+    /// 応答があってもウィンドウが 0 なら間隔を倍にして続け、応答がある限り接続は切らない (RFC 1122 4.2.2.17)
+    fn persist_tick(&mut self, now: Instant) -> Vec<TcpSegment> {
+        match self.persist_deadline {
+            Some(deadline) if now >= deadline => {}
+            _ => return Vec::new(),
+        }
+        self.persist_probes += 1;
+        let interval = self
+            .rto
+            .rto()
+            .saturating_mul(2u32.saturating_pow(self.persist_probes))
+            .min(TCP_RTO_MAX);
+        self.persist_deadline = Some(now + interval);
+        vec![self.segment(self.snd.una.wrapping_sub(1), TCP_FLAG_ACK, Vec::new())]
+    }
+
+    /// 確認済みの最後のバイトを送り直すように見せて相手の ACK を促す (RFC 1122 4.2.3.6)
+    fn keepalive_tick(&mut self, now: Instant) -> Vec<TcpSegment> {
+        let keepalive = match self.keepalive {
+            Some(keepalive) => keepalive,
+            None => return Vec::new(),
+        };
+        if !matches!(self.state, TcpState::Established | TcpState::CloseWait) || !self.is_idle() {
+            return Vec::new();
+        }
+        let due = self.last_received + keepalive.idle + keepalive.interval * self.keepalive_probes;
+        if now < due {
+            return Vec::new();
+        }
+        if self.keepalive_probes >= keepalive.probes {
+            self.error = Some(TcpErrorKind::Timeout);
+            return self.abort().into_iter().collect();
+        }
+        self.keepalive_probes += 1;
+        vec![self.segment(self.snd.nxt.wrapping_sub(1), TCP_FLAG_ACK, Vec::new())]
+    }
+
+    /// 送信バッファに積めるだけ積む。実際の送信は output で行う
+    pub fn send(&mut self, data: &[u8]) -> usize {
+        if !self.can_send() {
+            return 0;
+        }
+        let len = data.len().min(self.send_space());
+        self.send_buffer.extend(&data[..len]);
+        len
+    }
+
+    /// 受信バッファから読み出す
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.receive_buffer.len());
+        for (dst, src) in buf.iter_mut().zip(self.receive_buffer.drain(..len)) {
+            *dst = src;
+        }
+        len
+    }
+
+    /// 送れるだけのデータと、閉じる準備ができていれば FIN を作る
+    /// 読み出しで受信ウィンドウが十分に開いたときはウィンドウ更新の ACK を返す
+    pub fn output(&mut self) -> Vec<TcpSegment> {
+        let mut segments = Vec::new();
+        if !matches!(self.state, TcpState::Established | TcpState::CloseWait) {
+            return segments;
+        }
+        // 相手のウィンドウと輻輳ウィンドウの小さい方まで送る
+        let wnd = self.snd.wnd.min(self.congestion.cwnd());
+        loop {
+            let in_flight = self.in_flight();
+            let unsent = self.send_buffer.len() - self.sent;
+            if unsent == 0 || in_flight >= wnd {
+                break;
+            }
+            let len = unsent
+                .min(self.send_mss() as usize)
+                .min((wnd - in_flight) as usize);
+            let data: Vec<u8> = self
+                .send_buffer
+                .range(self.sent..self.sent + len)
+                .copied()
+                .collect();
+            let mut flags = TCP_FLAG_ACK;
+            if self.sent + len == self.send_buffer.len() {
+                flags |= TCP_FLAG_PSH;
+            }
+            let segment = self.segment(self.snd.nxt, flags, data);
+            self.track(&segment);
+            segments.push(segment);
+            self.snd.nxt = self.snd.nxt.wrapping_add(len as u32);
+            self.sent += len;
+        }
+        if self.fin_queued && !self.fin_sent && self.sent == self.send_buffer.len() {
+            let fin = self.segment(self.snd.nxt, TCP_FLAG_FIN | TCP_FLAG_ACK, Vec::new());
+            self.track(&fin);
+            segments.push(fin);
+            self.snd.nxt = self.snd.nxt.wrapping_add(1);
+            self.fin_sent = true;
+            self.state = match self.state {
+                TcpState::CloseWait => TcpState::LastAck,
+                _ => TcpState::FinWait1,
+            };
+        }
+        self.update_persist();
+        if segments.is_empty() && self.window_update_needed() {
+            segments.push(self.ack_segment());
+        }
+        segments
+    }
+
+    /// 相手のウィンドウが 0 で未送信のデータがあり、再送タイマーも止まっていれば持続タイマーを動かす
+    /// 窓が開けば止めて、プローブの間隔も初めに戻す
+    fn update_persist(&mut self) {
+        let in_flight = self.snd.nxt.wrapping_sub(self.snd.una);
+        let unsent = self.send_buffer.len() - self.sent;
+        if self.snd.wnd > 0 || unsent == 0 || in_flight > 0 {
+            self.persist_deadline = None;
+            self.persist_probes = 0;
+        } else if self.persist_deadline.is_none() {
+            self.persist_deadline = Some(Instant::now() + self.rto.rto());
+        }
+    }
+
+    /// 通知したウィンドウより MSS か バッファの半分以上開いたら知らせる (RFC 1122 4.2.3.3)
+    fn window_update_needed(&self) -> bool {
+        if self.fin_received {
+            return false;
+        }
+        let opened = self.window().saturating_sub(self.rcv.wnd);
+        opened >= (self.mss as u32).min(TCP_BUFFER_SIZE as u32 / 2)
+    }
+
+    /// 利用者からのクローズ。送信済みのデータの後に FIN を送る
+    /// 読まれていないデータが残っていれば、相手に伝わるように RST で切る (RFC 2525 2.17)
+    pub fn close(&mut self) -> Vec<TcpSegment> {
+        match self.state {
+            TcpState::Closed | TcpState::Listen | TcpState::SynSent => {
+                self.set_closed(None);
+                Vec::new()
+            }
+            TcpState::Established | TcpState::CloseWait if !self.receive_buffer.is_empty() => {
+                self.abort().into_iter().collect()
+            }
+            TcpState::SynReceived | TcpState::Established | TcpState::CloseWait => {
+                self.fin_queued = true;
+                self.output()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// 接続を直ちに破棄する。同期済みなら RST を返す
+    pub fn abort(&mut self) -> Option<TcpSegment> {
+        let reset = match self.state {
+            TcpState::SynReceived
+            | TcpState::Established
+            | TcpState::FinWait1
+            | TcpState::FinWait2
+            | TcpState::CloseWait => Some(TcpSegment::reset(self.snd.nxt)),
+            _ => None,
+        };
+        self.set_closed(None);
+        self.receive_buffer.clear();
+        reset
+    }
+
+    /// 受信ウィンドウに収まっているか (RFC 793 3.3)
+    fn acceptable(&self, segment: &TcpSegment) -> bool {
+        let window = self.window();
+        let len = segment.seq_len();
+        let in_window =
+            |seq: u32| seq_le(self.rcv.nxt, seq) && seq_lt(seq, self.rcv.nxt.wrapping_add(window));
+        match (len, window) {
+            (0, 0) => segment.seq == self.rcv.nxt,
+            (0, _) => in_window(segment.seq),
+            // ウィンドウが 0 でも ACK と RST は処理する
+            (_, 0) => segment.seq == self.rcv.nxt,
+            _ => in_window(segment.seq) || in_window(segment.seq.wrapping_add(len - 1)),
+        }
+    }
+
+    /// TIME-WAIT の接続に届いた SYN が新しい接続のものとみなせるか (RFC 6191)
+    /// Timestamp があれば TSval で、なければ順序番号で古い重複と見分ける
+    pub fn reusable_by(&self, segment: &TcpSegment) -> bool {
+        if self.state != TcpState::TimeWait
+            || !segment.has(TCP_FLAG_SYN)
+            || segment.has(TCP_FLAG_ACK | TCP_FLAG_RST)
+        {
+            return false;
+        }
+        match (self.timestamps_ok, segment.options.timestamp) {
+            (true, Some(timestamp)) => seq_gt(timestamp.value, self.ts_recent),
+            _ => seq_gt(segment.seq, self.rcv.nxt),
+        }
+    }
+
+    /// 受信したセグメントを処理して、返すべきセグメントを返す
+    pub fn input(&mut self, segment: &TcpSegment, now: Instant) -> Vec<TcpSegment> {
+        match self.state {
+            TcpState::Closed | TcpState::Listen => return Vec::new(),
+            TcpState::SynSent => return self.input_syn_sent(segment, now),
+            _ => {}
+        }
+
+        let mut segments = Vec::new();
+        // TIME-WAIT で FIN の再送を受けたら ACK を返して 2MSL を測り直す (RFC 793 3.9)
+        if self.state == TcpState::TimeWait
+            && segment.has(TCP_FLAG_FIN)
+            && !segment.has(TCP_FLAG_RST)
+            && segment.seq.wrapping_add(segment.seq_len()) == self.rcv.nxt
+        {
+            self.time_wait = Some(now);
+            segments.push(self.ack_segment());
+            return segments;
+        }
+        if !self.acceptable(segment) || !self.paws(segment, now) {
+            if !segment.has(TCP_FLAG_RST) {
+                segments.push(self.ack_segment());
+            }
+            return segments;
+        }
+        self.update_ts_recent(segment, now);
+        self.last_received = now;
+        self.keepalive_probes = 0;
+
+        if segment.has(TCP_FLAG_RST) {
+            // TIME-WAIT は RST で終わらせない (RFC 1337)
+            if self.state == TcpState::TimeWait {
+                return segments;
+            }
+            // ぴったり rcv.nxt の RST だけで閉じる。ウィンドウ内の他の位置なら確かめる (RFC 5961 3.2)
+            if segment.seq != self.rcv.nxt {
+                segments.extend(self.challenge_ack(now));
+                return segments;
+            }
+            let error = match self.state {
+                TcpState::SynReceived => Some(TcpErrorKind::ConnectionRefused),
+                TcpState::Established
+                | TcpState::FinWait1
+                | TcpState::FinWait2
+                | TcpState::CloseWait => Some(TcpErrorKind::ConnectionReset),
+                _ => None,
+            };
+            self.set_closed(error);
+            return segments;
+        }
+
+        // 同期した後の SYN は順序番号によらず確かめる ACK を返して捨てる (RFC 5961 4.2)
+        if segment.has(TCP_FLAG_SYN) {
+            segments.extend(self.challenge_ack(now));
+            return segments;
+        }
+
+        if !segment.has(TCP_FLAG_ACK) {
+            return segments;
+        }
+        if self.state == TcpState::SynReceived {
+            if seq_lt(self.snd.una, segment.ack) && seq_le(segment.ack, self.snd.nxt) {
+                self.state = TcpState::Established;
+                self.snd.una = segment.ack;
+                self.snd.wnd = self.send_window(segment);
+                self.snd.wl1 = segment.seq;
+                self.snd.wl2 = segment.ack;
+                self.acknowledge(segment.ack, now);
+            } else {
+                segments.push(TcpSegment::reset(segment.ack));
+                return segments;
+            }
+        }
+        // まだ送っていないものや古すぎるものへの ACK は捨てる (RFC 5961 5.2)
+        self.max_snd_wnd = self.max_snd_wnd.max(self.snd.wnd);
+        if seq_gt(segment.ack, self.snd.nxt)
+            || seq_lt(segment.ack, self.snd.una.wrapping_sub(self.max_snd_wnd))
+        {
+            segments.extend(self.challenge_ack(now));
+            return segments;
+        }
+        if self.sack_ok && !segment.options.sack.is_empty() {
+            self.retransmit.sack(&segment.options.sack);
+        }
+        if self.is_duplicate_ack(segment) {
+            self.dup_acks += 1;
+            if self.recover.is_some() {
+                self.congestion.on_duplicate_ack();
+                if self.sack_ok {
+                    segments.extend(self.retransmit_hole());
+                }
+            } else if self.dup_acks == TCP_DUP_ACK_THRESHOLD {
+                // 3 つ目の重複 ACK で RTO を待たずに再送して高速回復に入る (RFC 5681 3.2)
+                self.congestion.on_fast_retransmit(self.in_flight(), now);
+                self.recover = Some(self.snd.nxt);
+                segments.extend(self.retransmit_first());
+            }
+        }
+        if seq_lt(self.snd.una, segment.ack) {
+            let acked = segment.ack.wrapping_sub(self.snd.una);
+            let data_acked = (acked as usize).min(self.sent);
+            self.send_buffer.drain(..data_acked);
+            self.sent -= data_acked;
+            self.snd.una = segment.ack;
+            self.acknowledge(segment.ack, now);
+            match self.recover {
+                Some(recover) if seq_lt(segment.ack, recover) => {
+                    // 部分 ACK は次の穴をすぐに再送する (RFC 6582 3.2 step 5)
+                    self.congestion.on_partial_ack(acked);
+                    segments.extend(self.retransmit_first());
+                }
+                Some(_) => {
+                    self.congestion.on_recovery_exit();
+                    self.recover = None;
+                }
+                None => self.congestion.on_ack(acked, self.rto.srtt(), now),
+            }
+        }
+        if seq_le(self.snd.una, segment.ack)
+            && (seq_lt(self.snd.wl1, segment.seq)
+                || (self.snd.wl1 == segment.seq && seq_le(self.snd.wl2, segment.ack)))
+        {
+            self.snd.wnd = self.send_window(segment);
+            self.snd.wl1 = segment.seq;
+            self.snd.wl2 = segment.ack;
+        }
+        let fin_acked = self.fin_sent && self.snd.una == self.snd.nxt;
+        match self.state {
+            TcpState::FinWait1 if fin_acked => self.state = TcpState::FinWait2,
+            TcpState::Closing if fin_acked => self.set_time_wait(now),
+            TcpState::LastAck if fin_acked => {
+                self.state = TcpState::Closed;
+                return segments;
+            }
+            _ => {}
+        }
+
+        // 受信済みの部分を切り落としてから順序どおりのデータだけを受け取る
+        let mut need_ack = false;
+        let mut data = &segment.data[..];
+        let mut seq = segment.seq;
+        if seq_lt(seq, self.rcv.nxt) {
+            let skip = (self.rcv.nxt.wrapping_sub(seq) as usize).min(data.len());
+            data = &data[skip..];
+            seq = seq.wrapping_add(skip as u32);
+        }
+        let fin_seq = seq.wrapping_add(data.len() as u32);
+        let mut complete = data.is_empty();
+        if !data.is_empty() {
+            need_ack = true;
+            let receiving = matches!(
+                self.state,
+                TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+            );
+            if receiving && seq == self.rcv.nxt {
+                let len = data.len().min(TCP_BUFFER_SIZE - self.receive_buffer.len());
+                self.receive_buffer.extend(&data[..len]);
+                self.rcv.nxt = self.rcv.nxt.wrapping_add(len as u32);
+                complete = len == data.len();
+                self.pull_out_of_order();
+            } else if receiving {
+                self.store_out_of_order(seq, data);
+            }
+        }
+
+        if segment.has(TCP_FLAG_FIN) && complete && fin_seq == self.rcv.nxt {
+            need_ack = true;
+            match self.state {
+                TcpState::SynReceived | TcpState::Established => {
+                    self.state = TcpState::CloseWait;
+                }
+                TcpState::FinWait1 if fin_acked => self.set_time_wait(now),
+                TcpState::FinWait1 => self.state = TcpState::Closing,
+                TcpState::FinWait2 => self.set_time_wait(now),
+                _ => {}
+            }
+            if !self.fin_received {
+                self.fin_received = true;
+                self.rcv.nxt = self.rcv.nxt.wrapping_add(1);
+            }
+        }
+
+        let output = self.output();
+        if output.is_empty() && need_ack {
+            segments.push(self.ack_segment());
+        }
+        segments.extend(output);
+        segments
+    }
+
+    /// TSval が TS.Recent より古ければ古い重複として捨てる (RFC 7323 5.3 R1)
+    fn paws(&self, segment: &TcpSegment, now: Instant) -> bool {
+        if !self.timestamps_ok || segment.has(TCP_FLAG_RST) {
+            return true;
+        }
+        match segment.options.timestamp {
+            Some(timestamp) => {
+                !seq_lt(timestamp.value, self.ts_recent)
+                    || now.saturating_duration_since(self.ts_recent_age) > TCP_PAWS_IDLE
+            }
+            None => true,
+        }
+    }
+
+    /// 最後に送った ACK 以前から始まるセグメントの TSval を覚える (RFC 7323 4.3)
+    fn update_ts_recent(&mut self, segment: &TcpSegment, now: Instant) {
+        if let (true, Some(timestamp)) = (self.timestamps_ok, segment.options.timestamp) {
+            if seq_ge(timestamp.value, self.ts_recent) && seq_le(segment.seq, self.last_ack_sent) {
+                self.ts_recent = timestamp.value;
+                self.ts_recent_age = now;
+            }
+        }
+    }
+
+    /// 受信ウィンドウに収まる部分だけを取っておく
+    fn store_out_of_order(&mut self, seq: u32, data: &[u8]) {
+        let right = self.rcv.nxt.wrapping_add(self.window());
+        if !seq_lt(seq, right) {
+            return;
+        }
+        let len = (right.wrapping_sub(seq) as usize).min(data.len());
+        let stored: usize = self.out_of_order.iter().map(|(_, data)| data.len()).sum();
+        if stored + len > self.window() as usize {
+            return;
+        }
+        if !self
+            .out_of_order
+            .iter()
+            .any(|(s, d)| *s == seq && d.len() >= len)
+        {
+            self.out_of_order.push((seq, data[..len].to_vec()));
+        }
+        self.latest_out_of_order = Some(seq);
+    }
+
+    /// rcv.nxt に追いついた順序外のデータを受信バッファへ移す
+    fn pull_out_of_order(&mut self) {
+        loop {
+            let nxt = self.rcv.nxt;
+            self.out_of_order
+                .retain(|(seq, data)| seq_gt(seq.wrapping_add(data.len() as u32), nxt));
+            let i = match self
+                .out_of_order
+                .iter()
+                .position(|(seq, _)| seq_le(*seq, nxt))
+            {
+                Some(i) => i,
+                None => break,
+            };
+            let (seq, data) = self.out_of_order.remove(i);
+            let skip = nxt.wrapping_sub(seq) as usize;
+            let len = (data.len() - skip).min(TCP_BUFFER_SIZE - self.receive_buffer.len());
+            self.receive_buffer.extend(&data[skip..skip + len]);
+            self.rcv.nxt = self.rcv.nxt.wrapping_add(len as u32);
+            if len == 0 {
+                break;
+            }
+        }
+        if self.out_of_order.is_empty() {
+            self.latest_out_of_order = None;
+        }
+    }
+
+    /// 未確認のデータがあるときに snd.una を進めず、データもウィンドウの変化も無い ACK (RFC 5681 2)
+    fn is_duplicate_ack(&self, segment: &TcpSegment) -> bool {
+        segment.ack == self.snd.una
+            && segment.data.is_empty()
+            && !segment.has(TCP_FLAG_SYN | TCP_FLAG_FIN)
+            && self.send_window(segment) == self.snd.wnd
+            && !self.retransmit.is_empty()
+    }
+
+    fn input_syn_sent(&mut self, segment: &TcpSegment, now: Instant) -> Vec<TcpSegment> {
+        if segment.has(TCP_FLAG_ACK)
+            && (seq_le(segment.ack, self.iss) || seq_gt(segment.ack, self.snd.nxt))
+        {
+            return segment.reset_for().into_iter().collect();
+        }
+        if segment.has(TCP_FLAG_RST) {
+            if segment.has(TCP_FLAG_ACK) {
+                self.set_closed(Some(TcpErrorKind::ConnectionRefused));
+            }
+            return Vec::new();
+        }
+        if !segment.has(TCP_FLAG_SYN) {
+            return Vec::new();
+        }
+        self.irs = segment.seq;
+        self.rcv.nxt = segment.seq.wrapping_add(1);
+        self.last_received = now;
+        self.negotiate(segment, now);
+        if segment.has(TCP_FLAG_ACK) {
+            self.snd.una = segment.ack;
+        }
+        if seq_gt(self.snd.una, self.iss) {
+            self.state = TcpState::Established;
+            self.snd.wnd = segment.window as u32;
+            self.snd.wl1 = segment.seq;
+            self.snd.wl2 = segment.ack;
+            self.acknowledge(segment.ack, now);
+            let mut segments = self.output();
+            if segments.is_empty() {
+                segments.push(self.ack_segment());
+            }
+            segments
+        } else {
+            // 同時オープン
+            // 再送キューの SYN は SYN-ACK として送り直す
+            self.state = TcpState::SynReceived;
+            if let Some(entry) = self.retransmit.front_mut() {
+                entry.segment.flags |= TCP_FLAG_ACK;
+            }
+            vec![self.segment(self.iss, TCP_FLAG_SYN | TCP_FLAG_ACK, Vec::new())]
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use crate::ipv4::{self, Ipv4Address, Ipv4Endpoint, Protocol};
+use crate::utils::checksum;
+
+mod field {
+    use std::ops::Range;
+
+    pub const SRC_PORT: Range<usize> = 0..2;
+    pub const DST_PORT: Range<usize> = 2..4;
+    pub const SEQ: Range<usize> = 4..8;
+    pub const ACK: Range<usize> = 8..12;
+    pub const OFFSET: usize = 12;
+    pub const FLAGS: usize = 13;
+    pub const WINDOW: Range<usize> = 14..16;
+    pub const CHECKSUM: Range<usize> = 16..18;
+    pub const URGENT: Range<usize> = 18..20;
+}
+
+pub const TCP_HEADER_SIZE_MIN: usize = 20;
+pub const TCP_HEADER_SIZE_MAX: usize = 60;
+
+pub const TCP_FLAG_FIN: u8 = 0x01;
+pub const TCP_FLAG_SYN: u8 = 0x02;
+pub const TCP_FLAG_RST: u8 = 0x04;
+pub const TCP_FLAG_PSH: u8 = 0x08;
+pub const TCP_FLAG_ACK: u8 = 0x10;
+pub const TCP_FLAG_URG: u8 = 0x20;
+
+/// フラグをログ用に FSRPAU の並びで表す
+pub fn flags_to_string(flags: u8) -> String {
+    [
+        (TCP_FLAG_FIN, 'F'),
+        (TCP_FLAG_SYN, 'S'),
+        (TCP_FLAG_RST, 'R'),
+        (TCP_FLAG_PSH, 'P'),
+        (TCP_FLAG_ACK, 'A'),
+        (TCP_FLAG_URG, 'U'),
+    ]
+    .iter()
+    .map(|(flag, c)| if flags & flag != 0 { *c } else { '-' })
+    .collect()
+}
+
+/// バイト列の上に被せて TCP ヘッダを読むビュー
+pub struct TcpHeader<T: AsRef<[u8]>> {
+    buffer: T,
+}
+
+impl<T: AsRef<[u8]>> TcpHeader<T> {
+    /// データオフセットがバッファに収まっているかまで検査する
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        let len = buffer.as_ref().len();
+        if len < TCP_HEADER_SIZE_MIN {
+            return None;
+        }
+        let header = TcpHeader { buffer };
+        let header_length = header.header_length();
+        if header_length < TCP_HEADER_SIZE_MIN || header_length > len {
+            return None;
+        }
+        Some(header)
+    }
+
+    pub fn src_port(&self) -> u16 {
+        self.read_u16(field::SRC_PORT.start)
+    }
+
+    pub fn dst_port(&self) -> u16 {
+        self.read_u16(field::DST_PORT.start)
+    }
+
+    pub fn seq(&self) -> u32 {
+        self.read_u32(field::SEQ.start)
+    }
+
+    pub fn ack(&self) -> u32 {
+        self.read_u32(field::ACK.start)
+    }
+
+    /// オプションを含むヘッダの長さ (バイト)
+    pub fn header_length(&self) -> usize {
+        ((self.buffer.as_ref()[field::OFFSET] >> 4) as usize) * 4
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.buffer.as_ref()[field::FLAGS] & 0x3f
+    }
+
+    pub fn window(&self) -> u16 {
+        self.read_u16(field::WINDOW.start)
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.read_u16(field::CHECKSUM.start)
+    }
+
+    pub fn urgent(&self) -> u16 {
+        self.read_u16(field::URGENT.start)
+    }
+
+    pub fn options(&self) -> &[u8] {
+        &self.buffer.as_ref()[TCP_HEADER_SIZE_MIN..self.header_length()]
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[self.header_length()..]
+    }
+
+    /// バッファ全体をセグメントとして疑似ヘッダ込みで検査する
+    pub fn verify_checksum(&self, src: Ipv4Address, dst: Ipv4Address) -> bool {
+        let segment = self.buffer.as_ref();
+        let pseudo = ipv4::pseudo_header_sum(src, dst, Protocol::Tcp as u8, segment.len() as u16);
+        checksum(segment, pseudo) == 0
+    }
+
+    fn read_u16(&self, index: usize) -> u16 {
+        let data = self.buffer.as_ref();
+        u16::from_be_bytes([data[index], data[index + 1]])
+    }
+
+    fn read_u32(&self, index: usize) -> u32 {
+        let data = self.buffer.as_ref();
+        u32::from_be_bytes([
+            data[index],
+            data[index + 1],
+            data[index + 2],
+            data[index + 3],
+        ])
+    }
+}
+
+/// チェックサムを埋めたセグメントを作る
+/// options は 4 バイト境界に揃えておく
+#[allow(clippy::too_many_arguments)]
+pub fn build(
+    src: Ipv4Endpoint,
+    dst: Ipv4Endpoint,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    options: &[u8],
+    data: &[u8],
+) -> Vec<u8> {
+    let header_length = TCP_HEADER_SIZE_MIN + options.len();
+    let mut segment = Vec::with_capacity(header_length + data.len());
+    segment.extend_from_slice(&src.port.to_be_bytes());
+    segment.extend_from_slice(&dst.port.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.push(((header_length / 4) as u8) << 4);
+    segment.push(flags);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(options);
+    segment.extend_from_slice(data);
+    let pseudo = ipv4::pseudo_header_sum(
+        src.address,
+        dst.address,
+        Protocol::Tcp as u8,
+        segment.len() as u16,
+    );
+    let sum = checksum(&segment, pseudo);
+    segment[field::CHECKSUM].copy_from_slice(&sum.to_be_bytes());
+    segment
+}
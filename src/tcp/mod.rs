@@ -0,0 +1,1248 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::ipv4::{
+    self, pmtu, IpInterface, Ipv4Address, Ipv4Endpoint, Ipv4ErrorKind, Ipv4Header, Protocol,
+    IP_ADDRESS_ANY, IP_FLAG_DF,
+};
+use crate::net::{NetDevice, NetTimer};
+
+pub mod congestion;
+pub mod connection;
+pub mod header;
+pub mod option;
+pub mod retransmit;
+pub mod syncookie;
+
+pub use congestion::{CongestionAlgorithm, CongestionControl, Cubic, NewReno};
+pub use connection::{
+    seq_ge, seq_gt, seq_le, seq_lt, TcpConfig, TcpConnection, TcpKeepalive, TcpSegment, TcpState,
+    TCP_BUFFER_SIZE, TCP_CHALLENGE_ACK_LIMIT, TCP_DEFAULT_MSS, TCP_KEEPALIVE_IDLE,
+    TCP_KEEPALIVE_INTERVAL, TCP_KEEPALIVE_PROBES, TCP_MSL, TCP_PAWS_IDLE,
+};
+pub use header::{
+    TcpHeader, TCP_FLAG_ACK, TCP_FLAG_FIN, TCP_FLAG_PSH, TCP_FLAG_RST, TCP_FLAG_SYN, TCP_FLAG_URG,
+    TCP_HEADER_SIZE_MAX, TCP_HEADER_SIZE_MIN,
+};
+pub use option::{
+    SackBlock, TcpOption, TcpOptionError, TcpOptionErrorKind, TcpOptions, TcpTimestamp,
+    TCP_WINDOW_SCALE_MAX,
+};
+pub use retransmit::{RetransmitQueue, RtoEstimator, TCP_RTO_INITIAL, TCP_RTO_MAX, TCP_RTO_MIN};
+
+const TCP_PCB_SIZE: usize = 32;
+const TCP_TIMER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// listen の backlog の上限
+pub const TCP_BACKLOG_MAX: usize = 16;
+/// LISTEN ごとに確立を待つ接続の上限。あふれたら SYN クッキーで応える
+pub const TCP_SYN_QUEUE_SIZE: usize = 64;
+/// TIME-WAIT の接続の上限。超えたら古いものから終わらせて pcb を空ける
+pub const TCP_TIME_WAIT_MAX: usize = 8;
+
+/// 動的に割り当てるポートの範囲 (RFC 6335)
+const TCP_SOURCE_PORT_MIN: u16 = 49152;
+const TCP_SOURCE_PORT_MAX: u16 = 65535;
+
+#[derive(Debug)]
+pub struct TcpError {
+    pub kind: TcpErrorKind,
+}
+
+impl TcpError {
+    pub fn new(kind: TcpErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpErrorKind {
+    NoSpace,
+    InvalidId,
+    AddressInUse,
+    AddressNotAvailable,
+    NoPortAvailable,
+    InvalidState,
+    NotConnected,
+    NoRoute,
+    ConnectionRefused,
+    ConnectionReset,
+    Timeout,
+    Closed,
+}
+
+impl fmt::Display for TcpErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TcpErrorKind::NoSpace => "no pcb space",
+            TcpErrorKind::InvalidId => "invalid id",
+            TcpErrorKind::AddressInUse => "address in use",
+            TcpErrorKind::AddressNotAvailable => "address not available",
+            TcpErrorKind::NoPortAvailable => "no port available",
+            TcpErrorKind::InvalidState => "invalid state",
+            TcpErrorKind::NotConnected => "not connected",
+            TcpErrorKind::NoRoute => "no route",
+            TcpErrorKind::ConnectionRefused => "connection refused",
+            TcpErrorKind::ConnectionReset => "connection reset",
+            TcpErrorKind::Timeout => "timeout",
+            TcpErrorKind::Closed => "closed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+struct TcpListen {
+    /// accept キューの長さの上限
+    backlog: usize,
+    /// SYN を受け取って確立を待っている接続。pcb はまだ割り当てない
+    syn_queue: Vec<TcpConnection>,
+    /// 接続が確立して accept を待っている pcb
+    queue: VecDeque<usize>,
+}
+
+struct TcpPcb {
+    local: Ipv4Endpoint,
+    conn: Option<TcpConnection>,
+    listen: Option<TcpListen>,
+    /// accept されるまでは LISTEN している pcb を指す
+    parent: Option<usize>,
+    /// 利用者が close した。接続が終わったら解放する
+    released: bool,
+    /// poll_* で待っているタスク
+    wakers: Vec<Waker>,
+    /// 選ばれた輻輳制御。None なら接続を作るときの既定値を使う
+    congestion: Option<CongestionAlgorithm>,
+    /// キープアライブの設定。None なら送らない
+    keepalive: Option<TcpKeepalive>,
+}
+
+impl TcpPcb {
+    fn new() -> Self {
+        TcpPcb {
+            local: Ipv4Endpoint::new(IP_ADDRESS_ANY, 0),
+            conn: None,
+            listen: None,
+            parent: None,
+            released: false,
+            wakers: Vec::new(),
+            congestion: None,
+            keepalive: None,
+        }
+    }
+
+    /// 新しい接続に pcb の設定を引き継ぐ。輻輳制御は選ばれていれば差し替える
+    fn apply_options(&self, conn: &mut TcpConnection) {
+        if let Some(algorithm) = self.congestion {
+            conn.set_congestion_control(algorithm.build(conn.send_mss()));
+        }
+        conn.set_keepalive(self.keepalive);
+    }
+
+    fn state(&self) -> TcpState {
+        match (&self.conn, &self.listen) {
+            (Some(conn), _) => conn.state,
+            (None, Some(_)) => TcpState::Listen,
+            (None, None) => TcpState::Closed,
+        }
+    }
+
+    fn matches(&self, local: Ipv4Endpoint, foreign: Ipv4Endpoint) -> bool {
+        match &self.conn {
+            Some(conn) => {
+                conn.state != TcpState::Closed && conn.local == local && conn.foreign == foreign
+            }
+            None => false,
+        }
+    }
+
+    fn listens(&self, local: Ipv4Endpoint) -> bool {
+        self.listen.is_some()
+            && self.local.port == local.port
+            && (self.local.address.is_unspecified() || self.local.address == local.address)
+    }
+
+    /// 利用者の手を離れていて、接続も終わった
+    fn is_finished(&self) -> bool {
+        let closed = match &self.conn {
+            Some(conn) => conn.state == TcpState::Closed,
+            None => true,
+        };
+        closed && (self.released || self.parent.is_some())
+    }
+}
+
+lazy_static! {
+    static ref PCBS: Mutex<Vec<Option<TcpPcb>>> =
+        Mutex::new((0..TCP_PCB_SIZE).map(|_| None).collect());
+    static ref PCB_CONDVAR: Condvar = Condvar::new();
+}
+
+static ISS_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+fn pcbs() -> MutexGuard<'static, Vec<Option<TcpPcb>>> {
+    PCBS.lock().unwrap()
+}
+
+/// 利用者が持っている pcb だけを返す
+fn pcb_mut(pcbs: &mut [Option<TcpPcb>], id: usize) -> Result<&mut TcpPcb, TcpError> {
+    match pcbs.get_mut(id) {
+        Some(Some(pcb)) if !pcb.released && pcb.parent.is_none() => Ok(pcb),
+        _ => Err(TcpError::new(TcpErrorKind::InvalidId)),
+    }
+}
+
+fn conn_mut(pcbs: &mut [Option<TcpPcb>], id: usize) -> Result<&mut TcpConnection, TcpError> {
+    match &mut pcb_mut(pcbs, id)?.conn {
+        Some(conn) => Ok(conn),
+        None => Err(TcpError::new(TcpErrorKind::NotConnected)),
+    }
+}
+
+/// 時計に乱数を混ぜた初期順序番号 (RFC 6528 の簡略版)
+fn generate_iss() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    // 4 マイクロ秒ごとに 1 進む時計
+    let clock = (now.as_micros() / 4) as u32;
+    let salt = now.subsec_nanos().rotate_left(13);
+    clock
+        .wrapping_add(salt)
+        .wrapping_add(ISS_COUNTER.fetch_add(64000, Ordering::SeqCst))
+}
+
+fn ephemeral_port(pcbs: &[Option<TcpPcb>]) -> Option<u16> {
+    (TCP_SOURCE_PORT_MIN..=TCP_SOURCE_PORT_MAX).find(|port| {
+        !pcbs
+            .iter()
+            .any(|entry| matches!(entry, Some(pcb) if pcb.local.port == *port))
+    })
+}
+
+/// 接続が終わった pcb を解放し、accept 待ちの列からも取り除く
+fn release_finished(pcbs: &mut [Option<TcpPcb>]) {
+    for id in 0..pcbs.len() {
+        let finished = match &pcbs[id] {
+            Some(pcb) => pcb.is_finished(),
+            None => false,
+        };
+        if !finished {
+            continue;
+        }
+        if let Some(parent) = pcbs[id].as_ref().and_then(|pcb| pcb.parent) {
+            if let Some(Some(TcpPcb {
+                listen: Some(listen),
+                ..
+            })) = pcbs.get_mut(parent)
+            {
+                listen.queue.retain(|child| *child != id);
+            }
+        }
+        eprintln!("TCP pcb released ID={}", id);
+        pcbs[id] = None;
+    }
+}
+
+/// 待っているスレッドを起こし、ids の pcb に登録された waker を取り出す
+/// 子の接続が変われば accept を待つ親も起こす。waker はロックを外してから wake する
+fn notify(pcbs: &mut [Option<TcpPcb>], ids: &[usize]) -> Vec<Waker> {
+    PCB_CONDVAR.notify_all();
+    let mut targets = ids.to_vec();
+    targets.extend(
+        ids.iter()
+            .filter_map(|id| pcbs.get(*id).and_then(|pcb| pcb.as_ref()?.parent)),
+    );
+    let mut wakers = Vec::new();
+    for id in targets {
+        if let Some(Some(pcb)) = pcbs.get_mut(id) {
+            wakers.append(&mut pcb.wakers);
+        }
+    }
+    wakers
+}
+
+fn wake(wakers: Vec<Waker>) {
+    for waker in wakers {
+        waker.wake();
+    }
+}
+
+type Outgoing = (Ipv4Endpoint, Ipv4Endpoint, TcpSegment);
+
+fn outgoing(conn: &TcpConnection, segments: Vec<TcpSegment>) -> Vec<Outgoing> {
+    segments
+        .into_iter()
+        .map(|segment| (conn.local, conn.foreign, segment))
+        .collect()
+}
+
+/// 送出するデバイスの MTU から知らせる MSS を決める。わからなければ既定値
+fn config_for(foreign: Ipv4Address, dev: Option<&NetDevice>) -> TcpConfig {
+    let mss = match dev {
+        Some(dev) if dev.mtu > 0 => pmtu::mss(foreign, dev.mtu),
+        _ => TCP_DEFAULT_MSS,
+    };
+    TcpConfig {
+        mss,
+        ..TcpConfig::default()
+    }
+}
+
+/// dst への PMTU が変わったときに呼ぶ。その宛先との接続の MSS を PMTU に合わせて下げる
+pub fn path_mtu_changed(dst: Ipv4Address) {
+    let mut pcbs = pcbs();
+    for conn in pcbs
+        .iter_mut()
+        .flatten()
+        .filter_map(|pcb| pcb.conn.as_mut())
+    {
+        if conn.foreign.address != dst {
+            continue;
+        }
+        let dev = IpInterface::select(conn.local.address).and_then(|interface| interface.device());
+        if let Some(dev) = dev {
+            conn.clamp_mss(pmtu::mss(dst, dev.mtu));
+        }
+    }
+}
+
+/// pcb のロックを外してから呼ぶ。送れなかったものがあれば NoRoute を返す
+/// PMTU 探索のために DF を立てて送る。MSS を下げる前に作ったセグメントが PMTU を
+/// 超えたときは、送り直しを待たずに分割して送る
+fn transmit(segments: Vec<Outgoing>) -> Result<(), TcpError> {
+    let mut result = Ok(());
+    for (local, foreign, segment) in segments {
+        let data = header::build(
+            local,
+            foreign,
+            segment.seq,
+            segment.ack,
+            segment.flags,
+            segment.window,
+            &segment.options.to_bytes(),
+            &segment.data,
+        );
+        eprintln!(
+            "TCP output SRC={} DST={} FLAGS={} SEQ={} ACK={} WND={} LEN={}",
+            local,
+            foreign,
+            header::flags_to_string(segment.flags),
+            segment.seq,
+            segment.ack,
+            segment.window,
+            segment.data.len()
+        );
+        let sent = match ipv4::output_with_flags(
+            Protocol::Tcp as u8,
+            &data,
+            local.address,
+            foreign.address,
+            &[],
+            IP_FLAG_DF,
+        ) {
+            Err(e) if e.kind == Ipv4ErrorKind::TooLong => {
+                ipv4::output(Protocol::Tcp as u8, &data, local.address, foreign.address)
+            }
+            sent => sent,
+        };
+        if sent.is_err() {
+            eprintln!("TCP output error DST={}", foreign);
+            result = Err(TcpError::new(TcpErrorKind::NoRoute));
+        }
+    }
+    result
+}
+
+/// deadline まで条件変数で待つ。期限を過ぎていたら Timeout
+fn wait(
+    pcbs: MutexGuard<'static, Vec<Option<TcpPcb>>>,
+    deadline: Option<Instant>,
+) -> Result<MutexGuard<'static, Vec<Option<TcpPcb>>>, TcpError> {
+    match deadline {
+        Some(deadline) => {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(TcpError::new(TcpErrorKind::Timeout));
+            }
+            Ok(PCB_CONDVAR.wait_timeout(pcbs, deadline - now).unwrap().0)
+        }
+        None => Ok(PCB_CONDVAR.wait(pcbs).unwrap()),
+    }
+}
+
+pub fn open() -> Result<usize, TcpError> {
+    let mut pcbs = pcbs();
+    for (id, entry) in pcbs.iter_mut().enumerate() {
+        if entry.is_none() {
+            *entry = Some(TcpPcb::new());
+            return Ok(id);
+        }
+    }
+    eprintln!("TCP pcb is full");
+    Err(TcpError::new(TcpErrorKind::NoSpace))
+}
+
+pub fn bind(id: usize, local: Ipv4Endpoint) -> Result<(), TcpError> {
+    let mut pcbs = pcbs();
+    let pcb = pcb_mut(&mut pcbs, id)?;
+    if pcb.conn.is_some() || pcb.listen.is_some() {
+        return Err(TcpError::new(TcpErrorKind::InvalidState));
+    }
+    let in_use = pcbs.iter().enumerate().any(|(i, entry)| match entry {
+        Some(pcb) if i != id && local.port != 0 && pcb.local.port == local.port => {
+            pcb.local.address.is_unspecified()
+                || local.address.is_unspecified()
+                || pcb.local.address == local.address
+        }
+        _ => false,
+    });
+    if in_use {
+        eprintln!("TCP address in use LOCAL={}", local);
+        return Err(TcpError::new(TcpErrorKind::AddressInUse));
+    }
+    if !local.address.is_unspecified() && IpInterface::select(local.address).is_none() {
+        eprintln!("TCP local address not found LOCAL={}", local);
+        return Err(TcpError::new(TcpErrorKind::AddressNotAvailable));
+    }
+    pcb_mut(&mut pcbs, id)?.local = local;
+    eprintln!("TCP bound ID={} LOCAL={}", id, local);
+    Ok(())
+}
+
+/// 受動オープン。backlog は確立前のものも含めて抱えられる接続の数
+pub fn listen(id: usize, backlog: usize) -> Result<(), TcpError> {
+    let mut pcbs = pcbs();
+    let port = match pcb_mut(&mut pcbs, id)?.local.port {
+        0 => ephemeral_port(&pcbs).ok_or_else(|| TcpError::new(TcpErrorKind::NoPortAvailable))?,
+        port => port,
+    };
+    let pcb = pcb_mut(&mut pcbs, id)?;
+    if pcb.conn.is_some() {
+        return Err(TcpError::new(TcpErrorKind::InvalidState));
+    }
+    pcb.local.port = port;
+    let backlog = backlog.clamp(1, TCP_BACKLOG_MAX);
+    match &mut pcb.listen {
+        Some(listen) => listen.backlog = backlog,
+        None => {
+            pcb.listen = Some(TcpListen {
+                backlog,
+                syn_queue: Vec::new(),
+                queue: VecDeque::new(),
+            })
+        }
+    }
+    eprintln!(
+        "TCP listen ID={} LOCAL={} BACKLOG={}",
+        id, pcb.local, backlog
+    );
+    Ok(())
+}
+
+/// accept を待っている接続があれば取り出す
+fn try_accept(pcbs: &mut [Option<TcpPcb>], id: usize) -> Result<Option<usize>, TcpError> {
+    let listen = match &mut pcb_mut(pcbs, id)?.listen {
+        Some(listen) => listen,
+        None => return Err(TcpError::new(TcpErrorKind::InvalidState)),
+    };
+    let child = match listen.queue.pop_front() {
+        Some(child) => child,
+        None => return Ok(None),
+    };
+    if let Some(Some(pcb)) = pcbs.get_mut(child) {
+        pcb.parent = None;
+    }
+    Ok(Some(child))
+}
+
+/// 確立した接続を取り出して、その pcb の id を返す
+pub fn accept(id: usize, timeout: Option<Duration>) -> Result<usize, TcpError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut pcbs = pcbs();
+    loop {
+        if let Some(child) = try_accept(&mut pcbs, id)? {
+            return Ok(child);
+        }
+        pcbs = wait(pcbs, deadline)?;
+    }
+}
+
+/// 能動オープンを始めて SYN を送る。確立は待たない
+pub fn connect_start(id: usize, foreign: Ipv4Endpoint) -> Result<(), TcpError> {
+    let segments = {
+        let mut pcbs = pcbs();
+        let pcb = pcb_mut(&mut pcbs, id)?;
+        if pcb.conn.is_some() || pcb.listen.is_some() {
+            return Err(TcpError::new(TcpErrorKind::InvalidState));
+        }
+        let mut local = pcb.local;
+        if local.address.is_unspecified() {
+            local.address = match ipv4::route::lookup(foreign.address) {
+                Some(route) => route.interface.unicast,
+                None => match IpInterface::select_source(foreign.address) {
+                    Some(interface) => interface.unicast,
+                    None => {
+                        eprintln!("TCP no route DST={}", foreign);
+                        return Err(TcpError::new(TcpErrorKind::NoRoute));
+                    }
+                },
+            };
+        }
+        if local.port == 0 {
+            local.port = ephemeral_port(&pcbs)
+                .ok_or_else(|| TcpError::new(TcpErrorKind::NoPortAvailable))?;
+        }
+        let dev = ipv4::route::lookup(foreign.address).and_then(|route| route.interface.device());
+        let config = config_for(foreign.address, dev);
+        let pcb = pcb_mut(&mut pcbs, id)?;
+        let (mut conn, syn) = TcpConnection::connect_with(local, foreign, generate_iss(), config);
+        pcb.apply_options(&mut conn);
+        pcb.local = local;
+        let segments = outgoing(&conn, vec![syn]);
+        pcb.conn = Some(conn);
+        eprintln!("TCP connect ID={} LOCAL={} FOREIGN={}", id, local, foreign);
+        segments
+    };
+    // SYN を送れなければ待たずに失敗させる
+    if let Err(err) = transmit(segments) {
+        let mut pcbs = pcbs();
+        pcb_mut(&mut pcbs, id)?.conn = None;
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// 能動オープンの結果。まだ確立していなければ None
+/// 失敗したら接続を捨てて、もう一度 connect できるようにする
+fn connect_result(pcbs: &mut [Option<TcpPcb>], id: usize) -> Result<Option<()>, TcpError> {
+    let conn = conn_mut(pcbs, id)?;
+    match conn.state {
+        TcpState::SynSent | TcpState::SynReceived => Ok(None),
+        TcpState::Closed => {
+            let error = conn.error().unwrap_or(TcpErrorKind::ConnectionRefused);
+            pcb_mut(pcbs, id)?.conn = None;
+            Err(TcpError::new(error))
+        }
+        _ => Ok(Some(())),
+    }
+}
+
+/// 能動オープン。確立するか失敗するまで待つ
+pub fn connect(
+    id: usize,
+    foreign: Ipv4Endpoint,
+    timeout: Option<Duration>,
+) -> Result<(), TcpError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    connect_start(id, foreign)?;
+    let mut pcbs = pcbs();
+    loop {
+        if connect_result(&mut pcbs, id)?.is_some() {
+            return Ok(());
+        }
+        pcbs = match wait(pcbs, deadline) {
+            Ok(pcbs) => pcbs,
+            Err(err) => {
+                let mut pcbs = self::pcbs();
+                if let Ok(pcb) = pcb_mut(&mut pcbs, id) {
+                    pcb.conn = None;
+                }
+                return Err(err);
+            }
+        };
+    }
+}
+
+/// 送信バッファに積めるだけ積んで、送るべきセグメントと一緒に返す
+fn try_send(
+    pcbs: &mut [Option<TcpPcb>],
+    id: usize,
+    data: &[u8],
+) -> Result<(usize, Vec<Outgoing>), TcpError> {
+    let conn = conn_mut(pcbs, id)?;
+    if let Some(error) = conn.error() {
+        return Err(TcpError::new(error));
+    }
+    if !conn.can_send() {
+        return Err(TcpError::new(TcpErrorKind::Closed));
+    }
+    let len = conn.send(data);
+    let output = conn.output();
+    Ok((len, outgoing(conn, output)))
+}
+
+/// すべて送信バッファに積むまで待つ。期限が来たら積めた分だけ返す
+pub fn send(id: usize, data: &[u8], timeout: Option<Duration>) -> Result<usize, TcpError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut written = 0;
+    let mut pcbs = pcbs();
+    while written < data.len() {
+        let (len, segments) = try_send(&mut pcbs, id, &data[written..])?;
+        written += len;
+        if !segments.is_empty() {
+            drop(pcbs);
+            let _ = transmit(segments);
+            pcbs = self::pcbs();
+        }
+        if written < data.len() {
+            pcbs = match wait(pcbs, deadline) {
+                Ok(pcbs) => pcbs,
+                Err(_) if written > 0 => return Ok(written),
+                Err(err) => return Err(err),
+            };
+        }
+    }
+    Ok(written)
+}
+
+/// 読めるものがあれば読む。読んだらウィンドウの更新を返す
+/// まだ何も届いていなければ None
+fn try_receive(
+    pcbs: &mut [Option<TcpPcb>],
+    id: usize,
+    buf: &mut [u8],
+) -> Result<Option<(usize, Vec<Outgoing>)>, TcpError> {
+    let conn = conn_mut(pcbs, id)?;
+    if conn.available() > 0 {
+        let len = conn.read(buf);
+        let output = conn.output();
+        return Ok(Some((len, outgoing(conn, output))));
+    }
+    if let Some(error) = conn.error() {
+        return Err(TcpError::new(error));
+    }
+    if conn.is_eof() || conn.state == TcpState::Closed {
+        return Ok(Some((0, Vec::new())));
+    }
+    if matches!(conn.state, TcpState::SynSent | TcpState::SynReceived) {
+        return Err(TcpError::new(TcpErrorKind::NotConnected));
+    }
+    Ok(None)
+}
+
+/// 受信したデータを読む。相手が閉じていれば 0 を返す
+pub fn receive(id: usize, buf: &mut [u8], timeout: Option<Duration>) -> Result<usize, TcpError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut pcbs = pcbs();
+    loop {
+        if let Some((len, segments)) = try_receive(&mut pcbs, id, buf)? {
+            drop(pcbs);
+            let _ = transmit(segments);
+            return Ok(len);
+        }
+        pcbs = wait(pcbs, deadline)?;
+    }
+}
+
+/// input やタイマーで状態が変わったときに起こしてもらう
+fn register_waker(pcb: &mut TcpPcb, cx: &Context<'_>) {
+    if !pcb.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+        pcb.wakers.push(cx.waker().clone());
+    }
+}
+
+/// 準備ができていなければ waker を pcb に登録して Pending を返す
+fn poll_pcb<T, F>(id: usize, cx: &mut Context<'_>, f: F) -> Poll<Result<T, TcpError>>
+where
+    F: FnOnce(&mut [Option<TcpPcb>]) -> Result<Option<T>, TcpError>,
+{
+    let mut pcbs = pcbs();
+    match f(&mut pcbs) {
+        Ok(Some(value)) => Poll::Ready(Ok(value)),
+        Ok(None) => match pcb_mut(&mut pcbs, id) {
+            Ok(pcb) => {
+                register_waker(pcb, cx);
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(err)),
+        },
+        Err(err) => Poll::Ready(Err(err)),
+    }
+}
+
+pub fn poll_accept(id: usize, cx: &mut Context<'_>) -> Poll<Result<usize, TcpError>> {
+    poll_pcb(id, cx, |pcbs| try_accept(pcbs, id))
+}
+
+/// connect_start の後で確立を待つ
+pub fn poll_connect(id: usize, cx: &mut Context<'_>) -> Poll<Result<(), TcpError>> {
+    poll_pcb(id, cx, |pcbs| connect_result(pcbs, id))
+}
+
+/// 1 バイトでも積めたら積めた分を返す
+pub fn poll_send(id: usize, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize, TcpError>> {
+    let (len, segments) = {
+        let mut pcbs = pcbs();
+        let (len, segments) = match try_send(&mut pcbs, id, data) {
+            Ok(sent) => sent,
+            Err(err) => return Poll::Ready(Err(err)),
+        };
+        if len == 0 && !data.is_empty() {
+            register_waker(pcb_mut(&mut pcbs, id).unwrap(), cx);
+        }
+        (len, segments)
+    };
+    let _ = transmit(segments);
+    if len == 0 && !data.is_empty() {
+        return Poll::Pending;
+    }
+    Poll::Ready(Ok(len))
+}
+
+pub fn poll_receive(
+    id: usize,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+) -> Poll<Result<usize, TcpError>> {
+    let result = poll_pcb(id, cx, |pcbs| try_receive(pcbs, id, buf));
+    result.map_ok(|(len, segments)| {
+        let _ = transmit(segments);
+        len
+    })
+}
+
+/// 送信側だけを閉じる。id はそのまま使えて、相手からの受信は続けられる
+pub fn shutdown(id: usize) -> Result<(), TcpError> {
+    let segments = {
+        let mut pcbs = pcbs();
+        let conn = conn_mut(&mut pcbs, id)?;
+        let fin = conn.close();
+        outgoing(conn, fin)
+    };
+    let _ = transmit(segments);
+    Ok(())
+}
+
+/// 接続を閉じて id を手放す。確立していれば FIN を送る
+/// LISTEN していれば accept されていない接続をすべて RST で切る
+pub fn close(id: usize) -> Result<(), TcpError> {
+    let mut segments = Vec::new();
+    let wakers;
+    {
+        let mut pcbs = pcbs();
+        let pcb = pcb_mut(&mut pcbs, id)?;
+        pcb.released = true;
+        if let Some(conn) = &mut pcb.conn {
+            let fin = conn.close();
+            segments.extend(outgoing(conn, fin));
+        }
+        let mut ids = vec![id];
+        if let Some(mut listen) = pcb.listen.take() {
+            for conn in listen.syn_queue.iter_mut() {
+                let reset = conn.abort();
+                segments.extend(outgoing(conn, reset.into_iter().collect()));
+            }
+            for (child_id, child) in pcbs.iter_mut().enumerate() {
+                let Some(child) = child.as_mut().filter(|child| child.parent == Some(id)) else {
+                    continue;
+                };
+                if let Some(conn) = &mut child.conn {
+                    let reset = conn.abort();
+                    segments.extend(outgoing(conn, reset.into_iter().collect()));
+                }
+                ids.push(child_id);
+            }
+        }
+        wakers = notify(&mut pcbs, &ids);
+        release_finished(&mut pcbs);
+    }
+    wake(wakers);
+    let _ = transmit(segments);
+    Ok(())
+}
+
+/// RST を送って直ちに接続を破棄する
+pub fn abort(id: usize) -> Result<(), TcpError> {
+    let (segments, wakers) = {
+        let mut pcbs = pcbs();
+        let conn = conn_mut(&mut pcbs, id)?;
+        let reset = conn.abort();
+        let segments = outgoing(conn, reset.into_iter().collect());
+        (segments, notify(&mut pcbs, &[id]))
+    };
+    wake(wakers);
+    let _ = transmit(segments);
+    Ok(())
+}
+
+/// 輻輳制御を選ぶ。接続中なら今の接続もウィンドウを初期値に戻して切り替える
+pub fn set_congestion_control(id: usize, algorithm: CongestionAlgorithm) -> Result<(), TcpError> {
+    let mut pcbs = pcbs();
+    let pcb = pcb_mut(&mut pcbs, id)?;
+    pcb.congestion = Some(algorithm);
+    if let Some(conn) = &mut pcb.conn {
+        conn.set_congestion_control(algorithm.build(conn.send_mss()));
+    }
+    Ok(())
+}
+
+pub fn congestion_control(id: usize) -> Result<CongestionAlgorithm, TcpError> {
+    let mut pcbs = pcbs();
+    let pcb = pcb_mut(&mut pcbs, id)?;
+    Ok(pcb.congestion.unwrap_or_else(congestion::default_algorithm))
+}
+
+/// キープアライブを設定する。None で止める。LISTEN していれば受け入れる接続に引き継ぐ
+pub fn set_keepalive(id: usize, keepalive: Option<TcpKeepalive>) -> Result<(), TcpError> {
+    let mut pcbs = pcbs();
+    let pcb = pcb_mut(&mut pcbs, id)?;
+    pcb.keepalive = keepalive;
+    if let Some(conn) = &mut pcb.conn {
+        conn.set_keepalive(keepalive);
+    }
+    Ok(())
+}
+
+pub fn keepalive(id: usize) -> Result<Option<TcpKeepalive>, TcpError> {
+    let mut pcbs = pcbs();
+    Ok(pcb_mut(&mut pcbs, id)?.keepalive)
+}
+
+pub fn state(id: usize) -> Result<TcpState, TcpError> {
+    let mut pcbs = pcbs();
+    Ok(pcb_mut(&mut pcbs, id)?.state())
+}
+
+pub fn local_endpoint(id: usize) -> Result<Ipv4Endpoint, TcpError> {
+    let mut pcbs = pcbs();
+    Ok(pcb_mut(&mut pcbs, id)?.local)
+}
+
+pub fn foreign_endpoint(id: usize) -> Result<Ipv4Endpoint, TcpError> {
+    let mut pcbs = pcbs();
+    Ok(conn_mut(&mut pcbs, id)?.foreign)
+}
+
+/// LISTEN している pcb の SYN キューと accept キューの長さ
+pub fn queue_lengths(id: usize) -> Result<(usize, usize), TcpError> {
+    let mut pcbs = pcbs();
+    match &pcb_mut(&mut pcbs, id)?.listen {
+        Some(listen) => Ok((listen.syn_queue.len(), listen.queue.len())),
+        None => Err(TcpError::new(TcpErrorKind::InvalidState)),
+    }
+}
+
+/// LISTEN している pcb に届いたセグメントを処理する
+/// SYN キューにある接続宛てでなければ、SYN なら SYN キューに入れ、ACK なら SYN クッキーを確かめる
+fn input_listen(
+    pcbs: &mut [Option<TcpPcb>],
+    parent: usize,
+    local: Ipv4Endpoint,
+    foreign: Ipv4Endpoint,
+    segment: &TcpSegment,
+    dev: &NetDevice,
+) -> Vec<Outgoing> {
+    let now = Instant::now();
+    let pending = match pcbs[parent].as_ref().and_then(|pcb| pcb.listen.as_ref()) {
+        Some(listen) => listen
+            .syn_queue
+            .iter()
+            .position(|conn| conn.local == local && conn.foreign == foreign),
+        None => return Vec::new(),
+    };
+    if let Some(index) = pending {
+        return input_syn_queue(pcbs, parent, index, segment, now);
+    }
+    if segment.has(TCP_FLAG_RST) {
+        return Vec::new();
+    }
+    if segment.has(TCP_FLAG_SYN) && !segment.has(TCP_FLAG_ACK) {
+        return input_syn(pcbs, parent, local, foreign, segment, dev, now);
+    }
+    if segment.has(TCP_FLAG_ACK) && !segment.has(TCP_FLAG_SYN) {
+        let irs = segment.seq.wrapping_sub(1);
+        let cookie = segment.ack.wrapping_sub(1);
+        if let Some(mss) = syncookie::check(local, foreign, irs, cookie, now) {
+            return accept_cookie(pcbs, parent, segment, local, foreign, mss, now);
+        }
+    }
+    segment
+        .reset_for()
+        .map(|reset| vec![(local, foreign, reset)])
+        .unwrap_or_default()
+}
+
+/// SYN クッキーで応えたときの設定。状態を持たないので MSS 以外のオプションは諦める
+fn cookie_config(mss: u16) -> TcpConfig {
+    TcpConfig {
+        mss,
+        window_scale: false,
+        sack: false,
+        timestamps: false,
+    }
+}
+
+/// SYN キューに入れて SYN-ACK を返す。あふれていれば SYN クッキーで応える (RFC 4987 3.6)
+fn input_syn(
+    pcbs: &mut [Option<TcpPcb>],
+    parent: usize,
+    local: Ipv4Endpoint,
+    foreign: Ipv4Endpoint,
+    segment: &TcpSegment,
+    dev: &NetDevice,
+    now: Instant,
+) -> Vec<Outgoing> {
+    let pcb = pcbs[parent].as_mut().unwrap();
+    let config = config_for(foreign.address, Some(dev));
+    let full = pcb
+        .listen
+        .as_ref()
+        .is_none_or(|listen| listen.syn_queue.len() >= TCP_SYN_QUEUE_SIZE);
+    if full {
+        let mss = segment
+            .options
+            .mss
+            .unwrap_or(TCP_DEFAULT_MSS)
+            .min(config.mss);
+        let cookie = syncookie::generate(local, foreign, segment.seq, mss, now);
+        let (conn, syn_ack) =
+            TcpConnection::accept_with(local, foreign, segment, cookie, cookie_config(mss));
+        eprintln!(
+            "TCP SYN queue is full, sending SYN cookie LOCAL={} FOREIGN={}",
+            local, foreign
+        );
+        return outgoing(&conn, vec![syn_ack]);
+    }
+    let (mut conn, syn_ack) =
+        TcpConnection::accept_with(local, foreign, segment, generate_iss(), config);
+    // 輻輳制御は LISTEN している pcb から引き継ぐ
+    pcb.apply_options(&mut conn);
+    let segments = outgoing(&conn, vec![syn_ack]);
+    if let Some(listen) = &mut pcb.listen {
+        listen.syn_queue.push(conn);
+    }
+    eprintln!("TCP SYN received LOCAL={} FOREIGN={}", local, foreign);
+    segments
+}
+
+/// accept キューと pcb に空きがあれば、確立した接続を置く pcb を返す
+fn admit(pcbs: &[Option<TcpPcb>], parent: usize) -> Option<usize> {
+    let full = match &pcbs[parent] {
+        Some(TcpPcb {
+            listen: Some(listen),
+            ..
+        }) => listen.queue.len() >= listen.backlog,
+        _ => true,
+    };
+    if full {
+        eprintln!("TCP accept queue is full ID={}", parent);
+        return None;
+    }
+    let slot = pcbs.iter().position(|entry| entry.is_none());
+    if slot.is_none() {
+        eprintln!("TCP pcb is full");
+    }
+    slot
+}
+
+/// 確立した接続に pcb を割り当てて accept キューに入れる
+fn establish_child(pcbs: &mut [Option<TcpPcb>], parent: usize, slot: usize, conn: TcpConnection) {
+    let (local, foreign) = (conn.local, conn.foreign);
+    let (congestion, keepalive) = match &pcbs[parent] {
+        Some(pcb) => (pcb.congestion, pcb.keepalive),
+        None => (None, None),
+    };
+    pcbs[slot] = Some(TcpPcb {
+        local,
+        conn: Some(conn),
+        parent: Some(parent),
+        congestion,
+        keepalive,
+        ..TcpPcb::new()
+    });
+    if let Some(Some(TcpPcb {
+        listen: Some(listen),
+        ..
+    })) = pcbs.get_mut(parent)
+    {
+        listen.queue.push_back(slot);
+    }
+    eprintln!(
+        "TCP passive open ID={} LOCAL={} FOREIGN={}",
+        slot, local, foreign
+    );
+}
+
+/// SYN キューにある接続にセグメントを渡し、確立したら accept キューへ移す
+/// accept キューがあふれていれば ACK を捨てて、SYN-ACK の再送に任せる
+fn input_syn_queue(
+    pcbs: &mut [Option<TcpPcb>],
+    parent: usize,
+    index: usize,
+    segment: &TcpSegment,
+    now: Instant,
+) -> Vec<Outgoing> {
+    let slot = if segment.has(TCP_FLAG_ACK) && !segment.has(TCP_FLAG_RST) {
+        match admit(pcbs, parent) {
+            Some(slot) => Some(slot),
+            None => return Vec::new(),
+        }
+    } else {
+        None
+    };
+    let listen = match pcbs[parent].as_mut().and_then(|pcb| pcb.listen.as_mut()) {
+        Some(listen) => listen,
+        None => return Vec::new(),
+    };
+    let conn = &mut listen.syn_queue[index];
+    let output = conn.input(segment, now);
+    let segments = outgoing(conn, output);
+    if conn.state == TcpState::Closed {
+        eprintln!(
+            "TCP SYN queue dropped LOCAL={} FOREIGN={}",
+            conn.local, conn.foreign
+        );
+        listen.syn_queue.remove(index);
+    } else if let (true, Some(slot)) = (conn.state.is_synchronized(), slot) {
+        let conn = listen.syn_queue.remove(index);
+        establish_child(pcbs, parent, slot, conn);
+    }
+    segments
+}
+
+/// SYN クッキーの ACK から接続を作り直す
+fn accept_cookie(
+    pcbs: &mut [Option<TcpPcb>],
+    parent: usize,
+    segment: &TcpSegment,
+    local: Ipv4Endpoint,
+    foreign: Ipv4Endpoint,
+    mss: u16,
+    now: Instant,
+) -> Vec<Outgoing> {
+    let slot = match admit(pcbs, parent) {
+        Some(slot) => slot,
+        None => return Vec::new(),
+    };
+    let syn = TcpSegment {
+        seq: segment.seq.wrapping_sub(1),
+        ack: 0,
+        flags: TCP_FLAG_SYN,
+        window: segment.window,
+        options: TcpOptions {
+            mss: Some(mss),
+            ..TcpOptions::default()
+        },
+        data: Vec::new(),
+    };
+    let iss = segment.ack.wrapping_sub(1);
+    let (mut conn, _) = TcpConnection::accept_with(local, foreign, &syn, iss, cookie_config(mss));
+    if let Some(pcb) = &pcbs[parent] {
+        pcb.apply_options(&mut conn);
+    }
+    let output = conn.input(segment, now);
+    if !conn.state.is_synchronized() {
+        return Vec::new();
+    }
+    eprintln!(
+        "TCP SYN cookie accepted LOCAL={} FOREIGN={}",
+        local, foreign
+    );
+    let segments = outgoing(&conn, output);
+    establish_child(pcbs, parent, slot, conn);
+    segments
+}
+
+pub fn input(packet: &[u8], dev: &'static NetDevice) {
+    let ipv4_hdr = Ipv4Header::new_unchecked(packet);
+    let src = ipv4_hdr.src_address();
+    let dst = ipv4_hdr.dst_address();
+    let tcp_hdr = match TcpHeader::new_checked(ipv4_hdr.payload()) {
+        Some(hdr) => hdr,
+        None => {
+            eprintln!("TCP length error");
+            return;
+        }
+    };
+    if !tcp_hdr.verify_checksum(src, dst) {
+        eprintln!("TCP checksum error");
+        return;
+    }
+    // TCP はユニキャストだけ (RFC 1122 4.2.3.10)
+    if dst.is_multicast() || ipv4::is_broadcast(dst) {
+        return;
+    }
+    let local = Ipv4Endpoint::new(dst, tcp_hdr.dst_port());
+    let foreign = Ipv4Endpoint::new(src, tcp_hdr.src_port());
+    let segment = TcpSegment::from_header(&tcp_hdr);
+    eprintln!(
+        "TCP input SRC={} DST={} FLAGS={} SEQ={} ACK={} WND={} LEN={}",
+        foreign,
+        local,
+        header::flags_to_string(segment.flags),
+        segment.seq,
+        segment.ack,
+        segment.window,
+        segment.data.len()
+    );
+
+    let (segments, wakers) = {
+        let mut pcbs = pcbs();
+        let (segments, target) = segment_arrives(&mut pcbs, local, foreign, &segment, dev);
+        recycle_time_wait(&mut pcbs);
+        let wakers = notify(&mut pcbs, target.as_slice());
+        release_finished(&mut pcbs);
+        (segments, wakers)
+    };
+    wake(wakers);
+    let _ = transmit(segments);
+}
+
+/// TIME-WAIT の接続が上限を超えたら古いものから終わらせる
+fn recycle_time_wait(pcbs: &mut [Option<TcpPcb>]) {
+    let mut waiting: Vec<(Instant, usize)> = pcbs
+        .iter()
+        .enumerate()
+        .filter_map(|(id, entry)| {
+            let since = entry.as_ref()?.conn.as_ref()?.time_wait_since()?;
+            Some((since, id))
+        })
+        .collect();
+    if waiting.len() <= TCP_TIME_WAIT_MAX {
+        return;
+    }
+    waiting.sort_unstable();
+    for (_, id) in &waiting[..waiting.len() - TCP_TIME_WAIT_MAX] {
+        if let Some(conn) = pcbs[*id].as_mut().and_then(|pcb| pcb.conn.as_mut()) {
+            conn.abort();
+            eprintln!("TCP TIME-WAIT limit exceeded ID={}", id);
+        }
+    }
+}
+
+/// 処理した pcb の id も返す。LISTEN に渡したときは待ち受けの id になる
+fn segment_arrives(
+    pcbs: &mut [Option<TcpPcb>],
+    local: Ipv4Endpoint,
+    foreign: Ipv4Endpoint,
+    segment: &TcpSegment,
+    dev: &NetDevice,
+) -> (Vec<Outgoing>, Option<usize>) {
+    let mut found = pcbs
+        .iter()
+        .position(|entry| matches!(entry, Some(pcb) if pcb.matches(local, foreign)));
+    // TIME-WAIT の接続宛ての新しい SYN なら TIME-WAIT を終わらせて LISTEN に渡す
+    if let Some(id) = found {
+        let conn = pcbs[id].as_mut().and_then(|pcb| pcb.conn.as_mut()).unwrap();
+        if conn.reusable_by(segment) {
+            conn.abort();
+            eprintln!("TCP TIME-WAIT recycled ID={}", id);
+            found = None;
+        }
+    }
+    let id = match found {
+        Some(id) => id,
+        None => {
+            let listener = pcbs
+                .iter()
+                .position(|entry| matches!(entry, Some(pcb) if pcb.listens(local)));
+            return match listener {
+                Some(parent) => (
+                    input_listen(pcbs, parent, local, foreign, segment, dev),
+                    Some(parent),
+                ),
+                None => (
+                    segment
+                        .reset_for()
+                        .map(|reset| vec![(local, foreign, reset)])
+                        .unwrap_or_default(),
+                    None,
+                ),
+            };
+        }
+    };
+
+    let conn = pcbs[id].as_mut().unwrap().conn.as_mut().unwrap();
+    let before = conn.state;
+    let segments = conn.input(segment, Instant::now());
+    if before != conn.state {
+        eprintln!("TCP state ID={} {} => {}", id, before, conn.state);
+    }
+    (outgoing(conn, segments), Some(id))
+}
+
+/// TIME-WAIT の期限と再送タイマーを見る
+/// SYN キューの接続は SYN-ACK の再送が上限を超えたら捨てる
+fn timer() {
+    let mut pcbs = pcbs();
+    let now = Instant::now();
+    let mut changed = Vec::new();
+    let mut segments = Vec::new();
+    for listen in pcbs
+        .iter_mut()
+        .flatten()
+        .filter_map(|pcb| pcb.listen.as_mut())
+    {
+        for conn in listen.syn_queue.iter_mut() {
+            let output = conn.tick(now);
+            segments.extend(outgoing(conn, output));
+        }
+        listen.syn_queue.retain(|conn| {
+            let alive = conn.state != TcpState::Closed;
+            if !alive {
+                eprintln!(
+                    "TCP SYN-ACK retransmission limit exceeded LOCAL={} FOREIGN={}",
+                    conn.local, conn.foreign
+                );
+            }
+            alive
+        });
+    }
+    for (id, pcb) in pcbs.iter_mut().enumerate() {
+        if let Some(conn) = pcb.as_mut().and_then(|pcb| pcb.conn.as_mut()) {
+            let before = conn.state;
+            // 確認待ちがなければ送るのはキープアライブ
+            let probing = conn.is_idle();
+            let output = conn.tick(now);
+            if !output.is_empty() && conn.state != TcpState::Closed {
+                if probing {
+                    eprintln!("TCP keepalive ID={} SEQ={}", id, output[0].seq);
+                } else {
+                    eprintln!(
+                        "TCP retransmit ID={} SEQ={} RTO={:?}",
+                        id,
+                        output[0].seq,
+                        conn.rto().rto()
+                    );
+                }
+            }
+            if before != conn.state {
+                eprintln!("TCP state ID={} {} => {}", id, before, conn.state);
+                match conn.error() {
+                    Some(TcpErrorKind::Timeout) if probing => {
+                        eprintln!("TCP keepalive timeout ID={}", id);
+                    }
+                    Some(TcpErrorKind::Timeout) => {
+                        eprintln!("TCP retransmission limit exceeded ID={}", id);
+                    }
+                    _ => {}
+                }
+            }
+            segments.extend(outgoing(conn, output));
+            if before != conn.state {
+                changed.push(id);
+            }
+        }
+    }
+    if changed.is_empty() {
+        drop(pcbs);
+        let _ = transmit(segments);
+        return;
+    }
+    let wakers = notify(&mut pcbs, &changed);
+    release_finished(&mut pcbs);
+    drop(pcbs);
+    wake(wakers);
+    let _ = transmit(segments);
+}
+
+pub fn init() {
+    if ipv4::protocol_register(Protocol::Tcp as u8, input).is_err() {
+        eprintln!("TCP is already registered");
+    }
+    NetTimer::register(TCP_TIMER_INTERVAL, timer);
+}
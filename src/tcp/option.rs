@@ -0,0 +1,283 @@
+use std::fmt;
+
+pub const TCP_OPTION_END: u8 = 0;
+pub const TCP_OPTION_NOP: u8 = 1;
+pub const TCP_OPTION_MSS: u8 = 2;
+pub const TCP_OPTION_WINDOW_SCALE: u8 = 3;
+pub const TCP_OPTION_SACK_PERMITTED: u8 = 4;
+pub const TCP_OPTION_SACK: u8 = 5;
+pub const TCP_OPTION_TIMESTAMP: u8 = 8;
+
+/// ウィンドウスケールのシフト数の上限 (RFC 7323 2.3)
+pub const TCP_WINDOW_SCALE_MAX: u8 = 14;
+
+const MSS_LENGTH: u8 = 4;
+const WINDOW_SCALE_LENGTH: u8 = 3;
+const SACK_PERMITTED_LENGTH: u8 = 2;
+const TIMESTAMP_LENGTH: u8 = 10;
+const SACK_BLOCK_SIZE: usize = 8;
+
+/// SACK で知らせる受信済みの範囲。right は範囲の次の順序番号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SackBlock {
+    pub left: u32,
+    pub right: u32,
+}
+
+/// TSval と TSecr (RFC 7323 3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpTimestamp {
+    pub value: u32,
+    pub echo: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TcpOption {
+    EndOfList,
+    NoOperation,
+    MaxSegmentSize(u16),
+    WindowScale(u8),
+    SackPermitted,
+    Sack(Vec<SackBlock>),
+    Timestamp(TcpTimestamp),
+    Unknown { kind: u8, data: Vec<u8> },
+}
+
+#[derive(Debug)]
+pub struct TcpOptionError {
+    pub kind: TcpOptionErrorKind,
+    /// オプション領域の先頭からの問題のあるバイトの位置
+    pub offset: usize,
+}
+
+impl TcpOptionError {
+    pub fn new(kind: TcpOptionErrorKind, offset: usize) -> Self {
+        Self { kind, offset }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TcpOptionErrorKind {
+    Truncated,
+    InvalidLength,
+}
+
+impl fmt::Display for TcpOptionErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TcpOptionErrorKind::Truncated => "option truncated",
+            TcpOptionErrorKind::InvalidLength => "invalid option length",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl TcpOption {
+    pub fn kind(&self) -> u8 {
+        match self {
+            TcpOption::EndOfList => TCP_OPTION_END,
+            TcpOption::NoOperation => TCP_OPTION_NOP,
+            TcpOption::MaxSegmentSize(_) => TCP_OPTION_MSS,
+            TcpOption::WindowScale(_) => TCP_OPTION_WINDOW_SCALE,
+            TcpOption::SackPermitted => TCP_OPTION_SACK_PERMITTED,
+            TcpOption::Sack(_) => TCP_OPTION_SACK,
+            TcpOption::Timestamp(_) => TCP_OPTION_TIMESTAMP,
+            TcpOption::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    /// エンコードしたときのバイト数
+    pub fn length(&self) -> usize {
+        match self {
+            TcpOption::EndOfList | TcpOption::NoOperation => 1,
+            TcpOption::MaxSegmentSize(_) => MSS_LENGTH as usize,
+            TcpOption::WindowScale(_) => WINDOW_SCALE_LENGTH as usize,
+            TcpOption::SackPermitted => SACK_PERMITTED_LENGTH as usize,
+            TcpOption::Sack(blocks) => 2 + blocks.len() * SACK_BLOCK_SIZE,
+            TcpOption::Timestamp(_) => TIMESTAMP_LENGTH as usize,
+            TcpOption::Unknown { data, .. } => 2 + data.len(),
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.kind());
+        if let TcpOption::EndOfList | TcpOption::NoOperation = self {
+            return;
+        }
+        buf.push(self.length() as u8);
+        match self {
+            TcpOption::MaxSegmentSize(mss) => buf.extend_from_slice(&mss.to_be_bytes()),
+            TcpOption::WindowScale(shift) => buf.push(*shift),
+            TcpOption::Sack(blocks) => {
+                for block in blocks {
+                    buf.extend_from_slice(&block.left.to_be_bytes());
+                    buf.extend_from_slice(&block.right.to_be_bytes());
+                }
+            }
+            TcpOption::Timestamp(timestamp) => {
+                buf.extend_from_slice(&timestamp.value.to_be_bytes());
+                buf.extend_from_slice(&timestamp.echo.to_be_bytes());
+            }
+            TcpOption::Unknown { data, .. } => buf.extend_from_slice(data),
+            _ => {}
+        }
+    }
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+/// 長さが決まっているオプションは長さも検査する
+pub fn parse(data: &[u8]) -> Result<Vec<TcpOption>, TcpOptionError> {
+    let mut options = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let kind = data[i];
+        match kind {
+            TCP_OPTION_END => {
+                options.push(TcpOption::EndOfList);
+                break;
+            }
+            TCP_OPTION_NOP => {
+                options.push(TcpOption::NoOperation);
+                i += 1;
+                continue;
+            }
+            _ => (),
+        }
+        if i + 1 >= data.len() {
+            return Err(TcpOptionError::new(TcpOptionErrorKind::Truncated, i));
+        }
+        let length = data[i + 1] as usize;
+        if length < 2 {
+            return Err(TcpOptionError::new(
+                TcpOptionErrorKind::InvalidLength,
+                i + 1,
+            ));
+        }
+        if i + length > data.len() {
+            return Err(TcpOptionError::new(TcpOptionErrorKind::Truncated, i + 1));
+        }
+        let body = &data[i + 2..i + length];
+        let expected = match kind {
+            TCP_OPTION_MSS => Some(MSS_LENGTH),
+            TCP_OPTION_WINDOW_SCALE => Some(WINDOW_SCALE_LENGTH),
+            TCP_OPTION_SACK_PERMITTED => Some(SACK_PERMITTED_LENGTH),
+            TCP_OPTION_TIMESTAMP => Some(TIMESTAMP_LENGTH),
+            _ => None,
+        };
+        let valid = match (kind, expected) {
+            (_, Some(expected)) => length == expected as usize,
+            (TCP_OPTION_SACK, _) => body.len().is_multiple_of(SACK_BLOCK_SIZE),
+            _ => true,
+        };
+        if !valid {
+            return Err(TcpOptionError::new(
+                TcpOptionErrorKind::InvalidLength,
+                i + 1,
+            ));
+        }
+        let option = match kind {
+            TCP_OPTION_MSS => TcpOption::MaxSegmentSize(u16::from_be_bytes([body[0], body[1]])),
+            TCP_OPTION_WINDOW_SCALE => TcpOption::WindowScale(body[0]),
+            TCP_OPTION_SACK_PERMITTED => TcpOption::SackPermitted,
+            TCP_OPTION_SACK => TcpOption::Sack(
+                body.chunks_exact(SACK_BLOCK_SIZE)
+                    .map(|c| SackBlock {
+                        left: read_u32(&c[..4]),
+                        right: read_u32(&c[4..]),
+                    })
+                    .collect(),
+            ),
+            TCP_OPTION_TIMESTAMP => TcpOption::Timestamp(TcpTimestamp {
+                value: read_u32(&body[..4]),
+                echo: read_u32(&body[4..]),
+            }),
+            _ => TcpOption::Unknown {
+                kind,
+                data: body.to_vec(),
+            },
+        };
+        options.push(option);
+        i += length;
+    }
+    Ok(options)
+}
+
+/// ヘッダに収まるように 4 バイト境界まで End of Option List で埋める
+pub fn emit(options: &[TcpOption]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for option in options {
+        option.encode(&mut buf);
+    }
+    while buf.len() % 4 != 0 {
+        buf.push(TCP_OPTION_END);
+    }
+    buf
+}
+
+/// 接続の処理に使うオプションをまとめたもの
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TcpOptions {
+    pub mss: Option<u16>,
+    pub window_scale: Option<u8>,
+    pub sack_permitted: bool,
+    pub sack: Vec<SackBlock>,
+    pub timestamp: Option<TcpTimestamp>,
+}
+
+impl TcpOptions {
+    /// 壊れたオプションは無いものとして扱う
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let mut options = TcpOptions::default();
+        for option in parse(data).unwrap_or_default() {
+            match option {
+                TcpOption::MaxSegmentSize(mss) => options.mss = Some(mss),
+                TcpOption::WindowScale(shift) => options.window_scale = Some(shift),
+                TcpOption::SackPermitted => options.sack_permitted = true,
+                TcpOption::Sack(blocks) => options.sack = blocks,
+                TcpOption::Timestamp(timestamp) => options.timestamp = Some(timestamp),
+                _ => {}
+            }
+        }
+        options
+    }
+
+    /// 4 バイト境界に揃うように NOP を挟んで並べる
+    pub fn to_options(&self) -> Vec<TcpOption> {
+        let mut options = Vec::new();
+        if let Some(mss) = self.mss {
+            options.push(TcpOption::MaxSegmentSize(mss));
+        }
+        if let Some(timestamp) = self.timestamp {
+            if self.sack_permitted {
+                options.push(TcpOption::SackPermitted);
+            } else {
+                options.extend([TcpOption::NoOperation, TcpOption::NoOperation]);
+            }
+            options.push(TcpOption::Timestamp(timestamp));
+        } else if self.sack_permitted {
+            options.extend([TcpOption::NoOperation, TcpOption::NoOperation]);
+            options.push(TcpOption::SackPermitted);
+        }
+        if let Some(shift) = self.window_scale {
+            options.push(TcpOption::NoOperation);
+            options.push(TcpOption::WindowScale(shift));
+        }
+        if !self.sack.is_empty() {
+            options.extend([TcpOption::NoOperation, TcpOption::NoOperation]);
+            options.push(TcpOption::Sack(self.sack.clone()));
+        }
+        options
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        emit(&self.to_options())
+    }
+
+    /// エンコードしたときのバイト数
+    pub fn length(&self) -> usize {
+        self.to_bytes().len()
+    }
+}
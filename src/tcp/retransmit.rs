@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::connection::{seq_le, seq_lt, TcpSegment};
+use super::header::TCP_FLAG_SYN;
+use super::option::SackBlock;
+
+/// RTT を測る前の RTO (RFC 6298 2.1)
+pub const TCP_RTO_INITIAL: Duration = Duration::from_secs(1);
+pub const TCP_RTO_MIN: Duration = Duration::from_secs(1);
+pub const TCP_RTO_MAX: Duration = Duration::from_secs(60);
+/// SYN を再送したときに確立後に使う RTO (RFC 6298 5.7)
+pub const TCP_RTO_AFTER_SYN_TIMEOUT: Duration = Duration::from_secs(3);
+/// タイマーの刻み。RFC 6298 の G
+pub const TCP_CLOCK_GRANULARITY: Duration = Duration::from_millis(100);
+/// 同じセグメントをこの回数再送しても確認されなければ接続を破棄する
+pub const TCP_RETRANSMIT_LIMIT: u32 = 12;
+/// SYN と SYN-ACK の再送回数の上限
+pub const TCP_SYN_RETRANSMIT_LIMIT: u32 = 6;
+/// 高速再送するまでの重複 ACK の数 (RFC 5681 3.2)
+pub const TCP_DUP_ACK_THRESHOLD: u32 = 3;
+
+/// SRTT と RTTVAR から RTO を見積もる (RFC 6298 2)
+#[derive(Debug, Clone, Copy)]
+pub struct RtoEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+}
+
+impl Default for RtoEstimator {
+    fn default() -> Self {
+        RtoEstimator {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: TCP_RTO_INITIAL,
+        }
+    }
+}
+
+impl RtoEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rto(&self) -> Duration {
+        self.rto
+    }
+
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    pub fn rttvar(&self) -> Duration {
+        self.rttvar
+    }
+
+    /// 再送していないセグメントの RTT を 1 つ取り込む
+    pub fn sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                // RTTVAR = 3/4 RTTVAR + 1/4 |SRTT - R|, SRTT = 7/8 SRTT + 1/8 R
+                let delta = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        let srtt = self.srtt.unwrap();
+        let rto = srtt + TCP_CLOCK_GRANULARITY.max(self.rttvar * 4);
+        self.rto = rto.clamp(TCP_RTO_MIN, TCP_RTO_MAX);
+    }
+
+    /// タイムアウトしたら RTO を倍にする (RFC 6298 5.5)
+    pub fn backoff(&mut self) {
+        self.rto = (self.rto * 2).min(TCP_RTO_MAX);
+    }
+
+    /// 測り直すまで使う RTO を決める
+    pub fn reset(&mut self, rto: Duration) {
+        self.rto = rto;
+    }
+}
+
+/// 送ったがまだ確認されていないセグメント
+#[derive(Debug, Clone)]
+pub struct RetransmitEntry {
+    pub segment: TcpSegment,
+    pub sent_at: Instant,
+    /// 一度でも再送したものは RTT の計測に使わない (Karn のアルゴリズム)
+    pub retransmitted: bool,
+    /// 相手が SACK で受け取ったと知らせてきた
+    pub sacked: bool,
+}
+
+impl RetransmitEntry {
+    fn end(&self) -> u32 {
+        self.segment.seq.wrapping_add(self.segment.seq_len())
+    }
+}
+
+/// 接続ごとの再送キュー。順序番号の順に並ぶ
+#[derive(Debug, Default)]
+pub struct RetransmitQueue {
+    entries: VecDeque<RetransmitEntry>,
+}
+
+impl RetransmitQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// 順序番号空間を使うセグメントだけを積む
+    pub fn push(&mut self, segment: TcpSegment, now: Instant) {
+        if segment.seq_len() == 0 {
+            return;
+        }
+        self.entries.push_back(RetransmitEntry {
+            segment,
+            sent_at: now,
+            retransmitted: false,
+            sacked: false,
+        });
+    }
+
+    pub fn front_mut(&mut self) -> Option<&mut RetransmitEntry> {
+        self.entries.front_mut()
+    }
+
+    pub fn find_mut(&mut self, seq: u32) -> Option<&mut RetransmitEntry> {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.segment.seq == seq)
+    }
+
+    /// SACK のブロックにすっぽり収まるものに印を付ける
+    pub fn sack(&mut self, blocks: &[SackBlock]) {
+        for entry in self.entries.iter_mut() {
+            let end = entry.end();
+            if blocks
+                .iter()
+                .any(|block| seq_le(block.left, entry.segment.seq) && seq_le(end, block.right))
+            {
+                entry.sacked = true;
+            }
+        }
+    }
+
+    pub fn clear_sacked(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.sacked = false;
+        }
+    }
+
+    /// SACK されたものより前にあって、SACK されておらず再送もしていないもの
+    pub fn next_hole_mut(&mut self) -> Option<&mut RetransmitEntry> {
+        let highest = self
+            .entries
+            .iter()
+            .rev()
+            .find(|entry| entry.sacked)?
+            .segment
+            .seq;
+        self.entries
+            .iter_mut()
+            .take_while(|entry| seq_lt(entry.segment.seq, highest))
+            .find(|entry| !entry.sacked && !entry.retransmitted)
+    }
+
+    /// ack までに収まるものを取り除き、一部だけ確認されたデータは先頭を切り詰める
+    /// 取り除いたうちで最後のものが再送していなければ、その RTT を返す
+    pub fn acknowledge(&mut self, ack: u32, now: Instant) -> Option<Duration> {
+        let mut rtt = None;
+        while let Some(entry) = self.entries.front_mut() {
+            if seq_le(entry.end(), ack) {
+                rtt = if entry.retransmitted {
+                    None
+                } else {
+                    Some(now.saturating_duration_since(entry.sent_at))
+                };
+                self.entries.pop_front();
+                continue;
+            }
+            if seq_lt(entry.segment.seq, ack) && !entry.segment.has(TCP_FLAG_SYN) {
+                let acked = ack.wrapping_sub(entry.segment.seq) as usize;
+                let acked = acked.min(entry.segment.data.len());
+                entry.segment.data.drain(..acked);
+                entry.segment.seq = entry.segment.seq.wrapping_add(acked as u32);
+            }
+            break;
+        }
+        rtt
+    }
+}
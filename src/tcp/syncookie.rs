@@ -0,0 +1,62 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant};
+
+use crate::ipv4::Ipv4Endpoint;
+
+/// カウンタが 1 進む間隔。ひとつ前のカウンタまで受け付ける
+pub const SYN_COOKIE_PERIOD: Duration = Duration::from_secs(64);
+
+/// クッキーに埋め込める MSS。受け取った MSS 以下で最大のものを選ぶ
+pub const SYN_COOKIE_MSS: [u16; 4] = [536, 1300, 1440, 1460];
+
+lazy_static! {
+    /// 再起動のたびに変わる鍵
+    static ref SECRET: RandomState = RandomState::new();
+    static ref EPOCH: Instant = Instant::now();
+}
+
+fn counter(now: Instant) -> u32 {
+    (now.saturating_duration_since(*EPOCH).as_secs() / SYN_COOKIE_PERIOD.as_secs()) as u32
+}
+
+fn hash(local: Ipv4Endpoint, foreign: Ipv4Endpoint, irs: u32, t: u32) -> u32 {
+    SECRET.hash_one((local, foreign, irs, t & 0x1f)) as u32 & 0x00ff_ffff
+}
+
+/// SYN-ACK の初期順序番号にするクッキー (RFC 4987 3.6)
+/// 上位 5 ビットがカウンタ、次の 3 ビットが MSS の番号、残りが鍵付きのハッシュ
+pub fn generate(
+    local: Ipv4Endpoint,
+    foreign: Ipv4Endpoint,
+    irs: u32,
+    mss: u16,
+    now: Instant,
+) -> u32 {
+    let index = SYN_COOKIE_MSS
+        .iter()
+        .rposition(|candidate| *candidate <= mss)
+        .unwrap_or(0) as u32;
+    let t = counter(now);
+    (t & 0x1f) << 27 | index << 24 | hash(local, foreign, irs, t)
+}
+
+/// 3 ウェイハンドシェイクの ACK の ack - 1 がクッキーなら埋め込んだ MSS を返す
+pub fn check(
+    local: Ipv4Endpoint,
+    foreign: Ipv4Endpoint,
+    irs: u32,
+    cookie: u32,
+    now: Instant,
+) -> Option<u16> {
+    let t = cookie >> 27;
+    let current = counter(now);
+    // 期限切れを見分けられるのはカウンタが 32 周するまで
+    if t != current & 0x1f && t != current.wrapping_sub(1) & 0x1f {
+        return None;
+    }
+    if cookie & 0x00ff_ffff != hash(local, foreign, irs, t) {
+        return None;
+    }
+    SYN_COOKIE_MSS.get((cookie >> 24 & 0x7) as usize).copied()
+}
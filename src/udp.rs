@@ -0,0 +1,486 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::ptr;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::icmp::{self, UnreachableCode};
+use crate::ipv4::{
+    self, IpInterface, Ipv4Address, Ipv4Endpoint, Ipv4ErrorKind, Ipv4Header, Protocol,
+    IP_ADDRESS_ANY, IP_FLAG_DF, IP_PAYLOAD_SIZE_MAX,
+};
+use crate::net::NetDevice;
+use crate::utils::checksum;
+
+pub const UDP_HEADER_SIZE: usize = 8;
+pub const UDP_PAYLOAD_SIZE_MAX: usize = IP_PAYLOAD_SIZE_MAX as usize - UDP_HEADER_SIZE;
+
+const UDP_PCB_SIZE: usize = 16;
+const UDP_QUEUE_LIMIT: usize = 64;
+
+/// 動的に割り当てるポートの範囲 (RFC 6335)
+const UDP_SOURCE_PORT_MIN: u16 = 49152;
+const UDP_SOURCE_PORT_MAX: u16 = 65535;
+
+/// バイト列の上に被せて UDP ヘッダを読み書きするビュー
+pub struct UdpHeader<T: AsRef<[u8]>> {
+    buffer: T,
+}
+
+impl<T: AsRef<[u8]>> UdpHeader<T> {
+    /// 長さフィールドがバッファに収まっているかまで検査する
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        let len = buffer.as_ref().len();
+        if len < UDP_HEADER_SIZE {
+            return None;
+        }
+        let header = UdpHeader { buffer };
+        let length = header.length() as usize;
+        if length < UDP_HEADER_SIZE || length > len {
+            return None;
+        }
+        Some(header)
+    }
+
+    pub fn src_port(&self) -> u16 {
+        self.read_u16(0)
+    }
+
+    pub fn dst_port(&self) -> u16 {
+        self.read_u16(2)
+    }
+
+    pub fn length(&self) -> u16 {
+        self.read_u16(4)
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.read_u16(6)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[UDP_HEADER_SIZE..self.length() as usize]
+    }
+
+    /// チェックサムが 0 なら送信元が計算していないので検査しない
+    pub fn verify_checksum(&self, src: Ipv4Address, dst: Ipv4Address) -> bool {
+        if self.checksum() == 0 {
+            return true;
+        }
+        let datagram = &self.buffer.as_ref()[..self.length() as usize];
+        let pseudo = ipv4::pseudo_header_sum(src, dst, Protocol::Udp as u8, self.length());
+        checksum(datagram, pseudo) == 0
+    }
+
+    fn read_u16(&self, index: usize) -> u16 {
+        let data = self.buffer.as_ref();
+        u16::from_be_bytes([data[index], data[index + 1]])
+    }
+}
+
+/// チェックサムを埋めたデータグラムを作る
+pub fn build(src: Ipv4Endpoint, dst: Ipv4Endpoint, data: &[u8]) -> Vec<u8> {
+    let length = (UDP_HEADER_SIZE + data.len()) as u16;
+    let mut datagram = Vec::with_capacity(length as usize);
+    datagram.extend_from_slice(&src.port.to_be_bytes());
+    datagram.extend_from_slice(&dst.port.to_be_bytes());
+    datagram.extend_from_slice(&length.to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(data);
+    let pseudo = ipv4::pseudo_header_sum(src.address, dst.address, Protocol::Udp as u8, length);
+    let mut sum = checksum(&datagram, pseudo);
+    // 計算結果が 0 のときは 0xffff を送る (0 は「計算していない」の意味になる)
+    if sum == 0 {
+        sum = 0xffff;
+    }
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    datagram
+}
+
+#[derive(Debug)]
+pub struct UdpError {
+    pub kind: UdpErrorKind,
+}
+
+impl UdpError {
+    pub fn new(kind: UdpErrorKind) -> Self {
+        Self { kind }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UdpErrorKind {
+    NoSpace,
+    InvalidId,
+    AddressInUse,
+    AddressNotAvailable,
+    NoPortAvailable,
+    TooLong,
+    OutputError,
+    Timeout,
+    Closed,
+}
+
+impl fmt::Display for UdpErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            UdpErrorKind::NoSpace => "no pcb space",
+            UdpErrorKind::InvalidId => "invalid id",
+            UdpErrorKind::AddressInUse => "address in use",
+            UdpErrorKind::AddressNotAvailable => "address not available",
+            UdpErrorKind::NoPortAvailable => "no port available",
+            UdpErrorKind::TooLong => "datagram too long",
+            UdpErrorKind::OutputError => "output error",
+            UdpErrorKind::Timeout => "timeout",
+            UdpErrorKind::Closed => "closed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// 受信したデータグラム
+pub struct UdpDatagram {
+    pub data: Vec<u8>,
+    pub foreign: Ipv4Endpoint,
+    /// 受信したパケットの宛先アドレス (ブロードキャストかどうかの判別に使う)
+    pub local: Ipv4Address,
+    pub dev: &'static NetDevice,
+}
+
+struct UdpPcb {
+    local: Ipv4Endpoint,
+    dev: Option<&'static NetDevice>,
+    queue: VecDeque<UdpDatagram>,
+    closed: bool,
+    /// DF を立てて送り、PMTU を超えるものは分割せずに TooLong を返す
+    pmtu_discovery: bool,
+    /// poll_recvfrom で待っているタスク
+    wakers: Vec<Waker>,
+}
+
+impl UdpPcb {
+    fn new() -> Self {
+        UdpPcb {
+            local: Ipv4Endpoint::new(IP_ADDRESS_ANY, 0),
+            dev: None,
+            queue: VecDeque::new(),
+            closed: false,
+            pmtu_discovery: false,
+            wakers: Vec::new(),
+        }
+    }
+
+    fn accepts(&self, address: Ipv4Address, port: u16, dev: &NetDevice) -> bool {
+        if self.closed || self.local.port != port {
+            return false;
+        }
+        if let Some(bound) = self.dev {
+            if !ptr::eq(bound, dev) {
+                return false;
+            }
+        }
+        self.local.address.is_unspecified()
+            || self.local.address == address
+            || ipv4::is_broadcast(address)
+    }
+}
+
+lazy_static! {
+    static ref PCBS: Mutex<Vec<Option<UdpPcb>>> =
+        Mutex::new((0..UDP_PCB_SIZE).map(|_| None).collect());
+    static ref PCB_CONDVAR: Condvar = Condvar::new();
+}
+
+fn pcbs() -> MutexGuard<'static, Vec<Option<UdpPcb>>> {
+    PCBS.lock().unwrap()
+}
+
+fn pcb_mut(pcbs: &mut [Option<UdpPcb>], id: usize) -> Result<&mut UdpPcb, UdpError> {
+    match pcbs.get_mut(id) {
+        Some(Some(pcb)) if !pcb.closed => Ok(pcb),
+        _ => Err(UdpError::new(UdpErrorKind::InvalidId)),
+    }
+}
+
+pub fn open() -> Result<usize, UdpError> {
+    let mut pcbs = pcbs();
+    for (id, entry) in pcbs.iter_mut().enumerate() {
+        if entry.is_none() {
+            *entry = Some(UdpPcb::new());
+            return Ok(id);
+        }
+    }
+    eprintln!("UDP pcb is full");
+    Err(UdpError::new(UdpErrorKind::NoSpace))
+}
+
+pub fn close(id: usize) -> Result<(), UdpError> {
+    let wakers = {
+        let mut pcbs = pcbs();
+        match pcbs.get_mut(id) {
+            Some(entry @ Some(_)) => entry.take().unwrap().wakers,
+            _ => return Err(UdpError::new(UdpErrorKind::InvalidId)),
+        }
+    };
+    // 受信待ちのスレッドやタスクを起こして InvalidId で返させる
+    PCB_CONDVAR.notify_all();
+    for waker in wakers {
+        waker.wake();
+    }
+    Ok(())
+}
+
+pub fn bind(id: usize, local: Ipv4Endpoint) -> Result<(), UdpError> {
+    let mut pcbs = pcbs();
+    let dev = pcb_mut(&mut pcbs, id)?.dev;
+    // 別々のデバイスに固定されたものどうしは同じポートを使える
+    let in_use = pcbs.iter().enumerate().any(|(i, entry)| match entry {
+        Some(pcb) if i != id && local.port != 0 && pcb.local.port == local.port => {
+            let same_device = match (pcb.dev, dev) {
+                (Some(a), Some(b)) => ptr::eq(a, b),
+                _ => true,
+            };
+            same_device
+                && (pcb.local.address.is_unspecified()
+                    || local.address.is_unspecified()
+                    || pcb.local.address == local.address)
+        }
+        _ => false,
+    });
+    if in_use {
+        eprintln!("UDP address in use LOCAL={}", local);
+        return Err(UdpError::new(UdpErrorKind::AddressInUse));
+    }
+    if !local.address.is_unspecified() && IpInterface::select(local.address).is_none() {
+        eprintln!("UDP local address not found LOCAL={}", local);
+        return Err(UdpError::new(UdpErrorKind::AddressNotAvailable));
+    }
+    let pcb = pcb_mut(&mut pcbs, id)?;
+    pcb.local = local;
+    eprintln!("UDP bound ID={} LOCAL={}", id, local);
+    Ok(())
+}
+
+/// 送受信に使うデバイスを固定する (SO_BINDTODEVICE 相当)
+/// 固定すると経路表を使わずにそのデバイスから送る。bind より先に呼ぶ
+pub fn bind_device(id: usize, dev: Option<&'static NetDevice>) -> Result<(), UdpError> {
+    let mut pcbs = pcbs();
+    let pcb = pcb_mut(&mut pcbs, id)?;
+    pcb.dev = dev;
+    Ok(())
+}
+
+/// PMTU 探索を使うかどうか (IP_MTU_DISCOVER 相当)。既定は無効
+pub fn set_pmtu_discovery(id: usize, enable: bool) -> Result<(), UdpError> {
+    let mut pcbs = pcbs();
+    pcb_mut(&mut pcbs, id)?.pmtu_discovery = enable;
+    Ok(())
+}
+
+pub fn local_endpoint(id: usize) -> Result<Ipv4Endpoint, UdpError> {
+    let mut pcbs = pcbs();
+    Ok(pcb_mut(&mut pcbs, id)?.local)
+}
+
+fn ephemeral_port(pcbs: &[Option<UdpPcb>]) -> Option<u16> {
+    (UDP_SOURCE_PORT_MIN..=UDP_SOURCE_PORT_MAX).find(|port| {
+        !pcbs
+            .iter()
+            .any(|entry| matches!(entry, Some(pcb) if pcb.local.port == *port))
+    })
+}
+
+pub fn sendto(id: usize, data: &[u8], foreign: Ipv4Endpoint) -> Result<usize, UdpError> {
+    let (local, dev, pmtu_discovery) = {
+        let mut pcbs = pcbs();
+        let port = match pcb_mut(&mut pcbs, id)?.local.port {
+            0 => match ephemeral_port(&pcbs) {
+                Some(port) => port,
+                None => {
+                    eprintln!("UDP no port available");
+                    return Err(UdpError::new(UdpErrorKind::NoPortAvailable));
+                }
+            },
+            port => port,
+        };
+        let pcb = pcb_mut(&mut pcbs, id)?;
+        pcb.local.port = port;
+        (pcb.local, pcb.dev, pcb.pmtu_discovery)
+    };
+    let flags = if pmtu_discovery { IP_FLAG_DF } else { 0 };
+    output_with_flags(local, foreign, data, dev, flags)
+}
+
+/// timeout が None なら届くまで待つ
+pub fn recvfrom(id: usize, timeout: Option<Duration>) -> Result<UdpDatagram, UdpError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut pcbs = pcbs();
+    loop {
+        let pcb = pcb_mut(&mut pcbs, id)?;
+        if let Some(datagram) = pcb.queue.pop_front() {
+            return Ok(datagram);
+        }
+        pcbs = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(UdpError::new(UdpErrorKind::Timeout));
+                }
+                PCB_CONDVAR.wait_timeout(pcbs, deadline - now).unwrap().0
+            }
+            None => PCB_CONDVAR.wait(pcbs).unwrap(),
+        };
+    }
+}
+
+/// 届いていなければ waker を登録して Pending を返す。input で起こす
+pub fn poll_recvfrom(id: usize, cx: &mut Context<'_>) -> Poll<Result<UdpDatagram, UdpError>> {
+    let mut pcbs = pcbs();
+    let pcb = match pcb_mut(&mut pcbs, id) {
+        Ok(pcb) => pcb,
+        Err(err) => return Poll::Ready(Err(err)),
+    };
+    if let Some(datagram) = pcb.queue.pop_front() {
+        return Poll::Ready(Ok(datagram));
+    }
+    if !pcb.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+        pcb.wakers.push(cx.waker().clone());
+    }
+    Poll::Pending
+}
+
+/// dev が指定されていれば経路表を使わずにそのデバイスから送る
+pub fn output(
+    local: Ipv4Endpoint,
+    foreign: Ipv4Endpoint,
+    data: &[u8],
+    dev: Option<&NetDevice>,
+) -> Result<usize, UdpError> {
+    output_with_flags(local, foreign, data, dev, 0)
+}
+
+/// IP ヘッダのフラグを指定する output。IP_FLAG_DF を立てて PMTU を超えたら TooLong を返す
+pub fn output_with_flags(
+    local: Ipv4Endpoint,
+    foreign: Ipv4Endpoint,
+    data: &[u8],
+    dev: Option<&NetDevice>,
+    flags: u16,
+) -> Result<usize, UdpError> {
+    if data.len() > UDP_PAYLOAD_SIZE_MAX {
+        return Err(UdpError::new(UdpErrorKind::TooLong));
+    }
+    // チェックサムの計算には実際に使う送信元アドレスが要る
+    let src = if local.address.is_unspecified() && dev.is_none() {
+        match ipv4::route::lookup(foreign.address) {
+            Some(route) => route.interface.unicast,
+            None => match IpInterface::select_source(foreign.address) {
+                Some(interface) => interface.unicast,
+                None => {
+                    eprintln!("UDP no route DST={}", foreign);
+                    return Err(UdpError::new(UdpErrorKind::OutputError));
+                }
+            },
+        }
+    } else {
+        local.address
+    };
+    let datagram = build(Ipv4Endpoint::new(src, local.port), foreign, data);
+    eprintln!(
+        "UDP output SRC={}:{} DST={} LEN={}",
+        src,
+        local.port,
+        foreign,
+        data.len()
+    );
+    let result = match dev {
+        Some(dev) => ipv4::output_device(
+            dev,
+            Protocol::Udp as u8,
+            &datagram,
+            src,
+            foreign.address,
+            &[],
+            flags,
+        ),
+        None => ipv4::output_with_flags(
+            Protocol::Udp as u8,
+            &datagram,
+            src,
+            foreign.address,
+            &[],
+            flags,
+        ),
+    };
+    match result {
+        Ok(_) => Ok(data.len()),
+        Err(e) if e.kind == Ipv4ErrorKind::TooLong => Err(UdpError::new(UdpErrorKind::TooLong)),
+        Err(_) => Err(UdpError::new(UdpErrorKind::OutputError)),
+    }
+}
+
+pub fn input(packet: &[u8], dev: &'static NetDevice) {
+    let ipv4_hdr = Ipv4Header::new_unchecked(packet);
+    let src = ipv4_hdr.src_address();
+    let dst = ipv4_hdr.dst_address();
+    let udp_hdr = match UdpHeader::new_checked(ipv4_hdr.payload()) {
+        Some(hdr) => hdr,
+        None => {
+            eprintln!("UDP length error");
+            return;
+        }
+    };
+    if !udp_hdr.verify_checksum(src, dst) {
+        eprintln!("UDP checksum error");
+        return;
+    }
+    eprintln!(
+        "UDP input DEV={} SRC={}:{} DST={}:{} LEN={}",
+        dev.name,
+        src,
+        udp_hdr.src_port(),
+        dst,
+        udp_hdr.dst_port(),
+        udp_hdr.payload().len()
+    );
+
+    let wakers = {
+        let mut pcbs = pcbs();
+        let pcb = pcbs
+            .iter_mut()
+            .flatten()
+            .find(|pcb| pcb.accepts(dst, udp_hdr.dst_port(), dev));
+        match pcb {
+            Some(pcb) => {
+                if pcb.queue.len() >= UDP_QUEUE_LIMIT {
+                    eprintln!("UDP queue is full PORT={}", udp_hdr.dst_port());
+                    return;
+                }
+                pcb.queue.push_back(UdpDatagram {
+                    data: udp_hdr.payload().to_vec(),
+                    foreign: Ipv4Endpoint::new(src, udp_hdr.src_port()),
+                    local: dst,
+                    dev,
+                });
+                PCB_CONDVAR.notify_all();
+                Some(std::mem::take(&mut pcb.wakers))
+            }
+            None => None,
+        }
+    };
+    // ロックを外してから起こす
+    if let Some(wakers) = wakers {
+        for waker in wakers {
+            waker.wake();
+        }
+        return;
+    }
+    icmp::destination_unreachable(UnreachableCode::Port, packet);
+}
+
+pub fn init() {
+    if ipv4::protocol_register(Protocol::Udp as u8, input).is_err() {
+        eprintln!("UDP is already registered");
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Instant;
+
 pub fn checksum16(address: *const u16, header_count: u16, init: u32) -> u16 {
     let mut sum = init as u32;
     let mut count = header_count;
@@ -18,3 +20,55 @@ pub fn checksum16(address: *const u16, header_count: u16, init: u32) -> u16 {
 
     !(sum as u16)
 }
+
+/// インターネットチェックサム (RFC 1071)
+/// data をビッグエンディアンの 16bit 列として 1 の補数和をとり、その補数をホストバイトオーダーで返す
+/// 書き込むときは to_be_bytes で格納し、検証するときは結果が 0 になることを確認する
+pub fn checksum(data: &[u8], init: u32) -> u16 {
+    let mut sum = init as u64;
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u64;
+    }
+    if let [last] = chunks.remainder() {
+        sum += u16::from_be_bytes([*last, 0]) as u64;
+    }
+
+    while (sum >> 16) > 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+/// 送出を制限するトークンバケット。毎秒 rate 個ずつ溜まり、最大 burst 個まで続けて取れる
+pub struct RateLimiter {
+    rate: u32,
+    burst: u32,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32, burst: u32) -> Self {
+        RateLimiter {
+            rate,
+            burst,
+            tokens: burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// トークンを 1 つ取る。無ければ false
+    pub fn acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst as f64);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
@@ -0,0 +1,85 @@
+use std::slice;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use rustic_stack::ethernet::{MacAddress, MAC_LENGTH};
+use rustic_stack::ipv4::IpInterface;
+use rustic_stack::net::{NetDevice, NetDeviceFlag};
+
+/// デバイス名と送ったフレームと、指定されていればリンク層の宛先
+type Captured = (String, Vec<u8>, Option<MacAddress>);
+
+static CAPTURED: Mutex<Vec<Captured>> = Mutex::new(Vec::new());
+
+fn transmit(dev: &NetDevice, _protocol_type: u16, data: &[u8], dst: *mut u8) -> isize {
+    let hwaddr = if dst.is_null() {
+        None
+    } else {
+        let mut octets = [0; MAC_LENGTH];
+        octets.copy_from_slice(unsafe { slice::from_raw_parts(dst, MAC_LENGTH) });
+        Some(MacAddress::from(octets))
+    };
+    CAPTURED
+        .lock()
+        .unwrap()
+        .push((dev.name.clone(), data.to_vec(), hwaddr));
+    data.len() as isize
+}
+
+/// 送ったフレームを記録するだけのデバイスを作る。登録前にハードウェアアドレスなどを変えられる
+pub fn alloc(name: &str) -> Box<NetDevice> {
+    let mut dev = NetDevice::alloc();
+    dev.name = String::from(name);
+    dev.mtu = 1500;
+    dev.flags = NetDeviceFlag::Up as u16;
+    dev.ops.transmit = Some(transmit);
+    dev
+}
+
+pub fn register(dev: Box<NetDevice>) -> &'static NetDevice {
+    let dev: &'static Box<NetDevice> = Box::leak(Box::new(dev));
+    NetDevice::register(dev);
+    dev
+}
+
+/// alloc したデバイスに network のアドレスを付けて登録する
+/// テストどうしで混ざらないように name と network はテストごとに変える
+pub fn device(name: &str, network: &str) -> &'static NetDevice {
+    let dev = register(alloc(name));
+    IpInterface::register(IpInterface::alloc_cidr(network).unwrap(), dev).unwrap();
+    dev
+}
+
+/// dev から送られたフレームを取り出す
+pub fn take(dev: &NetDevice) -> Vec<Vec<u8>> {
+    take_with_hwaddr(dev)
+        .into_iter()
+        .map(|(data, _)| data)
+        .collect()
+}
+
+/// dev から送られたフレームをリンク層の宛先と一緒に取り出す
+pub fn take_with_hwaddr(dev: &NetDevice) -> Vec<(Vec<u8>, Option<MacAddress>)> {
+    let mut captured = CAPTURED.lock().unwrap();
+    let (taken, rest) = captured
+        .drain(..)
+        .partition::<Vec<_>, _>(|(name, _, _)| *name == dev.name);
+    *captured = rest;
+    taken
+        .into_iter()
+        .map(|(_, data, hwaddr)| (data, hwaddr))
+        .collect()
+}
+
+/// dev からフレームが送られるまで timeout だけ待って取り出す
+pub fn wait(dev: &NetDevice, timeout: Duration) -> Vec<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let frames = take(dev);
+        if !frames.is_empty() || Instant::now() >= deadline {
+            return frames;
+        }
+        sleep(Duration::from_millis(10));
+    }
+}
@@ -1,62 +1,57 @@
+use std::ptr;
+use std::sync::Mutex;
 use std::thread::sleep;
 use std::time::Duration;
 
 use rustic_stack::device::loopback::Loopback;
-use rustic_stack::ipv4::IpInterface;
-use rustic_stack::net::{
-    net_init, net_run, net_shutdown, NetDeviceType, NetProtocolType, NET_DEVICES,
-};
+use rustic_stack::net::{NetDevice, NetProtocol};
 
-const LOOPBACK_IP_ADDRESS: &str = "127.0.0.1";
-const LOOPBACK_IP_NETMASK: &str = "255.0.0.0";
+/// IPv4 のハンドラは net_init が登録するので、IEEE 802 の実験用 EtherType で受け取る
+const TEST_PROTOCOL_TYPE: u16 = 0x88b5;
 
-#[test]
-fn loopback() {
-    net_init();
+static RECEIVED: Mutex<Vec<(String, Vec<u8>)>> = Mutex::new(Vec::new());
 
-    let mut loopback_dev = Loopback::init();
+fn handler(data: &Vec<u8>, dev: &'static NetDevice) {
+    RECEIVED
+        .lock()
+        .unwrap()
+        .push((dev.name.clone(), data.clone()));
+}
 
-    let interface = IpInterface::alloc(LOOPBACK_IP_ADDRESS, LOOPBACK_IP_NETMASK);
-    if let None = interface {
-        panic!("IpInterface::alloc is failed");
-    }
-    let interface = IpInterface::register(interface.unwrap(), &loopback_dev);
-
-    let _ = net_run();
-
-    for _ in 0..3 {
-        let test_value = 0x32;
-        const TEST_COUNT: usize = 8;
-        let test_data: [u8; TEST_COUNT] = [test_value; TEST_COUNT];
-        let mut test_dst: [u8; TEST_COUNT] = [0; TEST_COUNT];
-        {
-            let mut net_devices = NET_DEVICES.lock();
-            for dev in net_devices.items.iter_mut() {
-                if NetDeviceType::from_u16(dev.device_type) == NetDeviceType::Loopback {
-                    let r = dev.output(
-                        NetDeviceType::Loopback as u16 & NetProtocolType::Ip as u16,
-                        &test_data as *const [u8] as *const u8,
-                        TEST_COUNT,
-                        &mut test_dst as *mut [u8] as *mut u8,
-                    );
-                    match r {
-                        Ok(_) => {
-                            for i in test_dst.iter() {
-                                if *i != test_value {
-                                    panic!("loopback is invalid! VALUE={}", i);
-                                }
-                            }
-                            println!("loopback device output");
-                        }
-                        Err(_) => {
-                            panic!("loopback device output error");
-                        }
-                    }
-                }
-            }
-        }
+fn open() -> &'static NetDevice {
+    let mut dev = Loopback::new();
+    assert!(dev.open().is_ok());
+    Box::leak(dev)
+}
 
-        sleep(Duration::from_secs(1));
+#[test]
+fn loopback() {
+    let _ = NetProtocol::register(TEST_PROTOCOL_TYPE, handler);
+    let a = open();
+    let b = open();
+    assert_ne!(a.name, b.name);
+
+    const TEST_COUNT: usize = 8;
+    let test_data: [u8; TEST_COUNT] = [0x32; TEST_COUNT];
+    assert!(a
+        .output(TEST_PROTOCOL_TYPE, &test_data, ptr::null_mut(),)
+        .is_ok());
+
+    // 別の loopback デバイスのキューからは取り出さない
+    assert_eq!(Loopback::poll(b), -1);
+    assert_eq!(Loopback::poll(a), TEST_COUNT as isize);
+    assert_eq!(Loopback::poll(a), -1);
+
+    // 他のテストの net_thread が先に渡していることもあるので少し待つ
+    for _ in 0..100 {
+        NetProtocol::poll();
+        if !RECEIVED.lock().unwrap().is_empty() {
+            break;
+        }
+        sleep(Duration::from_millis(10));
     }
-    let _ = net_shutdown();
+    assert_eq!(
+        RECEIVED.lock().unwrap().as_slice(),
+        [(a.name.clone(), test_data.to_vec())]
+    );
 }
@@ -25,8 +25,7 @@ fn null() {
                 if NetDeviceType::from_u16(dev.device_type) == NetDeviceType::Null {
                     let r = dev.output(
                         NetDeviceType::Null as u16 & NetProtocolType::Ip as u16,
-                        &test_data as *const [u8] as *const u8,
-                        TEST_COUNT,
+                        &test_data,
                         &test_dst as *const [u8] as *mut u8,
                     );
                     match r {
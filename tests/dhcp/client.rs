@@ -0,0 +1,243 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use rustic_stack::dhcp::client::DhcpState;
+use rustic_stack::dhcp::{
+    DhcpClient, DhcpClientHandle, DhcpMessage, DhcpMessageType, DhcpOption, DHCP_CLIENT_PORT,
+    DHCP_OP_BOOTREPLY, DHCP_SERVER_PORT,
+};
+use rustic_stack::dns::resolver;
+use rustic_stack::ipv4::{
+    self, route, IpInterface, Ipv4Address, Ipv4Endpoint, Ipv4Header, Ipv4HeaderBuilder,
+    Ipv4Network, Protocol, IP_ADDRESS_ANY, IP_ADDRESS_BROADCAST,
+};
+use rustic_stack::net::NetDevice;
+use rustic_stack::udp::{self, UdpHeader};
+
+use crate::{capture, dns};
+
+const CLIENT: [u8; 6] = [0x00, 0x00, 0x5e, 0x00, 0x53, 0x05];
+const SERVER: Ipv4Address = Ipv4Address::new(198, 18, 5, 1);
+const ADDRESS: Ipv4Address = Ipv4Address::new(198, 18, 5, 10);
+const OTHER_ADDRESS: Ipv4Address = Ipv4Address::new(198, 18, 5, 11);
+const GATEWAY: Ipv4Address = Ipv4Address::new(198, 18, 5, 254);
+const DNS_SERVER: Ipv4Address = Ipv4Address::new(198, 18, 5, 53);
+/// どのインターフェースのサブネットにも入らない宛先
+const OFF_LINK: Ipv4Address = Ipv4Address::new(192, 88, 99, 1);
+
+/// クライアントが次に送った DHCP メッセージと IP の宛先
+fn sent(dev: &NetDevice, timeout: Duration) -> (DhcpMessage, Ipv4Address) {
+    let frames = capture::wait(dev, timeout);
+    assert!(!frames.is_empty(), "no DHCP message within {:?}", timeout);
+    let header = Ipv4Header::new_checked(&frames[0][..]).unwrap();
+    assert_eq!(header.protocol_number(), Protocol::Udp as u8);
+    let datagram = UdpHeader::new_checked(header.payload()).unwrap();
+    assert_eq!(datagram.dst_port(), DHCP_SERVER_PORT);
+    let message = DhcpMessage::parse(datagram.payload()).unwrap();
+    (message, header.dst_address())
+}
+
+fn reply(
+    request: &DhcpMessage,
+    message_type: DhcpMessageType,
+    options: Vec<DhcpOption>,
+) -> DhcpMessage {
+    let mut reply = DhcpMessage::request(message_type, request.xid, &CLIENT);
+    reply.op = DHCP_OP_BOOTREPLY;
+    if message_type != DhcpMessageType::Nak {
+        reply.yiaddr = ADDRESS;
+        reply
+            .options
+            .push(DhcpOption::SubnetMask(Ipv4Address::new(255, 255, 255, 0)));
+    }
+    reply.options.push(DhcpOption::ServerIdentifier(SERVER));
+    reply.options.extend(options);
+    reply
+}
+
+/// サーバからのブロードキャストとして受信させる
+fn receive(dev: &'static NetDevice, message: &DhcpMessage) {
+    let src = Ipv4Endpoint::new(SERVER, DHCP_SERVER_PORT);
+    let dst = Ipv4Endpoint::new(IP_ADDRESS_BROADCAST, DHCP_CLIENT_PORT);
+    let datagram = udp::build(src, dst, &message.encode());
+    let packet = Ipv4HeaderBuilder::new(Protocol::Udp as u8, SERVER, IP_ADDRESS_BROADCAST)
+        .build(&datagram)
+        .unwrap();
+    ipv4::input(&packet, dev);
+}
+
+/// DISCOVER から ACK までを済ませて BOUND にする
+fn acquire(dev: &'static NetDevice, options: Vec<DhcpOption>) {
+    let (discover, dst) = sent(dev, Duration::from_secs(2));
+    assert_eq!(discover.message_type(), Some(DhcpMessageType::Discover));
+    assert_eq!(dst, IP_ADDRESS_BROADCAST);
+    receive(
+        dev,
+        &reply(&discover, DhcpMessageType::Offer, options.clone()),
+    );
+
+    let (request, dst) = sent(dev, Duration::from_secs(2));
+    assert_eq!(request.message_type(), Some(DhcpMessageType::Request));
+    assert_eq!(dst, IP_ADDRESS_BROADCAST);
+    assert_eq!(request.requested_address(), Some(ADDRESS));
+    assert_eq!(request.server_identifier(), Some(SERVER));
+
+    // 頼んだのと違うアドレスの ACK は受け取らない
+    let mut other = reply(&request, DhcpMessageType::Ack, options.clone());
+    other.yiaddr = OTHER_ADDRESS;
+    receive(dev, &other);
+    sleep(Duration::from_millis(100));
+    assert!(IpInterface::select(OTHER_ADDRESS).is_none());
+
+    receive(dev, &reply(&request, DhcpMessageType::Ack, options));
+}
+
+fn wait_state(handle: &DhcpClientHandle, state: DhcpState) {
+    for _ in 0..200 {
+        if handle.state() == state {
+            return;
+        }
+        sleep(Duration::from_millis(10));
+    }
+    assert_eq!(handle.state(), state);
+}
+
+// デフォルトゲートウェイは全体で 1 つなので、状態遷移を 1 つのテストの中で順に追う
+#[test]
+fn renew_rebind_nak_and_release() {
+    let _servers = dns::SERVERS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    udp::init();
+    let mut dev = capture::alloc("dhcpc0");
+    dev.hwaddr[..CLIENT.len()].copy_from_slice(&CLIENT);
+    dev.address_length = CLIENT.len() as u16;
+    let dev = capture::register(dev);
+
+    let handle = DhcpClient::new(dev).start().ok().unwrap();
+    acquire(
+        dev,
+        vec![
+            DhcpOption::LeaseTime(3),
+            DhcpOption::RenewalTime(1),
+            DhcpOption::RebindingTime(2),
+        ],
+    );
+    wait_state(&handle, DhcpState::Bound);
+    assert_eq!(handle.lease().unwrap().address, ADDRESS);
+    assert!(IpInterface::select(ADDRESS).is_some());
+    assert!(route::lookup(OFF_LINK).is_none());
+
+    // T1 になったらリースを得たサーバへ ciaddr を埋めてユニキャストで頼む
+    let (renew, dst) = sent(dev, Duration::from_secs(3));
+    assert_eq!(renew.message_type(), Some(DhcpMessageType::Request));
+    assert_eq!(dst, SERVER);
+    assert_eq!(renew.ciaddr, ADDRESS);
+    assert_eq!(renew.server_identifier(), None);
+    assert_eq!(handle.state(), DhcpState::Renewing);
+
+    // 応答が無いまま T2 になったらブロードキャストで頼む
+    let (rebind, dst) = sent(dev, Duration::from_secs(3));
+    assert_eq!(rebind.message_type(), Some(DhcpMessageType::Request));
+    assert_eq!(dst, IP_ADDRESS_BROADCAST);
+    assert_eq!(rebind.ciaddr, ADDRESS);
+    assert_ne!(rebind.xid, renew.xid);
+    assert_eq!(handle.state(), DhcpState::Rebinding);
+
+    // ACK で BOUND に戻り、ゲートウェイと DNS サーバが付いたのでデフォルト経路と DNS サーバを設定する
+    // T1 を 0 にしてすぐ次の延長を頼ませる
+    receive(
+        dev,
+        &reply(
+            &rebind,
+            DhcpMessageType::Ack,
+            vec![
+                DhcpOption::Router(vec![GATEWAY]),
+                DhcpOption::DnsServer(vec![DNS_SERVER]),
+                DhcpOption::LeaseTime(3),
+                DhcpOption::RenewalTime(0),
+                DhcpOption::RebindingTime(1),
+            ],
+        ),
+    );
+    let (renew, dst) = sent(dev, Duration::from_secs(2));
+    assert_eq!(renew.message_type(), Some(DhcpMessageType::Request));
+    assert_eq!(dst, SERVER);
+    assert_eq!(handle.lease().unwrap().gateway, Some(GATEWAY));
+    assert_eq!(route::lookup(OFF_LINK).unwrap().nexthop, Some(GATEWAY));
+    assert_eq!(resolver::servers(), vec![DNS_SERVER]);
+
+    // NAK ならインターフェースと自分で入れた設定を外して INIT からやり直す
+    receive(dev, &reply(&renew, DhcpMessageType::Nak, vec![]));
+    let (discover, _) = sent(dev, Duration::from_secs(2));
+    assert_eq!(discover.message_type(), Some(DhcpMessageType::Discover));
+    assert_eq!(discover.ciaddr, IP_ADDRESS_ANY);
+    assert!(handle.lease().is_none());
+    assert!(IpInterface::select(ADDRESS).is_none());
+    assert!(route::lookup(OFF_LINK).is_none());
+    assert!(resolver::servers().is_empty());
+
+    // 後から別のデフォルト経路に置き換えられていたら、それは外さない
+    receive(
+        dev,
+        &reply(
+            &discover,
+            DhcpMessageType::Offer,
+            vec![DhcpOption::Router(vec![GATEWAY])],
+        ),
+    );
+    let (request, _) = sent(dev, Duration::from_secs(2));
+    receive(
+        dev,
+        &reply(
+            &request,
+            DhcpMessageType::Ack,
+            vec![
+                DhcpOption::Router(vec![GATEWAY]),
+                DhcpOption::LeaseTime(3),
+                DhcpOption::RenewalTime(0),
+                DhcpOption::RebindingTime(1),
+            ],
+        ),
+    );
+    let (renew, _) = sent(dev, Duration::from_secs(2));
+    let other = capture::device("dhcpc1", "198.18.13.1/24");
+    let other_gateway = Ipv4Address::new(198, 18, 13, 254);
+    route::set_default_gateway(other_gateway, Ipv4Address::new(198, 18, 13, 1)).unwrap();
+    receive(dev, &reply(&renew, DhcpMessageType::Nak, vec![]));
+    let (discover, _) = sent(dev, Duration::from_secs(2));
+    assert!(IpInterface::select(ADDRESS).is_none());
+    assert_eq!(
+        route::lookup(OFF_LINK).unwrap().nexthop,
+        Some(other_gateway)
+    );
+    route::delete(Ipv4Network::new(IP_ADDRESS_ANY, 0).unwrap());
+    assert!(capture::take(other).is_empty());
+
+    // 取り直したリースを release で返す
+    receive(
+        dev,
+        &reply(
+            &discover,
+            DhcpMessageType::Offer,
+            vec![DhcpOption::LeaseTime(600)],
+        ),
+    );
+    let (request, _) = sent(dev, Duration::from_secs(2));
+    receive(
+        dev,
+        &reply(
+            &request,
+            DhcpMessageType::Ack,
+            vec![DhcpOption::LeaseTime(600)],
+        ),
+    );
+    wait_state(&handle, DhcpState::Bound);
+    assert!(IpInterface::select(ADDRESS).is_some());
+
+    handle.release();
+    let (release, dst) = sent(dev, Duration::from_secs(1));
+    assert_eq!(release.message_type(), Some(DhcpMessageType::Release));
+    assert_eq!(dst, SERVER);
+    assert_eq!(release.ciaddr, ADDRESS);
+    assert_eq!(release.server_identifier(), Some(SERVER));
+    assert!(IpInterface::select(ADDRESS).is_none());
+}
@@ -0,0 +1,100 @@
+mod client;
+mod server;
+
+use std::time::{Duration, Instant};
+
+use rustic_stack::dhcp::{
+    DhcpErrorKind, DhcpLease, DhcpMessage, DhcpMessageType, DhcpOption, DHCP_FLAG_BROADCAST,
+    DHCP_MESSAGE_SIZE_MIN, DHCP_OP_BOOTREPLY,
+};
+use rustic_stack::ipv4::Ipv4Address;
+
+const HWADDR: [u8; 6] = [0x00, 0x00, 0x5e, 0x00, 0x53, 0x01];
+
+fn ack() -> DhcpMessage {
+    let mut ack = DhcpMessage::request(DhcpMessageType::Ack, 0x1234_5678, &HWADDR);
+    ack.op = DHCP_OP_BOOTREPLY;
+    ack.yiaddr = Ipv4Address::new(192, 0, 2, 10);
+    ack.options.extend(vec![
+        DhcpOption::ServerIdentifier(Ipv4Address::new(192, 0, 2, 1)),
+        DhcpOption::SubnetMask(Ipv4Address::new(255, 255, 255, 0)),
+        DhcpOption::Router(vec![Ipv4Address::new(192, 0, 2, 1)]),
+        DhcpOption::DnsServer(vec![
+            Ipv4Address::new(192, 0, 2, 53),
+            Ipv4Address::new(198, 51, 100, 53),
+        ]),
+        DhcpOption::LeaseTime(3600),
+    ]);
+    ack
+}
+
+#[test]
+fn encode_and_parse() {
+    let mut discover = DhcpMessage::request(DhcpMessageType::Discover, 0xdead_beef, &HWADDR);
+    discover.flags = DHCP_FLAG_BROADCAST;
+    discover
+        .options
+        .push(DhcpOption::ParameterRequestList(vec![1, 3, 6]));
+    discover
+        .options
+        .push(DhcpOption::HostName(String::from("rustic")));
+    let data = discover.encode();
+    assert_eq!(data.len(), DHCP_MESSAGE_SIZE_MIN);
+
+    let parsed = DhcpMessage::parse(&data).unwrap();
+    assert_eq!(parsed, discover);
+    assert_eq!(parsed.hwaddr(), &HWADDR);
+    assert_eq!(parsed.message_type(), Some(DhcpMessageType::Discover));
+}
+
+#[test]
+fn parse_errors() {
+    let data = ack().encode();
+    assert_eq!(
+        DhcpMessage::parse(&data[..200]).unwrap_err().kind,
+        DhcpErrorKind::Truncated
+    );
+
+    let mut bad_cookie = data.clone();
+    bad_cookie[236] = 0;
+    assert_eq!(
+        DhcpMessage::parse(&bad_cookie).unwrap_err().kind,
+        DhcpErrorKind::InvalidCookie
+    );
+
+    // サブネットマスクの長さが 4 でない
+    let mut bad_option = data[..240].to_vec();
+    bad_option.extend_from_slice(&[53, 1, 5, 1, 3, 255, 255, 255, 255]);
+    assert_eq!(
+        DhcpMessage::parse(&bad_option).unwrap_err().kind,
+        DhcpErrorKind::InvalidOption
+    );
+
+    let mut no_type = data[..240].to_vec();
+    no_type.push(255);
+    assert_eq!(
+        DhcpMessage::parse(&no_type).unwrap_err().kind,
+        DhcpErrorKind::MissingMessageType
+    );
+}
+
+#[test]
+fn lease_from_ack() {
+    let now = Instant::now();
+    let ack = DhcpMessage::parse(&ack().encode()).unwrap();
+    let lease = DhcpLease::from_ack(&ack, Ipv4Address::new(192, 0, 2, 1), now).unwrap();
+    assert_eq!(lease.address, Ipv4Address::new(192, 0, 2, 10));
+    assert_eq!(lease.gateway, Some(Ipv4Address::new(192, 0, 2, 1)));
+    assert_eq!(lease.dns_servers.len(), 2);
+    assert_eq!(lease.network().unwrap().to_string(), "192.0.2.10/24");
+    // T1/T2 が無いときは 1/2 と 7/8
+    assert_eq!(lease.renewal_time, Duration::from_secs(1800));
+    assert_eq!(lease.rebinding_time, Duration::from_secs(3150));
+    assert_eq!(lease.expiry(), now + Duration::from_secs(3600));
+
+    let mut no_lease = ack.clone();
+    no_lease
+        .options
+        .retain(|o| !matches!(o, DhcpOption::LeaseTime(_)));
+    assert!(DhcpLease::from_ack(&no_lease, Ipv4Address::new(192, 0, 2, 1), now).is_none());
+}
@@ -0,0 +1,208 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use rustic_stack::dhcp::server::{load_leases, save_leases, DhcpLeaseState};
+use rustic_stack::dhcp::{
+    DhcpMessage, DhcpMessageType, DhcpOption, DhcpServer, DhcpServerLease, DHCP_CLIENT_PORT,
+    DHCP_OP_BOOTREPLY,
+};
+use rustic_stack::ipv4::{Ipv4Address, Ipv4Network, IP_ADDRESS_BROADCAST};
+
+const CLIENT1: [u8; 6] = [0x00, 0x00, 0x5e, 0x00, 0x53, 0x01];
+const CLIENT2: [u8; 6] = [0x00, 0x00, 0x5e, 0x00, 0x53, 0x02];
+
+fn server() -> DhcpServer {
+    DhcpServer::new(Ipv4Network::from_str("192.0.2.1/24").unwrap())
+        .pool(
+            Ipv4Address::new(192, 0, 2, 100),
+            Ipv4Address::new(192, 0, 2, 101),
+        )
+        .router(Ipv4Address::new(192, 0, 2, 1))
+        .lease_time(Duration::from_secs(600))
+}
+
+fn request(hwaddr: &[u8], requested: Ipv4Address) -> DhcpMessage {
+    let mut request = DhcpMessage::request(DhcpMessageType::Request, 1, hwaddr);
+    request
+        .options
+        .push(DhcpOption::RequestedAddress(requested));
+    request
+        .options
+        .push(DhcpOption::ServerIdentifier(Ipv4Address::new(192, 0, 2, 1)));
+    request
+}
+
+#[test]
+fn discover_and_request() {
+    let mut server = server();
+    let discover = DhcpMessage::request(DhcpMessageType::Discover, 1, &CLIENT1);
+    let (offer, dst) = server.process(&discover).unwrap();
+    assert_eq!(offer.op, DHCP_OP_BOOTREPLY);
+    assert_eq!(offer.message_type(), Some(DhcpMessageType::Offer));
+    assert_eq!(offer.yiaddr, Ipv4Address::new(192, 0, 2, 100));
+    assert_eq!(offer.lease_time(), Some(600));
+    assert_eq!(offer.routers(), vec![Ipv4Address::new(192, 0, 2, 1)]);
+    assert_eq!(dst.address, IP_ADDRESS_BROADCAST);
+    assert_eq!(dst.port, DHCP_CLIENT_PORT);
+
+    // OFFER 中のアドレスは他のクライアントに出さない
+    let discover = DhcpMessage::request(DhcpMessageType::Discover, 2, &CLIENT2);
+    let (offer2, _) = server.process(&discover).unwrap();
+    assert_eq!(offer2.yiaddr, Ipv4Address::new(192, 0, 2, 101));
+
+    let (ack, _) = server.process(&request(&CLIENT1, offer.yiaddr)).unwrap();
+    assert_eq!(ack.message_type(), Some(DhcpMessageType::Ack));
+    assert_eq!(ack.yiaddr, offer.yiaddr);
+    assert!(server
+        .leases()
+        .iter()
+        .any(|l| l.address == offer.yiaddr && l.state == DhcpLeaseState::Bound));
+
+    // 他人のアドレスを要求すると NAK
+    let (nak, _) = server.process(&request(&CLIENT2, offer.yiaddr)).unwrap();
+    assert_eq!(nak.message_type(), Some(DhcpMessageType::Nak));
+
+    // プールが尽きていれば OFFER しない
+    let discover = DhcpMessage::request(DhcpMessageType::Discover, 3, &[0, 1, 2, 3, 4, 5]);
+    assert!(server.process(&discover).is_none());
+
+    // 他のサーバ宛ての RELEASE ではリースを消さない
+    let release = |server_id| {
+        let mut release = DhcpMessage::request(DhcpMessageType::Release, 4, &CLIENT1);
+        release.ciaddr = offer.yiaddr;
+        release
+            .options
+            .push(DhcpOption::ServerIdentifier(server_id));
+        release
+    };
+    assert!(server
+        .process(&release(Ipv4Address::new(192, 0, 2, 2)))
+        .is_none());
+    assert!(server.leases().iter().any(|l| l.address == offer.yiaddr));
+    let server_id = server.server_address();
+    assert!(server.process(&release(server_id)).is_none());
+    assert!(!server.leases().iter().any(|l| l.address == offer.yiaddr));
+}
+
+#[test]
+fn reservation() {
+    let reserved = Ipv4Address::new(192, 0, 2, 50);
+    let mut server = server().reserve(&CLIENT2, reserved);
+    let discover = DhcpMessage::request(DhcpMessageType::Discover, 1, &CLIENT2);
+    let (offer, _) = server.process(&discover).unwrap();
+    assert_eq!(offer.yiaddr, reserved);
+
+    // 予約されたアドレスは他のクライアントには渡さない
+    let (nak, _) = server.process(&request(&CLIENT1, reserved)).unwrap();
+    assert_eq!(nak.message_type(), Some(DhcpMessageType::Nak));
+    let (ack, _) = server.process(&request(&CLIENT2, reserved)).unwrap();
+    assert_eq!(ack.message_type(), Some(DhcpMessageType::Ack));
+
+    // 同じアドレスを予約された別のクライアントにも、貸している間は渡さない
+    let mut server = server.reserve(&CLIENT1, reserved);
+    let discover = DhcpMessage::request(DhcpMessageType::Discover, 2, &CLIENT1);
+    assert!(server.process(&discover).is_none());
+    let (nak, _) = server.process(&request(&CLIENT1, reserved)).unwrap();
+    assert_eq!(nak.message_type(), Some(DhcpMessageType::Nak));
+
+    // DECLINE されたら予約したクライアントにもしばらく渡さない
+    let mut decline = DhcpMessage::request(DhcpMessageType::Decline, 3, &CLIENT2);
+    decline.options.push(DhcpOption::RequestedAddress(reserved));
+    decline
+        .options
+        .push(DhcpOption::ServerIdentifier(server.server_address()));
+    assert!(server.process(&decline).is_none());
+    let discover = DhcpMessage::request(DhcpMessageType::Discover, 4, &CLIENT2);
+    assert!(server.process(&discover).is_none());
+    let (nak, _) = server.process(&request(&CLIENT2, reserved)).unwrap();
+    assert_eq!(nak.message_type(), Some(DhcpMessageType::Nak));
+}
+
+#[test]
+fn init_reboot() {
+    let mut server = server();
+    let address = Ipv4Address::new(192, 0, 2, 100);
+    let init_reboot = |hwaddr: &[u8], address| {
+        let mut request = request(hwaddr, address);
+        request
+            .options
+            .retain(|o| !matches!(o, DhcpOption::ServerIdentifier(_)));
+        request
+    };
+    // 記録の無いクライアントには応えない
+    assert!(server.process(&init_reboot(&CLIENT1, address)).is_none());
+
+    let (ack, _) = server.process(&request(&CLIENT1, address)).unwrap();
+    assert_eq!(ack.message_type(), Some(DhcpMessageType::Ack));
+    let (ack, _) = server.process(&init_reboot(&CLIENT1, address)).unwrap();
+    assert_eq!(ack.message_type(), Some(DhcpMessageType::Ack));
+    assert_eq!(ack.yiaddr, address);
+
+    // 別のアドレスや別のクライアントの記録は無い
+    let other = Ipv4Address::new(192, 0, 2, 101);
+    assert!(server.process(&init_reboot(&CLIENT1, other)).is_none());
+    assert!(server.process(&init_reboot(&CLIENT2, address)).is_none());
+}
+
+#[test]
+fn decline() {
+    let mut server = server();
+    let address = Ipv4Address::new(192, 0, 2, 100);
+    server.process(&request(&CLIENT1, address)).unwrap();
+    let declined = |server: &DhcpServer| {
+        server
+            .leases()
+            .iter()
+            .any(|l| l.address == address && l.state == DhcpLeaseState::Declined)
+    };
+    let decline = |hwaddr: &[u8], server_id| {
+        let mut decline = DhcpMessage::request(DhcpMessageType::Decline, 2, hwaddr);
+        decline.options.push(DhcpOption::RequestedAddress(address));
+        decline
+            .options
+            .push(DhcpOption::ServerIdentifier(server_id));
+        decline
+    };
+
+    // 別のサーバ宛てや、アドレスを持っていないクライアントからのものは無視する
+    assert!(server
+        .process(&decline(&CLIENT1, Ipv4Address::new(192, 0, 2, 2)))
+        .is_none());
+    assert!(server
+        .process(&decline(&CLIENT2, server.server_address()))
+        .is_none());
+    assert!(!declined(&server));
+
+    let server_id = server.server_address();
+    assert!(server.process(&decline(&CLIENT1, server_id)).is_none());
+    assert!(declined(&server));
+    // しばらくは誰にも渡さない
+    let discover = DhcpMessage::request(DhcpMessageType::Discover, 3, &CLIENT1);
+    let (offer, _) = server.process(&discover).unwrap();
+    assert_ne!(offer.yiaddr, address);
+}
+
+#[test]
+fn lease_persistence() {
+    let path = std::env::temp_dir().join(format!("rustic-dhcp-{}.leases", std::process::id()));
+    let expires = SystemTime::UNIX_EPOCH + Duration::from_secs(4_000_000_000);
+    let leases = vec![
+        DhcpServerLease {
+            hwaddr: CLIENT1.to_vec(),
+            address: Ipv4Address::new(192, 0, 2, 100),
+            expires,
+            state: DhcpLeaseState::Bound,
+        },
+        // OFFER 中のものは保存しない
+        DhcpServerLease {
+            hwaddr: CLIENT2.to_vec(),
+            address: Ipv4Address::new(192, 0, 2, 101),
+            expires,
+            state: DhcpLeaseState::Offered,
+        },
+    ];
+    save_leases(&path, &leases).unwrap();
+    let loaded = load_leases(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, leases[..1].to_vec());
+}
@@ -0,0 +1,124 @@
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+
+use rustic_stack::dns::resolver::extract;
+use rustic_stack::dns::{
+    DnsErrorKind, DnsMessage, DnsRecord, DnsRecordData, DnsRecordType, DnsResponseCode,
+    DNS_CLASS_IN, DNS_FLAG_QR, DNS_FLAG_RD,
+};
+use rustic_stack::ipv4::Ipv4Address;
+
+mod resolver;
+
+/// 問い合わせ先の DNS サーバは全体で 1 つなので、変えるテストはこれを持っておく
+pub static SERVERS_LOCK: Mutex<()> = Mutex::new(());
+
+fn record(name: &str, ttl: u32, data: DnsRecordData) -> DnsRecord {
+    let rtype = match data {
+        DnsRecordData::A(_) => DnsRecordType::A,
+        DnsRecordData::Aaaa(_) => DnsRecordType::Aaaa,
+        DnsRecordData::Cname(_) => DnsRecordType::Cname,
+        DnsRecordData::Other(_) => DnsRecordType::Txt,
+    };
+    DnsRecord {
+        name: name.to_string(),
+        rtype: rtype as u16,
+        class: DNS_CLASS_IN,
+        ttl,
+        data,
+    }
+}
+
+#[test]
+fn encode_query() {
+    let query = DnsMessage::query(0x1234, "Example.COM.", DnsRecordType::A);
+    let data = query.encode().unwrap();
+    let expected = [
+        0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 7, b'e', b'x',
+        b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0x00, 0x01, 0x00, 0x01,
+    ];
+    assert_eq!(data, expected);
+    assert_eq!(DnsMessage::parse(&data).unwrap(), query);
+
+    let long = "a".repeat(64);
+    let query = DnsMessage::query(1, &format!("{}.example", long), DnsRecordType::A);
+    assert_eq!(query.encode().unwrap_err().kind, DnsErrorKind::InvalidName);
+}
+
+#[test]
+fn parse_compressed_response() {
+    let mut data = DnsMessage::query(0xbeef, "www.example.com", DnsRecordType::A)
+        .encode()
+        .unwrap();
+    data[2] = 0x81;
+    data[3] = 0x80;
+    data[7] = 2;
+    // www.example.com CNAME example.com (example.com は質問中の名前への圧縮ポインタ)
+    data.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 16]);
+    // example.com A 192.0.2.80
+    data.extend_from_slice(&[0xc0, 16, 0, 1, 0, 1, 0, 0, 0, 30, 0, 4, 192, 0, 2, 80]);
+
+    let response = DnsMessage::parse(&data).unwrap();
+    assert!(response.is_response());
+    assert!(!response.is_truncated());
+    assert_eq!(response.response_code(), DnsResponseCode::NoError);
+    assert_eq!(
+        response.answers[0].data,
+        DnsRecordData::Cname(String::from("example.com"))
+    );
+    assert_eq!(response.answers[1].name, "example.com");
+
+    let answer = extract(&response, "WWW.example.com", DnsRecordType::A);
+    assert_eq!(answer.canonical, "example.com");
+    assert_eq!(answer.addresses, vec![IpAddr::from([192, 0, 2, 80])]);
+    assert_eq!(answer.ttl, 30);
+
+    // 自分自身を指す圧縮ポインタ
+    let mut looped = data[..33].to_vec();
+    looped[7] = 1;
+    looped.extend_from_slice(&[0xc0, 33]);
+    assert_eq!(
+        DnsMessage::parse(&looped).unwrap_err().kind,
+        DnsErrorKind::InvalidMessage
+    );
+    assert_eq!(
+        DnsMessage::parse(&data[..data.len() - 2]).unwrap_err().kind,
+        DnsErrorKind::Truncated
+    );
+}
+
+#[test]
+fn cname_chain() {
+    let mut response = DnsMessage::query(1, "a.example", DnsRecordType::Aaaa);
+    response.flags = DNS_FLAG_QR | DNS_FLAG_RD;
+    response.answers = vec![
+        record(
+            "a.example",
+            300,
+            DnsRecordData::Cname(String::from("b.example")),
+        ),
+        record(
+            "b.example",
+            100,
+            DnsRecordData::Cname(String::from("c.example")),
+        ),
+        record("c.example", 200, DnsRecordData::Aaaa(Ipv6Addr::LOCALHOST)),
+        record(
+            "c.example",
+            200,
+            DnsRecordData::A(Ipv4Address::new(192, 0, 2, 1)),
+        ),
+    ];
+    let mut response = DnsMessage::parse(&response.encode().unwrap()).unwrap();
+
+    let answer = extract(&response, "a.example", DnsRecordType::Aaaa);
+    assert_eq!(answer.canonical, "c.example");
+    assert_eq!(answer.addresses, vec![IpAddr::V6(Ipv6Addr::LOCALHOST)]);
+    assert_eq!(answer.ttl, 100);
+
+    // 別名の先のアドレスが無ければ canonical を引き直すことになる
+    response.answers.truncate(2);
+    let answer = extract(&response, "a.example", DnsRecordType::A);
+    assert!(answer.addresses.is_empty());
+    assert_eq!(answer.canonical, "c.example");
+}
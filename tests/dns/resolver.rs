@@ -0,0 +1,117 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use rustic_stack::dns::resolver::{self, flush_cache};
+use rustic_stack::dns::{
+    DnsMessage, DnsRecord, DnsRecordData, DnsRecordType, DNS_CLASS_IN, DNS_FLAG_QR, DNS_FLAG_TC,
+    DNS_PORT,
+};
+use rustic_stack::ipv4::{self, IpInterface, Ipv4Address, Ipv4Endpoint};
+use rustic_stack::net::NetDevice;
+use rustic_stack::tcp;
+use rustic_stack::udp;
+
+use super::SERVERS_LOCK;
+use crate::capture;
+
+const SERVER: Ipv4Address = Ipv4Address::new(198, 18, 14, 1);
+const ANSWER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 77);
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static REFLECTED: Mutex<Option<Sender<Vec<u8>>>> = Mutex::new(None);
+
+/// 送ったパケットを受信したものとして同じデバイスに戻す
+fn reflect(_dev: &NetDevice, _protocol_type: u16, data: &[u8], _dst: *mut u8) -> isize {
+    match REFLECTED.lock().unwrap().as_ref() {
+        Some(sender) if sender.send(data.to_vec()).is_ok() => data.len() as isize,
+        _ => -1,
+    }
+}
+
+/// 自分のアドレスどうしでこのスタックの UDP と TCP を通すためのデバイス
+fn reflector(name: &str, network: &str) -> &'static NetDevice {
+    let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+    *REFLECTED.lock().unwrap() = Some(sender);
+    let mut dev = capture::alloc(name);
+    dev.ops.transmit = Some(reflect);
+    let dev = capture::register(dev);
+    IpInterface::register(IpInterface::alloc_cidr(network).unwrap(), dev).unwrap();
+    thread::spawn(move || {
+        for packet in receiver {
+            ipv4::input(&packet, dev);
+        }
+    });
+    dev
+}
+
+fn answer(query: &DnsMessage) -> DnsMessage {
+    let mut response = query.clone();
+    response.flags |= DNS_FLAG_QR;
+    response.answers.push(DnsRecord {
+        name: query.questions[0].name.clone(),
+        rtype: DnsRecordType::A as u16,
+        class: DNS_CLASS_IN,
+        ttl: 300,
+        data: DnsRecordData::A(Ipv4Address::from(ANSWER)),
+    });
+    response
+}
+
+fn receive_exact(id: usize, buf: &mut [u8]) {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let len = tcp::receive(id, &mut buf[filled..], Some(TIMEOUT)).unwrap();
+        assert!(len > 0);
+        filled += len;
+    }
+}
+
+#[test]
+fn truncated_answer_over_tcp() {
+    let _servers = SERVERS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    udp::init();
+    tcp::init();
+    reflector("dnsrefl0", "198.18.14.1/24");
+
+    // UDP では答えを載せずに TC を立てて返す
+    let udp_id = udp::open().unwrap();
+    udp::bind(udp_id, Ipv4Endpoint::new(SERVER, DNS_PORT)).unwrap();
+    let udp_server = thread::spawn(move || {
+        let datagram = udp::recvfrom(udp_id, Some(TIMEOUT)).ok().unwrap();
+        let mut response = DnsMessage::parse(&datagram.data).unwrap();
+        response.flags |= DNS_FLAG_QR | DNS_FLAG_TC;
+        udp::sendto(udp_id, &response.encode().unwrap(), datagram.foreign).unwrap();
+        udp::close(udp_id).unwrap();
+    });
+
+    // TCP では長さを前置きして答える
+    let listener = tcp::open().unwrap();
+    tcp::bind(listener, Ipv4Endpoint::new(SERVER, DNS_PORT)).unwrap();
+    tcp::listen(listener, 1).unwrap();
+    let tcp_server = thread::spawn(move || {
+        let id = tcp::accept(listener, Some(TIMEOUT)).unwrap();
+        let mut length = [0; 2];
+        receive_exact(id, &mut length);
+        let mut query = vec![0; u16::from_be_bytes(length) as usize];
+        receive_exact(id, &mut query);
+        let response = answer(&DnsMessage::parse(&query).unwrap())
+            .encode()
+            .unwrap();
+        let mut message = (response.len() as u16).to_be_bytes().to_vec();
+        message.extend_from_slice(&response);
+        tcp::send(id, &message, Some(TIMEOUT)).unwrap();
+        tcp::close(id).unwrap();
+        tcp::close(listener).unwrap();
+    });
+
+    resolver::set_servers(&[SERVER]);
+    flush_cache();
+    let addresses = resolver::lookup("truncated.example", DnsRecordType::A);
+    resolver::set_servers(&[]);
+    udp_server.join().unwrap();
+    tcp_server.join().unwrap();
+    assert_eq!(addresses.unwrap(), vec![IpAddr::V4(ANSWER)]);
+}
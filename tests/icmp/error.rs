@@ -0,0 +1,75 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use rustic_stack::icmp::{self, IcmpMessage, IcmpType, UnreachableCode};
+use rustic_stack::ipv4::{Ipv4Address, Ipv4Header, Ipv4HeaderBuilder, Protocol};
+
+use crate::capture;
+
+fn original(protocol: u8, src: Ipv4Address, dst: Ipv4Address, payload: &[u8]) -> Vec<u8> {
+    Ipv4HeaderBuilder::new(protocol, src, dst)
+        .build(payload)
+        .unwrap()
+}
+
+fn unreachable(original: &[u8]) {
+    icmp::destination_unreachable(UnreachableCode::Port, original);
+}
+
+// 送出の制限は全体で 1 つなので、制限の検査も同じテストの中で順に行う
+#[test]
+fn error_suppression_and_rate_limit() {
+    let dev = capture::device("icmp0", "198.18.2.1/24");
+    let local = Ipv4Address::new(198, 18, 2, 1);
+    let peer = Ipv4Address::new(198, 18, 2, 2);
+    icmp::set_error_rate_limit(1000, 1000);
+
+    // 元のヘッダとペイロードの先頭 8 バイトを載せて送信元へ返す
+    let udp = original(Protocol::Udp as u8, peer, local, &[0xab; 32]);
+    unreachable(&udp);
+    let sent = capture::take(dev);
+    assert_eq!(sent.len(), 1);
+    let header = Ipv4Header::new_checked(&sent[0][..]).unwrap();
+    assert_eq!(header.protocol_number(), Protocol::Icmp as u8);
+    assert_eq!(header.src_address(), local);
+    assert_eq!(header.dst_address(), peer);
+    let message = IcmpMessage::new_checked(header.payload()).unwrap();
+    assert_eq!(message.message_type(), IcmpType::DestinationUnreachable);
+    assert_eq!(message.code(), UnreachableCode::Port as u8);
+    assert_eq!(message.data(), &udp[..20 + 8]);
+    assert!(message.verify_checksum());
+
+    // エラーでない ICMP には返す
+    let echo = icmp::build(IcmpType::Echo, 0, 0, b"ping");
+    unreachable(&original(Protocol::Icmp as u8, peer, local, &echo));
+    assert_eq!(capture::take(dev).len(), 1);
+
+    // ICMP エラーに対するエラーは返さない
+    let error = icmp::build(IcmpType::TimeExceeded, 0, 0, &udp[..28]);
+    unreachable(&original(Protocol::Icmp as u8, peer, local, &error));
+    // ブロードキャストやマルチキャストが送信元か宛先のもの
+    let udp_to = |src, dst| original(Protocol::Udp as u8, src, dst, &[0; 8]);
+    unreachable(&udp_to(Ipv4Address::new(255, 255, 255, 255), local));
+    unreachable(&udp_to(Ipv4Address::new(224, 0, 0, 1), local));
+    unreachable(&udp_to(peer, Ipv4Address::new(255, 255, 255, 255)));
+    unreachable(&udp_to(peer, Ipv4Address::new(198, 18, 2, 255)));
+    unreachable(&udp_to(peer, Ipv4Address::new(224, 0, 0, 251)));
+    // 先頭以外のフラグメント
+    let fragment = Ipv4HeaderBuilder::new(Protocol::Udp as u8, peer, local)
+        .offset(8)
+        .build(&[0; 8])
+        .unwrap();
+    unreachable(&fragment);
+    assert!(capture::take(dev).is_empty());
+
+    // トークンバケットが空になれば補充されるまで送らない
+    icmp::set_error_rate_limit(100, 3);
+    for _ in 0..5 {
+        unreachable(&udp);
+    }
+    assert_eq!(capture::take(dev).len(), 3);
+    sleep(Duration::from_millis(20));
+    unreachable(&udp);
+    assert_eq!(capture::take(dev).len(), 1);
+    icmp::set_error_rate_limit(10, 10);
+}
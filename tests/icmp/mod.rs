@@ -0,0 +1,31 @@
+mod error;
+
+use rustic_stack::icmp::{self, IcmpMessage, IcmpType, UnreachableCode};
+
+#[test]
+fn build_message() {
+    let data = [0x45, 0x00, 0x00, 0x1c];
+    let message = icmp::build(
+        IcmpType::DestinationUnreachable,
+        UnreachableCode::FragmentationNeeded as u8,
+        1400,
+        &data,
+    );
+    assert_eq!(message.len(), icmp::ICMP_HEADER_SIZE + data.len());
+
+    let message = IcmpMessage::new_checked(&message[..]).unwrap();
+    assert_eq!(message.message_type(), IcmpType::DestinationUnreachable);
+    assert_eq!(message.code(), 4);
+    assert_eq!(message.values(), 1400);
+    assert_eq!(message.data(), &data);
+    assert!(message.verify_checksum());
+}
+
+#[test]
+fn error_types() {
+    assert!(IcmpType::TimeExceeded.is_error());
+    assert!(IcmpType::ParameterProblem.is_error());
+    assert!(!IcmpType::Echo.is_error());
+    assert!(!IcmpType::EchoReply.is_error());
+    assert!(IcmpMessage::new_checked(&[0u8; 4][..]).is_none());
+}
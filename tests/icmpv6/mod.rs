@@ -0,0 +1,40 @@
+use std::str::FromStr;
+
+use rustic_stack::icmpv6::{self, Icmpv6Message, Icmpv6Type, ICMPV6_HEADER_SIZE};
+use rustic_stack::ipv6::Ipv6Address;
+
+#[test]
+fn build_and_verify() {
+    let src = Ipv6Address::from_str("fe80::1").unwrap();
+    let dst = Ipv6Address::from_str("fe80::2").unwrap();
+    let message = icmpv6::build(Icmpv6Type::EchoRequest, 0, 0x1234_0001, b"ping", src, dst);
+    assert_eq!(message.len(), ICMPV6_HEADER_SIZE + 4);
+
+    let view = Icmpv6Message::new_checked(&message[..]).unwrap();
+    assert_eq!(view.message_type(), Icmpv6Type::EchoRequest);
+    assert_eq!(view.code(), 0);
+    assert_eq!(view.values(), 0x1234_0001);
+    assert_eq!(view.data(), b"ping");
+    assert!(view.verify_checksum(src, dst));
+    // 疑似ヘッダが違えばチェックサムは合わない
+    assert!(!view.verify_checksum(src, Ipv6Address::from_str("fe80::3").unwrap()));
+}
+
+#[test]
+fn known_checksum() {
+    // fe80::1 から ff02::1 への識別子 0、順序番号 0 のエコー要求
+    let src = Ipv6Address::from_str("fe80::1").unwrap();
+    let dst = Ipv6Address::from_str("ff02::1").unwrap();
+    let message = icmpv6::build(Icmpv6Type::EchoRequest, 0, 0, &[], src, dst);
+    assert_eq!(message, vec![0x80, 0, 0x82, 0x37, 0, 0, 0, 0]);
+}
+
+#[test]
+fn message_type() {
+    assert_eq!(Icmpv6Type::from_u8(135), Icmpv6Type::NeighborSolicitation);
+    assert_eq!(Icmpv6Type::from_u8(200), Icmpv6Type::Unknown);
+    assert!(Icmpv6Type::is_error(Icmpv6Type::PacketTooBig as u8));
+    assert!(Icmpv6Type::is_error(100));
+    assert!(!Icmpv6Type::is_error(Icmpv6Type::EchoReply as u8));
+    assert!(Icmpv6Message::new_checked(&[0x80, 0, 0, 0][..]).is_none());
+}
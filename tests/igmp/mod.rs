@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use rustic_stack::ethernet::{self, MacAddress, MAC_BROADCAST};
+use rustic_stack::igmp::{
+    self, IgmpGroupRecord, IgmpMessage, IgmpRecordType, IgmpVersion, IGMPV3_ROUTERS,
+    IGMP_TYPE_MEMBERSHIP_QUERY, IGMP_TYPE_V2_MEMBERSHIP_REPORT,
+};
+use rustic_stack::ipv4::Ipv4Address;
+use rustic_stack::net::NetDevice;
+
+#[test]
+fn build_v2_report() {
+    let group = Ipv4Address::new(239, 1, 2, 3);
+    let message = igmp::build(IGMP_TYPE_V2_MEMBERSHIP_REPORT, 0, group);
+    assert_eq!(message, vec![0x16, 0, 0xf8, 0xfa, 239, 1, 2, 3]);
+
+    let view = IgmpMessage::new_checked(&message[..]).unwrap();
+    assert!(view.verify_checksum());
+    assert_eq!(view.message_type(), IGMP_TYPE_V2_MEMBERSHIP_REPORT);
+    assert_eq!(view.group(), group);
+    assert!(IgmpMessage::new_checked(&message[..4]).is_none());
+}
+
+#[test]
+fn query_version() {
+    let v1 = igmp::build(IGMP_TYPE_MEMBERSHIP_QUERY, 0, Ipv4Address::new(0, 0, 0, 0));
+    let view = IgmpMessage::new_checked(&v1[..]).unwrap();
+    assert_eq!(view.query_version(), IgmpVersion::V1);
+    assert_eq!(view.max_response_time(), Duration::from_secs(10));
+
+    let v2 = igmp::build(
+        IGMP_TYPE_MEMBERSHIP_QUERY,
+        100,
+        Ipv4Address::new(0, 0, 0, 0),
+    );
+    let view = IgmpMessage::new_checked(&v2[..]).unwrap();
+    assert_eq!(view.query_version(), IgmpVersion::V2);
+    assert_eq!(view.max_response_time(), Duration::from_secs(10));
+
+    // Group-and-Source-Specific Query。送信元 2 つ
+    let mut v3 = vec![
+        IGMP_TYPE_MEMBERSHIP_QUERY,
+        0x8c,
+        0,
+        0,
+        239,
+        1,
+        2,
+        3,
+        0x02,
+        125,
+        0,
+        2,
+    ];
+    v3.extend_from_slice(&[192, 0, 2, 1, 192, 0, 2, 2]);
+    let view = IgmpMessage::new_checked(&v3[..]).unwrap();
+    assert_eq!(view.query_version(), IgmpVersion::V3);
+    assert_eq!(
+        view.sources(),
+        vec![
+            Ipv4Address::new(192, 0, 2, 1),
+            Ipv4Address::new(192, 0, 2, 2)
+        ]
+    );
+    // 0x8c は仮数 12、指数 0 なので (12 | 16) << 3 = 224 (22.4 秒)
+    assert_eq!(view.max_response_time(), Duration::from_millis(22400));
+}
+
+#[test]
+fn max_resp_code() {
+    assert_eq!(igmp::decode_max_resp_code(100), 100);
+    assert_eq!(igmp::decode_max_resp_code(127), 127);
+    assert_eq!(igmp::decode_max_resp_code(0x80), 128);
+    assert_eq!(igmp::decode_max_resp_code(0xff), 31744);
+}
+
+#[test]
+fn build_v3_report() {
+    let records = [
+        IgmpGroupRecord {
+            record_type: IgmpRecordType::ChangeToExclude,
+            group: Ipv4Address::new(239, 1, 2, 3),
+            sources: Vec::new(),
+        },
+        IgmpGroupRecord {
+            record_type: IgmpRecordType::ModeIsInclude,
+            group: Ipv4Address::new(232, 1, 1, 1),
+            sources: vec![Ipv4Address::new(192, 0, 2, 1)],
+        },
+    ];
+    let message = igmp::build_v3_report(&records);
+    assert_eq!(message.len(), 8 + 8 + 12);
+    assert_eq!(message[0], 0x22);
+    assert_eq!(&message[6..8], &[0, 2]);
+    assert_eq!(&message[8..16], &[4, 0, 0, 0, 239, 1, 2, 3]);
+    assert_eq!(&message[16..20], &[1, 0, 0, 1]);
+    assert_eq!(&message[24..28], &[192, 0, 2, 1]);
+    assert!(IgmpMessage::new_checked(&message[..])
+        .unwrap()
+        .verify_checksum());
+    assert!(IGMPV3_ROUTERS.is_multicast());
+}
+
+#[test]
+fn multicast_mac() {
+    // 上位 9 ビットは写らないので 224.1.2.3 と 239.129.2.3 は同じ MAC アドレスになる
+    let expected = MacAddress::from([0x01, 0x00, 0x5e, 0x01, 0x02, 0x03]);
+    assert_eq!(
+        ethernet::ipv4_multicast_mac(Ipv4Address::new(224, 1, 2, 3)),
+        expected
+    );
+    assert_eq!(
+        ethernet::ipv4_multicast_mac(Ipv4Address::new(239, 129, 2, 3)),
+        expected
+    );
+    assert!(expected.is_multicast());
+}
+
+#[test]
+fn multicast_filter() {
+    let mut dev = NetDevice::alloc();
+    dev.name = String::from("igmp-test0");
+    dev.hwaddr[..6].copy_from_slice(&[0x00, 0x00, 0x5e, 0x00, 0x53, 0x01]);
+    let own = MacAddress::from([0x00, 0x00, 0x5e, 0x00, 0x53, 0x01]);
+    let other = MacAddress::from([0x00, 0x00, 0x5e, 0x00, 0x53, 0x02]);
+    let group = ethernet::ipv4_multicast_mac(Ipv4Address::new(239, 1, 2, 3));
+
+    assert!(ethernet::accepts(&dev, &own));
+    assert!(ethernet::accepts(&dev, &MAC_BROADCAST));
+    assert!(!ethernet::accepts(&dev, &other));
+    assert!(ethernet::accepts(
+        &dev,
+        &ethernet::ipv4_multicast_mac(igmp::IGMP_ALL_SYSTEMS)
+    ));
+    assert!(!ethernet::accepts(&dev, &group));
+
+    ethernet::multicast_filter_add(&dev.name, group);
+    ethernet::multicast_filter_add(&dev.name, group);
+    assert!(ethernet::accepts(&dev, &group));
+    ethernet::multicast_filter_remove(&dev.name, group);
+    assert!(ethernet::accepts(&dev, &group));
+    ethernet::multicast_filter_remove(&dev.name, group);
+    assert!(!ethernet::accepts(&dev, &group));
+}
@@ -0,0 +1,66 @@
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use rustic_stack::ipv4::{IpInterface, Ipv4Address, Ipv4Network};
+
+#[test]
+fn address_round_trip() {
+    let addr = Ipv4Address::from_str("192.0.2.1").unwrap();
+    assert_eq!(addr.octets(), [192, 0, 2, 1]);
+    assert_eq!(addr.to_u32(), 0xc000_0201);
+    assert_eq!(Ipv4Address::from_u32(0xc000_0201), addr);
+    assert_eq!(addr.to_string(), "192.0.2.1");
+
+    assert!(Ipv4Address::from_str("192.0.2").is_err());
+    assert!(Ipv4Address::from_str("192.0.2.256").is_err());
+    assert!(Ipv4Address::from_str("c0:00:02:01").is_err());
+}
+
+#[test]
+fn address_std_interop() {
+    let std_addr = Ipv4Addr::new(127, 0, 0, 1);
+    let addr = Ipv4Address::from(std_addr);
+    assert_eq!(addr, Ipv4Address::new(127, 0, 0, 1));
+    assert!(addr.is_loopback());
+    assert_eq!(Ipv4Addr::from(addr), std_addr);
+}
+
+#[test]
+fn network() {
+    let network = Ipv4Network::from_str("192.0.2.2/24").unwrap();
+    assert_eq!(network.address(), Ipv4Address::new(192, 0, 2, 2));
+    assert_eq!(network.prefix(), 24);
+    assert_eq!(network.network(), Ipv4Address::new(192, 0, 2, 0));
+    assert_eq!(network.netmask(), Ipv4Address::new(255, 255, 255, 0));
+    assert_eq!(network.broadcast(), Ipv4Address::new(192, 0, 2, 255));
+    assert!(network.contains(Ipv4Address::new(192, 0, 2, 200)));
+    assert!(!network.contains(Ipv4Address::new(192, 0, 3, 1)));
+    assert_eq!(network.to_string(), "192.0.2.2/24");
+
+    assert_eq!(network.iter().count(), 256);
+    let hosts: Vec<Ipv4Address> = network.hosts().collect();
+    assert_eq!(hosts.len(), 254);
+    assert_eq!(hosts[0], Ipv4Address::new(192, 0, 2, 1));
+    assert_eq!(hosts[253], Ipv4Address::new(192, 0, 2, 254));
+
+    let host = Ipv4Network::from_str("198.51.100.7/32").unwrap();
+    assert_eq!(host.hosts().collect::<Vec<_>>(), vec![host.address()]);
+
+    assert!(Ipv4Network::from_str("192.0.2.0/33").is_err());
+    assert!(Ipv4Network::with_netmask(
+        Ipv4Address::new(192, 0, 2, 0),
+        Ipv4Address::new(255, 0, 255, 0)
+    )
+    .is_err());
+}
+
+#[test]
+fn interface_alloc() {
+    let interface = IpInterface::alloc("127.0.0.1", "255.0.0.0").unwrap();
+    assert_eq!(interface.broadcast, Ipv4Address::new(127, 255, 255, 255));
+    assert_eq!(interface.network().prefix(), 8);
+
+    let interface = IpInterface::alloc_cidr("192.0.2.2/24").unwrap();
+    assert_eq!(interface.netmask, Ipv4Address::new(255, 255, 255, 0));
+    assert_eq!(interface.broadcast, Ipv4Address::new(192, 0, 2, 255));
+}
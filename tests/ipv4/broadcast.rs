@@ -0,0 +1,104 @@
+use rustic_stack::ipv4::{
+    self, IpInterface, Ipv4Address, Ipv4Delivery, Ipv4ErrorKind, IP_ADDRESS_BROADCAST,
+};
+use rustic_stack::net::{NetInterfaceFamily, NetInterfaceType};
+
+use crate::capture;
+
+fn interfaces() -> Vec<IpInterface> {
+    vec![
+        *IpInterface::alloc_cidr("192.0.2.2/24").unwrap(),
+        *IpInterface::alloc_cidr("198.51.100.2/24").unwrap(),
+    ]
+}
+
+#[test]
+fn unicast() {
+    let interfaces = interfaces();
+    let address = Ipv4Address::new(198, 51, 100, 2);
+    // 受信したデバイスのどのインターフェースのアドレスでも受け取る
+    assert_eq!(
+        ipv4::classify(&interfaces, address),
+        Ipv4Delivery::Unicast(address)
+    );
+    assert_eq!(
+        ipv4::classify(&interfaces, Ipv4Address::new(192, 0, 2, 3)),
+        Ipv4Delivery::NotLocal
+    );
+}
+
+#[test]
+fn limited_broadcast() {
+    assert_eq!(
+        ipv4::classify(&interfaces(), IP_ADDRESS_BROADCAST),
+        Ipv4Delivery::Broadcast(Ipv4Address::new(192, 0, 2, 2))
+    );
+    // アドレスの無いデバイスでも受け取る
+    assert_eq!(
+        ipv4::classify(&[], IP_ADDRESS_BROADCAST),
+        Ipv4Delivery::Broadcast(Ipv4Address::new(0, 0, 0, 0))
+    );
+}
+
+#[test]
+fn directed_broadcast() {
+    let mut interfaces = interfaces();
+    let broadcast = Ipv4Address::new(198, 51, 100, 255);
+    assert_eq!(
+        ipv4::classify(&interfaces, broadcast),
+        Ipv4Delivery::Broadcast(Ipv4Address::new(198, 51, 100, 2))
+    );
+    interfaces[1].directed_broadcast = false;
+    assert_eq!(
+        ipv4::classify(&interfaces, broadcast),
+        Ipv4Delivery::Refused
+    );
+    // 他のインターフェースの設定には影響しない
+    assert_eq!(
+        ipv4::classify(&interfaces, Ipv4Address::new(192, 0, 2, 255)),
+        Ipv4Delivery::Broadcast(Ipv4Address::new(192, 0, 2, 2))
+    );
+}
+
+#[test]
+fn set_directed_broadcast() {
+    let dev = capture::device("bcast0", "198.18.3.1/24");
+    let address = Ipv4Address::new(198, 18, 3, 1);
+    assert!(IpInterface::set_directed_broadcast(address, false).is_ok());
+    assert!(!IpInterface::select(address).unwrap().directed_broadcast);
+    // デバイスが持っている複製も変わる
+    let interfaces = dev
+        .get_interfaces(NetInterfaceFamily::Ip)
+        .into_iter()
+        .filter_map(|interface| match interface {
+            NetInterfaceType::Ip(ip_interface) => Some(ip_interface),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ipv4::classify(&interfaces, Ipv4Address::new(198, 18, 3, 255)),
+        Ipv4Delivery::Refused
+    );
+
+    IpInterface::unregister(address, dev).unwrap();
+    assert_eq!(
+        IpInterface::set_directed_broadcast(address, true)
+            .unwrap_err()
+            .kind,
+        Ipv4ErrorKind::NoInterface
+    );
+    assert_eq!(
+        IpInterface::unregister(address, dev).unwrap_err().kind,
+        Ipv4ErrorKind::NoInterface
+    );
+}
+
+#[test]
+fn point_to_point_has_no_broadcast() {
+    // /31 の相手のアドレスはブロードキャストとして扱わない
+    let interfaces = vec![*IpInterface::alloc_cidr("203.0.113.0/31").unwrap()];
+    assert_eq!(
+        ipv4::classify(&interfaces, Ipv4Address::new(203, 0, 113, 1)),
+        Ipv4Delivery::NotLocal
+    );
+}
@@ -0,0 +1,138 @@
+use std::str::FromStr;
+
+use rustic_stack::ethernet::MacAddress;
+use rustic_stack::ipv4::{
+    self, fragment, neighbor, option, route, set_forwarding, IpInterface, Ipv4Address,
+    Ipv4ErrorKind, Ipv4Header, Ipv4HeaderBuilder, Ipv4Network, Ipv4Option, Protocol, IP_FLAG_DF,
+};
+use rustic_stack::net::{NetDevice, NetDeviceFlag};
+
+use crate::capture;
+
+#[test]
+fn fragment_packet() {
+    let payload: Vec<u8> = (0..100).collect();
+    let options = option::emit(&[Ipv4Option::RouterAlert(0), Ipv4Option::record_route(1)]);
+    let packet = Ipv4HeaderBuilder::new(
+        Protocol::Udp as u8,
+        Ipv4Address::new(192, 0, 2, 1),
+        Ipv4Address::new(198, 51, 100, 1),
+    )
+    .options(&options)
+    .build(&payload)
+    .unwrap();
+
+    let fragments = fragment(&packet, 68).unwrap();
+    assert_eq!(fragments.len(), 3);
+
+    let mut reassembled = Vec::new();
+    for (i, f) in fragments.iter().enumerate() {
+        let header = Ipv4Header::new_checked(&f[..]).unwrap();
+        assert!(f.len() <= 68);
+        assert!(header.verify_checksum());
+        assert_eq!(header.offset() as usize * 8, reassembled.len());
+        assert_eq!(header.more_fragments(), i != fragments.len() - 1);
+        if i == 0 {
+            assert_eq!(header.header_length(), 32);
+        } else {
+            // Router Alert だけがコピーされる
+            assert_eq!(header.header_length(), 24);
+            assert_eq!(
+                option::parse(header.options()).unwrap()[0],
+                Ipv4Option::RouterAlert(0)
+            );
+        }
+        reassembled.extend_from_slice(header.payload());
+    }
+    assert_eq!(reassembled, payload);
+}
+
+#[test]
+fn fragment_dont_fragment() {
+    let packet = Ipv4HeaderBuilder::new(
+        Protocol::Udp as u8,
+        Ipv4Address::new(192, 0, 2, 1),
+        Ipv4Address::new(198, 51, 100, 1),
+    )
+    .flags(IP_FLAG_DF)
+    .build(&[0; 100])
+    .unwrap();
+    assert_eq!(fragment(&packet, 120).unwrap().len(), 1);
+    let err = fragment(&packet, 68).unwrap_err();
+    assert_eq!(err.kind, Ipv4ErrorKind::TooLong);
+}
+
+#[test]
+fn route_lookup() {
+    let dev = NetDevice::alloc();
+    let interface = IpInterface::alloc_cidr("203.0.113.1/24").unwrap();
+    IpInterface::register(interface, &dev).unwrap();
+
+    let on_link = route::lookup(Ipv4Address::new(203, 0, 113, 9)).unwrap();
+    assert!(on_link.nexthop.is_none());
+    assert_eq!(on_link.interface.unicast, Ipv4Address::new(203, 0, 113, 1));
+
+    let gateway = Ipv4Address::new(203, 0, 113, 254);
+    route::add(
+        Ipv4Network::from_str("100.64.0.0/10").unwrap(),
+        Some(gateway),
+        Ipv4Address::new(203, 0, 113, 1),
+    )
+    .unwrap();
+    let via = route::lookup(Ipv4Address::new(100, 64, 1, 1)).unwrap();
+    assert_eq!(via.nexthop, Some(gateway));
+    assert_eq!(via.network.prefix(), 10);
+    assert_eq!(via.gateway_for(Ipv4Address::new(100, 64, 1, 1)), gateway);
+
+    assert!(route::add(
+        Ipv4Network::from_str("100.128.0.0/10").unwrap(),
+        None,
+        Ipv4Address::new(203, 0, 114, 1),
+    )
+    .is_err());
+}
+
+#[test]
+fn forward_via_gateway() {
+    let inside = capture::device("fwd0", "198.18.7.1/24");
+    let mut outside = capture::alloc("fwd1");
+    outside.flags |= NetDeviceFlag::NeedArp as u16;
+    let outside = capture::register(outside);
+    IpInterface::register(IpInterface::alloc_cidr("198.18.8.1/24").unwrap(), outside).unwrap();
+
+    let gateway = Ipv4Address::new(198, 18, 8, 254);
+    let gateway_mac = MacAddress::from([0x00, 0x00, 0x5e, 0x00, 0x53, 0x08]);
+    let dst = Ipv4Address::new(100, 100, 1, 1);
+    route::add(
+        Ipv4Network::from_str("100.100.0.0/16").unwrap(),
+        Some(gateway),
+        Ipv4Address::new(198, 18, 8, 1),
+    )
+    .unwrap();
+    // 宛先の MAC アドレスを引いてしまわないよう、宛先にも別のアドレスを登録しておく
+    neighbor::add("fwd1", gateway, gateway_mac);
+    neighbor::add(
+        "fwd1",
+        dst,
+        MacAddress::from([0x00, 0x00, 0x5e, 0x00, 0x53, 0x09]),
+    );
+
+    let packet = Ipv4HeaderBuilder::new(Protocol::Udp as u8, Ipv4Address::new(198, 18, 7, 9), dst)
+        .build(&[0; 8])
+        .unwrap();
+    set_forwarding(true);
+    ipv4::input(&packet, inside);
+    set_forwarding(false);
+
+    let frames = capture::take_with_hwaddr(outside);
+    assert_eq!(frames.len(), 1);
+    let (frame, hwaddr) = &frames[0];
+    let header = Ipv4Header::new_checked(&frame[..]).unwrap();
+    assert_eq!(header.dst_address(), dst);
+    assert_eq!(
+        header.time_to_live(),
+        Ipv4Header::new_unchecked(&packet[..]).time_to_live() - 1
+    );
+    assert_eq!(*hwaddr, Some(gateway_mac));
+    assert!(capture::take(inside).is_empty());
+}
@@ -0,0 +1,87 @@
+use rustic_stack::ipv4::header::IP_FLAG_DF;
+use rustic_stack::ipv4::{
+    Ipv4Address, Ipv4Header, Ipv4HeaderBuilder, Ipv4HeaderErrorKind, Protocol,
+};
+
+const SAMPLE: [u8; 20] = [
+    0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00, 0x01,
+    0xc0, 0xa8, 0x00, 0xc7,
+];
+
+#[test]
+fn parse() {
+    let mut packet = SAMPLE.to_vec();
+    packet.resize(0x73, 0);
+
+    let header = Ipv4Header::new_checked(&packet[..]).unwrap();
+    assert_eq!(header.version(), 4);
+    assert_eq!(header.header_length(), 20);
+    assert_eq!(header.total_length(), 0x73);
+    assert_eq!(header.id(), 0);
+    assert!(header.dont_fragment());
+    assert!(!header.more_fragments());
+    assert_eq!(header.offset(), 0);
+    assert_eq!(header.time_to_live(), 64);
+    assert!(matches!(header.protocol(), Protocol::Udp));
+    assert_eq!(header.checksum(), 0xb861);
+    assert_eq!(header.src_address(), Ipv4Address::new(192, 168, 0, 1));
+    assert_eq!(header.dst_address(), Ipv4Address::new(192, 168, 0, 199));
+    assert!(header.verify_checksum());
+    assert_eq!(header.payload().len(), 0x73 - 20);
+}
+
+#[test]
+fn parse_error() {
+    let err = Ipv4Header::new_checked(&SAMPLE[..10]).unwrap_err();
+    assert_eq!(err.kind, Ipv4HeaderErrorKind::TooShort);
+
+    let mut packet = SAMPLE.to_vec();
+    packet.resize(0x73, 0);
+    packet[0] = 0x65;
+    let err = Ipv4Header::new_checked(&packet[..]).unwrap_err();
+    assert_eq!(err.kind, Ipv4HeaderErrorKind::Version);
+
+    packet[0] = 0x44;
+    let err = Ipv4Header::new_checked(&packet[..]).unwrap_err();
+    assert_eq!(err.kind, Ipv4HeaderErrorKind::HeaderLength);
+
+    // total length が実際のデータより長い
+    let err = Ipv4Header::new_checked(&SAMPLE[..]).unwrap_err();
+    assert_eq!(err.kind, Ipv4HeaderErrorKind::TotalLength);
+}
+
+#[test]
+fn build() {
+    let src = Ipv4Address::new(192, 0, 2, 1);
+    let dst = Ipv4Address::new(192, 0, 2, 2);
+    let payload = [1, 2, 3, 4, 5];
+    let packet = Ipv4HeaderBuilder::new(Protocol::Icmp as u8, src, dst)
+        .id(0x1234)
+        .flags(IP_FLAG_DF)
+        .time_to_live(32)
+        .build(&payload)
+        .unwrap();
+
+    let header = Ipv4Header::new_checked(&packet[..]).unwrap();
+    assert_eq!(header.total_length() as usize, 20 + payload.len());
+    assert_eq!(header.id(), 0x1234);
+    assert!(header.dont_fragment());
+    assert_eq!(header.time_to_live(), 32);
+    assert_eq!(header.protocol_number(), 1);
+    assert_eq!(header.src_address(), src);
+    assert_eq!(header.dst_address(), dst);
+    assert!(header.verify_checksum());
+    assert_eq!(header.payload(), &payload);
+}
+
+#[test]
+fn set_and_fill_checksum() {
+    let mut packet = SAMPLE.to_vec();
+    packet.resize(0x73, 0);
+    let mut header = Ipv4Header::new_unchecked(&mut packet[..]);
+    header.set_time_to_live(63);
+    assert!(!header.verify_checksum());
+    header.fill_checksum();
+    assert!(header.verify_checksum());
+    assert_eq!(header.time_to_live(), 63);
+}
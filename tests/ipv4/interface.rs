@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use rustic_stack::ipv4::{
+    self, IpInterface, Ipv4Address, Ipv4Endpoint, Ipv4HeaderBuilder, Protocol, IP_ADDRESS_ANY,
+    IP_INTERFACES,
+};
+use rustic_stack::net::{NetDevice, NetDeviceErrorKind, NetInterfaceFamily, NetInterfaceType};
+use rustic_stack::udp;
+
+fn addresses(dev: &NetDevice) -> Vec<Ipv4Address> {
+    dev.get_interfaces(NetInterfaceFamily::Ip)
+        .into_iter()
+        .filter_map(|interface| match interface {
+            NetInterfaceType::Ip(ip_interface) => Some(ip_interface.unicast),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn several_addresses_on_one_device() {
+    let dev: &'static NetDevice = Box::leak(NetDevice::alloc());
+    IpInterface::register(IpInterface::alloc_cidr("10.20.0.1/16").unwrap(), dev).unwrap();
+    IpInterface::register(IpInterface::alloc_cidr("10.20.30.1/24").unwrap(), dev).unwrap();
+    assert_eq!(
+        addresses(dev),
+        [
+            Ipv4Address::new(10, 20, 0, 1),
+            Ipv4Address::new(10, 20, 30, 1)
+        ]
+    );
+
+    // 同じアドレスの二重登録は拒否する
+    let duplicate = IpInterface::alloc_cidr("10.20.30.1/24").unwrap();
+    assert!(matches!(
+        dev.add_interface(NetInterfaceType::Ip(*duplicate.clone())),
+        Err(e) if matches!(e.kind, NetDeviceErrorKind::AlreadyRegistered)
+    ));
+    IpInterface::register(duplicate, dev).unwrap();
+    assert_eq!(addresses(dev).len(), 2);
+    let registered = IP_INTERFACES
+        .lock()
+        .items
+        .iter()
+        .filter(|entry| entry.unicast == Ipv4Address::new(10, 20, 30, 1))
+        .count();
+    assert_eq!(registered, 1);
+
+    // プレフィックスが最長のサブネットのアドレスを送信元に選ぶ
+    let source = |dst| IpInterface::select_source(dst).map(|interface| interface.unicast);
+    assert_eq!(
+        source(Ipv4Address::new(10, 20, 30, 9)),
+        Some(Ipv4Address::new(10, 20, 30, 1))
+    );
+    assert_eq!(
+        source(Ipv4Address::new(10, 20, 99, 9)),
+        Some(Ipv4Address::new(10, 20, 0, 1))
+    );
+    // どのサブネットにも含まれなければ最初に登録されたアドレスを使う
+    let first = IP_INTERFACES
+        .lock()
+        .items
+        .first()
+        .map(|entry| entry.unicast);
+    assert_eq!(source(Ipv4Address::new(192, 88, 99, 1)), first);
+}
+
+#[test]
+fn input_for_secondary_address() {
+    let dev: &'static NetDevice = Box::leak(NetDevice::alloc());
+    IpInterface::register(IpInterface::alloc_cidr("10.21.0.1/24").unwrap(), dev).unwrap();
+    IpInterface::register(IpInterface::alloc_cidr("10.21.1.1/24").unwrap(), dev).unwrap();
+    udp::init();
+    let id = udp::open().unwrap();
+    udp::bind(id, Ipv4Endpoint::new(IP_ADDRESS_ANY, 40060)).unwrap();
+
+    let src = Ipv4Endpoint::new(Ipv4Address::new(10, 21, 1, 2), 5000);
+    let dst = Ipv4Endpoint::new(Ipv4Address::new(10, 21, 1, 1), 40060);
+    let packet = Ipv4HeaderBuilder::new(Protocol::Udp as u8, src.address, dst.address)
+        .build(&udp::build(src, dst, b"secondary"))
+        .unwrap();
+    ipv4::input(&packet, dev);
+
+    let datagram = udp::recvfrom(id, Some(Duration::from_millis(10)))
+        .ok()
+        .unwrap();
+    assert_eq!(datagram.data, b"secondary");
+    assert_eq!(datagram.local, dst.address);
+    assert_eq!(datagram.foreign, src);
+    udp::close(id).unwrap();
+}
@@ -0,0 +1,8 @@
+mod address;
+mod broadcast;
+mod forward;
+mod header;
+mod interface;
+mod option;
+mod pmtu;
+mod raw;
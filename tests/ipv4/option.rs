@@ -0,0 +1,125 @@
+use rustic_stack::ipv4::option::{self, TimestampEntry, TimestampFlag};
+use rustic_stack::ipv4::{
+    Ipv4Address, Ipv4Header, Ipv4HeaderBuilder, Ipv4Option, Ipv4OptionErrorKind, Protocol,
+};
+
+#[test]
+fn parse_and_emit() {
+    let options = vec![
+        Ipv4Option::NoOperation,
+        Ipv4Option::RouterAlert(0),
+        Ipv4Option::LooseSourceRoute {
+            pointer: 8,
+            route: vec![
+                Ipv4Address::new(192, 0, 2, 1),
+                Ipv4Address::new(192, 0, 2, 2),
+            ],
+        },
+        Ipv4Option::Timestamp {
+            pointer: 5,
+            overflow: 0,
+            flag: TimestampFlag::AddressAndTimestamp,
+            entries: vec![TimestampEntry {
+                address: Some(Ipv4Address::new(0, 0, 0, 0)),
+                timestamp: 0,
+            }],
+        },
+        Ipv4Option::EndOfList,
+    ];
+    let encoded = option::emit(&options);
+    assert_eq!(encoded.len(), 1 + 4 + 11 + 12 + 1);
+    assert_eq!(&encoded[..5], &[1, 148, 4, 0, 0]);
+    assert_eq!(option::parse(&encoded).unwrap(), options);
+}
+
+#[test]
+fn parse_error() {
+    // 長さが足りない
+    let err = option::parse(&[7, 7, 4, 0]).unwrap_err();
+    assert_eq!(err.kind, Ipv4OptionErrorKind::Truncated);
+
+    // 長さが 2 未満
+    let err = option::parse(&[7, 1, 0, 0]).unwrap_err();
+    assert_eq!(err.kind, Ipv4OptionErrorKind::InvalidLength);
+    assert_eq!(err.offset, 1);
+
+    // ポインタが枠の境界を指していない
+    let err = option::parse(&[7, 7, 5, 0, 0, 0, 0, 0]).unwrap_err();
+    assert_eq!(err.kind, Ipv4OptionErrorKind::InvalidPointer);
+    assert_eq!(err.offset, 2);
+
+    // タイムスタンプのフラグが未定義
+    let err = option::parse(&[68, 8, 5, 2, 0, 0, 0, 0]).unwrap_err();
+    assert_eq!(err.kind, Ipv4OptionErrorKind::InvalidFlag);
+
+    // 同じオプションが 2 つある
+    let err = option::parse(&[148, 4, 0, 0, 148, 4, 0, 0]).unwrap_err();
+    assert_eq!(err.kind, Ipv4OptionErrorKind::Duplicated);
+}
+
+#[test]
+fn record_route_and_timestamp() {
+    let address = Ipv4Address::new(198, 51, 100, 1);
+
+    let mut rr = Ipv4Option::record_route(1);
+    assert_eq!(rr.next_route_slot(), Some(0));
+    assert!(rr.record(address));
+    assert_eq!(rr.next_route_slot(), None);
+    assert!(!rr.record(address));
+    match &rr {
+        Ipv4Option::RecordRoute { pointer, route } => {
+            assert_eq!(*pointer, 8);
+            assert_eq!(route[0], address);
+        }
+        _ => panic!("unexpected option"),
+    }
+
+    let mut ts = Ipv4Option::timestamp(TimestampFlag::AddressAndTimestamp, 1);
+    assert!(ts.stamp(address, 1000));
+    assert!(!ts.stamp(address, 2000));
+    match &ts {
+        Ipv4Option::Timestamp {
+            pointer,
+            overflow,
+            entries,
+            ..
+        } => {
+            assert_eq!(*pointer, 13);
+            assert_eq!(*overflow, 1);
+            assert_eq!(entries[0].address, Some(address));
+            assert_eq!(entries[0].timestamp, 1000);
+        }
+        _ => panic!("unexpected option"),
+    }
+}
+
+#[test]
+fn build_with_options() {
+    let options = option::emit(&[Ipv4Option::RouterAlert(0), Ipv4Option::record_route(2)]);
+    let packet = Ipv4HeaderBuilder::new(
+        Protocol::Udp as u8,
+        Ipv4Address::new(192, 0, 2, 1),
+        Ipv4Address::new(192, 0, 2, 2),
+    )
+    .options(&options)
+    .build(&[0; 8])
+    .unwrap();
+
+    let header = Ipv4Header::new_checked(&packet[..]).unwrap();
+    assert_eq!(header.header_length(), 20 + 16);
+    assert!(header.verify_checksum());
+    let parsed = option::parse(header.options()).unwrap();
+    assert_eq!(parsed[0], Ipv4Option::RouterAlert(0));
+    assert_eq!(parsed[1], Ipv4Option::record_route(2));
+    assert_eq!(parsed[2], Ipv4Option::EndOfList);
+
+    let too_many = option::emit(&[Ipv4Option::record_route(10)]);
+    assert!(Ipv4HeaderBuilder::new(
+        Protocol::Udp as u8,
+        Ipv4Address::new(192, 0, 2, 1),
+        Ipv4Address::new(192, 0, 2, 2),
+    )
+    .options(&too_many)
+    .build(&[])
+    .is_err());
+}
@@ -0,0 +1,196 @@
+use std::time::{Duration, Instant};
+
+use rustic_stack::icmp::{self, IcmpType, UnreachableCode};
+use rustic_stack::ipv4::pmtu::{self, PMTU_TIMEOUT};
+use rustic_stack::ipv4::{
+    self, Ipv4Address, Ipv4Endpoint, Ipv4Header, Ipv4HeaderBuilder, Protocol,
+};
+use rustic_stack::net::NetDevice;
+use rustic_stack::tcp::{self, header, TcpHeader, TCP_FLAG_ACK, TCP_FLAG_SYN};
+use rustic_stack::udp::{self, UdpErrorKind};
+
+use crate::capture;
+
+#[test]
+fn update_and_lookup() {
+    let dst = Ipv4Address::new(198, 51, 100, 10);
+    assert_eq!(pmtu::lookup(dst), None);
+    assert_eq!(pmtu::path_mtu(dst, 1500), 1500);
+
+    pmtu::update(dst, 1400, 1500);
+    assert_eq!(pmtu::lookup(dst), Some(1400));
+    assert_eq!(pmtu::path_mtu(dst, 1500), 1400);
+    assert_eq!(pmtu::path_mtu(dst, 1280), 1280);
+    assert_eq!(pmtu::mss(dst, 1500), 1360);
+
+    // 増やす方向の通知は無視する
+    pmtu::update(dst, 1450, 1400);
+    assert_eq!(pmtu::lookup(dst), Some(1400));
+
+    // 最小値より小さくはしない
+    pmtu::update(dst, 20, 1400);
+    assert_eq!(pmtu::lookup(dst), Some(pmtu::PMTU_MIN));
+}
+
+#[test]
+fn plateau() {
+    let dst = Ipv4Address::new(198, 51, 100, 11);
+    pmtu::update(dst, 0, 1500);
+    assert_eq!(pmtu::lookup(dst), Some(1492));
+    pmtu::update(dst, 0, 1492);
+    assert_eq!(pmtu::lookup(dst), Some(1006));
+}
+
+#[test]
+fn bogus_report() {
+    let dst = Ipv4Address::new(198, 51, 100, 12);
+    // 送ったパケット以上の MTU は通知として信用しない
+    pmtu::update(dst, 1500, 1500);
+    assert_eq!(pmtu::lookup(dst), None);
+    pmtu::update(dst, 1400, 1500);
+    assert_eq!(pmtu::lookup(dst), Some(1400));
+}
+
+#[test]
+fn aging_and_relearn() {
+    let dst = Ipv4Address::new(198, 51, 100, 13);
+    let now = Instant::now();
+    pmtu::update_at(dst, 1200, 1500, now);
+    assert_eq!(pmtu::lookup_at(dst, now), Some(1200));
+    pmtu::update_at(dst, 1400, 1500, now);
+    assert_eq!(pmtu::lookup_at(dst, now), Some(1200));
+
+    // 期限が切れたら大きな値の通知からも学び直す
+    let expired = now + PMTU_TIMEOUT;
+    assert_eq!(pmtu::lookup_at(dst, expired), None);
+    pmtu::update_at(dst, 1400, 1500, expired);
+    assert_eq!(pmtu::lookup_at(dst, expired), Some(1400));
+    assert_eq!(pmtu::lookup_at(dst, expired + PMTU_TIMEOUT), None);
+}
+
+/// original を送ったときに途中のルータから返ってくる Fragmentation Needed を受信させる
+fn fragmentation_needed(dev: &'static NetDevice, router: Ipv4Address, mtu: u16, original: &[u8]) {
+    icmp::init();
+    let header = Ipv4Header::new_unchecked(original);
+    let message = icmp::build(
+        IcmpType::DestinationUnreachable,
+        UnreachableCode::FragmentationNeeded as u8,
+        mtu as u32,
+        &original[..header.header_length() as usize + 8],
+    );
+    let packet = Ipv4HeaderBuilder::new(Protocol::Icmp as u8, router, header.src_address())
+        .build(&message)
+        .unwrap();
+    ipv4::input(&packet, dev);
+}
+
+#[test]
+fn report_for_foreign_packet() {
+    let dev = capture::device("pmtu0", "198.18.9.1/24");
+    let router = Ipv4Address::new(198, 18, 9, 254);
+    let dst = Ipv4Address::new(198, 51, 100, 20);
+    let sent = |src| {
+        Ipv4HeaderBuilder::new(Protocol::Udp as u8, src, dst)
+            .build(&[0; 1400])
+            .unwrap()
+    };
+
+    // 自分のアドレスから送ったものでなければ偽の通知として無視する
+    fragmentation_needed(dev, router, 1200, &sent(Ipv4Address::new(192, 88, 99, 1)));
+    assert_eq!(pmtu::lookup(dst), None);
+    fragmentation_needed(dev, router, 1200, &sent(Ipv4Address::new(198, 18, 9, 1)));
+    assert_eq!(pmtu::lookup(dst), Some(1200));
+}
+
+#[test]
+fn udp_dont_fragment() {
+    udp::init();
+    let dev = capture::device("pmtu1", "198.18.10.1/24");
+    let dst = Ipv4Endpoint::new(Ipv4Address::new(198, 18, 10, 2), 9);
+    pmtu::update(dst.address, 576, 1500);
+
+    // 既定では PMTU に合わせて分割して送る
+    let id = udp::open().unwrap();
+    assert!(udp::sendto(id, &[0; 1000], dst).is_ok());
+    let frames = capture::take(dev);
+    assert_eq!(frames.len(), 2);
+    assert!(frames.iter().all(|frame| frame.len() <= 576));
+
+    // PMTU 探索を使うなら DF を立て、PMTU を超えるものは送らずに TooLong を返す
+    udp::set_pmtu_discovery(id, true).unwrap();
+    assert_eq!(
+        udp::sendto(id, &[0; 1000], dst).err().unwrap().kind,
+        UdpErrorKind::TooLong
+    );
+    assert!(capture::take(dev).is_empty());
+    assert!(udp::sendto(id, &[0; 500], dst).is_ok());
+    let frames = capture::take(dev);
+    assert_eq!(frames.len(), 1);
+    assert!(Ipv4Header::new_unchecked(&frames[0][..]).dont_fragment());
+    udp::close(id).unwrap();
+}
+
+/// dev から送られた TCP セグメントを IP ヘッダと一緒に取り出す
+fn tcp_segments(dev: &NetDevice) -> Vec<Vec<u8>> {
+    capture::take(dev)
+        .into_iter()
+        .filter(|frame| {
+            Ipv4Header::new_unchecked(&frame[..]).protocol_number() == Protocol::Tcp as u8
+        })
+        .collect()
+}
+
+fn tcp_header(packet: &[u8]) -> TcpHeader<&[u8]> {
+    let header_length = Ipv4Header::new_unchecked(packet).header_length() as usize;
+    TcpHeader::new_checked(&packet[header_length..]).unwrap()
+}
+
+#[test]
+fn tcp_mss_follows_path_mtu() {
+    let dev = capture::device("pmtu2", "198.18.11.1/24");
+    let foreign = Ipv4Endpoint::new(Ipv4Address::new(198, 18, 11, 2), 80);
+    let id = tcp::open().unwrap();
+    tcp::connect_start(id, foreign).unwrap();
+    let syn = tcp_segments(dev).remove(0);
+    assert!(Ipv4Header::new_unchecked(&syn[..]).dont_fragment());
+    let local = Ipv4Endpoint::new(
+        Ipv4Header::new_unchecked(&syn[..]).src_address(),
+        tcp_header(&syn).src_port(),
+    );
+    let iss = tcp_header(&syn).seq();
+
+    let reply = |seq: u32, ack: u32, flags: u8, options: &[u8]| {
+        let segment = header::build(foreign, local, seq, ack, flags, 65535, options, &[]);
+        let packet = Ipv4HeaderBuilder::new(Protocol::Tcp as u8, foreign.address, local.address)
+            .build(&segment)
+            .unwrap();
+        tcp::input(&packet, dev);
+    };
+    // MSS 1460
+    reply(
+        5000,
+        iss.wrapping_add(1),
+        TCP_FLAG_SYN | TCP_FLAG_ACK,
+        &[2, 4, 0x05, 0xb4],
+    );
+    tcp_segments(dev);
+
+    tcp::send(id, &[0x5a; 1460], Some(Duration::from_secs(1))).unwrap();
+    let full = tcp_segments(dev).remove(0);
+    assert_eq!(full.len(), 1500);
+    assert!(Ipv4Header::new_unchecked(&full[..]).dont_fragment());
+    reply(5001, iss.wrapping_add(1 + 1460), TCP_FLAG_ACK, &[]);
+
+    // 経路の途中で 576 しか通らないと知らされたら、張られている接続の MSS も下げる
+    fragmentation_needed(dev, Ipv4Address::new(198, 18, 11, 254), 576, &full);
+    assert_eq!(pmtu::lookup(foreign.address), Some(576));
+    tcp::send(id, &[0x5a; 1460], Some(Duration::from_secs(1))).unwrap();
+    let segments = tcp_segments(dev);
+    assert_eq!(segments.len(), 3);
+    for segment in segments.iter() {
+        assert!(segment.len() <= 576);
+        assert!(Ipv4Header::new_unchecked(&segment[..]).dont_fragment());
+        assert!(tcp_header(segment).payload().len() <= 536);
+    }
+    tcp::abort(id).unwrap();
+}
@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use rustic_stack::ipv4::raw::{self, RawErrorKind, IP_PROTOCOL_RAW};
+use rustic_stack::ipv4::{self, IpInterface, Ipv4Address, Ipv4Header, Ipv4HeaderBuilder};
+use rustic_stack::net::NetDevice;
+
+use crate::capture;
+
+/// 実験用のプロトコル番号 (RFC 3692)
+const EXPERIMENT: u8 = 253;
+
+fn device(network: &str) -> &'static NetDevice {
+    let dev: &'static NetDevice = Box::leak(NetDevice::alloc());
+    IpInterface::register(IpInterface::alloc_cidr(network).unwrap(), dev).unwrap();
+    dev
+}
+
+#[test]
+fn receive_every_packet_of_protocol() {
+    let dev = device("198.18.0.1/24");
+    let local = Ipv4Address::new(198, 18, 0, 1);
+    let src = Ipv4Address::new(198, 18, 0, 2);
+    let any = raw::open(EXPERIMENT).unwrap();
+    let bound = raw::open(EXPERIMENT).unwrap();
+    raw::bind(bound, local).unwrap();
+    assert_eq!(
+        raw::bind(bound, Ipv4Address::new(192, 0, 2, 99))
+            .unwrap_err()
+            .kind,
+        RawErrorKind::AddressNotAvailable
+    );
+
+    let packet = Ipv4HeaderBuilder::new(EXPERIMENT, src, local)
+        .build(b"experiment")
+        .unwrap();
+    ipv4::input(&packet, dev);
+
+    // 同じ番号の pcb すべてに IP ヘッダごと複製が届く
+    for id in [any, bound] {
+        let received = raw::recvfrom(id, Some(Duration::from_millis(10))).unwrap();
+        assert_eq!(received.data, packet);
+        assert_eq!(received.src, src);
+        assert_eq!(received.dst, local);
+        let header = Ipv4Header::new_checked(&received.data[..]).unwrap();
+        assert_eq!(header.protocol_number(), EXPERIMENT);
+        assert_eq!(header.payload(), b"experiment");
+    }
+    assert!(matches!(
+        raw::recvfrom(any, Some(Duration::from_millis(10))),
+        Err(e) if e.kind == RawErrorKind::Timeout
+    ));
+    raw::close(any).unwrap();
+    raw::close(bound).unwrap();
+    assert_eq!(raw::close(any).unwrap_err().kind, RawErrorKind::InvalidId);
+}
+
+#[test]
+fn header_included() {
+    assert_eq!(
+        raw::open(0).unwrap_err().kind,
+        RawErrorKind::InvalidProtocol
+    );
+
+    // IPPROTO_RAW は常にヘッダを含める
+    let id = raw::open(IP_PROTOCOL_RAW).unwrap();
+    assert!(raw::header_included(id).unwrap());
+    assert_eq!(
+        raw::set_header_included(id, false).unwrap_err().kind,
+        RawErrorKind::InvalidProtocol
+    );
+    raw::close(id).unwrap();
+
+    let id = raw::open(EXPERIMENT).unwrap();
+    assert!(!raw::header_included(id).unwrap());
+    raw::set_header_included(id, true).unwrap();
+    let dst = Ipv4Address::new(203, 0, 113, 200);
+    assert_eq!(
+        raw::sendto(id, &[0x45; 10], dst).unwrap_err().kind,
+        RawErrorKind::InvalidHeader
+    );
+    let mut packet = Ipv4HeaderBuilder::new(EXPERIMENT, Ipv4Address::new(0, 0, 0, 0), dst)
+        .build(b"data")
+        .unwrap();
+    packet[0] = 0x65;
+    assert_eq!(
+        raw::sendto(id, &packet, dst).unwrap_err().kind,
+        RawErrorKind::InvalidHeader
+    );
+    raw::close(id).unwrap();
+}
+
+#[test]
+fn header_included_output() {
+    let dev = capture::device("raw0", "198.18.4.1/24");
+    let local = Ipv4Address::new(198, 18, 4, 1);
+    let peer = Ipv4Address::new(198, 18, 4, 2);
+    let id = raw::open(IP_PROTOCOL_RAW).unwrap();
+
+    // 全長とチェックサムは埋め直し、0 の ID と送信元と宛先は埋める
+    let mut packet = Ipv4HeaderBuilder::new(
+        EXPERIMENT,
+        Ipv4Address::new(0, 0, 0, 0),
+        Ipv4Address::new(0, 0, 0, 0),
+    )
+    .build(b"data")
+    .unwrap();
+    packet[2..4].copy_from_slice(&[0, 16]);
+    packet[10..12].copy_from_slice(&[0x12, 0x34]);
+    assert_eq!(raw::sendto(id, &packet, peer).unwrap(), packet.len());
+    let sent = capture::take(dev);
+    assert_eq!(sent.len(), 1);
+    let header = Ipv4Header::new_checked(&sent[0][..]).unwrap();
+    assert_eq!(header.total_length() as usize, packet.len());
+    assert!(header.verify_checksum());
+    assert_ne!(header.id(), 0);
+    assert_eq!(header.src_address(), local);
+    assert_eq!(header.dst_address(), peer);
+    assert_eq!(header.protocol_number(), EXPERIMENT);
+    assert_eq!(header.payload(), b"data");
+
+    // 0 でない ID と送信元はそのまま送る
+    let spoofed = Ipv4Address::new(198, 18, 4, 99);
+    let packet = Ipv4HeaderBuilder::new(EXPERIMENT, spoofed, peer)
+        .id(0x4242)
+        .build(b"data")
+        .unwrap();
+    raw::sendto(id, &packet, Ipv4Address::new(0, 0, 0, 0)).unwrap();
+    let sent = capture::take(dev);
+    assert_eq!(sent, vec![packet]);
+    raw::close(id).unwrap();
+}
@@ -0,0 +1,107 @@
+mod nd;
+mod slaac;
+
+use std::str::FromStr;
+
+use rustic_stack::ipv6::{
+    self, Ipv6Address, Ipv6ErrorKind, Ipv6Header, Ipv6HeaderBuilder, Ipv6HeaderErrorKind,
+    Ipv6Interface, Ipv6Network, IPV6_ADDRESS_ALL_NODES, IPV6_HEADER_SIZE,
+    IPV6_NEXT_HEADER_FRAGMENT, IPV6_NEXT_HEADER_HOP_BY_HOP, IPV6_NEXT_HEADER_ICMPV6,
+};
+
+#[test]
+fn address() {
+    let address = Ipv6Address::from_str("2001:DB8::1").unwrap();
+    assert_eq!(address.to_string(), "2001:db8::1");
+    assert_eq!(
+        address,
+        Ipv6Address::from_segments([0x2001, 0x0db8, 0, 0, 0, 0, 0, 1])
+    );
+    assert_eq!(Ipv6Address::from_u128(address.to_u128()), address);
+    assert!(!address.is_link_local());
+    assert!(Ipv6Address::from_str("fe80::1").unwrap().is_link_local());
+    assert!(IPV6_ADDRESS_ALL_NODES.is_multicast());
+    assert_eq!(
+        Ipv6Address::from_str("2001:db8::12:3456")
+            .unwrap()
+            .solicited_node()
+            .to_string(),
+        "ff02::1:ff12:3456"
+    );
+    assert!(Ipv6Address::from_str("2001:db8::g").is_err());
+}
+
+#[test]
+fn network() {
+    let network = Ipv6Network::from_str("2001:db8:1::10/48").unwrap();
+    assert_eq!(network.network().to_string(), "2001:db8:1::");
+    assert!(network.contains(Ipv6Address::from_str("2001:db8:1:ffff::1").unwrap()));
+    assert!(!network.contains(Ipv6Address::from_str("2001:db8:2::1").unwrap()));
+    assert!(Ipv6Network::from_str("2001:db8::/129").is_err());
+
+    let interface = Ipv6Interface::alloc("2001:db8::1/64").unwrap();
+    assert_eq!(interface.network().to_string(), "2001:db8::1/64");
+    assert!(interface.contains(Ipv6Address::from_str("2001:db8::2").unwrap()));
+}
+
+#[test]
+fn header() {
+    let src = Ipv6Address::from_str("fe80::1").unwrap();
+    let dst = Ipv6Address::from_str("fe80::2").unwrap();
+    let packet = Ipv6HeaderBuilder::new(IPV6_NEXT_HEADER_ICMPV6, src, dst)
+        .traffic_class(0xb8)
+        .flow_label(0x12345)
+        .hop_limit(255)
+        .build(b"payload")
+        .unwrap();
+    assert_eq!(packet.len(), IPV6_HEADER_SIZE + 7);
+
+    let header = Ipv6Header::new_checked(&packet[..]).unwrap();
+    assert_eq!(header.version(), 6);
+    assert_eq!(header.traffic_class(), 0xb8);
+    assert_eq!(header.flow_label(), 0x12345);
+    assert_eq!(header.payload_length(), 7);
+    assert_eq!(header.next_header(), IPV6_NEXT_HEADER_ICMPV6);
+    assert_eq!(header.hop_limit(), 255);
+    assert_eq!(header.src_address(), src);
+    assert_eq!(header.dst_address(), dst);
+    assert_eq!(header.payload(), b"payload");
+
+    assert_eq!(
+        Ipv6Header::new_checked(&packet[..IPV6_HEADER_SIZE + 3])
+            .err()
+            .unwrap()
+            .kind,
+        Ipv6HeaderErrorKind::PayloadLength
+    );
+    let mut v4 = packet.clone();
+    v4[0] = 0x45;
+    assert_eq!(
+        Ipv6Header::new_checked(&v4[..]).err().unwrap().kind,
+        Ipv6HeaderErrorKind::Version
+    );
+}
+
+#[test]
+fn extension_headers() {
+    let src = Ipv6Address::from_str("2001:db8::1").unwrap();
+    let dst = Ipv6Address::from_str("2001:db8::2").unwrap();
+    // Hop-by-Hop (PadN で 8 バイト) の後ろに ICMPv6
+    let mut payload = vec![IPV6_NEXT_HEADER_ICMPV6, 0, 1, 4, 0, 0, 0, 0];
+    payload.extend_from_slice(&[128, 0, 0, 0]);
+    let packet = Ipv6HeaderBuilder::new(IPV6_NEXT_HEADER_HOP_BY_HOP, src, dst)
+        .build(&payload)
+        .unwrap();
+    assert_eq!(
+        ipv6::upper_layer(&packet).unwrap(),
+        (IPV6_NEXT_HEADER_ICMPV6, IPV6_HEADER_SIZE + 8)
+    );
+
+    let packet = Ipv6HeaderBuilder::new(IPV6_NEXT_HEADER_FRAGMENT, src, dst)
+        .build(&payload)
+        .unwrap();
+    assert_eq!(
+        ipv6::upper_layer(&packet).unwrap_err(),
+        Ipv6ErrorKind::InvalidHeader
+    );
+}
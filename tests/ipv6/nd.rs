@@ -0,0 +1,172 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rustic_stack::ipv6::nd::{
+    self, NdOption, NeighborCache, NeighborLookup, NeighborState, DELAY_FIRST_PROBE_TIME,
+    MAX_MULTICAST_SOLICIT, ND_FLAG_OVERRIDE, ND_FLAG_SOLICITED, REACHABLE_TIME, RETRANS_TIMER,
+};
+use rustic_stack::ipv6::Ipv6Address;
+
+const MAC1: [u8; 6] = [0x00, 0x00, 0x5e, 0x00, 0x53, 0x01];
+const MAC2: [u8; 6] = [0x00, 0x00, 0x5e, 0x00, 0x53, 0x02];
+
+fn neighbor() -> Ipv6Address {
+    Ipv6Address::from_str("fe80::2").unwrap()
+}
+
+#[test]
+fn option_codec() {
+    let option = NdOption::SourceLinkLayerAddress(MAC1.to_vec());
+    let data = option.encode();
+    assert_eq!(data, vec![1, 1, 0x00, 0x00, 0x5e, 0x00, 0x53, 0x01]);
+
+    let mut both = data.clone();
+    both.extend(NdOption::Unknown(14, vec![0; 14]).encode());
+    let options = NdOption::parse_all(&both).unwrap();
+    assert_eq!(options.len(), 2);
+    assert_eq!(options[0], option);
+    assert_eq!(options[1], NdOption::Unknown(14, vec![0; 14]));
+
+    // 長さ 0 と途中で切れたオプションは不正
+    assert!(NdOption::parse_all(&[1, 0, 0, 0, 0, 0, 0, 0]).is_none());
+    assert!(NdOption::parse_all(&[1, 2, 0, 0, 0, 0, 0, 0]).is_none());
+    assert_eq!(NdOption::parse_all(&[]).unwrap(), vec![]);
+}
+
+#[test]
+fn multicast_hwaddr() {
+    let address = Ipv6Address::from_str("ff02::1:ff00:2").unwrap();
+    assert_eq!(
+        nd::multicast_hwaddr(address),
+        [0x33, 0x33, 0xff, 0x00, 0x00, 0x02]
+    );
+}
+
+#[test]
+fn resolve_queues_until_advertised() {
+    let mut cache = NeighborCache::new();
+    let now = Instant::now();
+    assert_eq!(
+        cache.resolve("eth0", neighbor(), vec![1], now),
+        NeighborLookup::Solicit
+    );
+    assert_eq!(
+        cache.resolve("eth0", neighbor(), vec![2], now),
+        NeighborLookup::Queued
+    );
+    assert_eq!(
+        cache.get("eth0", neighbor()).unwrap().state,
+        NeighborState::Incomplete
+    );
+
+    // リンク層アドレスの無い通知では解決しない
+    let pending = cache.advertise("eth0", neighbor(), None, ND_FLAG_SOLICITED, now);
+    assert!(pending.is_empty());
+
+    let pending = cache.advertise(
+        "eth0",
+        neighbor(),
+        Some(&MAC1),
+        ND_FLAG_SOLICITED | ND_FLAG_OVERRIDE,
+        now,
+    );
+    assert_eq!(pending, vec![vec![1], vec![2]]);
+    let entry = cache.get("eth0", neighbor()).unwrap();
+    assert_eq!(entry.state, NeighborState::Reachable);
+    assert_eq!(entry.hwaddr, MAC1.to_vec());
+    assert_eq!(
+        cache.resolve("eth0", neighbor(), vec![3], now),
+        NeighborLookup::Resolved(MAC1.to_vec())
+    );
+    // 別のデバイスのエントリは別物
+    assert!(cache.get("eth1", neighbor()).is_none());
+}
+
+#[test]
+fn unsolicited_advertisement() {
+    let mut cache = NeighborCache::new();
+    let now = Instant::now();
+    // 知らない相手の通知ではエントリを作らない
+    cache.advertise("eth0", neighbor(), Some(&MAC1), ND_FLAG_OVERRIDE, now);
+    assert!(cache.entries().is_empty());
+
+    cache.resolve("eth0", neighbor(), vec![1], now);
+    cache.advertise(
+        "eth0",
+        neighbor(),
+        Some(&MAC1),
+        ND_FLAG_SOLICITED | ND_FLAG_OVERRIDE,
+        now,
+    );
+    // 上書きしない通知は REACHABLE を STALE にするだけ
+    cache.advertise("eth0", neighbor(), Some(&MAC2), 0, now);
+    let entry = cache.get("eth0", neighbor()).unwrap();
+    assert_eq!(entry.state, NeighborState::Stale);
+    assert_eq!(entry.hwaddr, MAC1.to_vec());
+    // 上書きする通知はアドレスを置き換える
+    cache.advertise("eth0", neighbor(), Some(&MAC2), ND_FLAG_OVERRIDE, now);
+    let entry = cache.get("eth0", neighbor()).unwrap();
+    assert_eq!(entry.state, NeighborState::Stale);
+    assert_eq!(entry.hwaddr, MAC2.to_vec());
+}
+
+#[test]
+fn reachability_states() {
+    let mut cache = NeighborCache::new();
+    let now = Instant::now();
+    cache.learn("eth0", neighbor(), &MAC1, now);
+    assert_eq!(
+        cache.get("eth0", neighbor()).unwrap().state,
+        NeighborState::Stale
+    );
+
+    // STALE のエントリを使うと DELAY になり、期限が来たらユニキャストで確認する
+    assert_eq!(
+        cache.resolve("eth0", neighbor(), vec![1], now),
+        NeighborLookup::Resolved(MAC1.to_vec())
+    );
+    assert_eq!(
+        cache.get("eth0", neighbor()).unwrap().state,
+        NeighborState::Delay
+    );
+    let now = now + DELAY_FIRST_PROBE_TIME;
+    let probes = cache.tick(now);
+    assert_eq!(probes.len(), 1);
+    assert_eq!(probes[0].hwaddr, Some(MAC1.to_vec()));
+    assert_eq!(
+        cache.get("eth0", neighbor()).unwrap().state,
+        NeighborState::Probe
+    );
+
+    // 上位層からの確認で REACHABLE に戻り、期限が切れると STALE になる
+    cache.confirm(neighbor(), now);
+    assert_eq!(
+        cache.get("eth0", neighbor()).unwrap().state,
+        NeighborState::Reachable
+    );
+    assert!(cache
+        .tick(now + REACHABLE_TIME - Duration::from_millis(1))
+        .is_empty());
+    cache.tick(now + REACHABLE_TIME);
+    assert_eq!(
+        cache.get("eth0", neighbor()).unwrap().state,
+        NeighborState::Stale
+    );
+}
+
+#[test]
+fn incomplete_gives_up() {
+    let mut cache = NeighborCache::new();
+    let mut now = Instant::now();
+    cache.resolve("eth0", neighbor(), vec![1], now);
+    for _ in 1..MAX_MULTICAST_SOLICIT {
+        now += RETRANS_TIMER;
+        let probes = cache.tick(now);
+        assert_eq!(probes.len(), 1);
+        assert_eq!(probes[0].target, neighbor());
+        assert_eq!(probes[0].hwaddr, None);
+    }
+    now += RETRANS_TIMER;
+    assert!(cache.tick(now).is_empty());
+    assert!(cache.entries().is_empty());
+}
@@ -0,0 +1,177 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rustic_stack::ethernet::MAC_LENGTH;
+use rustic_stack::icmpv6::{self, Icmpv6Message, Icmpv6Type};
+use rustic_stack::ipv6::nd::{NdOption, NdPrefixInformation, ND_HOP_LIMIT};
+use rustic_stack::ipv6::slaac::{
+    self, DefaultRouterList, RouterAdvertisement, SLAAC_PREFIX_LENGTH,
+};
+use rustic_stack::ipv6::{
+    Ipv6Address, Ipv6Header, Ipv6HeaderBuilder, Ipv6Interface, IPV6_ADDRESS_ALL_NODES,
+    IPV6_NEXT_HEADER_ICMPV6,
+};
+use rustic_stack::net::NetDeviceType;
+
+use crate::capture;
+
+const MAC: [u8; 6] = [0x00, 0x00, 0x5e, 0x00, 0x53, 0x01];
+
+#[test]
+fn interface_id() {
+    let interface_id = slaac::interface_id(&MAC);
+    assert_eq!(
+        interface_id,
+        [0x02, 0x00, 0x5e, 0xff, 0xfe, 0x00, 0x53, 0x01]
+    );
+    assert_eq!(
+        slaac::link_local(&interface_id).to_string(),
+        "fe80::200:5eff:fe00:5301"
+    );
+    let prefix = Ipv6Address::from_str("2001:db8:1:2::").unwrap();
+    assert_eq!(
+        slaac::address_from_prefix(prefix, &interface_id).to_string(),
+        "2001:db8:1:2:200:5eff:fe00:5301"
+    );
+}
+
+#[test]
+fn parse_router_advertisement() {
+    let info = NdPrefixInformation {
+        prefix_length: SLAAC_PREFIX_LENGTH,
+        on_link: true,
+        autonomous: true,
+        valid_lifetime: 86400,
+        preferred_lifetime: 14400,
+        prefix: Ipv6Address::from_str("2001:db8::").unwrap(),
+    };
+    let mut data = vec![0, 0, 0x75, 0x30, 0, 0, 0x03, 0xe8];
+    data.extend(NdOption::SourceLinkLayerAddress(MAC.to_vec()).encode());
+    data.extend(NdOption::PrefixInformation(info).encode());
+    data.extend(NdOption::Mtu(1500).encode());
+
+    // 現在のホップリミット 64、M フラグ、ルータの寿命 1800 秒
+    let advertisement = RouterAdvertisement::parse(0x4080_0708, &data).unwrap();
+    assert_eq!(advertisement.cur_hop_limit, 64);
+    assert!(advertisement.managed);
+    assert!(!advertisement.other);
+    assert_eq!(advertisement.router_lifetime, 1800);
+    assert_eq!(advertisement.reachable_time, 30000);
+    assert_eq!(advertisement.retrans_timer, 1000);
+    assert_eq!(advertisement.prefixes().collect::<Vec<_>>(), vec![&info]);
+    assert!(advertisement.options.contains(&NdOption::Mtu(1500)));
+
+    assert!(RouterAdvertisement::parse(0, &data[..4]).is_none());
+}
+
+#[test]
+fn valid_lifetime_rule() {
+    let hour = Duration::from_secs(3600);
+    // 長くする広告と 2 時間を超える広告はそのまま受け入れる
+    assert_eq!(
+        slaac::update_valid_lifetime(Some(hour * 3), Some(hour)),
+        Some(hour * 3)
+    );
+    assert_eq!(
+        slaac::update_valid_lifetime(Some(hour), Some(hour / 2)),
+        Some(hour)
+    );
+    assert_eq!(slaac::update_valid_lifetime(None, Some(hour)), None);
+    // 残りが 2 時間以下なら短縮を無視し、それより長ければ 2 時間にとどめる
+    assert_eq!(
+        slaac::update_valid_lifetime(Some(Duration::from_secs(60)), Some(hour)),
+        Some(hour)
+    );
+    assert_eq!(
+        slaac::update_valid_lifetime(Some(Duration::from_secs(60)), Some(hour * 5)),
+        Some(hour * 2)
+    );
+    assert_eq!(
+        slaac::update_valid_lifetime(Some(Duration::from_secs(60)), None),
+        Some(hour * 2)
+    );
+    assert_eq!(slaac::lifetime(0xffff_ffff), None);
+}
+
+#[test]
+fn default_router_selection() {
+    let router1 = Ipv6Address::from_str("fe80::1").unwrap();
+    let router2 = Ipv6Address::from_str("fe80::2").unwrap();
+    let now = Instant::now();
+    let mut routers = DefaultRouterList::new();
+    assert_eq!(routers.select("eth0", |_| true), None);
+
+    routers.update("eth0", router1, Duration::from_secs(10), now);
+    routers.update("eth0", router2, Duration::from_secs(1800), now);
+    assert_eq!(routers.select("eth0", |_| false), Some(router1));
+    assert_eq!(
+        routers.select("eth0", |router| router.address == router2),
+        Some(router2)
+    );
+    assert_eq!(routers.select("eth1", |_| true), None);
+
+    routers.expire(now + Duration::from_secs(10));
+    assert_eq!(routers.routers().len(), 1);
+    // 寿命 0 の広告で外れる
+    routers.update("eth0", router2, Duration::from_secs(0), now);
+    assert!(routers.routers().is_empty());
+}
+
+#[test]
+fn deprecated_address_is_not_preferred_as_source() {
+    const SLAAC_MAC: [u8; 6] = [0x00, 0x00, 0x5e, 0x00, 0x53, 0x39];
+    let mut dev = capture::alloc("slaac0");
+    dev.device_type = NetDeviceType::Ethernet as u16;
+    dev.hwaddr[..MAC_LENGTH].copy_from_slice(&SLAAC_MAC);
+    dev.address_length = MAC_LENGTH as u16;
+    let dev = capture::register(dev);
+    slaac::device_open(dev);
+
+    // 優先寿命 1 秒、有効期間 1 時間のプレフィックスを広告する
+    let prefix = Ipv6Address::from_str("2001:db8:39::").unwrap();
+    let info = NdPrefixInformation {
+        prefix_length: SLAAC_PREFIX_LENGTH,
+        on_link: true,
+        autonomous: true,
+        valid_lifetime: 3600,
+        preferred_lifetime: 1,
+        prefix,
+    };
+    let mut data = vec![0; 8];
+    data.extend(NdOption::PrefixInformation(info).encode());
+    let router = Ipv6Address::from_str("fe80::39").unwrap();
+    let message = icmpv6::build(
+        Icmpv6Type::RouterAdvertisement,
+        0,
+        0x4000_0000,
+        &data,
+        router,
+        IPV6_ADDRESS_ALL_NODES,
+    );
+    let packet = Ipv6HeaderBuilder::new(IPV6_NEXT_HEADER_ICMPV6, router, IPV6_ADDRESS_ALL_NODES)
+        .hop_limit(ND_HOP_LIMIT)
+        .build(&message)
+        .unwrap();
+    let ipv6_hdr = Ipv6Header::new_checked(&packet[..]).unwrap();
+    let message = Icmpv6Message::new_checked(ipv6_hdr.payload()).unwrap();
+    let now = Instant::now();
+    slaac::input(&ipv6_hdr, &message, dev);
+
+    // ND を使わないデバイスなので DAD を待たずに使える
+    let autoconf = slaac::address_from_prefix(prefix, &slaac::interface_id(&SLAAC_MAC));
+    assert!(Ipv6Interface::select(autoconf).is_some());
+    let manual = Ipv6Address::from_str("2001:db8:39::1").unwrap();
+    Ipv6Interface::register(Ipv6Interface::alloc("2001:db8:39::1/64").unwrap(), dev).unwrap();
+
+    // 共通するプレフィックスは自動設定のアドレスのほうが長い
+    let dst = Ipv6Address::from_u128(autoconf.to_u128() ^ 1);
+    let source = |now| Ipv6Interface::select_source_at(dst, now).unwrap().unicast;
+    assert_eq!(source(now), autoconf);
+    // 優先寿命が切れたら他のアドレスを使う
+    let later = now + Duration::from_secs(2);
+    assert_eq!(slaac::deprecated_addresses(later), vec![autoconf]);
+    assert_eq!(source(later), manual);
+    // 他に無ければ優先寿命が切れていても使う
+    Ipv6Interface::unregister(manual, dev).unwrap();
+    assert_eq!(source(later), autoconf);
+}
@@ -1 +1,12 @@
+mod capture;
 mod device;
+mod dhcp;
+mod dns;
+mod icmp;
+mod icmpv6;
+mod igmp;
+mod ipv4;
+mod ipv6;
+mod socket;
+mod tcp;
+mod udp;
@@ -0,0 +1,129 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use rustic_stack::ipv4::{Ipv4Address, Ipv4Endpoint, Ipv4HeaderBuilder, Protocol};
+use rustic_stack::net::NetDevice;
+use rustic_stack::socket::{AsyncTcpListener, AsyncTcpStream, AsyncUdpSocket};
+use rustic_stack::udp;
+
+/// 起こされた回数を数えるだけの waker
+struct CountWaker(AtomicUsize);
+
+impl Wake for CountWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn count_waker() -> (Arc<CountWaker>, Waker) {
+    let count = Arc::new(CountWaker(AtomicUsize::new(0)));
+    (count.clone(), Waker::from(count))
+}
+
+fn poll_once<F: Future>(future: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+    future.poll(&mut Context::from_waker(waker))
+}
+
+/// net_thread の代わりに UDP の受信処理へ直接渡す
+fn deliver(dev: &'static NetDevice, src: Ipv4Endpoint, dst: Ipv4Endpoint, data: &[u8]) {
+    let segment = udp::build(src, dst, data);
+    let packet = Ipv4HeaderBuilder::new(Protocol::Udp as u8, src.address, dst.address)
+        .build(&segment)
+        .unwrap();
+    udp::input(&packet, dev);
+}
+
+#[test]
+fn udp_recv_wakes_task() {
+    let dev: &'static NetDevice = Box::leak(NetDevice::alloc());
+    let local = Ipv4Endpoint::new(Ipv4Address::new(192, 0, 2, 1), 40101);
+    let peer = Ipv4Endpoint::new(Ipv4Address::new(192, 0, 2, 2), 5000);
+    let other = Ipv4Endpoint::new(Ipv4Address::new(192, 0, 2, 3), 5000);
+    let socket = AsyncUdpSocket::bind("0.0.0.0:40101").unwrap();
+    socket.connect("192.0.2.2:5000").unwrap();
+
+    let (count, waker) = count_waker();
+    let mut buf = [0; 16];
+    let mut future = Box::pin(socket.recv_from(&mut buf));
+    assert!(poll_once(future.as_mut(), &waker).is_pending());
+
+    // connect した相手以外からのものは捨てて待ち続ける
+    deliver(dev, other, local, b"ignored");
+    assert_eq!(count.0.load(Ordering::SeqCst), 1);
+    assert!(poll_once(future.as_mut(), &waker).is_pending());
+
+    deliver(dev, peer, local, b"hello");
+    assert_eq!(count.0.load(Ordering::SeqCst), 2);
+    match poll_once(future.as_mut(), &waker) {
+        Poll::Ready(Ok((len, from))) => {
+            assert_eq!(len, 5);
+            assert_eq!(from, "192.0.2.2:5000".parse::<SocketAddr>().unwrap());
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    drop(future);
+    assert_eq!(&buf[..5], b"hello");
+}
+
+#[test]
+fn udp_close_wakes_task() {
+    let socket = AsyncUdpSocket::bind("0.0.0.0:40102").unwrap();
+    let (count, waker) = count_waker();
+    let mut buf = [0; 16];
+    {
+        let mut future = Box::pin(socket.recv(&mut buf));
+        assert!(poll_once(future.as_mut(), &waker).is_pending());
+    }
+    let fd = socket.as_raw_fd();
+    drop(socket);
+    assert_eq!(count.0.load(Ordering::SeqCst), 1);
+    assert!(rustic_stack::socket::close(fd).is_err());
+}
+
+#[test]
+fn tcp_accept_pending() {
+    let listener = AsyncTcpListener::bind("0.0.0.0:40103").unwrap();
+    assert_eq!(
+        listener.local_addr().unwrap(),
+        "0.0.0.0:40103".parse::<SocketAddr>().unwrap()
+    );
+    let (count, waker) = count_waker();
+    let mut future = Box::pin(listener.accept());
+    assert!(poll_once(future.as_mut(), &waker).is_pending());
+    assert!(poll_once(future.as_mut(), &waker).is_pending());
+    assert_eq!(count.0.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn tcp_close_wakes_only_its_task() {
+    let closed = AsyncTcpListener::bind("0.0.0.0:40104").unwrap();
+    let other = AsyncTcpListener::bind("0.0.0.0:40105").unwrap();
+    let (closed_count, closed_waker) = count_waker();
+    let (other_count, other_waker) = count_waker();
+    {
+        let mut future = Box::pin(closed.accept());
+        assert!(poll_once(future.as_mut(), &closed_waker).is_pending());
+    }
+    let mut future = Box::pin(other.accept());
+    assert!(poll_once(future.as_mut(), &other_waker).is_pending());
+
+    drop(closed);
+    assert_eq!(closed_count.0.load(Ordering::SeqCst), 1);
+    assert_eq!(other_count.0.load(Ordering::SeqCst), 0);
+    assert!(poll_once(future.as_mut(), &other_waker).is_pending());
+}
+
+#[test]
+fn tcp_connect_ipv6_is_unsupported() {
+    let (_, waker) = count_waker();
+    let mut future = Box::pin(AsyncTcpStream::connect("[::1]:80".parse().unwrap()));
+    match poll_once(future.as_mut(), &waker) {
+        Poll::Ready(Err(e)) => assert_eq!(e.kind(), io::ErrorKind::Unsupported),
+        _ => panic!("IPv6 must be rejected without waiting"),
+    }
+}
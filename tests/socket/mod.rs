@@ -0,0 +1,227 @@
+mod async_io;
+mod stream;
+
+use std::time::Duration;
+
+use rustic_stack::ipv4::{
+    self, IpInterface, Ipv4Address, Ipv4Endpoint, Ipv4HeaderBuilder, IP_ADDRESS_ANY,
+};
+use rustic_stack::net::NetDevice;
+use rustic_stack::socket::{self, SocketErrorKind, SocketOption, SocketOptionName, SocketType};
+use rustic_stack::tcp::{CongestionAlgorithm, TcpKeepalive};
+
+#[test]
+fn bad_descriptor() {
+    let fd = socket::socket(SocketType::Datagram).unwrap();
+    socket::close(fd).unwrap();
+    let err = socket::send(fd, b"data").unwrap_err();
+    assert_eq!(err.kind, SocketErrorKind::BadDescriptor);
+    assert_eq!(err.kind.errno(), 9);
+    assert!(socket::close(fd).is_err());
+}
+
+#[test]
+fn datagram_bind_and_timeout() {
+    let local = Ipv4Endpoint::new(IP_ADDRESS_ANY, 40001);
+    let a = socket::socket(SocketType::Datagram).unwrap();
+    let b = socket::socket(SocketType::Datagram).unwrap();
+    socket::bind(a, local).unwrap();
+    assert_eq!(
+        socket::bind(b, local).unwrap_err().kind,
+        SocketErrorKind::AddressInUse
+    );
+    // どのインターフェースにもないアドレスには bind できない
+    assert_eq!(
+        socket::bind(
+            b,
+            Ipv4Endpoint::new(Ipv4Address::new(192, 88, 99, 1), 40009)
+        )
+        .unwrap_err()
+        .kind,
+        SocketErrorKind::AddressNotAvailable
+    );
+    assert_eq!(socket::getsockname(a).unwrap(), local);
+
+    socket::setsockopt(
+        a,
+        SocketOption::ReceiveTimeout(Some(Duration::from_millis(10))),
+    )
+    .unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(
+        socket::recv(a, &mut buf).unwrap_err().kind,
+        SocketErrorKind::TimedOut
+    );
+    // 接続していない UDP ソケットは send で送り先を決められない
+    assert_eq!(
+        socket::send(b, b"data").unwrap_err().kind,
+        SocketErrorKind::NotConnected
+    );
+    assert_eq!(
+        socket::listen(a, 1).unwrap_err().kind,
+        SocketErrorKind::NotSupported
+    );
+    socket::close(a).unwrap();
+    socket::close(b).unwrap();
+}
+
+#[test]
+fn datagram_connect() {
+    let fd = socket::socket(SocketType::Datagram).unwrap();
+    assert_eq!(
+        socket::getpeername(fd).unwrap_err().kind,
+        SocketErrorKind::NotConnected
+    );
+    let peer = Ipv4Endpoint::new(Ipv4Address::new(192, 0, 2, 53), 53);
+    socket::connect(fd, peer).unwrap();
+    assert_eq!(socket::getpeername(fd).unwrap(), peer);
+    socket::close(fd).unwrap();
+}
+
+#[test]
+fn stream_listen_and_accept_timeout() {
+    let fd = socket::socket(SocketType::Stream).unwrap();
+    socket::bind(fd, Ipv4Endpoint::new(IP_ADDRESS_ANY, 40002)).unwrap();
+    socket::listen(fd, 4).unwrap();
+    socket::setsockopt(
+        fd,
+        SocketOption::ReceiveTimeout(Some(Duration::from_millis(10))),
+    )
+    .unwrap();
+    assert_eq!(
+        socket::accept(fd).unwrap_err().kind,
+        SocketErrorKind::TimedOut
+    );
+    // LISTEN しているソケットはつながっていない
+    let mut buf = [0; 16];
+    assert_eq!(
+        socket::recv(fd, &mut buf).unwrap_err().kind,
+        SocketErrorKind::NotConnected
+    );
+    socket::close(fd).unwrap();
+}
+
+#[test]
+fn stream_connect_without_route() {
+    let fd = socket::socket(SocketType::Stream).unwrap();
+    let err =
+        socket::connect(fd, Ipv4Endpoint::new(Ipv4Address::new(203, 0, 113, 1), 80)).unwrap_err();
+    assert_eq!(err.kind, SocketErrorKind::NetworkUnreachable);
+    assert_eq!(
+        socket::send(fd, b"data").unwrap_err().kind,
+        SocketErrorKind::NotConnected
+    );
+    socket::close(fd).unwrap();
+}
+
+#[test]
+fn congestion_control_option() {
+    let stream = socket::socket(SocketType::Stream).unwrap();
+    socket::setsockopt(
+        stream,
+        SocketOption::CongestionControl(CongestionAlgorithm::Cubic),
+    )
+    .unwrap();
+    assert_eq!(
+        socket::getsockopt(stream, SocketOptionName::CongestionControl).unwrap(),
+        SocketOption::CongestionControl(CongestionAlgorithm::Cubic)
+    );
+    socket::close(stream).unwrap();
+
+    // UDP には輻輳制御が無い
+    let datagram = socket::socket(SocketType::Datagram).unwrap();
+    let err = socket::setsockopt(
+        datagram,
+        SocketOption::CongestionControl(CongestionAlgorithm::NewReno),
+    )
+    .unwrap_err();
+    assert_eq!(err.kind, SocketErrorKind::NotSupported);
+    socket::close(datagram).unwrap();
+}
+
+#[test]
+fn keepalive_option() {
+    let stream = socket::socket(SocketType::Stream).unwrap();
+    assert_eq!(
+        socket::getsockopt(stream, SocketOptionName::KeepAlive).unwrap(),
+        SocketOption::KeepAlive(None)
+    );
+    let keepalive = TcpKeepalive {
+        idle: Duration::from_secs(60),
+        ..TcpKeepalive::default()
+    };
+    socket::setsockopt(stream, SocketOption::KeepAlive(Some(keepalive))).unwrap();
+    assert_eq!(
+        socket::getsockopt(stream, SocketOptionName::KeepAlive).unwrap(),
+        SocketOption::KeepAlive(Some(keepalive))
+    );
+    socket::close(stream).unwrap();
+
+    let datagram = socket::socket(SocketType::Datagram).unwrap();
+    let err = socket::getsockopt(datagram, SocketOptionName::KeepAlive).unwrap_err();
+    assert_eq!(err.kind, SocketErrorKind::NotSupported);
+    socket::close(datagram).unwrap();
+}
+
+#[test]
+fn raw_socket() {
+    let err = socket::socket(SocketType::Raw(0)).unwrap_err();
+    assert_eq!(err.kind, SocketErrorKind::ProtocolNotSupported);
+    assert_eq!(err.kind.errno(), 93);
+
+    let dev: &'static NetDevice = Box::leak(NetDevice::alloc());
+    IpInterface::register(IpInterface::alloc_cidr("198.18.1.1/24").unwrap(), dev).unwrap();
+    let local = Ipv4Address::new(198, 18, 1, 1);
+    let peer = Ipv4Address::new(198, 18, 1, 2);
+    let other = Ipv4Address::new(198, 18, 1, 3);
+
+    let fd = socket::socket(SocketType::Raw(254)).unwrap();
+    socket::bind(fd, Ipv4Endpoint::new(local, 0)).unwrap();
+    assert_eq!(
+        socket::getsockname(fd).unwrap(),
+        Ipv4Endpoint::new(local, 0)
+    );
+    assert_eq!(
+        socket::getsockopt(fd, SocketOptionName::HeaderIncluded).unwrap(),
+        SocketOption::HeaderIncluded(false)
+    );
+    socket::setsockopt(fd, SocketOption::HeaderIncluded(true)).unwrap();
+    assert_eq!(
+        socket::getsockopt(fd, SocketOptionName::HeaderIncluded).unwrap(),
+        SocketOption::HeaderIncluded(true)
+    );
+    assert_eq!(
+        socket::listen(fd, 1).unwrap_err().kind,
+        SocketErrorKind::NotSupported
+    );
+
+    // connect した相手以外からのパケットは捨てる
+    socket::connect(fd, Ipv4Endpoint::new(peer, 0)).unwrap();
+    for src in [other, peer] {
+        let packet = Ipv4HeaderBuilder::new(254, src, local)
+            .build(b"raw")
+            .unwrap();
+        ipv4::input(&packet, dev);
+    }
+    socket::setsockopt(
+        fd,
+        SocketOption::ReceiveTimeout(Some(Duration::from_millis(10))),
+    )
+    .unwrap();
+    let mut buf = [0; 64];
+    let (len, from) = socket::recvfrom(fd, &mut buf).unwrap();
+    assert_eq!(from, Ipv4Endpoint::new(peer, 0));
+    // IP ヘッダから受け取る
+    assert_eq!(len, 23);
+    assert_eq!(&buf[20..len], b"raw");
+    assert_eq!(
+        socket::recv(fd, &mut buf).unwrap_err().kind,
+        SocketErrorKind::TimedOut
+    );
+    socket::close(fd).unwrap();
+
+    let datagram = socket::socket(SocketType::Datagram).unwrap();
+    let err = socket::setsockopt(datagram, SocketOption::HeaderIncluded(true)).unwrap_err();
+    assert_eq!(err.kind, SocketErrorKind::NotSupported);
+    socket::close(datagram).unwrap();
+}
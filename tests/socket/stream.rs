@@ -0,0 +1,94 @@
+use std::io::ErrorKind;
+use std::net::{Shutdown, SocketAddr};
+use std::time::Duration;
+
+use rustic_stack::socket::{TcpListener, TcpStream, UdpSocket};
+
+#[test]
+fn udp_socket() {
+    let socket = UdpSocket::bind("0.0.0.0:40101").unwrap();
+    assert_eq!(
+        socket.local_addr().unwrap(),
+        "0.0.0.0:40101".parse::<SocketAddr>().unwrap()
+    );
+    assert_eq!(
+        UdpSocket::bind("0.0.0.0:40101").unwrap_err().kind(),
+        ErrorKind::AddrInUse
+    );
+
+    // std と同じく 0 秒のタイムアウトは拒否する
+    assert_eq!(
+        socket
+            .set_read_timeout(Some(Duration::ZERO))
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidInput
+    );
+    let timeout = Some(Duration::from_millis(10));
+    socket.set_read_timeout(timeout).unwrap();
+    assert_eq!(socket.read_timeout().unwrap(), timeout);
+    assert_eq!(socket.write_timeout().unwrap(), None);
+    let mut buf = [0; 16];
+    assert_eq!(
+        socket.recv_from(&mut buf).unwrap_err().kind(),
+        ErrorKind::TimedOut
+    );
+
+    assert_eq!(
+        socket.peer_addr().unwrap_err().kind(),
+        ErrorKind::NotConnected
+    );
+    socket.connect("192.0.2.53:53").unwrap();
+    assert_eq!(
+        socket.peer_addr().unwrap(),
+        "192.0.2.53:53".parse::<SocketAddr>().unwrap()
+    );
+}
+
+#[test]
+fn ipv6_is_unsupported() {
+    assert_eq!(
+        UdpSocket::bind("[::1]:40102").unwrap_err().kind(),
+        ErrorKind::Unsupported
+    );
+    assert_eq!(
+        TcpListener::bind("[::1]:40102").unwrap_err().kind(),
+        ErrorKind::Unsupported
+    );
+}
+
+#[test]
+fn tcp_listener() {
+    let listener = TcpListener::bind("0.0.0.0:40103").unwrap();
+    assert_eq!(listener.local_addr().unwrap().port(), 40103);
+    assert_eq!(
+        TcpListener::bind("0.0.0.0:40103").unwrap_err().kind(),
+        ErrorKind::AddrInUse
+    );
+    drop(listener);
+    // drop で閉じたので同じポートをもう一度使える
+    TcpListener::bind("0.0.0.0:40103").unwrap();
+}
+
+#[test]
+fn tcp_stream_unreachable() {
+    let err = TcpStream::connect_timeout(
+        &"203.0.113.1:80".parse().unwrap(),
+        Duration::from_millis(10),
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NetworkUnreachable);
+    let err = TcpStream::connect("203.0.113.1:80").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NetworkUnreachable);
+}
+
+#[test]
+fn read_after_shutdown() {
+    // UDP でも受信側を閉じた後の読み出しは 0 を返す
+    let socket = UdpSocket::bind("0.0.0.0:40104").unwrap();
+    socket.connect("192.0.2.1:7").unwrap();
+    let fd = socket.as_raw_fd();
+    rustic_stack::socket::shutdown(fd, Shutdown::Read).unwrap();
+    let mut buf = [0; 4];
+    assert_eq!(socket.recv(&mut buf).unwrap(), 0);
+}
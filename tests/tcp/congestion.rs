@@ -0,0 +1,139 @@
+use std::time::{Duration, Instant};
+
+use rustic_stack::tcp::congestion::{self, initial_window};
+use rustic_stack::tcp::{
+    self, CongestionAlgorithm, CongestionControl, Cubic, NewReno, TCP_DEFAULT_MSS,
+};
+
+use super::establish;
+
+const MSS: u32 = TCP_DEFAULT_MSS as u32;
+
+#[test]
+fn newreno_window() {
+    let now = Instant::now();
+    let mut cc = NewReno::new(TCP_DEFAULT_MSS);
+    assert_eq!(cc.cwnd(), initial_window(MSS));
+    assert_eq!(cc.cwnd(), 4 * MSS);
+
+    // slow start では 1 つの ACK で 1 MSS まで
+    cc.on_ack(3 * MSS, None, now);
+    assert_eq!(cc.cwnd(), 5 * MSS);
+
+    cc.on_timeout(10 * MSS, now);
+    assert_eq!(cc.ssthresh(), 5 * MSS);
+    assert_eq!(cc.cwnd(), MSS);
+    for _ in 0..4 {
+        cc.on_ack(MSS, None, now);
+    }
+    assert_eq!(cc.cwnd(), 5 * MSS);
+
+    // 輻輳回避では cwnd 分が確認されて 1 MSS 増える
+    for _ in 0..4 {
+        cc.on_ack(MSS, None, now);
+    }
+    assert_eq!(cc.cwnd(), 5 * MSS);
+    cc.on_ack(MSS, None, now);
+    assert_eq!(cc.cwnd(), 6 * MSS);
+}
+
+#[test]
+fn newreno_fast_recovery() {
+    let now = Instant::now();
+    let mut cc = NewReno::new(TCP_DEFAULT_MSS);
+    cc.on_fast_retransmit(20 * MSS, now);
+    assert_eq!(cc.ssthresh(), 10 * MSS);
+    assert_eq!(cc.cwnd(), 13 * MSS);
+    cc.on_duplicate_ack();
+    assert_eq!(cc.cwnd(), 14 * MSS);
+    cc.on_partial_ack(2 * MSS);
+    assert_eq!(cc.cwnd(), 13 * MSS);
+    cc.on_recovery_exit();
+    assert_eq!(cc.cwnd(), 10 * MSS);
+
+    // 小さすぎる ssthresh は 2 MSS にする
+    cc.on_fast_retransmit(MSS, now);
+    assert_eq!(cc.ssthresh(), 2 * MSS);
+}
+
+/// rtt ごとに cwnd 分の ACK を受け取ったとして、経過時間ごとの cwnd を返す
+fn run(cc: &mut dyn CongestionControl, start: Instant, rtt: Duration, rounds: u32) -> Vec<u32> {
+    (1..=rounds)
+        .map(|round| {
+            let now = start + rtt * round;
+            for _ in 0..cc.cwnd() / MSS {
+                cc.on_ack(MSS, Some(rtt), now);
+            }
+            cc.cwnd() / MSS
+        })
+        .collect()
+}
+
+#[test]
+fn cubic_growth() {
+    let start = Instant::now();
+    let mut cc = Cubic::new(TCP_DEFAULT_MSS);
+    cc.window_mut().cwnd = 100 * MSS;
+    cc.window_mut().ssthresh = 50 * MSS;
+    cc.on_fast_retransmit(100 * MSS, start);
+    assert_eq!(cc.ssthresh(), 70 * MSS);
+    cc.on_recovery_exit();
+    assert_eq!(cc.cwnd(), 70 * MSS);
+
+    // K = (30 / 0.4)^(1/3) ≒ 4.2 秒で元のウィンドウに戻り、そこでは伸びが鈍る
+    let rtt = Duration::from_millis(100);
+    let windows = run(&mut cc, start, rtt, 80);
+    let at = |secs: f64| windows[(secs / rtt.as_secs_f64()) as usize - 1];
+    assert!(at(1.0) > 80);
+    assert!((95..=102).contains(&at(4.2)), "{}", at(4.2));
+    assert!(at(4.2) - at(3.2) < at(1.0) - 70);
+    // w_max を超えると再び速く伸びる
+    assert!(at(8.0) > 110, "{}", at(8.0));
+
+    cc.on_timeout(at(8.0) * MSS, start);
+    assert_eq!(cc.cwnd(), MSS);
+    assert_eq!(cc.name(), "cubic");
+}
+
+#[test]
+fn cwnd_limits_output() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    // Timestamp オプションの分だけ 1 つのセグメントに載るデータは減る
+    let mss = a.send_mss();
+    a.set_congestion_control(Box::new(NewReno::new(mss)));
+    let data = vec![1; 10 * mss as usize];
+    a.send(&data);
+    let segments = a.output();
+    assert_eq!(segments.len(), 4);
+    assert!(a.output().is_empty());
+
+    // ACK ごとに 1 MSS 広がるので次は 8 つまで出せる
+    let acks: Vec<_> = segments
+        .iter()
+        .flat_map(|segment| b.input(segment, now))
+        .collect();
+    let more: Vec<_> = acks.iter().flat_map(|ack| a.input(ack, now)).collect();
+    assert_eq!(a.congestion().cwnd(), 8 * mss as u32);
+    assert_eq!(more.len(), 6);
+}
+
+#[test]
+fn select_algorithm() {
+    let id = tcp::open().unwrap();
+    assert_eq!(
+        tcp::congestion_control(id).unwrap(),
+        congestion::default_algorithm()
+    );
+    tcp::set_congestion_control(id, CongestionAlgorithm::Cubic).unwrap();
+    assert_eq!(
+        tcp::congestion_control(id).unwrap(),
+        CongestionAlgorithm::Cubic
+    );
+    tcp::close(id).unwrap();
+    assert_eq!(
+        CongestionAlgorithm::Cubic.build(TCP_DEFAULT_MSS).name(),
+        "cubic"
+    );
+    assert_eq!(CongestionAlgorithm::NewReno.to_string(), "newreno");
+}
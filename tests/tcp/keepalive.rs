@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+use rustic_stack::tcp::{
+    TcpErrorKind, TcpKeepalive, TcpState, TCP_FLAG_ACK, TCP_FLAG_RST, TCP_KEEPALIVE_IDLE,
+};
+
+use super::{deliver, establish};
+
+fn keepalive() -> TcpKeepalive {
+    TcpKeepalive {
+        idle: Duration::from_secs(10),
+        interval: Duration::from_secs(1),
+        probes: 2,
+    }
+}
+
+#[test]
+fn probe_and_answer() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    assert!(a.tick(now + TCP_KEEPALIVE_IDLE).is_empty());
+    a.set_keepalive(Some(keepalive()));
+    assert!(a.tick(now + Duration::from_secs(9)).is_empty());
+
+    // 確認済みの最後のバイトを送り直すように見せる
+    let probe = a.tick(now + Duration::from_secs(10));
+    assert_eq!(probe.len(), 1);
+    assert_eq!(probe[0].flags, TCP_FLAG_ACK);
+    assert_eq!(probe[0].seq, a.snd.nxt.wrapping_sub(1));
+    assert!(probe[0].data.is_empty());
+
+    // 応答があれば idle を測り直す
+    let later = now + Duration::from_secs(10);
+    let answer = deliver(&mut b, probe, later);
+    assert_eq!(answer.len(), 1);
+    assert!(deliver(&mut a, answer, later).is_empty());
+    assert!(a.tick(later + Duration::from_secs(5)).is_empty());
+    assert_eq!(a.tick(later + Duration::from_secs(10)).len(), 1);
+    assert_eq!(a.state, TcpState::Established);
+}
+
+#[test]
+fn probes_exhausted() {
+    let now = Instant::now();
+    let (mut a, _) = establish(now);
+    a.set_keepalive(Some(keepalive()));
+    assert_eq!(a.tick(now + Duration::from_secs(10)).len(), 1);
+    assert!(a.tick(now + Duration::from_millis(10_500)).is_empty());
+    assert_eq!(a.tick(now + Duration::from_secs(11)).len(), 1);
+
+    let reset = a.tick(now + Duration::from_secs(12));
+    assert_eq!(reset[0].flags, TCP_FLAG_RST);
+    assert_eq!(a.state, TcpState::Closed);
+    assert_eq!(a.error(), Some(TcpErrorKind::Timeout));
+}
+
+#[test]
+fn no_probe_with_data_in_flight() {
+    let now = Instant::now();
+    let (mut a, _) = establish(now);
+    a.set_keepalive(Some(keepalive()));
+    a.send(b"pending");
+    let sent = a.output();
+    // 確認待ちがあれば再送タイマーに任せる
+    let segments = a.tick(now + Duration::from_secs(10));
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].data, sent[0].data);
+}
@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant};
+
+use rustic_stack::ipv4::{Ipv4Address, Ipv4Endpoint, Ipv4HeaderBuilder, Protocol};
+use rustic_stack::net::NetDevice;
+use rustic_stack::tcp::syncookie::{self, SYN_COOKIE_PERIOD};
+use rustic_stack::tcp::{
+    self, header, TcpErrorKind, TcpState, TCP_FLAG_ACK, TCP_FLAG_RST, TCP_FLAG_SYN,
+    TCP_SYN_QUEUE_SIZE,
+};
+
+use super::{client, server};
+
+/// net_thread の代わりに TCP の受信処理へ直接渡す
+fn inject(
+    dev: &'static NetDevice,
+    src: Ipv4Endpoint,
+    dst: Ipv4Endpoint,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+) {
+    let segment = header::build(src, dst, seq, ack, flags, 8192, &[], &[]);
+    let packet = Ipv4HeaderBuilder::new(Protocol::Tcp as u8, src.address, dst.address)
+        .build(&segment)
+        .unwrap();
+    tcp::input(&packet, dev);
+}
+
+fn listener(port: u16, backlog: usize) -> (usize, Ipv4Endpoint) {
+    let local = Ipv4Endpoint::new(Ipv4Address::new(192, 0, 2, 10), port);
+    let id = tcp::open().unwrap();
+    tcp::bind(id, Ipv4Endpoint::new(Ipv4Address::new(0, 0, 0, 0), port)).unwrap();
+    tcp::listen(id, backlog).unwrap();
+    (id, local)
+}
+
+fn peer(port: u16) -> Ipv4Endpoint {
+    Ipv4Endpoint::new(Ipv4Address::new(198, 51, 100, 1), port)
+}
+
+#[test]
+fn syn_cookie() {
+    // カウンタの起点は最初に使ったときなので、それより後の時刻で試す
+    let now = Instant::now() + SYN_COOKIE_PERIOD * 4;
+    let cookie = syncookie::generate(server(), client(), 1000, 1460, now);
+    assert_eq!(
+        syncookie::check(server(), client(), 1000, cookie, now),
+        Some(1460)
+    );
+    // 表にない MSS は小さい方に丸める
+    let cookie = syncookie::generate(server(), client(), 1000, 1400, now);
+    assert_eq!(
+        syncookie::check(server(), client(), 1000, cookie, now),
+        Some(1300)
+    );
+
+    // 次の周期までは受け付ける
+    let later = now + SYN_COOKIE_PERIOD;
+    assert_eq!(
+        syncookie::check(server(), client(), 1000, cookie, later),
+        Some(1300)
+    );
+    let expired = now + SYN_COOKIE_PERIOD * 2;
+    assert_eq!(
+        syncookie::check(server(), client(), 1000, cookie, expired),
+        None
+    );
+
+    assert_eq!(
+        syncookie::check(server(), client(), 1001, cookie, now),
+        None
+    );
+    assert_eq!(
+        syncookie::check(client(), server(), 1000, cookie, now),
+        None
+    );
+    assert_eq!(
+        syncookie::check(server(), client(), 1000, cookie ^ 1, now),
+        None
+    );
+}
+
+#[test]
+fn syn_flood_uses_cookies() {
+    let dev: &'static NetDevice = Box::leak(NetDevice::alloc());
+    let (id, local) = listener(40301, 1);
+    // SYN キューがいっぱいになっても pcb は使わない
+    for port in 0..TCP_SYN_QUEUE_SIZE as u16 {
+        inject(dev, peer(20000 + port), local, 1000, 0, TCP_FLAG_SYN);
+    }
+    assert_eq!(tcp::queue_lengths(id).unwrap(), (TCP_SYN_QUEUE_SIZE, 0));
+    inject(dev, peer(30000), local, 7000, 0, TCP_FLAG_SYN);
+    assert_eq!(tcp::queue_lengths(id).unwrap(), (TCP_SYN_QUEUE_SIZE, 0));
+
+    // 偽のクッキーでは接続できない
+    let foreign = peer(30000);
+    inject(dev, foreign, local, 7001, 12345, TCP_FLAG_ACK);
+    assert_eq!(tcp::queue_lengths(id).unwrap(), (TCP_SYN_QUEUE_SIZE, 0));
+
+    let cookie = syncookie::generate(local, foreign, 7000, 536, Instant::now());
+    inject(
+        dev,
+        foreign,
+        local,
+        7001,
+        cookie.wrapping_add(1),
+        TCP_FLAG_ACK,
+    );
+    assert_eq!(tcp::queue_lengths(id).unwrap(), (TCP_SYN_QUEUE_SIZE, 1));
+
+    // accept キューがあふれていれば ACK を捨てる
+    let other = peer(30001);
+    let cookie = syncookie::generate(local, other, 9000, 536, Instant::now());
+    inject(
+        dev,
+        other,
+        local,
+        9001,
+        cookie.wrapping_add(1),
+        TCP_FLAG_ACK,
+    );
+    assert_eq!(tcp::queue_lengths(id).unwrap(), (TCP_SYN_QUEUE_SIZE, 1));
+
+    let child = tcp::accept(id, Some(Duration::from_millis(10))).unwrap();
+    assert_eq!(tcp::state(child).unwrap(), TcpState::Established);
+    assert_eq!(tcp::foreign_endpoint(child).unwrap(), foreign);
+    assert_eq!(tcp::queue_lengths(id).unwrap(), (TCP_SYN_QUEUE_SIZE, 0));
+    tcp::abort(child).unwrap();
+    tcp::close(child).unwrap();
+    tcp::close(id).unwrap();
+}
+
+#[test]
+fn syn_queue_reset() {
+    let dev: &'static NetDevice = Box::leak(NetDevice::alloc());
+    let (id, local) = listener(40302, 4);
+    inject(dev, peer(20000), local, 1000, 0, TCP_FLAG_SYN);
+    inject(dev, peer(20001), local, 2000, 0, TCP_FLAG_SYN);
+    assert_eq!(tcp::queue_lengths(id).unwrap(), (2, 0));
+
+    inject(dev, peer(20000), local, 1001, 0, TCP_FLAG_RST);
+    assert_eq!(tcp::queue_lengths(id).unwrap(), (1, 0));
+    tcp::close(id).unwrap();
+    assert_eq!(
+        tcp::queue_lengths(id).unwrap_err().kind,
+        TcpErrorKind::InvalidId
+    );
+}
@@ -0,0 +1,251 @@
+mod congestion;
+mod keepalive;
+mod listen;
+mod option;
+mod persist;
+mod reset;
+mod retransmit;
+mod time_wait;
+
+use std::time::Instant;
+
+use rustic_stack::ipv4::{Ipv4Address, Ipv4Endpoint};
+use rustic_stack::tcp::header::{self, flags_to_string};
+use rustic_stack::tcp::{
+    seq_gt, seq_lt, TcpConnection, TcpErrorKind, TcpHeader, TcpSegment, TcpState, TCP_FLAG_ACK,
+    TCP_FLAG_FIN, TCP_FLAG_PSH, TCP_FLAG_RST, TCP_FLAG_SYN, TCP_HEADER_SIZE_MIN, TCP_MSL,
+};
+
+fn client() -> Ipv4Endpoint {
+    Ipv4Endpoint::new(Ipv4Address::new(192, 0, 2, 1), 49152)
+}
+
+fn server() -> Ipv4Endpoint {
+    Ipv4Endpoint::new(Ipv4Address::new(192, 0, 2, 2), 80)
+}
+
+/// segments を conn に順に渡して、返ってきたものをまとめて返す
+fn deliver(conn: &mut TcpConnection, segments: Vec<TcpSegment>, now: Instant) -> Vec<TcpSegment> {
+    segments
+        .iter()
+        .flat_map(|segment| conn.input(segment, now))
+        .collect()
+}
+
+fn establish(now: Instant) -> (TcpConnection, TcpConnection) {
+    let (mut a, syn) = TcpConnection::connect(client(), server(), 1000);
+    let (mut b, syn_ack) = TcpConnection::accept(server(), client(), &syn, 5000);
+    let ack = deliver(&mut a, vec![syn_ack], now);
+    assert!(deliver(&mut b, ack, now).is_empty());
+    (a, b)
+}
+
+#[test]
+fn build_and_parse() {
+    let data = header::build(
+        client(),
+        server(),
+        1,
+        2,
+        TCP_FLAG_SYN | TCP_FLAG_ACK,
+        1024,
+        &[],
+        b"data",
+    );
+    let header = TcpHeader::new_checked(&data[..]).unwrap();
+    assert_eq!(header.src_port(), 49152);
+    assert_eq!(header.dst_port(), 80);
+    assert_eq!(header.seq(), 1);
+    assert_eq!(header.ack(), 2);
+    assert_eq!(header.header_length(), TCP_HEADER_SIZE_MIN);
+    assert_eq!(header.flags(), TCP_FLAG_SYN | TCP_FLAG_ACK);
+    assert_eq!(header.window(), 1024);
+    assert_eq!(header.payload(), b"data");
+    assert!(header.verify_checksum(client().address, server().address));
+    assert!(!header.verify_checksum(client().address, Ipv4Address::new(192, 0, 2, 3)));
+
+    // データオフセットがバッファを超えている
+    let mut broken = data.clone();
+    broken[12] = 0xf0;
+    assert!(TcpHeader::new_checked(&broken[..24]).is_none());
+    assert_eq!(flags_to_string(TCP_FLAG_SYN | TCP_FLAG_ACK), "-S--A-");
+}
+
+#[test]
+fn sequence_comparison() {
+    assert!(seq_lt(1, 2));
+    assert!(seq_lt(u32::MAX, 0));
+    assert!(seq_gt(5, u32::MAX - 5));
+    assert!(!seq_lt(3, 3));
+}
+
+#[test]
+fn three_way_handshake() {
+    let now = Instant::now();
+    let (mut a, syn) = TcpConnection::connect(client(), server(), 1000);
+    assert_eq!(a.state, TcpState::SynSent);
+    assert_eq!(syn.flags, TCP_FLAG_SYN);
+    assert_eq!(syn.seq, 1000);
+
+    let (mut b, syn_ack) = TcpConnection::accept(server(), client(), &syn, 5000);
+    assert_eq!(b.state, TcpState::SynReceived);
+    assert_eq!(syn_ack.flags, TCP_FLAG_SYN | TCP_FLAG_ACK);
+    assert_eq!(syn_ack.ack, 1001);
+
+    let ack = a.input(&syn_ack, now);
+    assert_eq!(a.state, TcpState::Established);
+    assert_eq!(ack.len(), 1);
+    assert_eq!(ack[0].flags, TCP_FLAG_ACK);
+    assert_eq!((ack[0].seq, ack[0].ack), (1001, 5001));
+
+    assert!(b.input(&ack[0], now).is_empty());
+    assert_eq!(b.state, TcpState::Established);
+}
+
+#[test]
+fn data_transfer() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    // MSS ごとに分けて送る
+    let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    assert_eq!(a.send(&data), 1000);
+    let segments = a.output();
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[0].data.len(), a.send_mss() as usize);
+    assert_eq!(segments[1].flags, TCP_FLAG_ACK | TCP_FLAG_PSH);
+    assert!(!a.is_idle());
+
+    let acks = deliver(&mut b, segments, now);
+    assert_eq!(b.available(), 1000);
+    let mut buf = [0; 1500];
+    assert_eq!(b.read(&mut buf), 1000);
+    assert_eq!(&buf[..1000], &data[..]);
+
+    assert!(deliver(&mut a, acks, now).is_empty());
+    assert!(a.is_idle());
+}
+
+#[test]
+fn duplicate_data_is_acked_but_not_delivered() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    a.send(b"hello");
+    let segments = a.output();
+    deliver(&mut b, segments.clone(), now);
+    let acks = deliver(&mut b, segments, now);
+    assert_eq!(acks.len(), 1);
+    assert_eq!(acks[0].ack, b.rcv.nxt);
+    assert_eq!(b.available(), 5);
+}
+
+#[test]
+fn active_and_passive_close() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    let fin = a.close();
+    assert_eq!(a.state, TcpState::FinWait1);
+    assert_eq!(fin[0].flags, TCP_FLAG_FIN | TCP_FLAG_ACK);
+
+    let ack = deliver(&mut b, fin, now);
+    assert_eq!(b.state, TcpState::CloseWait);
+    assert!(b.is_eof());
+    deliver(&mut a, ack, now);
+    assert_eq!(a.state, TcpState::FinWait2);
+    // 片側だけ閉じても逆向きには送れる
+    assert!(b.can_send());
+    assert!(!a.can_send());
+
+    let fin = b.close();
+    assert_eq!(b.state, TcpState::LastAck);
+    let ack = deliver(&mut a, fin, now);
+    assert_eq!(a.state, TcpState::TimeWait);
+    deliver(&mut b, ack, now);
+    assert_eq!(b.state, TcpState::Closed);
+    assert_eq!(b.error(), None);
+
+    a.tick(now + TCP_MSL);
+    assert_eq!(a.state, TcpState::TimeWait);
+    a.tick(now + TCP_MSL * 2);
+    assert_eq!(a.state, TcpState::Closed);
+}
+
+#[test]
+fn simultaneous_close() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    let fin_a = a.close();
+    let fin_b = b.close();
+    let ack_b = deliver(&mut b, fin_a, now);
+    let ack_a = deliver(&mut a, fin_b, now);
+    assert_eq!(a.state, TcpState::Closing);
+    assert_eq!(b.state, TcpState::Closing);
+    deliver(&mut a, ack_b, now);
+    deliver(&mut b, ack_a, now);
+    assert_eq!(a.state, TcpState::TimeWait);
+    assert_eq!(b.state, TcpState::TimeWait);
+}
+
+#[test]
+fn refused_and_reset() {
+    let now = Instant::now();
+    let (mut a, syn) = TcpConnection::connect(client(), server(), 1000);
+    // 閉じたポートへの SYN には RST|ACK を返す
+    let reset = syn.reset_for().unwrap();
+    assert_eq!(reset.flags, TCP_FLAG_RST | TCP_FLAG_ACK);
+    assert_eq!(reset.ack, 1001);
+    assert!(reset.reset_for().is_none());
+    assert!(a.input(&reset, now).is_empty());
+    assert_eq!(a.state, TcpState::Closed);
+    assert_eq!(a.error(), Some(TcpErrorKind::ConnectionRefused));
+
+    let (mut a, mut b) = establish(now);
+    let reset = b.abort().unwrap();
+    assert_eq!(reset.flags, TCP_FLAG_RST);
+    a.input(&reset, now);
+    assert_eq!(a.state, TcpState::Closed);
+    assert_eq!(a.error(), Some(TcpErrorKind::ConnectionReset));
+}
+
+#[test]
+fn out_of_window_segment() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    // ウィンドウの外の RST は無視する
+    let reset = TcpSegment {
+        seq: b.rcv.nxt.wrapping_add(100_000),
+        ack: 0,
+        flags: TCP_FLAG_RST,
+        window: 0,
+        options: Default::default(),
+        data: Vec::new(),
+    };
+    assert!(b.input(&reset, now).is_empty());
+    assert_eq!(b.state, TcpState::Established);
+
+    // ウィンドウの外のデータには ACK だけ返す
+    a.send(b"x");
+    let mut segment = a.output().remove(0);
+    segment.seq = segment.seq.wrapping_add(100_000);
+    let acks = b.input(&segment, now);
+    assert_eq!(acks.len(), 1);
+    assert_eq!(acks[0].flags, TCP_FLAG_ACK);
+    assert_eq!(b.available(), 0);
+}
+
+#[test]
+fn window_limits_output() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    let data = vec![0; 3000];
+    a.send(&data);
+    // 相手のウィンドウが 1000 バイトなら 1000 バイトまでしか送らない
+    a.snd.wnd = 1000;
+    let segments = a.output();
+    let sent: usize = segments.iter().map(|segment| segment.data.len()).sum();
+    assert_eq!(sent, 1000);
+    assert!(a.output().is_empty());
+
+    let acks = deliver(&mut b, segments, now);
+    let more = deliver(&mut a, acks, now);
+    assert!(!more.is_empty());
+}
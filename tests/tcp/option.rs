@@ -0,0 +1,231 @@
+use std::time::Instant;
+
+use rustic_stack::tcp::header;
+use rustic_stack::tcp::option::{self, TcpOptionErrorKind};
+use rustic_stack::tcp::{
+    SackBlock, TcpConfig, TcpConnection, TcpHeader, TcpOption, TcpOptions, TcpSegment,
+    TcpTimestamp, TCP_FLAG_ACK, TCP_FLAG_SYN,
+};
+
+use super::{client, deliver, establish, server};
+
+#[test]
+fn options_round_trip() {
+    let options = TcpOptions {
+        mss: Some(1460),
+        window_scale: Some(7),
+        sack_permitted: true,
+        sack: vec![SackBlock {
+            left: 100,
+            right: 200,
+        }],
+        timestamp: Some(TcpTimestamp { value: 1, echo: 2 }),
+    };
+    let bytes = options.to_bytes();
+    assert_eq!(bytes.len() % 4, 0);
+    assert_eq!(TcpOptions::from_bytes(&bytes), options);
+
+    // ヘッダに載せて読み直しても同じ
+    let data = header::build(client(), server(), 1, 0, TCP_FLAG_SYN, 1024, &bytes, &[]);
+    let header = TcpHeader::new_checked(&data[..]).unwrap();
+    assert_eq!(TcpSegment::from_header(&header).options, options);
+
+    assert_eq!(
+        option::parse(&[1, 1, 4, 2, 0]).unwrap(),
+        vec![
+            TcpOption::NoOperation,
+            TcpOption::NoOperation,
+            TcpOption::SackPermitted,
+            TcpOption::EndOfList,
+        ]
+    );
+}
+
+#[test]
+fn malformed_options() {
+    let err = option::parse(&[2]).unwrap_err();
+    assert_eq!(err.kind, TcpOptionErrorKind::Truncated);
+    let err = option::parse(&[1, 2, 1]).unwrap_err();
+    assert_eq!(
+        (err.kind, err.offset),
+        (TcpOptionErrorKind::InvalidLength, 2)
+    );
+    let err = option::parse(&[2, 3, 0]).unwrap_err();
+    assert_eq!(err.kind, TcpOptionErrorKind::InvalidLength);
+    let err = option::parse(&[8, 10, 0, 0]).unwrap_err();
+    assert_eq!(err.kind, TcpOptionErrorKind::Truncated);
+    // 壊れていれば何も付いていないものとして扱う
+    assert_eq!(TcpOptions::from_bytes(&[2, 3, 0]), TcpOptions::default());
+}
+
+#[test]
+fn negotiation() {
+    let now = Instant::now();
+    let config = TcpConfig {
+        mss: 1460,
+        ..TcpConfig::default()
+    };
+    let (mut a, syn) = TcpConnection::connect_with(client(), server(), 1000, config);
+    assert_eq!(syn.options.mss, Some(1460));
+    assert!(syn.options.sack_permitted);
+    assert!(syn.options.window_scale.is_some());
+    assert_eq!(syn.options.timestamp.unwrap().echo, 0);
+
+    // 相手が申し出なかったものは使わない
+    let config = TcpConfig {
+        mss: 1400,
+        sack: false,
+        ..TcpConfig::default()
+    };
+    let (b, syn_ack) = TcpConnection::accept_with(server(), client(), &syn, 5000, config);
+    assert!(!syn_ack.options.sack_permitted);
+    assert_eq!(
+        syn_ack.options.timestamp.unwrap().echo,
+        syn.options.timestamp.unwrap().value
+    );
+    a.input(&syn_ack, now);
+    for conn in [&a, &b] {
+        assert_eq!(conn.mss, 1400);
+        assert!(!conn.sack_ok);
+        assert!(conn.timestamps_ok);
+        assert!(conn.window_scale_ok);
+        // Timestamp の分だけ載せるデータを減らす
+        assert_eq!(conn.send_mss(), 1388);
+    }
+
+    // MSS がなければ 536 とみなす
+    let mut bare = syn.clone();
+    bare.options = TcpOptions::default();
+    let (b, syn_ack) = TcpConnection::accept(server(), client(), &bare, 5000);
+    assert_eq!(b.mss, 536);
+    assert!(!b.timestamps_ok && !b.sack_ok && !b.window_scale_ok);
+    assert!(syn_ack.options.timestamp.is_none());
+}
+
+#[test]
+fn peer_window_scale() {
+    let now = Instant::now();
+    let (mut a, syn) = TcpConnection::connect(client(), server(), 1000);
+    let (mut b, mut syn_ack) = TcpConnection::accept(server(), client(), &syn, 5000);
+    syn_ack.options.window_scale = Some(2);
+    let ack = a.input(&syn_ack, now);
+    // SYN のウィンドウはずらさない
+    assert_eq!(a.snd.wnd, syn_ack.window as u32);
+    b.input(&ack[0], now);
+
+    b.send(b"scaled");
+    let segments = b.output();
+    a.input(&segments[0], now);
+    assert_eq!(a.snd.wnd, (segments[0].window as u32) << 2);
+}
+
+#[test]
+fn sack_blocks() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    let data = vec![3; a.send_mss() as usize * 4];
+    a.send(&data);
+    let segments = a.output();
+    assert_eq!(segments.len(), 4);
+    let end = |segment: &TcpSegment| segment.seq.wrapping_add(segment.data.len() as u32);
+
+    let ack = b.input(&segments[3], now);
+    assert_eq!(ack[0].ack, segments[0].seq);
+    assert_eq!(
+        ack[0].options.sack,
+        vec![SackBlock {
+            left: segments[3].seq,
+            right: end(&segments[3]),
+        }]
+    );
+
+    // 最後に受け取った範囲を先頭に置く
+    let ack = b.input(&segments[1], now);
+    assert_eq!(
+        ack[0].options.sack,
+        vec![
+            SackBlock {
+                left: segments[1].seq,
+                right: end(&segments[1]),
+            },
+            SackBlock {
+                left: segments[3].seq,
+                right: end(&segments[3]),
+            },
+        ]
+    );
+
+    // つながった範囲はまとめる
+    let ack = b.input(&segments[2], now);
+    assert_eq!(
+        ack[0].options.sack,
+        vec![SackBlock {
+            left: segments[1].seq,
+            right: end(&segments[3]),
+        }]
+    );
+
+    // 穴が埋まれば SACK は付けない
+    let ack = b.input(&segments[0], now);
+    assert_eq!(ack[0].ack, end(&segments[3]));
+    assert!(ack[0].options.sack.is_empty());
+    assert_eq!(b.available(), data.len());
+}
+
+#[test]
+fn paws_drops_old_segment() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    b.send(b"data");
+    let segment = b.output().remove(0);
+
+    let mut old = segment.clone();
+    let timestamp = old.options.timestamp.as_mut().unwrap();
+    timestamp.value = timestamp.value.wrapping_sub(10_000);
+    let ack = a.input(&old, now);
+    assert_eq!(ack.len(), 1);
+    assert_eq!(ack[0].flags, TCP_FLAG_ACK);
+    assert_eq!(ack[0].ack, segment.seq);
+    assert_eq!(a.available(), 0);
+
+    deliver(&mut a, vec![segment], now);
+    assert_eq!(a.available(), 4);
+}
+
+#[test]
+fn sack_recovery() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    let data = vec![9; a.send_mss() as usize * 4];
+    a.send(&data);
+    let segments = a.output();
+    assert_eq!(segments.len(), 4);
+
+    // 先頭の 2 つが落ちて後ろの 2 つが届き、最後の 1 つは重複して何度か届く
+    let dup_acks = deliver(
+        &mut b,
+        vec![
+            segments[2].clone(),
+            segments[3].clone(),
+            segments[3].clone(),
+            segments[3].clone(),
+        ],
+        now,
+    );
+    assert_eq!(dup_acks.len(), 4);
+    assert!(a.input(&dup_acks[0], now).is_empty());
+    assert!(a.input(&dup_acks[1], now).is_empty());
+    let retransmitted = a.input(&dup_acks[2], now);
+    assert_eq!(retransmitted[0].seq, segments[0].seq);
+
+    // 次の重複 ACK では SACK されていない 2 つ目を送り直す
+    let hole = a.input(&dup_acks[3], now);
+    assert_eq!(hole.len(), 1);
+    assert_eq!(hole[0].seq, segments[1].seq);
+    assert_eq!(hole[0].data, segments[1].data);
+
+    let acks = deliver(&mut b, vec![retransmitted[0].clone(), hole[0].clone()], now);
+    deliver(&mut a, acks, now);
+    assert_eq!(a.unacknowledged(), 0);
+    assert_eq!(b.available(), data.len());
+}
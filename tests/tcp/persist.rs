@@ -0,0 +1,48 @@
+use std::time::Instant;
+
+use rustic_stack::tcp::{TcpState, TCP_FLAG_ACK};
+
+use super::{deliver, establish};
+
+#[test]
+fn probe_zero_window() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    a.send(b"hello");
+    let data = a.output();
+    let update = deliver(&mut b, data, now);
+    assert_eq!(update.len(), 1);
+    assert!(update[0].window > 0);
+
+    // b の受信バッファが埋まったことにして窓を閉じる。開いたことを知らせる更新は落ちる
+    let mut closed = update[0].clone();
+    closed.window = 0;
+    assert!(a.input(&closed, now).is_empty());
+    assert_eq!(a.send(b"world"), 5);
+    assert!(a.output().is_empty());
+
+    let rto = a.rto().rto();
+    assert!(a.tick(now + rto / 2).is_empty());
+    let at = Instant::now() + rto;
+    let probe = a.tick(at);
+    assert_eq!(probe.len(), 1);
+    assert_eq!(probe[0].flags, TCP_FLAG_ACK);
+    assert_eq!(probe[0].seq, a.snd.una.wrapping_sub(1));
+    assert!(probe[0].data.is_empty());
+
+    // 窓が 0 のままの応答なら間隔を倍にして尋ね続ける
+    let mut answer = deliver(&mut b, probe, at);
+    assert_eq!(answer.len(), 1);
+    answer[0].window = 0;
+    assert!(deliver(&mut a, answer, at).is_empty());
+    assert!(a.tick(at + rto).is_empty());
+    let probe = a.tick(at + rto * 2);
+    assert_eq!(probe.len(), 1);
+
+    // 窓が開いたと知れば待っていたデータを送る
+    let answer = deliver(&mut b, probe, at + rto * 2);
+    let data = deliver(&mut a, answer, at + rto * 2);
+    assert_eq!(data.len(), 1);
+    assert_eq!(data[0].data, b"world");
+    assert_eq!(a.state, TcpState::Established);
+}
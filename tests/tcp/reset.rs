@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+
+use rustic_stack::tcp::{
+    TcpErrorKind, TcpSegment, TcpState, TCP_CHALLENGE_ACK_LIMIT, TCP_FLAG_ACK, TCP_FLAG_RST,
+    TCP_FLAG_SYN,
+};
+
+use super::{deliver, establish};
+
+fn control(seq: u32, ack: u32, flags: u8) -> TcpSegment {
+    TcpSegment {
+        seq,
+        ack,
+        flags,
+        window: 1024,
+        options: Default::default(),
+        data: Vec::new(),
+    }
+}
+
+#[test]
+fn in_window_reset_is_challenged() {
+    let now = Instant::now();
+    let (_, mut b) = establish(now);
+    // ぴったりでない RST には ACK を返して相手に確かめる
+    let challenge = b.input(&control(b.rcv.nxt.wrapping_add(1), 0, TCP_FLAG_RST), now);
+    assert_eq!(challenge.len(), 1);
+    assert_eq!(challenge[0].flags, TCP_FLAG_ACK);
+    assert_eq!(challenge[0].ack, b.rcv.nxt);
+    assert_eq!(b.state, TcpState::Established);
+
+    assert!(b
+        .input(&control(b.rcv.nxt, 0, TCP_FLAG_RST), now)
+        .is_empty());
+    assert_eq!(b.state, TcpState::Closed);
+    assert_eq!(b.error(), Some(TcpErrorKind::ConnectionReset));
+}
+
+#[test]
+fn syn_is_challenged() {
+    let now = Instant::now();
+    let (_, mut b) = establish(now);
+    let challenge = b.input(&control(b.rcv.nxt, 0, TCP_FLAG_SYN), now);
+    assert_eq!(challenge.len(), 1);
+    assert_eq!(challenge[0].flags, TCP_FLAG_ACK);
+    assert_eq!(b.state, TcpState::Established);
+}
+
+#[test]
+fn challenge_ack_limit() {
+    let now = Instant::now();
+    let (_, mut b) = establish(now);
+    let reset = control(b.rcv.nxt.wrapping_add(10), 0, TCP_FLAG_RST);
+    let acks = deliver(&mut b, vec![reset.clone(); 20], now);
+    assert_eq!(acks.len(), TCP_CHALLENGE_ACK_LIMIT as usize);
+    // 1 秒経てばまた返す
+    let acks = deliver(&mut b, vec![reset], now + Duration::from_secs(1));
+    assert_eq!(acks.len(), 1);
+}
+
+#[test]
+fn unacceptable_ack_is_dropped() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    b.send(b"x");
+    let mut segment = b.output().remove(0);
+    segment.ack = a.snd.una.wrapping_sub(1_000_000);
+    let challenge = a.input(&segment, now);
+    assert_eq!(challenge.len(), 1);
+    assert_eq!(a.available(), 0);
+
+    segment.ack = a.snd.nxt.wrapping_add(1);
+    assert_eq!(a.input(&segment, now).len(), 1);
+    assert_eq!(a.available(), 0);
+}
+
+#[test]
+fn close_with_unread_data() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    a.send(b"unread");
+    deliver(&mut b, a.output(), now);
+    // 読まれないデータは捨てたと RST で伝える
+    let reset = b.close();
+    assert_eq!(reset.len(), 1);
+    assert_eq!(reset[0].flags, TCP_FLAG_RST);
+    assert_eq!(b.state, TcpState::Closed);
+    deliver(&mut a, reset, now);
+    assert_eq!(a.error(), Some(TcpErrorKind::ConnectionReset));
+}
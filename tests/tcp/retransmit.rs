@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use rustic_stack::tcp::retransmit::{TCP_RTO_AFTER_SYN_TIMEOUT, TCP_SYN_RETRANSMIT_LIMIT};
+use rustic_stack::tcp::{
+    RtoEstimator, TcpConnection, TcpErrorKind, TcpState, TCP_FLAG_ACK, TCP_FLAG_SYN,
+    TCP_RTO_INITIAL, TCP_RTO_MAX, TCP_RTO_MIN,
+};
+
+use super::{client, deliver, establish, server};
+
+#[test]
+fn rto_estimation() {
+    let mut rto = RtoEstimator::new();
+    assert_eq!(rto.rto(), TCP_RTO_INITIAL);
+    assert!(rto.srtt().is_none());
+
+    rto.sample(Duration::from_secs(2));
+    assert_eq!(rto.srtt(), Some(Duration::from_secs(2)));
+    assert_eq!(rto.rttvar(), Duration::from_secs(1));
+    assert_eq!(rto.rto(), Duration::from_secs(6));
+
+    rto.sample(Duration::from_secs(1));
+    assert_eq!(rto.rttvar(), Duration::from_secs(1));
+    assert_eq!(rto.srtt(), Some(Duration::from_millis(1875)));
+    assert_eq!(rto.rto(), Duration::from_millis(5875));
+
+    rto.backoff();
+    assert_eq!(rto.rto(), Duration::from_millis(11750));
+    for _ in 0..4 {
+        rto.backoff();
+    }
+    assert_eq!(rto.rto(), TCP_RTO_MAX);
+
+    // 短い RTT でも下限より小さくはしない
+    let mut rto = RtoEstimator::new();
+    rto.sample(Duration::from_millis(10));
+    assert_eq!(rto.rto(), TCP_RTO_MIN);
+}
+
+#[test]
+fn retransmit_on_timeout() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    a.send(b"lost");
+    let lost = a.output();
+    assert_eq!(a.unacknowledged(), 1);
+
+    let sent = Instant::now();
+    assert!(a.tick(sent + TCP_RTO_MIN / 2).is_empty());
+    let retransmitted = a.tick(sent + TCP_RTO_MIN + Duration::from_millis(100));
+    assert_eq!(retransmitted.len(), 1);
+    assert_eq!(retransmitted[0].seq, lost[0].seq);
+    assert_eq!(retransmitted[0].data, b"lost");
+    assert_eq!(a.rto().rto(), TCP_RTO_MIN * 2);
+
+    let acks = deliver(&mut b, retransmitted, now);
+    assert!(deliver(&mut a, acks, Instant::now()).is_empty());
+    assert_eq!(a.unacknowledged(), 0);
+    // 再送したセグメントでは測らないので、倍にした RTO のまま
+    assert_eq!(a.rto().rto(), TCP_RTO_MIN * 2);
+    let mut buf = [0; 8];
+    assert_eq!(b.read(&mut buf), 4);
+}
+
+#[test]
+fn fast_retransmit() {
+    let now = Instant::now();
+    let (mut a, mut b) = establish(now);
+    let data = vec![7; a.send_mss() as usize * 4];
+    a.send(&data);
+    let mut segments = a.output();
+    assert_eq!(segments.len(), 4);
+    let first = segments.remove(0);
+
+    let dup_acks = deliver(&mut b, segments, now);
+    assert_eq!(dup_acks.len(), 3);
+    assert!(dup_acks.iter().all(|ack| ack.ack == first.seq));
+    assert!(a.input(&dup_acks[0], now).is_empty());
+    assert!(a.input(&dup_acks[1], now).is_empty());
+    let retransmitted = a.input(&dup_acks[2], now);
+    assert_eq!(retransmitted.len(), 1);
+    assert_eq!(retransmitted[0].seq, first.seq);
+    assert_eq!(retransmitted[0].data, first.data);
+
+    // 後ろは b が取っておいたので、先頭が届けばすべて確認される
+    let acks = deliver(&mut b, retransmitted, now);
+    assert_eq!(acks.len(), 1);
+    deliver(&mut a, acks, now);
+    assert_eq!(a.unacknowledged(), 0);
+    assert_eq!(b.available(), data.len());
+}
+
+#[test]
+fn syn_retransmit_limit() {
+    let (mut a, syn) = TcpConnection::connect(client(), server(), 1000);
+    let mut now = Instant::now();
+    for _ in 0..TCP_SYN_RETRANSMIT_LIMIT {
+        now += TCP_RTO_MAX;
+        // TSval は送るたびに変わるので比べない
+        let segments = a.tick(now);
+        assert_eq!(segments.len(), 1);
+        assert_eq!((segments[0].seq, segments[0].flags), (syn.seq, syn.flags));
+    }
+    now += TCP_RTO_MAX;
+    assert!(a.tick(now).is_empty());
+    assert_eq!(a.state, TcpState::Closed);
+    assert_eq!(a.error(), Some(TcpErrorKind::Timeout));
+}
+
+#[test]
+fn rto_after_syn_timeout() {
+    let (mut a, _) = TcpConnection::connect(client(), server(), 1000);
+    let syn = a.tick(Instant::now() + TCP_RTO_INITIAL).remove(0);
+    assert_eq!(syn.flags, TCP_FLAG_SYN);
+
+    let now = Instant::now();
+    let (_, syn_ack) = TcpConnection::accept(server(), client(), &syn, 5000);
+    let ack = a.input(&syn_ack, now);
+    assert_eq!(ack[0].flags, TCP_FLAG_ACK);
+    assert_eq!(a.state, TcpState::Established);
+    assert_eq!(a.rto().rto(), TCP_RTO_AFTER_SYN_TIMEOUT);
+    assert_eq!(a.unacknowledged(), 0);
+}
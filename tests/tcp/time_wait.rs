@@ -0,0 +1,84 @@
+use std::time::Instant;
+
+use rustic_stack::tcp::{
+    TcpConfig, TcpConnection, TcpSegment, TcpState, TCP_FLAG_ACK, TCP_FLAG_RST, TCP_MSL,
+};
+
+use super::{client, deliver, server};
+
+/// a を TIME-WAIT まで進める。b が最後に送った FIN も返す
+fn time_wait(config: TcpConfig, now: Instant) -> (TcpConnection, TcpConnection, Vec<TcpSegment>) {
+    let (mut a, syn) = TcpConnection::connect_with(client(), server(), 1000, config);
+    let (mut b, syn_ack) = TcpConnection::accept_with(server(), client(), &syn, 5000, config);
+    let ack = deliver(&mut a, vec![syn_ack], now);
+    deliver(&mut b, ack, now);
+    let fin = a.close();
+    let ack = deliver(&mut b, fin, now);
+    deliver(&mut a, ack, now);
+    let fin = b.close();
+    let ack = deliver(&mut a, fin.clone(), now);
+    deliver(&mut b, ack, now);
+    assert_eq!(a.state, TcpState::TimeWait);
+    assert_eq!(a.time_wait_since(), Some(now));
+    (a, b, fin)
+}
+
+#[test]
+fn retransmitted_fin_restarts_timer() {
+    let now = Instant::now();
+    let (mut a, _, fin) = time_wait(TcpConfig::default(), now);
+    let later = now + TCP_MSL;
+    let ack = deliver(&mut a, fin, later);
+    assert_eq!(ack.len(), 1);
+    assert_eq!(ack[0].flags, TCP_FLAG_ACK);
+    assert_eq!(a.time_wait_since(), Some(later));
+
+    a.tick(now + TCP_MSL * 2);
+    assert_eq!(a.state, TcpState::TimeWait);
+    a.tick(later + TCP_MSL * 2);
+    assert_eq!(a.state, TcpState::Closed);
+}
+
+#[test]
+fn reset_does_not_end_time_wait() {
+    let now = Instant::now();
+    let (mut a, _, _) = time_wait(TcpConfig::default(), now);
+    let reset = TcpSegment {
+        seq: a.rcv.nxt,
+        ack: 0,
+        flags: TCP_FLAG_RST,
+        window: 0,
+        options: Default::default(),
+        data: Vec::new(),
+    };
+    assert!(a.input(&reset, now).is_empty());
+    assert_eq!(a.state, TcpState::TimeWait);
+}
+
+#[test]
+fn new_syn_reuses_time_wait() {
+    let now = Instant::now();
+    // Timestamp があれば TSval で見分ける。b の TSval は ISS の 5000 から数える
+    let (a, _, _) = time_wait(TcpConfig::default(), now);
+    let (_, mut syn) = TcpConnection::connect(server(), client(), 1);
+    syn.seq = a.rcv.nxt.wrapping_sub(1);
+    syn.options.timestamp.as_mut().unwrap().value = 5000 + 1_000_000;
+    assert!(a.reusable_by(&syn));
+    syn.options.timestamp.as_mut().unwrap().value = 4000;
+    assert!(!a.reusable_by(&syn));
+
+    // なければ順序番号で見分ける
+    let config = TcpConfig {
+        timestamps: false,
+        ..TcpConfig::default()
+    };
+    let (a, _, _) = time_wait(config, now);
+    let (_, mut syn) = TcpConnection::connect_with(server(), client(), 1, config);
+    syn.seq = a.rcv.nxt.wrapping_add(100_000);
+    assert!(a.reusable_by(&syn));
+    syn.seq = a.rcv.nxt.wrapping_sub(1);
+    assert!(!a.reusable_by(&syn));
+    syn.seq = a.rcv.nxt.wrapping_add(100_000);
+    syn.flags |= TCP_FLAG_ACK;
+    assert!(!a.reusable_by(&syn));
+}
@@ -0,0 +1,24 @@
+use rustic_stack::ipv4::{Ipv4Address, Ipv4Endpoint};
+use rustic_stack::udp::{self, UdpHeader, UDP_HEADER_SIZE};
+
+#[test]
+fn build_datagram() {
+    let src = Ipv4Endpoint::new(Ipv4Address::new(192, 0, 2, 1), 49152);
+    let dst = Ipv4Endpoint::new(Ipv4Address::new(192, 0, 2, 2), 7);
+    let data = udp::build(src, dst, b"hello");
+    assert_eq!(data.len(), UDP_HEADER_SIZE + 5);
+
+    let header = UdpHeader::new_checked(&data[..]).unwrap();
+    assert_eq!(header.src_port(), 49152);
+    assert_eq!(header.dst_port(), 7);
+    assert_eq!(header.payload(), b"hello");
+    assert!(header.verify_checksum(src.address, dst.address));
+    assert!(!header.verify_checksum(src.address, Ipv4Address::new(192, 0, 2, 3)));
+}
+
+#[test]
+fn length_check() {
+    assert!(UdpHeader::new_checked(&[0u8; 4][..]).is_none());
+    // 長さフィールドがバッファより長い
+    assert!(UdpHeader::new_checked(&[0, 1, 0, 2, 0, 16, 0, 0][..]).is_none());
+}